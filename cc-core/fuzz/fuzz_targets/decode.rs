@@ -0,0 +1,35 @@
+#![no_main]
+
+use cancomponents_core::button_message::ButtonMessage;
+use cancomponents_core::can_id::CanId;
+use cancomponents_core::compress::Decoder;
+use cancomponents_core::device_message::IdTypeMsg;
+use cancomponents_core::error_report::ErrorReport;
+use cancomponents_core::message::CanMessage;
+use cancomponents_core::ota::{Chunk, ReadRange, BLOCK_SIZE};
+use cancomponents_core::relais_message::RelaisMessage;
+use libfuzzer_sys::fuzz_target;
+
+// input layout: 4 byte raw can id, 1 byte rtr flag, payload
+fuzz_target!(|input: &[u8]| {
+    if input.len() < 5 {
+        return;
+    }
+    let id = CanId::from(u32::from_le_bytes([input[0], input[1], input[2], input[3]]));
+    let rtr = input[4] & 1 != 0;
+    let data = &input[5..];
+
+    let _ = ButtonMessage::from_bytes(data);
+    let _ = RelaisMessage::from_bytes(data);
+    let _ = IdTypeMsg::parse(data);
+    let _ = ErrorReport::try_from(data);
+    let _ = ReadRange::parse(data);
+    let _ = Chunk::parse(data);
+    let mut block = Default::default();
+    let _ = Decoder::new().feed(data, &mut block, BLOCK_SIZE);
+
+    if let Ok(msg) = CanMessage::decode(id, data, rtr) {
+        let again = CanMessage::decode(id, &msg.encode(), msg.is_request());
+        assert_eq!(again, Ok(msg));
+    }
+});
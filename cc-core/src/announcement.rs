@@ -0,0 +1,165 @@
+use crate::decode_error::{expect_len, DecodeError};
+use crate::extension::Extension;
+use crate::image::Version;
+
+/// Features of a node, one bit each.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Capabilities(pub u8);
+
+impl Capabilities {
+    pub const RELAIS: Self = Self(1 << 0);
+    pub const ROLLERSHUTTER: Self = Self(1 << 1);
+    pub const BUTTONS: Self = Self(1 << 2);
+    /// only takes signed images with a matching header
+    pub const SIGNED_UPDATE: Self = Self(1 << 3);
+    pub const COMPRESSED_UPDATE: Self = Self(1 << 4);
+    pub const MULTICAST_UPDATE: Self = Self(1 << 5);
+    /// claims an address with its MAC, see `AddressClaim`
+    pub const ADDRESS_CLAIM: Self = Self(1 << 6);
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl core::fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let names = [
+            (Self::RELAIS, "relais"),
+            (Self::ROLLERSHUTTER, "rollershutter"),
+            (Self::BUTTONS, "buttons"),
+            (Self::SIGNED_UPDATE, "signed-update"),
+            (Self::COMPRESSED_UPDATE, "compressed-update"),
+            (Self::MULTICAST_UPDATE, "multicast-update"),
+            (Self::ADDRESS_CLAIM, "address-claim"),
+        ];
+        let mut first = true;
+        for (capability, name) in names {
+            if self.contains(capability) {
+                if !first {
+                    f.write_str(",")?;
+                }
+                f.write_str(name)?;
+                first = false;
+            }
+        }
+        if first {
+            f.write_str("none")?;
+        }
+        Ok(())
+    }
+}
+
+impl core::ops::BitOr for Capabilities {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// `Available` payload: what the node is, so the gateway does not have to
+/// ask for every parameter. Followed by a `Channels` frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Announcement {
+    /// update protocol, see `image::PROTOCOL_VERSION`
+    pub protocol: u8,
+    /// configured `DeviceType`, the CAN id only carries 6 bits of it
+    pub device_type: u8,
+    pub hwrev: u8,
+    pub version: Version,
+    pub extension: Extension,
+    pub capabilities: Capabilities,
+}
+
+impl Announcement {
+    pub fn parse(data: &[u8]) -> Result<Self, DecodeError> {
+        expect_len(data, 8)?;
+        Ok(Self {
+            protocol: data[0],
+            device_type: data[1],
+            hwrev: data[2],
+            version: Version {
+                major: data[3],
+                minor: data[4],
+                patch: data[5],
+            },
+            extension: Extension::from(data[6]),
+            capabilities: Capabilities(data[7]),
+        })
+    }
+
+    pub fn to_bytes(&self) -> [u8; 8] {
+        [
+            self.protocol,
+            self.device_type,
+            self.hwrev,
+            self.version.major,
+            self.version.minor,
+            self.version.patch,
+            self.extension.into(),
+            self.capabilities.0,
+        ]
+    }
+}
+
+/// Number of channels of each kind, sent after the `Announcement`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Channels {
+    pub relais: u8,
+    pub rollershutters: u8,
+    pub buttons: u8,
+}
+
+impl Channels {
+    pub fn parse(data: &[u8]) -> Result<Self, DecodeError> {
+        expect_len(data, 3)?;
+        Ok(Self {
+            relais: data[0],
+            rollershutters: data[1],
+            buttons: data[2],
+        })
+    }
+
+    pub fn to_bytes(&self) -> [u8; 3] {
+        [self.relais, self.rollershutters, self.buttons]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::fmt::Write;
+
+    #[test]
+    fn test_announcement() {
+        let announcement = Announcement {
+            protocol: 1,
+            device_type: 5,
+            hwrev: 2,
+            version: Version {
+                major: 1,
+                minor: 4,
+                patch: 0,
+            },
+            extension: Extension::Button,
+            capabilities: Capabilities::RELAIS | Capabilities::SIGNED_UPDATE,
+        };
+        assert_eq!(
+            Announcement::parse(&announcement.to_bytes()),
+            Ok(announcement)
+        );
+        assert!(announcement.capabilities.contains(Capabilities::RELAIS));
+        assert!(!announcement.capabilities.contains(Capabilities::BUTTONS));
+        assert!(Announcement::parse(&[1]).is_err());
+        let mut names: heapless::String<32> = heapless::String::new();
+        write!(
+            names,
+            "{} {}",
+            announcement.capabilities,
+            Capabilities::default()
+        )
+        .unwrap();
+        assert_eq!(names, "relais,signed-update none");
+    }
+}
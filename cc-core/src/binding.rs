@@ -0,0 +1,164 @@
+//! Bindings: relay nodes switch on `ButtonEvent` frames of other nodes
+//! themselves, so buttons keep working without the gateway.
+
+use crate::button_message::{ButtonMessage, ButtonState};
+use crate::decode_error::{expect_len, DecodeError};
+use crate::relais_message::RelaisState;
+use embassy_time::Duration;
+
+/// Bindings a node stores, numbered from 0.
+pub const MAX_BINDINGS: u8 = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Toggle,
+    On,
+    Off,
+    /// on, then off again after the duration
+    Timed(Duration),
+    Up,
+    Down,
+}
+
+impl Action {
+    /// State and duration to switch a relay in `current` to.
+    pub fn resolve(&self, current: &RelaisState) -> (RelaisState, Duration) {
+        let state = match self {
+            Action::Toggle if *current == RelaisState::Off => RelaisState::On,
+            Action::Toggle | Action::Off => RelaisState::Off,
+            Action::On => RelaisState::On,
+            Action::Timed(duration) => return (RelaisState::On, *duration),
+            Action::Up => RelaisState::Up,
+            Action::Down => RelaisState::Down,
+        };
+        (state, Duration::from_millis(0))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Binding {
+    pub slot: u8,
+    /// type and id of the button node
+    pub source_type: u8,
+    pub source_id: u8,
+    pub button: u8,
+    pub event: ButtonState,
+    /// only applied by members of this group, 0 for the node storing it
+    pub group: u8,
+    pub relay: u8,
+    pub action: Action,
+}
+
+impl Binding {
+    /// Slot, source type and id, button, event, group, relay in the low
+    /// five bits and action in the high three, then the seconds of a timed
+    /// action.
+    pub fn parse(data: &[u8]) -> Result<Self, DecodeError> {
+        expect_len(data, 8)?;
+        if data[0] >= MAX_BINDINGS {
+            return Err(DecodeError::InvalidValue(data[0]));
+        }
+        let action = match data[6] >> 5 {
+            0 => Action::Toggle,
+            1 => Action::On,
+            2 => Action::Off,
+            3 => Action::Timed(Duration::from_secs(data[7] as u64)),
+            4 => Action::Up,
+            5 => Action::Down,
+            _ => return Err(DecodeError::InvalidValue(data[6])),
+        };
+        Ok(Self {
+            slot: data[0],
+            source_type: data[1] & 0x3F,
+            source_id: data[2],
+            button: data[3],
+            event: ButtonState::try_from(data[4])?,
+            group: data[5] & 0x3F,
+            relay: data[6] & 0x1F,
+            action,
+        })
+    }
+
+    pub fn to_bytes(&self) -> [u8; 8] {
+        let (action, secs) = match self.action {
+            Action::Toggle => (0, 0),
+            Action::On => (1, 0),
+            Action::Off => (2, 0),
+            Action::Timed(duration) => (3, duration.as_secs().min(u8::MAX as u64) as u8),
+            Action::Up => (4, 0),
+            Action::Down => (5, 0),
+        };
+        [
+            self.slot,
+            self.source_type & 0x3F,
+            self.source_id,
+            self.button,
+            self.event as u8,
+            self.group & 0x3F,
+            (action << 5) | (self.relay & 0x1F),
+            secs,
+        ]
+    }
+
+    /// Whether `msg` sent by `source_type`/`source_id` triggers the binding.
+    pub fn matches(&self, source_type: u8, source_id: u8, msg: &ButtonMessage) -> bool {
+        self.source_type == source_type & 0x3F
+            && self.source_id == source_id
+            && self.button as usize == msg.num
+            && self.event == msg.state
+    }
+}
+
+/// Bits of the device id every source and id 0 have cleared, the
+/// acceptance filter for multicast also lets the sources through with
+/// those compared.
+pub fn source_filter_mask<'a>(bindings: impl IntoIterator<Item = &'a Binding>) -> u8 {
+    !bindings
+        .into_iter()
+        .fold(0, |bits, binding| bits | binding.source_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_binding() {
+        let binding = Binding {
+            slot: 4,
+            source_type: 4,
+            source_id: 3,
+            button: 2,
+            event: ButtonState::Double,
+            group: 0,
+            relay: 11,
+            action: Action::Timed(Duration::from_secs(120)),
+        };
+        assert_eq!(Binding::parse(&binding.to_bytes()), Ok(binding));
+        let press = ButtonMessage::new(2, ButtonState::Double, 0);
+        assert!(binding.matches(4, 3, &press));
+        assert!(!binding.matches(4, 4, &press));
+        assert!(!binding.matches(4, 3, &ButtonMessage::new(1, ButtonState::Double, 0)));
+
+        let mut bytes = binding.to_bytes();
+        bytes[6] = 7 << 5;
+        assert!(Binding::parse(&bytes).is_err());
+
+        let other = Binding {
+            source_id: 40,
+            ..binding
+        };
+        assert_eq!(source_filter_mask([&binding, &other]), !43);
+        assert_eq!(source_filter_mask([]), 0xFF);
+    }
+
+    #[test]
+    fn test_action() {
+        let (off, on) = (RelaisState::Off, RelaisState::On);
+        assert_eq!(Action::Toggle.resolve(&off).0, on);
+        assert_eq!(Action::Toggle.resolve(&on).0, off);
+        assert_eq!(Action::Off.resolve(&on).0, off);
+        let timed = Action::Timed(Duration::from_secs(60)).resolve(&on);
+        assert_eq!(timed, (on, Duration::from_secs(60)));
+    }
+}
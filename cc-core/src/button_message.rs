@@ -1,3 +1,5 @@
+use crate::decode_error::{expect_len, DecodeError};
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub enum ButtonState {
@@ -12,7 +14,7 @@ pub enum ButtonState {
 }
 
 impl core::convert::TryFrom<u8> for ButtonState {
-    type Error = ();
+    type Error = DecodeError;
 
     fn try_from(value: u8) -> Result<Self, Self::Error> {
         use ButtonState::*;
@@ -24,13 +26,14 @@ impl core::convert::TryFrom<u8> for ButtonState {
             4 => Double,
             5 => Tripple,
             6 => Quadruple,
-            127 => Multi,
-            _ => return Err(()),
+            // 127 is what older firmware sent, `Multi` is sent as 128
+            127 | 128 => Multi,
+            _ => return Err(DecodeError::UnknownState(value)),
         };
         Ok(result)
     }
 }
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct ButtonMessage {
     pub num: usize,
     pub state: ButtonState,
@@ -40,17 +43,18 @@ pub struct ButtonMessage {
 impl ButtonMessage {
     pub fn new(num: usize, state: ButtonState, count: u16) -> ButtonMessage {
         let state = if state == ButtonState::Multi {
-            ButtonState::try_from(count as u8 + 2).unwrap_or(ButtonState::Multi)
+            u8::try_from(count.saturating_add(2))
+                .ok()
+                .and_then(|v| ButtonState::try_from(v).ok())
+                .unwrap_or(ButtonState::Multi)
         } else {
             state
         };
         ButtonMessage { num, state, count }
     }
 
-    pub async fn from_bytes(data: &[u8]) -> Result<Self, ()> {
-        if data.len() < 2 {
-            return Err(());
-        }
+    pub fn from_bytes(data: &[u8]) -> Result<Self, DecodeError> {
+        expect_len(data, 4)?;
 
         let num = data[0] as usize;
         let state: ButtonState = ButtonState::try_from(data[1])?;
@@ -68,3 +72,17 @@ impl ButtonMessage {
         bytes
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_multi() {
+        let msg = ButtonMessage::from_bytes(&[1, 127, 0, 7]).unwrap();
+        assert_eq!(msg.state, ButtonState::Multi);
+        assert_eq!(msg.to_bytes(), [1, 128, 0, 7]);
+        assert_eq!(ButtonMessage::from_bytes(&msg.to_bytes()), Ok(msg));
+        assert!(ButtonMessage::from_bytes(&[1, 129, 0, 7]).is_err());
+    }
+}
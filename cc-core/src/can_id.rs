@@ -1,4 +1,5 @@
 use crate::can_message_type::CanMessageType;
+use crate::group::Groups;
 use embedded_can::ExtendedId;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -20,6 +21,27 @@ impl CanId {
             msg_type,
         }
     }
+
+    /// To every member of `group`, with `device_type` 0 of any type.
+    pub fn group(group: u8, device_type: u8, msg_type: CanMessageType) -> Self {
+        Self {
+            group: group & 0x3F,
+            ..Self::new(device_type, 0, msg_type)
+        }
+    }
+
+    /// Whether a node with this address and groups handles the frame: its
+    /// own address, the broadcast 0/0, its type with id 0 or, with id 0
+    /// and a group set, one of its groups.
+    pub fn is_for(&self, device_type: u8, device_id: u8, groups: Groups) -> bool {
+        let device_type = device_type & 0x3F;
+        if self.device_type == device_type && self.device_id == device_id {
+            return true;
+        }
+        let multicast =
+            self.device_id == 0 && (self.device_type == 0 || self.device_type == device_type);
+        multicast && (self.group == 0 || groups.contains(self.group))
+    }
 }
 
 impl From<CanId> for u32 {
@@ -71,6 +93,7 @@ mod tests {
     use super::*;
 
     #[test]
+    #[allow(clippy::unnecessary_fallible_conversions)]
     fn test_device_init() {
         let can_id = CanId::new(0x01, 0x01, CanMessageType::Nightlight);
         let can_id_u32: u32 = can_id.into();
@@ -83,4 +106,31 @@ mod tests {
         let can_id_ext_back = TryInto::<CanId>::try_into(can_id_ext.unwrap());
         assert!(can_id_ext_back.is_ok())
     }
+
+    #[test]
+    fn test_roundtrip() {
+        let can_id = CanId::new(0x01, 0x01, CanMessageType::Nightlight);
+        let can_id_u32: u32 = can_id.into();
+        assert_eq!(CanId::from(can_id_u32), can_id);
+
+        let can_id_ext: ExtendedId = can_id.into();
+        assert_eq!(CanId::from(can_id_ext), can_id);
+    }
+
+    #[test]
+    fn test_is_for() {
+        let groups: Groups = [2, 7].into_iter().collect();
+        let to = |id: CanId| id.is_for(5, 12, groups);
+        assert!(to(CanId::new(5, 12, CanMessageType::Relais)));
+        assert!(to(CanId::new(0, 0, CanMessageType::Relais)));
+        assert!(to(CanId::new(5, 0, CanMessageType::Relais)));
+        assert!(!to(CanId::new(4, 0, CanMessageType::Relais)));
+        assert!(!to(CanId::new(5, 13, CanMessageType::Relais)));
+        assert!(to(CanId::group(7, 0, CanMessageType::Relais)));
+        assert!(to(CanId::group(2, 5, CanMessageType::Relais)));
+        assert!(!to(CanId::group(2, 4, CanMessageType::Relais)));
+        assert!(!to(CanId::group(3, 0, CanMessageType::Relais)));
+        let raw: u32 = CanId::group(7, 5, CanMessageType::Relais).into();
+        assert_eq!(CanId::from(raw).group, 7);
+    }
 }
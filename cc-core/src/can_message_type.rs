@@ -24,10 +24,24 @@ pub enum CanMessageType {
     FlashVerify = 20,
     FlashProgress = 21,
     FlashComplete = 22,
+    UpdateConfirmWindow = 23,
+    FlashHeader = 24,
+    FlashMissing = 25,
+    FlashBlock = 26,
+    AddressClaim = 27,
+    Channels = 28,
+    NodeMode = 29,
     ButtonEvent = 30,
     TemperatureSensor = 31,
+    Segment = 32,
+    Metadata = 33,
     HwRev = 41,
     ExtensionMode = 42,
+    AutonomousPolicy = 43,
+    Supervision = 44,
+    PowerOn = 45,
+    ShutterTravel = 46,
+    DeadTime = 47,
     LampGroup = 90,
     PirSensor = 128,
     HumiditySensor = 129,
@@ -36,6 +50,11 @@ pub enum CanMessageType {
     Rollershutter = 132,
     RollershutterState = 133,
     RelaisMode = 134,
+    SceneDefine = 135,
+    SceneRecall = 136,
+    SceneStore = 137,
+    BindingDefine = 138,
+    BindingClear = 139,
     AmbientLightSensor = 140,
     AmbientLightSensorWhite = 141,
     Nightlight = 150,
@@ -77,10 +96,24 @@ impl From<u8> for CanMessageType {
             20 => FlashVerify,
             21 => FlashProgress,
             22 => FlashComplete,
+            23 => UpdateConfirmWindow,
+            24 => FlashHeader,
+            25 => FlashMissing,
+            26 => FlashBlock,
+            27 => AddressClaim,
+            28 => Channels,
+            29 => NodeMode,
             30 => ButtonEvent,
             31 => TemperatureSensor,
+            32 => Segment,
+            33 => Metadata,
             41 => HwRev,
             42 => ExtensionMode,
+            43 => AutonomousPolicy,
+            44 => Supervision,
+            45 => PowerOn,
+            46 => ShutterTravel,
+            47 => DeadTime,
             90 => LampGroup,
             128 => PirSensor,
             129 => HumiditySensor,
@@ -89,6 +122,11 @@ impl From<u8> for CanMessageType {
             132 => Rollershutter,
             133 => RollershutterState,
             134 => RelaisMode,
+            135 => SceneDefine,
+            136 => SceneRecall,
+            137 => SceneStore,
+            138 => BindingDefine,
+            139 => BindingClear,
             140 => AmbientLightSensor,
             141 => AmbientLightSensorWhite,
             150 => Nightlight,
@@ -99,7 +137,25 @@ impl From<u8> for CanMessageType {
             155 => LogDownload,
             156 => Ping,
             157 => PingDisable,
+            158 => Echo,
             _ => InvalidMessage,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_u8() {
+        for value in 0..=u8::MAX {
+            let msg_type = CanMessageType::from(value);
+            if msg_type != CanMessageType::InvalidMessage {
+                assert_eq!(msg_type as u8, value);
+            }
+        }
+        assert_eq!(CanMessageType::from(158), CanMessageType::Echo);
+        assert_eq!(CanMessageType::from(44), CanMessageType::Supervision);
+    }
+}
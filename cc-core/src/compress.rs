@@ -0,0 +1,218 @@
+//! LZSS in the bit format of heatshrink. Every block of an update is
+//! compressed on its own, so a node decompresses straight into its block
+//! buffer without a separate window and a block can be sent again or
+//! resumed without the ones before it.
+
+use crate::decode_error::DecodeError;
+use crate::ota::BLOCK_SIZE;
+use heapless::Vec;
+
+/// Back references reach the whole block.
+pub const WINDOW_BITS: u32 = 12;
+
+/// Bits of the match length.
+pub const LENGTH_BITS: u32 = 5;
+
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 1 << LENGTH_BITS;
+const MAX_CHAIN: usize = 256;
+const HASH_BITS: u32 = 12;
+const NONE: u16 = u16::MAX;
+
+/// Largest compressed block: every byte a literal of 9 bits.
+pub const MAX_COMPRESSED_BLOCK: usize = (BLOCK_SIZE * 9).div_ceil(8);
+
+/// Compress one block, `out` gets the bytes. Padding bits of the last
+/// byte are zero.
+pub fn compress(block: &[u8], mut out: impl FnMut(u8)) {
+    debug_assert!(block.len() <= BLOCK_SIZE);
+    let mut writer = BitWriter::default();
+    let mut head = [NONE; 1 << HASH_BITS];
+    let mut prev = [NONE; BLOCK_SIZE];
+    let hash = |pos: usize| {
+        let v = u32::from_le_bytes([block[pos], block[pos + 1], block[pos + 2], 0]);
+        (v.wrapping_mul(0x9E37_79B1) >> (32 - HASH_BITS)) as usize
+    };
+    let insert = |pos: usize, head: &mut [u16], prev: &mut [u16]| {
+        if pos + MIN_MATCH <= block.len() {
+            let h = hash(pos);
+            prev[pos] = head[h];
+            head[h] = pos as u16;
+        }
+    };
+
+    let mut pos = 0;
+    while pos < block.len() {
+        let max = (block.len() - pos).min(MAX_MATCH);
+        let (mut best_len, mut best_pos) = (0, 0);
+        if max >= MIN_MATCH {
+            let mut candidate = head[hash(pos)];
+            for _ in 0..MAX_CHAIN {
+                if candidate == NONE {
+                    break;
+                }
+                let c = candidate as usize;
+                let len = (0..max)
+                    .take_while(|&i| block[c + i] == block[pos + i])
+                    .count();
+                if len > best_len {
+                    (best_len, best_pos) = (len, c);
+                    if len == max {
+                        break;
+                    }
+                }
+                candidate = prev[c];
+            }
+        }
+        if best_len >= MIN_MATCH {
+            writer.push(0, 1, &mut out);
+            writer.push((pos - best_pos - 1) as u32, WINDOW_BITS, &mut out);
+            writer.push((best_len - 1) as u32, LENGTH_BITS, &mut out);
+            for p in pos..pos + best_len {
+                insert(p, &mut head, &mut prev);
+            }
+            pos += best_len;
+        } else {
+            writer.push(0x100 | block[pos] as u32, 9, &mut out);
+            insert(pos, &mut head, &mut prev);
+            pos += 1;
+        }
+    }
+    writer.flush(&mut out);
+}
+
+#[derive(Default)]
+struct BitWriter {
+    bits: u32,
+    count: u32,
+}
+
+impl BitWriter {
+    fn push(&mut self, value: u32, count: u32, out: &mut impl FnMut(u8)) {
+        self.bits = (self.bits << count) | value;
+        self.count += count;
+        while self.count >= 8 {
+            self.count -= 8;
+            out((self.bits >> self.count) as u8);
+        }
+        self.bits &= (1 << self.count) - 1;
+    }
+
+    fn flush(&mut self, out: &mut impl FnMut(u8)) {
+        if self.count > 0 {
+            out((self.bits << (8 - self.count)) as u8);
+            self.count = 0;
+        }
+    }
+}
+
+/// Incremental decompression of one block, fed chunk by chunk.
+#[derive(Debug, Clone, Default)]
+pub struct Decoder {
+    bits: u32,
+    count: u32,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decompress `data` and append it to `out` until it holds `len`
+    /// bytes. Fails on references outside of the block and on data left
+    /// over once the block is full.
+    pub fn feed(
+        &mut self,
+        data: &[u8],
+        out: &mut Vec<u8, BLOCK_SIZE>,
+        len: usize,
+    ) -> Result<(), DecodeError> {
+        for &byte in data {
+            if out.len() >= len {
+                return Err(DecodeError::Trailing);
+            }
+            self.bits = (self.bits << 8) | byte as u32;
+            self.count += 8;
+            while out.len() < len && self.count > 0 {
+                let literal = self.bits >> (self.count - 1) & 1 == 1;
+                if literal {
+                    if self.count < 9 {
+                        break;
+                    }
+                    self.count -= 9;
+                    out.push((self.bits >> self.count) as u8).unwrap();
+                } else {
+                    if self.count < 1 + WINDOW_BITS + LENGTH_BITS {
+                        break;
+                    }
+                    self.count -= 1 + WINDOW_BITS + LENGTH_BITS;
+                    let token = self.bits >> self.count;
+                    let distance = (token >> LENGTH_BITS & ((1 << WINDOW_BITS) - 1)) as usize + 1;
+                    let count = (token & (MAX_MATCH as u32 - 1)) as usize + 1;
+                    if distance > out.len() || out.len() + count > len {
+                        return Err(DecodeError::InvalidValue(byte));
+                    }
+                    let start = out.len() - distance;
+                    for i in start..start + count {
+                        out.push(out[i]).unwrap();
+                    }
+                }
+                self.bits &= (1 << self.count) - 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip(block: &[u8]) -> usize {
+        let mut compressed: Vec<u8, MAX_COMPRESSED_BLOCK> = Vec::new();
+        compress(block, |b| compressed.push(b).unwrap());
+        let mut out = Vec::new();
+        let mut decoder = Decoder::new();
+        for chunk in compressed.chunks(6) {
+            decoder.feed(chunk, &mut out, block.len()).unwrap();
+        }
+        assert_eq!(out, block);
+        compressed.len()
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let mut block = [0xFF; BLOCK_SIZE];
+        assert!(roundtrip(&block) < 300);
+        for (i, b) in block.iter_mut().enumerate().take(3000) {
+            *b = (i * 7 % 13) as u8 ^ (i / 200) as u8;
+        }
+        assert!(roundtrip(&block) < BLOCK_SIZE / 4);
+        // incompressible: xorshift noise
+        let mut x = 0x1234_5678u32;
+        for b in block.iter_mut() {
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            *b = x as u8;
+        }
+        assert!(roundtrip(&block) <= MAX_COMPRESSED_BLOCK);
+        roundtrip(&block[..5]);
+        roundtrip(&[]);
+    }
+
+    #[test]
+    fn test_corrupt() {
+        let mut out = Vec::new();
+        // a reference before the start of the block
+        assert!(Decoder::new().feed(&[0, 0, 0], &mut out, 10).is_err());
+        let mut out = Vec::new();
+        let mut compressed: Vec<u8, MAX_COMPRESSED_BLOCK> = Vec::new();
+        compress(b"abcd", |b| compressed.push(b).unwrap());
+        compressed.push(0).unwrap();
+        assert_eq!(
+            Decoder::new().feed(&compressed, &mut out, 4),
+            Err(DecodeError::Trailing)
+        );
+    }
+}
@@ -0,0 +1,73 @@
+/// Reason why a payload could not be decoded. None of the parsers in this
+/// crate panic on malformed input, they return one of these instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    /// payload has fewer bytes than the message format needs
+    TooShort { expected: usize, got: usize },
+    /// payload has more bytes than the message format allows
+    Trailing,
+    /// state or mode byte is not a known variant
+    UnknownState(u8),
+    /// value byte is outside of the allowed range
+    InvalidValue(u8),
+    /// string payload is not valid UTF-8
+    InvalidUtf8,
+    /// message type is not known
+    UnknownMessageType,
+    /// value longer than its field allows
+    TooLong { max: usize, got: usize },
+}
+
+impl DecodeError {
+    /// Stable numeric code, used as `local_code` in error reports.
+    pub fn code(&self) -> u8 {
+        match self {
+            DecodeError::TooShort { .. } => 1,
+            DecodeError::Trailing => 2,
+            DecodeError::UnknownState(_) => 3,
+            DecodeError::InvalidValue(_) => 4,
+            DecodeError::InvalidUtf8 => 5,
+            DecodeError::UnknownMessageType => 6,
+            DecodeError::TooLong { .. } => 7,
+        }
+    }
+
+    /// One byte of context: the expected length or the offending value.
+    pub fn detail(&self) -> u8 {
+        match self {
+            DecodeError::TooShort { expected, .. } => *expected as u8,
+            DecodeError::TooLong { max, .. } => *max as u8,
+            DecodeError::UnknownState(v) | DecodeError::InvalidValue(v) => *v,
+            _ => 0,
+        }
+    }
+}
+
+impl core::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            DecodeError::TooShort { expected, got } => {
+                write!(f, "payload too short: expected {expected} bytes, got {got}")
+            }
+            DecodeError::Trailing => write!(f, "trailing bytes in payload"),
+            DecodeError::UnknownState(v) => write!(f, "unknown state {v}"),
+            DecodeError::InvalidValue(v) => write!(f, "invalid value {v}"),
+            DecodeError::InvalidUtf8 => write!(f, "invalid utf-8"),
+            DecodeError::UnknownMessageType => write!(f, "unknown message type"),
+            DecodeError::TooLong { max, got } => {
+                write!(f, "value too long: at most {max} bytes, got {got}")
+            }
+        }
+    }
+}
+
+impl core::error::Error for DecodeError {}
+
+/// Check that `data` is exactly `expected` bytes long.
+pub fn expect_len(data: &[u8], expected: usize) -> Result<(), DecodeError> {
+    match data.len() {
+        got if got < expected => Err(DecodeError::TooShort { expected, got }),
+        got if got > expected => Err(DecodeError::Trailing),
+        _ => Ok(()),
+    }
+}
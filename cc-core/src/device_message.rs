@@ -1,14 +1,111 @@
+use crate::can_id::CanId;
+use crate::can_message_type::CanMessageType;
+use crate::decode_error::{expect_len, DecodeError};
+
+/// Id of a node that has no address yet. It sends `AddressClaim` until the
+/// gateway assigns one.
+pub const UNASSIGNED_ID: u8 = 255;
+
+/// `DeviceIdType` payload: new id and type, optionally followed by the 6
+/// byte UID (MAC) of the node that has to apply them. Without UID only a
+/// node that was sent its own UID as `DeviceUid0` and `DeviceUid1` before
+/// applies it.
 #[derive(Debug, Copy, Clone)]
 pub struct IdTypeMsg {}
 
 impl IdTypeMsg {
-    pub fn parse(data: &[u8]) -> Option<(u8, u8)> {
-        if data.len() != 2 {
-            return None;
+    pub fn parse(data: &[u8]) -> Result<(u8, u8, Option<u64>), DecodeError> {
+        if data.len() > 2 {
+            expect_len(data, 8)?;
+        } else {
+            expect_len(data, 2)?;
         }
 
         let id = data[0];
         let dtype = data[1];
-        Some((id, dtype))
+        let uid = (data.len() == 8).then(|| {
+            let mut buf = [0u8; 8];
+            buf[..6].copy_from_slice(&data[2..]);
+            u64::from_le_bytes(buf)
+        });
+        Ok((id, dtype, uid))
+    }
+
+    pub fn to_bytes(id: u8, dtype: u8, uid: Option<u64>) -> heapless::Vec<u8, 8> {
+        let mut out = heapless::Vec::from_slice(&[id, dtype]).unwrap();
+        if let Some(uid) = uid {
+            out.extend_from_slice(&uid.to_le_bytes()[..6]).unwrap();
+        }
+        out
+    }
+}
+
+/// Whether a frame for our own address was sent by another node using the
+/// same address. Only nodes report errors and announce themselves with a
+/// payload, the gateway pings a node with an `Available` without one.
+pub fn is_address_conflict(
+    device_type: u8,
+    device_id: u8,
+    id: CanId,
+    remote_request: bool,
+    data: &[u8],
+) -> bool {
+    let sent_by_node = match id.msg_type {
+        CanMessageType::Available => !data.is_empty(),
+        CanMessageType::DeviceError => true,
+        _ => false,
+    };
+    sent_by_node
+        && !remote_request
+        && device_id != 0
+        && device_id != UNASSIGNED_ID
+        && id.device_id == device_id
+        && id.device_type == device_type & 0x3F
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_id_type() {
+        assert_eq!(IdTypeMsg::parse(&[12, 5]), Ok((12, 5, None)));
+        let data = IdTypeMsg::to_bytes(12, 5, Some(0xa1b2_c3d4_e5f6));
+        assert_eq!(data.len(), 8);
+        assert_eq!(IdTypeMsg::parse(&data), Ok((12, 5, Some(0xa1b2_c3d4_e5f6))));
+        assert!(IdTypeMsg::parse(&data[..5]).is_err());
+        assert!(IdTypeMsg::parse(&[12]).is_err());
+    }
+
+    #[test]
+    fn test_address_conflict() {
+        let id = CanId::new(5, 12, CanMessageType::Available);
+        assert!(is_address_conflict(5, 12, id, false, &[1]));
+        assert!(!is_address_conflict(5, 12, id, true, &[]));
+        assert!(!is_address_conflict(5, 13, id, false, &[1]));
+        assert!(!is_address_conflict(
+            5,
+            12,
+            CanId::new(5, 12, CanMessageType::Relais),
+            false,
+            &[1]
+        ));
+        let unassigned = CanId::new(5, UNASSIGNED_ID, CanMessageType::Available);
+        assert!(!is_address_conflict(
+            5,
+            UNASSIGNED_ID,
+            unassigned,
+            false,
+            &[1]
+        ));
+    }
+
+    #[test]
+    fn test_gateway_ping() {
+        // the gateway asking whether we are there is no conflict
+        let id = CanId::new(5, 12, CanMessageType::Available);
+        assert!(!is_address_conflict(5, 12, id, false, &[]));
+        let error = CanId::new(5, 12, CanMessageType::DeviceError);
+        assert!(is_address_conflict(5, 12, error, false, &[0; 8]));
     }
 }
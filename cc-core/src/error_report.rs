@@ -0,0 +1,128 @@
+use crate::decode_error::{expect_len, DecodeError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ErrorReport {
+    pub component: Component,
+    pub code: ErrorCode,
+    pub severity: Severity,
+    pub local_code: u8,
+    pub details: [u8; 4],
+}
+
+impl ErrorReport {
+    pub fn new(
+        component: Component,
+        code: ErrorCode,
+        severity: Severity,
+        local_code: u8,
+        details: &[u8],
+    ) -> Self {
+        let mut d = [0u8; 4];
+        d[..details.len().min(4)].copy_from_slice(&details[..details.len().min(4)]);
+        Self {
+            component,
+            code,
+            severity,
+            local_code,
+            details: d,
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; 8] {
+        [
+            self.component as u8,
+            self.code as u8,
+            self.severity as u8,
+            self.local_code,
+            self.details[0],
+            self.details[1],
+            self.details[2],
+            self.details[3],
+        ]
+    }
+}
+
+impl TryFrom<&[u8]> for ErrorReport {
+    type Error = DecodeError;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        expect_len(value, 8)?;
+
+        Ok(Self {
+            component: Component::from(value[0]),
+            code: ErrorCode::from(value[1]),
+            severity: Severity::from(value[2]),
+            local_code: value[3],
+            details: [value[4], value[5], value[6], value[7]],
+        })
+    }
+}
+
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    Unknown = 0,
+    InvalidData = 1,
+    /// another node uses our address
+    AddressConflict = 2,
+}
+
+impl From<u8> for ErrorCode {
+    fn from(value: u8) -> Self {
+        match value {
+            1 => ErrorCode::InvalidData,
+            2 => ErrorCode::AddressConflict,
+            _ => ErrorCode::Unknown,
+        }
+    }
+}
+
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Severity {
+    Unknown = 0,
+    Warning = 1,
+    RecoverableError = 2,
+    RepeatingError = 3,
+    Error = 4,
+    CriticalError = 5,
+}
+
+impl From<u8> for Severity {
+    fn from(value: u8) -> Self {
+        match value {
+            1 => Severity::Warning,
+            2 => Severity::RecoverableError,
+            3 => Severity::RepeatingError,
+            4 => Severity::Error,
+            5 => Severity::CriticalError,
+            _ => Severity::Unknown,
+        }
+    }
+}
+
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Component {
+    Unknown = 0,
+    Can = 1,
+    Device = 2,
+    Update = 3,
+    Storage = 4,
+    Ota = 5,
+    Relais = 6,
+}
+
+impl From<u8> for Component {
+    fn from(value: u8) -> Self {
+        match value {
+            1 => Component::Can,
+            2 => Component::Device,
+            3 => Component::Update,
+            4 => Component::Storage,
+            5 => Component::Ota,
+            6 => Component::Relais,
+            _ => Component::Unknown,
+        }
+    }
+}
@@ -0,0 +1,72 @@
+//! Group addressing: a frame with a `group` in its id and device id 0 goes
+//! to every member of the group, optionally only those of one type.
+
+/// Highest group, the id field has 6 bits. Group 0 is no group.
+pub const MAX_GROUP: u8 = 63;
+
+/// Groups a node is a member of, bit n for group n.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Groups(pub u64);
+
+impl Groups {
+    pub fn contains(&self, group: u8) -> bool {
+        (1..=MAX_GROUP).contains(&group) && self.0 & (1 << group) != 0
+    }
+
+    /// Returns false for group 0 and groups above `MAX_GROUP`.
+    pub fn insert(&mut self, group: u8) -> bool {
+        if !(1..=MAX_GROUP).contains(&group) {
+            return false;
+        }
+        self.0 |= 1 << group;
+        true
+    }
+
+    pub fn remove(&mut self, group: u8) {
+        if group <= MAX_GROUP {
+            self.0 &= !(1 << group);
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = u8> + '_ {
+        (1..=MAX_GROUP).filter(|&group| self.contains(group))
+    }
+
+    /// Bits of the group field every member group and group 0 have
+    /// cleared, the acceptance filter compares those. Lets through more
+    /// than the members, `CanId::is_for` decides.
+    pub fn filter_mask(&self) -> u8 {
+        let used = self.iter().fold(0, |bits, group| bits | group);
+        !used & 0x3F
+    }
+}
+
+impl FromIterator<u8> for Groups {
+    fn from_iter<I: IntoIterator<Item = u8>>(iter: I) -> Self {
+        let mut groups = Groups::default();
+        for group in iter {
+            groups.insert(group);
+        }
+        groups
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_groups() {
+        let mut groups: Groups = [3, 5, 0, 64].into_iter().collect();
+        assert!(groups.contains(3) && groups.contains(5));
+        assert!(!groups.contains(0) && !groups.contains(4));
+        assert_eq!(groups.filter_mask(), 0x38);
+        groups.remove(3);
+        assert!(groups.iter().eq([5]));
+        assert_eq!(Groups::default().filter_mask(), 0x3F);
+        // every group that passes the filter, members and others
+        let mask = groups.filter_mask();
+        let passing = (0..=MAX_GROUP).filter(|g| g & mask == 0).count();
+        assert_eq!(passing, 4);
+    }
+}
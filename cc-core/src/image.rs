@@ -0,0 +1,396 @@
+use crate::decode_error::{expect_len, DecodeError};
+use crate::ota::UpdateErrorCode;
+use ed25519_dalek::SIGNATURE_LENGTH;
+pub use ed25519_dalek::{Signature, SigningKey, VerifyingKey, PUBLIC_KEY_LENGTH};
+use sha2::{Digest, Sha512};
+
+/// Signed images end with this marker followed by the signature.
+pub const MAGIC: [u8; 4] = *b"CCS1";
+
+/// Length of the trailer appended by the signing tool.
+pub const TRAILER_LEN: usize = MAGIC.len() + SIGNATURE_LENGTH;
+
+/// Ed25519ph context, keeps the key from signing anything but images.
+const CONTEXT: &[u8] = b"cancomponents-ota";
+
+/// Marks the `ImageHeader` build.rs appends to the application image.
+pub const HEADER_MAGIC: [u8; 4] = *b"CCH1";
+
+/// Length of the stamped header: magic, header bytes, one reserved byte.
+pub const HEADER_LEN: usize = HEADER_MAGIC.len() + 8;
+
+/// Version of the update protocol. Nodes only accept images speaking the
+/// same protocol, so they can still be updated and confirmed afterwards.
+pub const PROTOCOL_VERSION: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version {
+    pub major: u8,
+    pub minor: u8,
+    pub patch: u8,
+}
+
+impl core::fmt::Display for Version {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// What an image is built for. Sent as `FlashHeader` before `FlashStart`,
+/// the node refuses the update if it does not fit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageHeader {
+    pub protocol: u8,
+    /// `DeviceType` the image runs on, 0 for any
+    pub device_type: u8,
+    pub hwrev_min: u8,
+    pub hwrev_max: u8,
+    pub version: Version,
+}
+
+impl ImageHeader {
+    pub fn parse(data: &[u8]) -> Result<Self, DecodeError> {
+        expect_len(data, 7)?;
+        if data[2] > data[3] {
+            return Err(DecodeError::InvalidValue(data[2]));
+        }
+        Ok(Self {
+            protocol: data[0],
+            device_type: data[1],
+            hwrev_min: data[2],
+            hwrev_max: data[3],
+            version: Version {
+                major: data[4],
+                minor: data[5],
+                patch: data[6],
+            },
+        })
+    }
+
+    pub fn to_bytes(&self) -> [u8; 7] {
+        [
+            self.protocol,
+            self.device_type,
+            self.hwrev_min,
+            self.hwrev_max,
+            self.version.major,
+            self.version.minor,
+            self.version.patch,
+        ]
+    }
+
+    /// The header as build.rs appends it to the image.
+    pub fn stamp(&self) -> [u8; HEADER_LEN] {
+        let mut out = [0; HEADER_LEN];
+        out[..HEADER_MAGIC.len()].copy_from_slice(&HEADER_MAGIC);
+        out[HEADER_MAGIC.len()..HEADER_MAGIC.len() + 7].copy_from_slice(&self.to_bytes());
+        out
+    }
+
+    pub fn parse_stamp(stamp: &[u8]) -> Result<Self, ImageError> {
+        match stamp.split_at_checked(HEADER_MAGIC.len()) {
+            Some((magic, rest)) if magic == HEADER_MAGIC && rest.len() == 8 => {
+                Self::parse(&rest[..7]).map_err(|_| ImageError::NoHeader)
+            }
+            _ => Err(ImageError::NoHeader),
+        }
+    }
+
+    /// Header of a stamped image, signed or not.
+    pub fn find(image: &[u8]) -> Result<Self, ImageError> {
+        let unsigned = split(image).map(|(unsigned, _)| unsigned).unwrap_or(image);
+        let start = unsigned
+            .len()
+            .checked_sub(HEADER_LEN)
+            .ok_or(ImageError::NoHeader)?;
+        Self::parse_stamp(&unsigned[start..])
+    }
+
+    /// Can a node of `device_type` and `hwrev` that runs `running` take
+    /// this image?
+    pub fn check(
+        &self,
+        device_type: u8,
+        hwrev: u8,
+        running: Version,
+        allow_downgrade: bool,
+    ) -> Result<(), UpdateErrorCode> {
+        if self.protocol != PROTOCOL_VERSION {
+            return Err(UpdateErrorCode::Protocol);
+        }
+        if self.device_type != 0 && self.device_type != device_type {
+            return Err(UpdateErrorCode::WrongDevice);
+        }
+        if !(self.hwrev_min..=self.hwrev_max).contains(&hwrev) {
+            return Err(UpdateErrorCode::WrongHardware);
+        }
+        if self.version < running && !allow_downgrade {
+            return Err(UpdateErrorCode::Downgrade);
+        }
+        Ok(())
+    }
+}
+
+/// Why an image is not accepted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageError {
+    /// not all bytes written yet
+    Incomplete,
+    /// trailer missing
+    Unsigned,
+    /// signature does not match the image or the key
+    BadSignature,
+    /// no `ImageHeader` in front of the trailer
+    NoHeader,
+}
+
+impl ImageError {
+    /// Stable numeric code, used as detail in error reports.
+    pub fn code(&self) -> u8 {
+        match self {
+            ImageError::Incomplete => 1,
+            ImageError::Unsigned => 2,
+            ImageError::BadSignature => 3,
+            ImageError::NoHeader => 4,
+        }
+    }
+}
+
+impl core::fmt::Display for ImageError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ImageError::Incomplete => write!(f, "image incomplete"),
+            ImageError::Unsigned => write!(f, "image is not signed"),
+            ImageError::BadSignature => write!(f, "image signature does not verify"),
+            ImageError::NoHeader => write!(f, "image has no header"),
+        }
+    }
+}
+
+impl core::error::Error for ImageError {}
+
+/// Split a signed image into the application image and its signature.
+pub fn split(signed: &[u8]) -> Result<(&[u8], Signature), ImageError> {
+    let len = signed
+        .len()
+        .checked_sub(TRAILER_LEN)
+        .ok_or(ImageError::Unsigned)?;
+    let (image, trailer) = signed.split_at(len);
+    parse_trailer(trailer).map(|signature| (image, signature))
+}
+
+fn parse_trailer(trailer: &[u8]) -> Result<Signature, ImageError> {
+    let (magic, signature) = trailer.split_at(MAGIC.len());
+    if magic != MAGIC {
+        return Err(ImageError::Unsigned);
+    }
+    let signature = <[u8; SIGNATURE_LENGTH]>::try_from(signature).unwrap();
+    Ok(Signature::from_bytes(&signature))
+}
+
+/// Signature to append (after `MAGIC`) to `image`.
+pub fn sign(image: &[u8], key: &SigningKey) -> Signature {
+    key.sign_prehashed(Sha512::new().chain_update(image), Some(CONTEXT))
+        .expect("context is short enough")
+}
+
+/// Checks a signed image while it streams in, without keeping it in memory.
+#[derive(Clone)]
+pub struct Verifier {
+    hasher: Sha512,
+    image_len: u32,
+    tail_start: u32,
+    offset: u32,
+    /// header and trailer
+    tail: [u8; HEADER_LEN + TRAILER_LEN],
+}
+
+impl core::fmt::Debug for Verifier {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Verifier")
+            .field("image_len", &self.image_len)
+            .field("offset", &self.offset)
+            .finish()
+    }
+}
+
+impl Verifier {
+    /// `size` is the length of the signed image, trailer included.
+    pub fn new(size: u32) -> Self {
+        Self {
+            hasher: Sha512::new(),
+            image_len: size.saturating_sub(TRAILER_LEN as u32),
+            tail_start: size.saturating_sub((HEADER_LEN + TRAILER_LEN) as u32),
+            offset: 0,
+            tail: [0; HEADER_LEN + TRAILER_LEN],
+        }
+    }
+
+    /// Feed the next bytes of the signed image.
+    pub fn update(&mut self, data: &[u8]) {
+        let in_image = (self.image_len.saturating_sub(self.offset) as usize).min(data.len());
+        self.hasher.update(&data[..in_image]);
+        let skip = (self.tail_start.saturating_sub(self.offset) as usize).min(data.len());
+        self.offset += skip as u32;
+        for &byte in &data[skip..] {
+            let pos = (self.offset - self.tail_start) as usize;
+            if let Some(slot) = self.tail.get_mut(pos) {
+                *slot = byte;
+            }
+            self.offset += 1;
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        self.offset >= self.image_len + TRAILER_LEN as u32
+    }
+
+    /// Captured bytes of header and trailer.
+    fn tail(&self) -> &[u8] {
+        let len = (self.offset - self.tail_start) as usize;
+        &self.tail[..len.min(self.tail.len())]
+    }
+
+    /// Header stamped into the image, once all of it is written.
+    pub fn header(&self) -> Result<ImageHeader, ImageError> {
+        if !self.is_complete() {
+            return Err(ImageError::Incomplete);
+        }
+        let tail = self.tail();
+        ImageHeader::parse_stamp(&tail[..tail.len() - TRAILER_LEN])
+    }
+
+    pub fn verify(&self, key: &VerifyingKey) -> Result<(), ImageError> {
+        if !self.is_complete() {
+            return Err(ImageError::Incomplete);
+        }
+        let tail = self.tail();
+        let signature = parse_trailer(&tail[tail.len() - TRAILER_LEN..])?;
+        key.verify_prehashed(self.hasher.clone(), Some(CONTEXT), &signature)
+            .map_err(|_| ImageError::BadSignature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signed(image: &[u8], key: &SigningKey) -> heapless::Vec<u8, 256> {
+        let mut out = heapless::Vec::new();
+        out.extend_from_slice(image).unwrap();
+        out.extend_from_slice(&MAGIC).unwrap();
+        out.extend_from_slice(&sign(image, key).to_bytes()).unwrap();
+        out
+    }
+
+    #[test]
+    fn test_sign_verify() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let image = [0x5A; 100];
+        let signed = signed(&image, &key);
+
+        let (body, signature) = split(&signed).unwrap();
+        assert_eq!(body, &image);
+        assert_eq!(signature, sign(&image, &key));
+
+        // in odd pieces that straddle the trailer
+        let mut verifier = Verifier::new(signed.len() as u32);
+        for piece in signed.chunks(7) {
+            assert_eq!(
+                verifier.verify(&key.verifying_key()),
+                Err(ImageError::Incomplete)
+            );
+            verifier.update(piece);
+        }
+        assert_eq!(verifier.verify(&key.verifying_key()), Ok(()));
+
+        let other = SigningKey::from_bytes(&[8; 32]);
+        assert_eq!(
+            verifier.verify(&other.verifying_key()),
+            Err(ImageError::BadSignature)
+        );
+
+        let mut tampered = signed.clone();
+        tampered[3] ^= 1;
+        let mut verifier = Verifier::new(tampered.len() as u32);
+        verifier.update(&tampered);
+        assert_eq!(
+            verifier.verify(&key.verifying_key()),
+            Err(ImageError::BadSignature)
+        );
+
+        let mut verifier = Verifier::new(image.len() as u32);
+        verifier.update(&image);
+        assert_eq!(
+            verifier.verify(&key.verifying_key()),
+            Err(ImageError::Unsigned)
+        );
+        assert_eq!(split(&image).unwrap_err(), ImageError::Unsigned);
+    }
+
+    #[test]
+    fn test_header() {
+        let header = ImageHeader {
+            protocol: PROTOCOL_VERSION,
+            device_type: 5,
+            hwrev_min: 1,
+            hwrev_max: 2,
+            version: Version {
+                major: 1,
+                minor: 4,
+                patch: 0,
+            },
+        };
+        assert_eq!(ImageHeader::parse(&header.to_bytes()), Ok(header));
+        assert!(ImageHeader::parse(&[1, 5, 3, 2, 0, 0, 0]).is_err());
+
+        let mut image: heapless::Vec<u8, 256> = heapless::Vec::new();
+        image.extend_from_slice(&[0xE9; 50]).unwrap();
+        assert_eq!(ImageHeader::find(&image), Err(ImageError::NoHeader));
+        image.extend_from_slice(&header.stamp()).unwrap();
+        assert_eq!(ImageHeader::find(&image), Ok(header));
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let signed = signed(&image, &key);
+        assert_eq!(ImageHeader::find(&signed), Ok(header));
+
+        let mut verifier = Verifier::new(signed.len() as u32);
+        for piece in signed.chunks(5) {
+            verifier.update(piece);
+        }
+        assert_eq!(verifier.header(), Ok(header));
+        assert_eq!(verifier.verify(&key.verifying_key()), Ok(()));
+
+        let running = Version {
+            major: 1,
+            minor: 3,
+            patch: 9,
+        };
+        assert_eq!(header.check(5, 2, running, false), Ok(()));
+        assert_eq!(
+            header.check(4, 2, running, false),
+            Err(UpdateErrorCode::WrongDevice)
+        );
+        assert_eq!(
+            header.check(5, 3, running, false),
+            Err(UpdateErrorCode::WrongHardware)
+        );
+        let newer = Version {
+            minor: 5,
+            ..running
+        };
+        assert_eq!(
+            header.check(5, 1, newer, false),
+            Err(UpdateErrorCode::Downgrade)
+        );
+        assert_eq!(header.check(5, 1, newer, true), Ok(()));
+        let any = ImageHeader {
+            device_type: 0,
+            protocol: PROTOCOL_VERSION + 1,
+            ..header
+        };
+        assert_eq!(
+            any.check(7, 1, running, false),
+            Err(UpdateErrorCode::Protocol)
+        );
+    }
+}
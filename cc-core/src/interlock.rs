@@ -0,0 +1,245 @@
+//! Protects rollershutter motors: a reversal stops the motor and waits a
+//! dead time before it runs the other way, and the two outputs of a shutter
+//! never drive both directions at once.
+
+use crate::decode_error::{expect_len, DecodeError};
+use crate::relais_message::{RelaisMode, RelaisState};
+use embassy_time::{Duration, Instant};
+
+/// Pause between the directions unless configured.
+pub const DEFAULT_DEAD_TIME: Duration = Duration::from_millis(500);
+
+/// `DeadTime` payload: milliseconds as u16.
+pub fn parse_dead_time(data: &[u8]) -> Result<Duration, DecodeError> {
+    expect_len(data, 2)?;
+    Ok(Duration::from_millis(
+        u16::from_le_bytes([data[0], data[1]]) as u64,
+    ))
+}
+
+pub fn dead_time_to_bytes(dead_time: Duration) -> [u8; 2] {
+    (dead_time.as_millis().min(u16::MAX as u64) as u16).to_le_bytes()
+}
+
+/// Outputs of the two relais of a shutter for the motor `state`: up and
+/// down in `SoftwareRollershutter`, power and direction in
+/// `HardwareRollershutter`.
+pub fn outputs(mode: RelaisMode, state: &RelaisState) -> [bool; 2] {
+    match (mode, state) {
+        (_, RelaisState::Up) => [true, false],
+        (RelaisMode::HardwareRollershutter, RelaisState::Down) => [true, true],
+        (RelaisMode::SoftwareRollershutter, RelaisState::Down) => [false, true],
+        _ => [false, false],
+    }
+}
+
+/// The motor of one shutter. Every request goes through `request`, the
+/// outputs follow `output`.
+#[derive(Debug, Clone)]
+pub struct Interlock {
+    dead_time: Duration,
+    output: RelaisState,
+    /// direction the motor ran last, up or not, and when it stopped
+    stopped: Option<(bool, Instant)>,
+    /// direction waiting for the dead time to pass
+    pending: Option<(RelaisState, Instant)>,
+}
+
+impl Default for Interlock {
+    fn default() -> Self {
+        Self::new(DEFAULT_DEAD_TIME)
+    }
+}
+
+impl Interlock {
+    pub const fn new(dead_time: Duration) -> Self {
+        Self {
+            dead_time,
+            output: RelaisState::Off,
+            stopped: None,
+            pending: None,
+        }
+    }
+
+    pub fn dead_time(&self) -> Duration {
+        self.dead_time
+    }
+
+    /// Applies from the next stop, a waiting direction keeps its time.
+    pub fn set_dead_time(&mut self, dead_time: Duration) {
+        self.dead_time = dead_time;
+    }
+
+    /// `Up`, `Down` or `Off`, what the outputs drive.
+    pub fn output(&self) -> RelaisState {
+        self.output.clone()
+    }
+
+    /// Output for a requested motor state. A reversal stops the motor and
+    /// starts the new direction with `poll` once the dead time passed.
+    pub fn request(&mut self, state: &RelaisState, now: Instant) -> RelaisState {
+        self.pending = None;
+        let up = match state {
+            RelaisState::Up => true,
+            RelaisState::Down => false,
+            _ => {
+                self.stop(now);
+                return RelaisState::Off;
+            }
+        };
+        if self.output == *state {
+            return self.output();
+        }
+        // running the other way
+        self.stop(now);
+        match self.stopped {
+            Some((last, at)) if last != up && now < at + self.dead_time => {
+                self.pending = Some((state.clone(), at + self.dead_time));
+            }
+            _ => self.output = state.clone(),
+        }
+        self.output()
+    }
+
+    /// When `poll` starts a waiting direction.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.pending.as_ref().map(|(_, at)| *at)
+    }
+
+    /// The direction started at `now`, if one was waiting for it.
+    pub fn poll(&mut self, now: Instant) -> Option<RelaisState> {
+        let (state, _) = self.pending.take_if(|(_, at)| *at <= now)?;
+        self.output = state.clone();
+        Some(state)
+    }
+
+    fn stop(&mut self, now: Instant) {
+        match self.output {
+            RelaisState::Up => self.stopped = Some((true, now)),
+            RelaisState::Down => self.stopped = Some((false, now)),
+            _ => {}
+        }
+        self.output = RelaisState::Off;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(ms: u64) -> Instant {
+        Instant::from_millis(ms)
+    }
+
+    #[test]
+    fn test_reversal() {
+        let mut interlock = Interlock::default();
+        assert_eq!(interlock.request(&RelaisState::Up, at(0)), RelaisState::Up);
+        assert_eq!(
+            interlock.request(&RelaisState::Up, at(100)),
+            RelaisState::Up
+        );
+
+        // straight from up to down stops first
+        assert_eq!(
+            interlock.request(&RelaisState::Down, at(1_000)),
+            RelaisState::Off
+        );
+        assert_eq!(interlock.deadline(), Some(at(1_500)));
+        assert_eq!(interlock.poll(at(1_499)), None);
+        assert_eq!(interlock.output(), RelaisState::Off);
+        assert_eq!(interlock.poll(at(1_500)), Some(RelaisState::Down));
+        assert_eq!(interlock.output(), RelaisState::Down);
+        assert_eq!(interlock.deadline(), None);
+
+        // a stop in between does not shorten the pause
+        interlock.request(&RelaisState::Off, at(2_000));
+        assert_eq!(
+            interlock.request(&RelaisState::Up, at(2_200)),
+            RelaisState::Off
+        );
+        assert_eq!(interlock.deadline(), Some(at(2_500)));
+
+        // stop or the old direction drop the waiting one
+        assert_eq!(
+            interlock.request(&RelaisState::Down, at(2_300)),
+            RelaisState::Down
+        );
+        assert_eq!(interlock.deadline(), None);
+        interlock.request(&RelaisState::Up, at(2_400));
+        assert_eq!(
+            interlock.request(&RelaisState::Off, at(2_450)),
+            RelaisState::Off
+        );
+        assert_eq!(interlock.poll(at(3_000)), None);
+        assert_eq!(interlock.output(), RelaisState::Off);
+    }
+
+    #[test]
+    fn test_dead_time() {
+        let mut interlock = Interlock::new(Duration::from_millis(0));
+        interlock.request(&RelaisState::Down, at(0));
+        assert_eq!(interlock.request(&RelaisState::Up, at(10)), RelaisState::Up);
+
+        interlock.set_dead_time(Duration::from_secs(2));
+        interlock.request(&RelaisState::Off, at(100));
+        // same direction again needs no pause
+        assert_eq!(
+            interlock.request(&RelaisState::Up, at(200)),
+            RelaisState::Up
+        );
+        interlock.request(&RelaisState::Off, at(300));
+        assert_eq!(
+            interlock.request(&RelaisState::Down, at(2_300)),
+            RelaisState::Down
+        );
+
+        assert_eq!(
+            parse_dead_time(&dead_time_to_bytes(Duration::from_millis(750))),
+            Ok(Duration::from_millis(750))
+        );
+        assert!(parse_dead_time(&[0]).is_err());
+    }
+
+    #[test]
+    fn test_outputs() {
+        for mode in [
+            RelaisMode::SoftwareRollershutter,
+            RelaisMode::HardwareRollershutter,
+        ] {
+            assert_eq!(outputs(mode, &RelaisState::Off), [false, false]);
+            assert_eq!(outputs(mode, &RelaisState::Up), [true, false]);
+        }
+        assert_eq!(
+            outputs(RelaisMode::SoftwareRollershutter, &RelaisState::Down),
+            [false, true]
+        );
+        assert_eq!(
+            outputs(RelaisMode::HardwareRollershutter, &RelaisState::Down),
+            [true, true]
+        );
+    }
+
+    #[test]
+    fn test_reversal_outputs() {
+        // both outputs stay off for the dead time after the motor stopped,
+        // reversed straight away or stopped first
+        let dead_time = Duration::from_millis(300);
+        for (stop_first, reverse_at) in [(false, 1_000), (true, 1_100), (true, 1_299)] {
+            let mut interlock = Interlock::new(dead_time);
+            interlock.request(&RelaisState::Up, at(0));
+            if stop_first {
+                interlock.request(&RelaisState::Off, at(1_000));
+            }
+            interlock.request(&RelaisState::Down, at(reverse_at));
+            for ms in reverse_at..1_500 {
+                interlock.poll(at(ms));
+                let output = outputs(RelaisMode::SoftwareRollershutter, &interlock.output());
+                match ms < 1_300 {
+                    true => assert_eq!(output, [false, false], "{reverse_at} {ms}"),
+                    false => assert_eq!(output, [false, true], "{reverse_at} {ms}"),
+                }
+            }
+        }
+    }
+}
@@ -1,9 +1,24 @@
 #![no_std]
+pub mod announcement;
+pub mod binding;
 pub mod button_message;
 pub mod can_id;
 pub mod can_message_type;
+pub mod compress;
+pub mod decode_error;
 pub mod device_message;
 pub mod device_type;
+pub mod error_report;
 pub mod extension;
+pub mod group;
+pub mod image;
+pub mod interlock;
+pub mod message;
+pub mod metadata;
+pub mod ota;
+pub mod power_on;
 pub mod relais_message;
-
+pub mod rollershutter;
+pub mod scene;
+pub mod supervisor;
+pub mod transport;
@@ -0,0 +1,771 @@
+use crate::announcement::{Announcement, Channels};
+use crate::binding::{Binding, MAX_BINDINGS};
+use crate::button_message::ButtonMessage;
+use crate::can_id::CanId;
+use crate::can_message_type::CanMessageType;
+use crate::decode_error::{expect_len, DecodeError};
+use crate::device_message::IdTypeMsg;
+use crate::error_report::ErrorReport;
+use crate::extension::Extension;
+use crate::group::Groups;
+use crate::image::ImageHeader;
+use crate::interlock;
+use crate::metadata::Entry;
+use crate::ota::Chunk;
+use crate::power_on::PowerOnPolicy;
+use crate::relais_message::{RelaisMessage, RelaisMode, RelaisState};
+use crate::rollershutter::{self, Calibration, ShutterCommand, ShutterReport};
+use crate::scene::{Scene, MAX_SCENES};
+use crate::supervisor::{self, ModeReport, Policy, Supervision};
+use crate::transport::Segment;
+use embassy_time::Duration;
+use heapless::{String, Vec};
+
+pub const MAX_PAYLOAD: usize = 8;
+
+/// Raw frame payload, used for message types without a defined format yet.
+pub type Payload = Vec<u8, MAX_PAYLOAD>;
+
+/// A decoded CAN frame. One variant per `CanMessageType`, plus `Request`
+/// for remote (RTR) frames which never carry a payload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CanMessage {
+    Request(CanMessageType),
+    /// sent after boot and on request. Firmware before the announcement
+    /// sends a single byte or nothing, that decodes as `None`.
+    Available(Option<Announcement>),
+    DeviceError(ErrorReport),
+    Restart,
+    DeviceUid0(u64),
+    DeviceUid1(u64),
+    /// new address, for the node with this UID if given
+    DeviceIdType {
+        id: u8,
+        device_type: u8,
+        uid: Option<u64>,
+    },
+    /// group memberships, active after a restart
+    DeviceGroup(Groups),
+    ApplicationVersion(Payload),
+    Baudrate(u8),
+    /// uptime in minutes
+    Uptime(u32),
+    CustomString(String<8>),
+    PwmFrequency(Payload),
+    RequestParameter,
+    ApplicationVersionString(String<8>),
+    UpdateSilence(Payload),
+    FlashStart {
+        crc: u32,
+        size: u32,
+    },
+    /// OTA slot to boot from, 0 for ota_0 and 1 for ota_1
+    FlashSelect(u8),
+    /// abort the running update and wipe the pending slot
+    FlashErase,
+    /// a `ota::ReadRange` towards the node, raw flash contents back
+    FlashRead(Payload),
+    FlashWrite(Chunk),
+    /// CRC over the first `written` bytes of the pending slot
+    FlashVerify {
+        written: u32,
+        crc: u32,
+    },
+    /// bytes received of the announced image size
+    FlashProgress {
+        received: u32,
+        size: u32,
+    },
+    /// activate a multicast update and restart
+    FlashComplete,
+    /// seconds a freshly updated image has to hear from the gateway before
+    /// it rolls back
+    UpdateConfirmWindow(u32),
+    /// what the announced image is built for, sent before `FlashStart`.
+    /// The last byte holds flags: bit 0 allows a downgrade, bit 1 announces
+    /// compressed blocks.
+    FlashHeader {
+        header: ImageHeader,
+        allow_downgrade: bool,
+        compressed: bool,
+    },
+    /// blocks still missing of a multicast update, one bit per block from
+    /// `block` on. The host asks with `missing` zero.
+    FlashMissing {
+        block: u16,
+        missing: u32,
+    },
+    /// the following `FlashWrite` chunks of a multicast update belong to
+    /// this block
+    FlashBlock(u16),
+    /// UID (MAC) of a node without address, sent from TYPE/255 until the
+    /// gateway assigns an address with `DeviceIdType`
+    AddressClaim(u64),
+    /// channel counts, sent after `Available`
+    Channels(Channels),
+    /// sent on a change of mode, remote request: the current one
+    NodeMode(ModeReport),
+    ButtonEvent(ButtonMessage),
+    TemperatureSensor(Payload),
+    /// part of a payload longer than one frame, see `transport`
+    Segment(Segment),
+    /// one field that fits a frame, see `metadata::Entry`
+    Metadata(Payload),
+    HwRev(u8),
+    ExtensionMode(Extension),
+    /// what the node does without the gateway
+    AutonomousPolicy(Policy),
+    Supervision(Supervision),
+    PowerOn(PowerOnPolicy),
+    ShutterTravel(Calibration),
+    DeadTime(Duration),
+    LampGroup(Payload),
+    PirSensor(Payload),
+    HumiditySensor(Payload),
+    Relais(RelaisMessage),
+    RelaisState(RelaisState),
+    Rollershutter(ShutterCommand),
+    RollershutterState(ShutterReport),
+    RelaisMode(RelaisMode),
+    /// replace a stored scene, also the node's answer to `SceneStore`
+    SceneDefine(Scene),
+    SceneRecall(u8),
+    /// store the current states as a scene
+    SceneStore {
+        scene: u8,
+        delay: Duration,
+    },
+    /// replace a binding, remote request: the node sends all of them
+    BindingDefine(Binding),
+    /// remove the binding in a slot
+    BindingClear(u8),
+    AmbientLightSensor(Payload),
+    AmbientLightSensorWhite(Payload),
+    Nightlight(Payload),
+    PressureSensor(Payload),
+    Co2Equivalent(Payload),
+    VocBreath(Payload),
+    AirQuality(Payload),
+    LogDownload(Payload),
+    Ping,
+    /// suspend gateway supervision for the duration, zero resumes
+    PingDisable(Duration),
+    Echo(Payload),
+}
+
+impl CanMessage {
+    pub fn decode(id: CanId, data: &[u8], rtr: bool) -> Result<Self, DecodeError> {
+        use CanMessageType as T;
+
+        if rtr {
+            return match id.msg_type {
+                T::InvalidMessage => Err(DecodeError::UnknownMessageType),
+                msg_type => Ok(CanMessage::Request(msg_type)),
+            };
+        }
+
+        let msg = match id.msg_type {
+            T::Available => CanMessage::Available(match data.len() {
+                8 => Some(Announcement::parse(data)?),
+                _ => {
+                    raw(data)?;
+                    None
+                }
+            }),
+            T::DeviceError => CanMessage::DeviceError(ErrorReport::try_from(data)?),
+            T::Restart => match single(data)? {
+                1 => CanMessage::Restart,
+                v => return Err(DecodeError::InvalidValue(v)),
+            },
+            T::DeviceUid0 => CanMessage::DeviceUid0(u64::from_le_bytes(exact(data)?)),
+            T::DeviceUid1 => CanMessage::DeviceUid1(u64::from_le_bytes(exact(data)?)),
+            T::AddressClaim => CanMessage::AddressClaim(u64::from_le_bytes(exact(data)?)),
+            T::Channels => CanMessage::Channels(Channels::parse(data)?),
+            T::NodeMode => CanMessage::NodeMode(ModeReport::parse(data)?),
+            T::DeviceIdType => {
+                let (id, device_type, uid) = IdTypeMsg::parse(data)?;
+                CanMessage::DeviceIdType {
+                    id,
+                    device_type,
+                    uid,
+                }
+            }
+            T::DeviceGroup => CanMessage::DeviceGroup(Groups(u64::from_le_bytes(exact(data)?))),
+            T::ApplicationVersion => CanMessage::ApplicationVersion(raw(data)?),
+            T::Baudrate => CanMessage::Baudrate(single(data)?),
+            T::Uptime => CanMessage::Uptime(u32::from_le_bytes(exact(data)?)),
+            T::CustomString => CanMessage::CustomString(string(data)?),
+            T::PwmFrequency => CanMessage::PwmFrequency(raw(data)?),
+            T::RequestParameter => CanMessage::RequestParameter,
+            T::ApplicationVersionString => CanMessage::ApplicationVersionString(string(data)?),
+            T::UpdateSilence => CanMessage::UpdateSilence(raw(data)?),
+            T::FlashStart => {
+                let (crc, size) = be_pair(data)?;
+                CanMessage::FlashStart { crc, size }
+            }
+            T::FlashSelect => CanMessage::FlashSelect(single(data)?),
+            T::FlashErase => {
+                expect_len(data, 0)?;
+                CanMessage::FlashErase
+            }
+            T::FlashRead => CanMessage::FlashRead(raw(data)?),
+            T::FlashWrite => CanMessage::FlashWrite(Chunk::parse(data)?),
+            T::FlashVerify => {
+                let (written, crc) = be_pair(data)?;
+                CanMessage::FlashVerify { written, crc }
+            }
+            T::FlashProgress => {
+                let (received, size) = be_pair(data)?;
+                CanMessage::FlashProgress { received, size }
+            }
+            T::FlashComplete => {
+                expect_len(data, 0)?;
+                CanMessage::FlashComplete
+            }
+            T::UpdateConfirmWindow => {
+                CanMessage::UpdateConfirmWindow(u32::from_le_bytes(exact(data)?))
+            }
+            T::FlashHeader => {
+                let buf: [u8; 8] = exact(data)?;
+                CanMessage::FlashHeader {
+                    header: ImageHeader::parse(&buf[..7])?,
+                    allow_downgrade: match buf[7] {
+                        v if v & !0b11 != 0 => return Err(DecodeError::InvalidValue(v)),
+                        v => v & 0b01 != 0,
+                    },
+                    compressed: buf[7] & 0b10 != 0,
+                }
+            }
+            T::FlashMissing => {
+                let buf: [u8; 6] = exact(data)?;
+                CanMessage::FlashMissing {
+                    block: u16::from_be_bytes([buf[0], buf[1]]),
+                    missing: u32::from_be_bytes([buf[2], buf[3], buf[4], buf[5]]),
+                }
+            }
+            T::FlashBlock => CanMessage::FlashBlock(u16::from_be_bytes(exact(data)?)),
+            T::ButtonEvent => CanMessage::ButtonEvent(ButtonMessage::from_bytes(data)?),
+            T::TemperatureSensor => CanMessage::TemperatureSensor(raw(data)?),
+            T::Segment => CanMessage::Segment(Segment::parse(data)?),
+            T::Metadata => {
+                Entry::parse(data)?;
+                CanMessage::Metadata(raw(data)?)
+            }
+            T::HwRev => CanMessage::HwRev(single(data)?),
+            T::ExtensionMode => CanMessage::ExtensionMode(Extension::from(single(data)?)),
+            T::AutonomousPolicy => CanMessage::AutonomousPolicy(Policy::from_byte(single(data)?)),
+            T::Supervision => CanMessage::Supervision(Supervision::parse(data)?),
+            T::PowerOn => CanMessage::PowerOn(PowerOnPolicy::parse(data)?),
+            T::ShutterTravel => CanMessage::ShutterTravel(Calibration::parse(data)?),
+            T::DeadTime => CanMessage::DeadTime(interlock::parse_dead_time(data)?),
+            T::LampGroup => CanMessage::LampGroup(raw(data)?),
+            T::PirSensor => CanMessage::PirSensor(raw(data)?),
+            T::HumiditySensor => CanMessage::HumiditySensor(raw(data)?),
+            T::Relais => CanMessage::Relais(RelaisMessage::from_bytes(data)?),
+            T::RelaisState => CanMessage::RelaisState(RelaisState::try_from(single(data)?)?),
+            T::Rollershutter => CanMessage::Rollershutter(ShutterCommand::parse(data)?),
+            T::RollershutterState => CanMessage::RollershutterState(ShutterReport::parse(data)?),
+            T::RelaisMode => {
+                let mode = single(data)?;
+                CanMessage::RelaisMode(
+                    RelaisMode::try_from(mode).map_err(|_| DecodeError::UnknownState(mode))?,
+                )
+            }
+            T::SceneDefine => CanMessage::SceneDefine(Scene::parse(data)?),
+            T::SceneRecall => CanMessage::SceneRecall(scene_num(single(data)?)?),
+            T::SceneStore => {
+                let [scene, delay] = exact(data)?;
+                CanMessage::SceneStore {
+                    scene: scene_num(scene)?,
+                    delay: Duration::from_secs(delay as u64),
+                }
+            }
+            T::BindingDefine => CanMessage::BindingDefine(Binding::parse(data)?),
+            T::BindingClear => {
+                let slot = single(data)?;
+                if slot >= MAX_BINDINGS {
+                    return Err(DecodeError::InvalidValue(slot));
+                }
+                CanMessage::BindingClear(slot)
+            }
+            T::AmbientLightSensor => CanMessage::AmbientLightSensor(raw(data)?),
+            T::AmbientLightSensorWhite => CanMessage::AmbientLightSensorWhite(raw(data)?),
+            T::Nightlight => CanMessage::Nightlight(raw(data)?),
+            T::PressureSensor => CanMessage::PressureSensor(raw(data)?),
+            T::Co2Equivalent => CanMessage::Co2Equivalent(raw(data)?),
+            T::VocBreath => CanMessage::VocBreath(raw(data)?),
+            T::AirQuality => CanMessage::AirQuality(raw(data)?),
+            T::LogDownload => CanMessage::LogDownload(raw(data)?),
+            T::Ping => CanMessage::Ping,
+            T::PingDisable => CanMessage::PingDisable(supervisor::parse_suspend(data)?),
+            T::Echo => CanMessage::Echo(raw(data)?),
+            T::InvalidMessage => return Err(DecodeError::UnknownMessageType),
+        };
+        Ok(msg)
+    }
+
+    pub fn msg_type(&self) -> CanMessageType {
+        use CanMessage as M;
+        use CanMessageType as T;
+
+        match self {
+            M::Request(msg_type) => *msg_type,
+            M::Available(_) => T::Available,
+            M::DeviceError(_) => T::DeviceError,
+            M::Restart => T::Restart,
+            M::DeviceUid0(_) => T::DeviceUid0,
+            M::DeviceUid1(_) => T::DeviceUid1,
+            M::AddressClaim(_) => T::AddressClaim,
+            M::Channels(_) => T::Channels,
+            M::NodeMode(_) => T::NodeMode,
+            M::DeviceIdType { .. } => T::DeviceIdType,
+            M::DeviceGroup(_) => T::DeviceGroup,
+            M::ApplicationVersion(_) => T::ApplicationVersion,
+            M::Baudrate(_) => T::Baudrate,
+            M::Uptime(_) => T::Uptime,
+            M::CustomString(_) => T::CustomString,
+            M::PwmFrequency(_) => T::PwmFrequency,
+            M::RequestParameter => T::RequestParameter,
+            M::ApplicationVersionString(_) => T::ApplicationVersionString,
+            M::UpdateSilence(_) => T::UpdateSilence,
+            M::FlashStart { .. } => T::FlashStart,
+            M::FlashSelect(_) => T::FlashSelect,
+            M::FlashErase => T::FlashErase,
+            M::FlashRead(_) => T::FlashRead,
+            M::FlashWrite(_) => T::FlashWrite,
+            M::FlashVerify { .. } => T::FlashVerify,
+            M::FlashProgress { .. } => T::FlashProgress,
+            M::FlashComplete => T::FlashComplete,
+            M::UpdateConfirmWindow(_) => T::UpdateConfirmWindow,
+            M::FlashHeader { .. } => T::FlashHeader,
+            M::FlashMissing { .. } => T::FlashMissing,
+            M::FlashBlock(_) => T::FlashBlock,
+            M::ButtonEvent(_) => T::ButtonEvent,
+            M::TemperatureSensor(_) => T::TemperatureSensor,
+            M::Segment(_) => T::Segment,
+            M::Metadata(_) => T::Metadata,
+            M::HwRev(_) => T::HwRev,
+            M::ExtensionMode(_) => T::ExtensionMode,
+            M::AutonomousPolicy(_) => T::AutonomousPolicy,
+            M::Supervision(_) => T::Supervision,
+            M::PowerOn(_) => T::PowerOn,
+            M::ShutterTravel(_) => T::ShutterTravel,
+            M::DeadTime(_) => T::DeadTime,
+            M::LampGroup(_) => T::LampGroup,
+            M::PirSensor(_) => T::PirSensor,
+            M::HumiditySensor(_) => T::HumiditySensor,
+            M::Relais(_) => T::Relais,
+            M::RelaisState(_) => T::RelaisState,
+            M::Rollershutter(_) => T::Rollershutter,
+            M::RollershutterState(_) => T::RollershutterState,
+            M::RelaisMode(_) => T::RelaisMode,
+            M::SceneDefine(_) => T::SceneDefine,
+            M::SceneRecall(_) => T::SceneRecall,
+            M::SceneStore { .. } => T::SceneStore,
+            M::BindingDefine(_) => T::BindingDefine,
+            M::BindingClear(_) => T::BindingClear,
+            M::AmbientLightSensor(_) => T::AmbientLightSensor,
+            M::AmbientLightSensorWhite(_) => T::AmbientLightSensorWhite,
+            M::Nightlight(_) => T::Nightlight,
+            M::PressureSensor(_) => T::PressureSensor,
+            M::Co2Equivalent(_) => T::Co2Equivalent,
+            M::VocBreath(_) => T::VocBreath,
+            M::AirQuality(_) => T::AirQuality,
+            M::LogDownload(_) => T::LogDownload,
+            M::Ping => T::Ping,
+            M::PingDisable(_) => T::PingDisable,
+            M::Echo(_) => T::Echo,
+        }
+    }
+
+    /// True for remote (RTR) frames.
+    pub fn is_request(&self) -> bool {
+        matches!(self, CanMessage::Request(_))
+    }
+
+    /// Payload bytes as they go on the wire. Use together with `msg_type()`
+    /// and `is_request()` to build the frame.
+    pub fn encode(&self) -> Payload {
+        use CanMessage as M;
+
+        let mut out = Payload::new();
+        match self {
+            M::Request(_) | M::RequestParameter | M::Ping | M::FlashErase | M::FlashComplete => {}
+            M::Restart => out.push(1).unwrap(),
+            M::DeviceError(report) => out.extend_from_slice(&report.to_bytes()).unwrap(),
+            M::DeviceGroup(groups) => out.extend_from_slice(&groups.0.to_le_bytes()).unwrap(),
+            M::DeviceUid0(uid) | M::DeviceUid1(uid) | M::AddressClaim(uid) => {
+                out.extend_from_slice(&uid.to_le_bytes()).unwrap()
+            }
+            M::DeviceIdType {
+                id,
+                device_type,
+                uid,
+            } => out
+                .extend_from_slice(&IdTypeMsg::to_bytes(*id, *device_type, *uid))
+                .unwrap(),
+            M::Baudrate(v) | M::HwRev(v) | M::FlashSelect(v) => out.push(*v).unwrap(),
+            M::Uptime(v) | M::UpdateConfirmWindow(v) => {
+                out.extend_from_slice(&v.to_le_bytes()).unwrap()
+            }
+            M::CustomString(s) | M::ApplicationVersionString(s) => {
+                out.extend_from_slice(s.as_bytes()).unwrap();
+                out.resize(MAX_PAYLOAD, 0).unwrap();
+            }
+            M::FlashStart { crc: a, size: b }
+            | M::FlashVerify { written: a, crc: b }
+            | M::FlashProgress {
+                received: a,
+                size: b,
+            } => {
+                out.extend_from_slice(&a.to_be_bytes()).unwrap();
+                out.extend_from_slice(&b.to_be_bytes()).unwrap();
+            }
+            M::FlashWrite(chunk) => out = chunk.to_bytes(),
+            M::FlashMissing { block, missing } => {
+                out.extend_from_slice(&block.to_be_bytes()).unwrap();
+                out.extend_from_slice(&missing.to_be_bytes()).unwrap();
+            }
+            M::FlashBlock(block) => out.extend_from_slice(&block.to_be_bytes()).unwrap(),
+            M::FlashHeader {
+                header,
+                allow_downgrade,
+                compressed,
+            } => {
+                out.extend_from_slice(&header.to_bytes()).unwrap();
+                out.push(*allow_downgrade as u8 | (*compressed as u8) << 1)
+                    .unwrap();
+            }
+            M::Available(Some(announcement)) => {
+                out.extend_from_slice(&announcement.to_bytes()).unwrap()
+            }
+            M::Available(None) => out.push(1).unwrap(),
+            M::Channels(channels) => out.extend_from_slice(&channels.to_bytes()).unwrap(),
+            M::ButtonEvent(msg) => out.extend_from_slice(&msg.to_bytes()).unwrap(),
+            M::ExtensionMode(extension) => out.push((*extension).into()).unwrap(),
+            M::AutonomousPolicy(policy) => out.push(policy.to_byte()).unwrap(),
+            M::Segment(segment) => out = segment.to_bytes(),
+            M::PowerOn(policy) => out.extend_from_slice(&policy.to_bytes()).unwrap(),
+            M::DeadTime(dead_time) => out
+                .extend_from_slice(&interlock::dead_time_to_bytes(*dead_time))
+                .unwrap(),
+            M::ShutterTravel(calibration) => {
+                out.extend_from_slice(&calibration.to_bytes()).unwrap()
+            }
+            M::Supervision(supervision) => out.extend_from_slice(&supervision.to_bytes()).unwrap(),
+            M::PingDisable(duration) => out
+                .extend_from_slice(&supervisor::suspend_to_bytes(*duration))
+                .unwrap(),
+            M::NodeMode(report) => out.extend_from_slice(&report.to_bytes()).unwrap(),
+            M::Relais(msg) | M::Rollershutter(ShutterCommand::Move(msg)) => {
+                out.extend_from_slice(&msg.to_bytes()).unwrap()
+            }
+            M::Rollershutter(ShutterCommand::Position { num, percent }) => out
+                .extend_from_slice(&[*num as u8, rollershutter::POSITION, *percent])
+                .unwrap(),
+            M::RollershutterState(report) => out.extend_from_slice(&report.to_bytes()).unwrap(),
+            M::RelaisState(state) => out.push(state.clone() as u8).unwrap(),
+            M::RelaisMode(mode) => out.push((*mode).into()).unwrap(),
+            M::SceneDefine(scene) => out.extend_from_slice(&scene.to_bytes()).unwrap(),
+            M::SceneRecall(scene) | M::BindingClear(scene) => out.push(*scene).unwrap(),
+            M::BindingDefine(binding) => out.extend_from_slice(&binding.to_bytes()).unwrap(),
+            M::SceneStore { scene, delay } => {
+                let delay = delay.as_secs().min(u8::MAX as u64) as u8;
+                out.extend_from_slice(&[*scene, delay]).unwrap()
+            }
+            M::ApplicationVersion(data)
+            | M::PwmFrequency(data)
+            | M::UpdateSilence(data)
+            | M::FlashRead(data)
+            | M::TemperatureSensor(data)
+            | M::LampGroup(data)
+            | M::PirSensor(data)
+            | M::HumiditySensor(data)
+            | M::AmbientLightSensor(data)
+            | M::AmbientLightSensorWhite(data)
+            | M::Nightlight(data)
+            | M::PressureSensor(data)
+            | M::Co2Equivalent(data)
+            | M::VocBreath(data)
+            | M::AirQuality(data)
+            | M::LogDownload(data)
+            | M::Metadata(data)
+            | M::Echo(data) => out = data.clone(),
+        }
+        out
+    }
+}
+
+fn raw(data: &[u8]) -> Result<Payload, DecodeError> {
+    Payload::from_slice(data).map_err(|_| DecodeError::Trailing)
+}
+
+fn single(data: &[u8]) -> Result<u8, DecodeError> {
+    expect_len(data, 1)?;
+    Ok(data[0])
+}
+
+fn scene_num(num: u8) -> Result<u8, DecodeError> {
+    match num < MAX_SCENES {
+        true => Ok(num),
+        false => Err(DecodeError::InvalidValue(num)),
+    }
+}
+
+fn exact<const N: usize>(data: &[u8]) -> Result<[u8; N], DecodeError> {
+    expect_len(data, N)?;
+    let mut buf = [0u8; N];
+    buf.copy_from_slice(data);
+    Ok(buf)
+}
+
+/// Two big endian u32, as used by the flash messages.
+fn be_pair(data: &[u8]) -> Result<(u32, u32), DecodeError> {
+    let buf: [u8; 8] = exact(data)?;
+    Ok((
+        u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]),
+        u32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]]),
+    ))
+}
+
+/// Strings are sent zero padded to the full frame length.
+fn string(data: &[u8]) -> Result<String<8>, DecodeError> {
+    let len = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+    let s = core::str::from_utf8(&data[..len]).map_err(|_| DecodeError::InvalidUtf8)?;
+    let mut string = String::new();
+    string.push_str(s).map_err(|_| DecodeError::Trailing)?;
+    Ok(string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::announcement::Capabilities;
+    use crate::binding::Action;
+    use crate::button_message::ButtonState;
+    use crate::image::Version;
+    use crate::rollershutter::TravelTimes;
+    use crate::supervisor::{Mode, ShutterSafeState};
+
+    fn roundtrip(msg: CanMessage) {
+        let id = CanId::new(5, 12, msg.msg_type());
+        let data = msg.encode();
+        let back = CanMessage::decode(id, &data, msg.is_request());
+        assert_eq!(back, Ok(msg));
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        roundtrip(CanMessage::Request(CanMessageType::Uptime));
+        roundtrip(CanMessage::Restart);
+        roundtrip(CanMessage::DeviceUid0(0x0000_a1b2_c3d4_e5f6));
+        roundtrip(CanMessage::DeviceIdType {
+            id: 12,
+            device_type: 5,
+            uid: None,
+        });
+        roundtrip(CanMessage::DeviceIdType {
+            id: 12,
+            device_type: 5,
+            uid: Some(0x0000_a1b2_c3d4_e5f6),
+        });
+        roundtrip(CanMessage::AddressClaim(0x0000_a1b2_c3d4_e5f6));
+        roundtrip(CanMessage::Available(None));
+        roundtrip(CanMessage::Available(Some(Announcement {
+            protocol: 1,
+            device_type: 1,
+            hwrev: 0,
+            version: Version {
+                major: 1,
+                minor: 2,
+                patch: 3,
+            },
+            extension: Extension::Off,
+            capabilities: Capabilities::RELAIS | Capabilities::ADDRESS_CLAIM,
+        })));
+        roundtrip(CanMessage::Channels(Channels {
+            relais: 16,
+            rollershutters: 0,
+            buttons: 0,
+        }));
+        roundtrip(CanMessage::DeviceGroup([1, 7, 63].into_iter().collect()));
+        let mut scene = Scene::new(2);
+        scene.states[4] = Some(RelaisState::On);
+        roundtrip(CanMessage::SceneDefine(scene));
+        roundtrip(CanMessage::SceneRecall(2));
+        roundtrip(CanMessage::SceneStore {
+            scene: 15,
+            delay: Duration::from_secs(10),
+        });
+        roundtrip(CanMessage::BindingDefine(Binding {
+            slot: 0,
+            source_type: 4,
+            source_id: 3,
+            button: 1,
+            event: ButtonState::Single,
+            group: 2,
+            relay: 5,
+            action: Action::Toggle,
+        }));
+        roundtrip(CanMessage::BindingClear(31));
+        roundtrip(CanMessage::Uptime(1440));
+        roundtrip(CanMessage::CustomString(
+            String::try_from("kitchen").unwrap(),
+        ));
+        roundtrip(CanMessage::FlashStart {
+            crc: 0xdead_beef,
+            size: 123_456,
+        });
+        roundtrip(CanMessage::FlashProgress {
+            received: 4096,
+            size: 123_456,
+        });
+        roundtrip(CanMessage::FlashVerify {
+            written: 4096,
+            crc: 0xdead_beef,
+        });
+        roundtrip(CanMessage::FlashSelect(1));
+        roundtrip(CanMessage::FlashWrite(crate::ota::Chunk {
+            block: 3,
+            index: 682,
+            data: Payload::from_slice(&[1, 2, 3, 4]).unwrap(),
+        }));
+        roundtrip(CanMessage::FlashErase);
+        roundtrip(CanMessage::FlashComplete);
+        roundtrip(CanMessage::FlashMissing {
+            block: 32,
+            missing: 0x8000_0001,
+        });
+        roundtrip(CanMessage::FlashBlock(255));
+        roundtrip(CanMessage::UpdateConfirmWindow(300));
+        roundtrip(CanMessage::FlashHeader {
+            header: ImageHeader::parse(&[1, 5, 1, 2, 0, 3, 1]).unwrap(),
+            allow_downgrade: true,
+            compressed: false,
+        });
+        roundtrip(CanMessage::FlashHeader {
+            header: ImageHeader::parse(&[1, 5, 1, 2, 0, 3, 1]).unwrap(),
+            allow_downgrade: false,
+            compressed: true,
+        });
+        roundtrip(CanMessage::ButtonEvent(ButtonMessage::new(
+            2,
+            ButtonState::Multi,
+            7,
+        )));
+        roundtrip(CanMessage::Relais(RelaisMessage {
+            num: 3,
+            state: RelaisState::On,
+            duration: Duration::from_millis(30_000),
+            bank: 1,
+        }));
+        roundtrip(CanMessage::RelaisState(RelaisState::Down));
+        roundtrip(CanMessage::RelaisMode(RelaisMode::SoftwareRollershutter));
+        roundtrip(CanMessage::ExtensionMode(Extension::Button));
+        roundtrip(CanMessage::AutonomousPolicy(Policy {
+            shutters: ShutterSafeState::Down,
+            relais_off: true,
+            bindings_autonomous_only: false,
+        }));
+        roundtrip(CanMessage::NodeMode(ModeReport {
+            mode: Mode::Autonomous,
+            duration: 120,
+        }));
+        roundtrip(CanMessage::Supervision(Supervision {
+            interval: Duration::from_secs(15),
+            max_misses: 2,
+        }));
+        roundtrip(CanMessage::PingDisable(Duration::from_secs(600)));
+        roundtrip(CanMessage::PowerOn(PowerOnPolicy::from_u32(0xDEAD_BEEF)));
+        roundtrip(CanMessage::DeadTime(Duration::from_millis(800)));
+        roundtrip(CanMessage::ShutterTravel(Calibration {
+            num: 7,
+            travel: TravelTimes::from_u32(250 | 230 << 16),
+        }));
+        roundtrip(CanMessage::Rollershutter(ShutterCommand::Position {
+            num: 1,
+            percent: 75,
+        }));
+        roundtrip(CanMessage::Rollershutter(ShutterCommand::Move(
+            RelaisMessage {
+                num: 1,
+                state: RelaisState::Up,
+                duration: Duration::from_millis(5_000),
+                bank: 0,
+            },
+        )));
+        roundtrip(CanMessage::RollershutterState(ShutterReport {
+            num: 1,
+            state: RelaisState::Off,
+            position: Some(75),
+        }));
+        roundtrip(CanMessage::Metadata(
+            Payload::from_slice(b"\x01Attic").unwrap(),
+        ));
+        roundtrip(CanMessage::Segment(Segment::Consecutive {
+            seq: 3,
+            data: Payload::from_slice(&[1, 2, 3]).unwrap(),
+        }));
+        roundtrip(CanMessage::Ping);
+    }
+
+    #[test]
+    fn test_decode_rejects_invalid() {
+        let id = CanId::new(5, 12, CanMessageType::InvalidMessage);
+        assert!(CanMessage::decode(id, &[], false).is_err());
+        assert!(CanMessage::decode(id, &[], true).is_err());
+
+        let id = CanId::new(5, 12, CanMessageType::HwRev);
+        assert_eq!(
+            CanMessage::decode(id, &[1, 2], false),
+            Err(DecodeError::Trailing)
+        );
+
+        let id = CanId::new(5, 12, CanMessageType::ButtonEvent);
+        assert_eq!(
+            CanMessage::decode(id, &[1, 1], false),
+            Err(DecodeError::TooShort {
+                expected: 4,
+                got: 2
+            })
+        );
+
+        let id = CanId::new(5, 12, CanMessageType::Relais);
+        assert_eq!(
+            CanMessage::decode(id, &[1, 9], false),
+            Err(DecodeError::UnknownState(9))
+        );
+        assert_eq!(
+            CanMessage::decode(id, &[1, 3, 0, 0], false),
+            Err(DecodeError::TooShort {
+                expected: 6,
+                got: 4
+            })
+        );
+    }
+
+    /// Every message type with every length and a few byte patterns must
+    /// decode without panicking, and re-encoding a decoded message must be
+    /// stable.
+    #[test]
+    fn test_decode_never_panics() {
+        let patterns: [fn(usize) -> u8; 4] = [|_| 0x00, |_| 0xff, |i| i as u8, |i| 0x80 | i as u8];
+        for raw_type in 0..=255u8 {
+            let id = CanId::new(5, 12, CanMessageType::from(raw_type));
+            for len in 0..=12 {
+                for pattern in patterns {
+                    let mut data = [0u8; 12];
+                    for (i, b) in data.iter_mut().enumerate() {
+                        *b = pattern(i);
+                    }
+                    for rtr in [false, true] {
+                        if let Ok(msg) = CanMessage::decode(id, &data[..len], rtr) {
+                            let again = CanMessage::decode(id, &msg.encode(), msg.is_request());
+                            assert_eq!(again, Ok(msg));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,109 @@
+//! Descriptive metadata of a node beyond the 8 bytes of `CustomString`.
+//! Every field travels on its own as `Metadata`: the field, then the value
+//! as UTF-8. Values longer than a frame go over the multi-frame transport.
+
+use crate::decode_error::DecodeError;
+use heapless::{String, Vec};
+
+/// Longest value of any field.
+pub const MAX_VALUE_LEN: usize = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Field {
+    Name = 0,
+    Room = 1,
+    Floor = 2,
+    Notes = 3,
+}
+
+impl Field {
+    pub const ALL: [Field; 4] = [Field::Name, Field::Room, Field::Floor, Field::Notes];
+
+    /// Bytes of UTF-8 the field holds.
+    pub fn max_len(&self) -> usize {
+        match self {
+            Field::Name => 64,
+            Field::Room => 32,
+            Field::Floor => 16,
+            Field::Notes => MAX_VALUE_LEN,
+        }
+    }
+}
+
+impl TryFrom<u8> for Field {
+    type Error = DecodeError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Field::ALL
+            .get(value as usize)
+            .copied()
+            .ok_or(DecodeError::UnknownState(value))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub field: Field,
+    /// empty clears the field
+    pub value: String<MAX_VALUE_LEN>,
+}
+
+impl Entry {
+    /// `value` within the limit of `field`.
+    pub fn new(field: Field, value: &str) -> Result<Self, DecodeError> {
+        if value.len() > field.max_len() {
+            return Err(DecodeError::TooLong {
+                max: field.max_len(),
+                got: value.len(),
+            });
+        }
+        Ok(Self {
+            field,
+            value: String::try_from(value).unwrap(),
+        })
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self, DecodeError> {
+        let Some((&field, value)) = data.split_first() else {
+            return Err(DecodeError::TooShort {
+                expected: 1,
+                got: 0,
+            });
+        };
+        let field = Field::try_from(field)?;
+        let value = core::str::from_utf8(value).map_err(|_| DecodeError::InvalidUtf8)?;
+        Self::new(field, value)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8, { MAX_VALUE_LEN + 1 }> {
+        let mut out = Vec::new();
+        out.push(self.field as u8).unwrap();
+        out.extend_from_slice(self.value.as_bytes()).unwrap();
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entry() {
+        let entry = Entry::new(Field::Name, "Kitchen north wall").unwrap();
+        assert_eq!(Entry::parse(&entry.to_bytes()), Ok(entry));
+        let cleared = Entry::new(Field::Notes, "").unwrap();
+        assert_eq!(Entry::parse(&cleared.to_bytes()), Ok(cleared));
+
+        assert_eq!(
+            Entry::new(Field::Floor, "a floor with a long name"),
+            Err(DecodeError::TooLong { max: 16, got: 24 })
+        );
+        assert_eq!(
+            Entry::parse(&[Field::Room as u8, 0xC3, 0x28]),
+            Err(DecodeError::InvalidUtf8)
+        );
+        assert_eq!(Entry::parse(&[4, b'x']), Err(DecodeError::UnknownState(4)));
+        assert!(Entry::parse(&[]).is_err());
+    }
+}
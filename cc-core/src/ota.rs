@@ -0,0 +1,788 @@
+use crate::compress::Decoder;
+use crate::decode_error::{expect_len, DecodeError};
+use crate::image::{ImageError, ImageHeader, Verifier, VerifyingKey};
+use crate::message::{CanMessage, Payload};
+use heapless::Vec;
+use num_enum::{FromPrimitive, IntoPrimitive};
+
+/// The node collects `FlashWrite` chunks into blocks of this size before
+/// writing them to flash.
+pub const BLOCK_SIZE: usize = 4096;
+
+/// `local_code` of `ErrorReport`s sent by the update component.
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, FromPrimitive, IntoPrimitive)]
+pub enum UpdateErrorCode {
+    #[num_enum(default)]
+    Unknown = 0,
+    InvalidData = 1,
+    Begin = 2,
+    Init = 3,
+    Write = 4,
+    NotStarted = 5,
+    VerifyFailed = 6,
+    Confirm = 7,
+    Signature = 8,
+    /// image is for another `DeviceType`
+    WrongDevice = 9,
+    /// image does not support the `HardwareRevision`
+    WrongHardware = 10,
+    /// image speaks another update protocol
+    Protocol = 11,
+    /// image is older than the running one
+    Downgrade = 12,
+    /// `FlashStart` without `FlashHeader`
+    NoHeader = 13,
+    /// written image carries another header than announced
+    HeaderMismatch = 14,
+}
+
+/// Upper limit for one `FlashRead` request, longer reads are cut.
+pub const MAX_READ_LEN: u32 = BLOCK_SIZE as u32;
+
+/// Number of OTA slots (ota_0 and ota_1).
+pub const SLOT_COUNT: u8 = 2;
+
+/// Size of one OTA slot, see partitions.csv.
+pub const SLOT_SIZE: u32 = 0x10_0000;
+
+/// Blocks in one slot.
+pub const MAX_BLOCKS: usize = SLOT_SIZE as usize / BLOCK_SIZE;
+
+/// Range of the pending slot to read back with `FlashRead`, two big endian
+/// u32. The node answers with one `FlashRead` frame per 8 bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadRange {
+    pub offset: u32,
+    pub len: u32,
+}
+
+impl ReadRange {
+    pub fn parse(data: &[u8]) -> Result<Self, DecodeError> {
+        expect_len(data, 8)?;
+        Ok(Self {
+            offset: u32::from_be_bytes([data[0], data[1], data[2], data[3]]),
+            len: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+        })
+    }
+
+    pub fn to_bytes(&self) -> [u8; 8] {
+        let mut out = [0u8; 8];
+        out[..4].copy_from_slice(&self.offset.to_be_bytes());
+        out[4..].copy_from_slice(&self.len.to_be_bytes());
+        out
+    }
+
+    /// Clamp the range to `MAX_READ_LEN` and to a slot of `slot_size` bytes.
+    pub fn clamp(&self, slot_size: u32) -> Self {
+        let offset = self.offset.min(slot_size);
+        Self {
+            offset,
+            len: self.len.min(MAX_READ_LEN).min(slot_size - offset),
+        }
+    }
+}
+
+/// Image bytes per `FlashWrite` frame, the first two bytes are the header.
+pub const CHUNK_SIZE: usize = 6;
+
+/// `FlashWrite` frames needed for a full block.
+pub const CHUNKS_PER_BLOCK: usize = BLOCK_SIZE.div_ceil(CHUNK_SIZE);
+
+const BLOCK_TAG_MASK: u32 = 0x3F;
+
+/// One `FlashWrite` frame. The header carries the lower 6 bits of the
+/// block number and the index of the chunk inside the block (10 bits), so a
+/// lost, repeated or stale frame can be told apart from the next one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    pub block: u8,
+    pub index: u16,
+    pub data: Payload,
+}
+
+impl Chunk {
+    /// Chunk `index` of block `block` of `image`.
+    pub fn of(image: &[u8], block: u32, index: usize) -> Self {
+        let start = block as usize * BLOCK_SIZE;
+        let end = (start + BLOCK_SIZE).min(image.len());
+        Self::of_block(&image[start..end], block, index)
+    }
+
+    /// Chunk `index` of block `block` as sent, `data` being the whole block,
+    /// compressed or not.
+    pub fn of_block(data: &[u8], block: u32, index: usize) -> Self {
+        let start = index * CHUNK_SIZE;
+        let end = (start + CHUNK_SIZE).min(data.len());
+        Self {
+            block: (block & BLOCK_TAG_MASK) as u8,
+            index: index as u16,
+            data: Payload::from_slice(&data[start..end]).unwrap(),
+        }
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self, DecodeError> {
+        if data.len() < 3 {
+            return Err(DecodeError::TooShort {
+                expected: 3,
+                got: data.len(),
+            });
+        }
+        if data.len() > 2 + CHUNK_SIZE {
+            return Err(DecodeError::Trailing);
+        }
+        let header = u16::from_be_bytes([data[0], data[1]]);
+        Ok(Self {
+            block: (header >> 10) as u8,
+            index: header & 0x3FF,
+            data: Payload::from_slice(&data[2..]).unwrap(),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Payload {
+        let header = ((self.block as u16 & 0x3F) << 10) | (self.index & 0x3FF);
+        let mut out = Payload::from_slice(&header.to_be_bytes()).unwrap();
+        out.extend_from_slice(&self.data).unwrap();
+        out
+    }
+}
+
+/// What `Session::accept` did with a chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Accept {
+    /// duplicate, stale or after a gap, dropped
+    Ignored,
+    /// appended, the block is not full yet
+    Buffered,
+    /// the block is full, write `Session::block()` and `commit()` it
+    Block,
+    /// a chunk is missing, the block starts over. Answer with `progress()`.
+    Gap,
+}
+
+/// Append the data of `chunk` to `buffer`, decompressing it if there is a
+/// `decoder`. `None` if the chunk does not fit, `Some(true)` once the block
+/// holds `block_len` bytes.
+fn collect(
+    buffer: &mut Vec<u8, BLOCK_SIZE>,
+    decoder: Option<&mut Decoder>,
+    chunk: &Chunk,
+    block_len: usize,
+) -> Option<bool> {
+    match decoder {
+        Some(decoder) => {
+            decoder.feed(&chunk.data, buffer, block_len).ok()?;
+            // only the last chunk of a block may be short
+            let full = buffer.len() == block_len;
+            (full || chunk.data.len() == CHUNK_SIZE).then_some(full)
+        }
+        None => {
+            let expected = (block_len - buffer.len()).min(CHUNK_SIZE);
+            if chunk.data.len() != expected {
+                return None;
+            }
+            buffer.extend_from_slice(&chunk.data).unwrap();
+            Some(buffer.len() == block_len)
+        }
+    }
+}
+
+/// State of a running update, independent of the flash backend. Chunks are
+/// collected in order into one block; the node acknowledges every written
+/// block with a `FlashProgress` and sends the same message when it detects a
+/// gap, the host then resends from `received`. With compression every block
+/// is compressed on its own and decompressed while it is collected, sizes,
+/// offsets and CRCs always refer to the decompressed image.
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub size: u32,
+    pub crc: u32,
+    written: u32,
+    written_crc: u32,
+    verifier: Verifier,
+    buffer: Vec<u8, BLOCK_SIZE>,
+    decoder: Option<Decoder>,
+    next_index: u16,
+    gap: bool,
+}
+
+impl Session {
+    pub fn new(size: u32, crc: u32) -> Self {
+        Self {
+            size,
+            crc,
+            written: 0,
+            written_crc: 0,
+            verifier: Verifier::new(size),
+            buffer: Vec::new(),
+            decoder: None,
+            next_index: 0,
+            gap: false,
+        }
+    }
+
+    /// Whether the following blocks arrive compressed, as announced by
+    /// `FlashHeader`. Drops a partly collected block.
+    pub fn set_compressed(&mut self, compressed: bool) {
+        self.decoder = compressed.then(Decoder::new);
+        self.discard_block();
+    }
+
+    fn block_len(&self) -> usize {
+        (self.size - self.written).min(BLOCK_SIZE as u32) as usize
+    }
+
+    pub fn accept(&mut self, chunk: &Chunk) -> Accept {
+        let block = self.written / BLOCK_SIZE as u32;
+        if self.is_complete() || chunk.block as u32 != block & BLOCK_TAG_MASK {
+            return Accept::Ignored;
+        }
+        if chunk.index == 0 {
+            // first chunk of the block, also when the host starts over
+            self.discard_block();
+        }
+        if chunk.index < self.next_index || (chunk.index > self.next_index && self.gap) {
+            return Accept::Ignored;
+        }
+        let block_len = self.block_len();
+        let collected = match chunk.index > self.next_index {
+            true => None,
+            false => collect(&mut self.buffer, self.decoder.as_mut(), chunk, block_len),
+        };
+        match collected {
+            None => {
+                self.discard_block();
+                self.gap = true;
+                Accept::Gap
+            }
+            Some(true) => {
+                self.next_index += 1;
+                Accept::Block
+            }
+            Some(false) => {
+                self.next_index += 1;
+                Accept::Buffered
+            }
+        }
+    }
+
+    /// The block collected so far.
+    pub fn block(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    /// Drop a partly collected block, used when the host resumes an update.
+    /// `progress()` then reports the committed offset.
+    pub fn discard_block(&mut self) {
+        self.buffer.clear();
+        if let Some(decoder) = &mut self.decoder {
+            *decoder = Decoder::new();
+        }
+        self.next_index = 0;
+        self.gap = false;
+    }
+
+    /// Same image as announced by a new `FlashStart`.
+    pub fn is_resumable(&self, size: u32, crc: u32) -> bool {
+        self.size == size && self.crc == crc && !self.is_complete()
+    }
+
+    /// Mark the collected block as written to flash.
+    pub fn commit(&mut self) {
+        self.written += self.buffer.len() as u32;
+        self.written_crc = crc32_update(self.written_crc, &self.buffer);
+        self.verifier.update(&self.buffer);
+        self.discard_block();
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.written >= self.size
+    }
+
+    /// Check the signature over everything written. Only a complete image
+    /// with a valid signature may be activated.
+    pub fn verify_signature(&self, key: &VerifyingKey) -> Result<(), ImageError> {
+        self.verifier.verify(key)
+    }
+
+    /// Header stamped into the written image.
+    pub fn header(&self) -> Result<ImageHeader, ImageError> {
+        self.verifier.header()
+    }
+
+    /// Acknowledgement and answer to a `FlashProgress` request.
+    pub fn progress(&self) -> CanMessage {
+        CanMessage::FlashProgress {
+            received: self.written + self.buffer.len() as u32,
+            size: self.size,
+        }
+    }
+
+    /// Answer to a `FlashVerify` request.
+    pub fn verify(&self) -> CanMessage {
+        CanMessage::FlashVerify {
+            written: self.written,
+            crc: self.written_crc,
+        }
+    }
+}
+
+/// State of an update sent to all nodes of a type at once. Nodes do not
+/// acknowledge anything here: every block is announced with `FlashBlock`, a
+/// block with a lost chunk is dropped, and the host later asks each node
+/// with `FlashMissing` which blocks to send again. Blocks are written in any
+/// order, so the image is checked by reading it back from flash once all
+/// are there.
+#[derive(Debug, Clone)]
+pub struct Multicast {
+    pub size: u32,
+    pub crc: u32,
+    present: [u32; MAX_BLOCKS / 32],
+    current: Option<u16>,
+    buffer: Vec<u8, BLOCK_SIZE>,
+    decoder: Option<Decoder>,
+    next_index: u16,
+    verified: bool,
+}
+
+impl Multicast {
+    /// `None` if the image does not fit into a slot.
+    pub fn new(size: u32, crc: u32) -> Option<Self> {
+        if size == 0 || size > SLOT_SIZE {
+            return None;
+        }
+        Some(Self {
+            size,
+            crc,
+            present: [0; MAX_BLOCKS / 32],
+            current: None,
+            buffer: Vec::new(),
+            decoder: None,
+            next_index: 0,
+            verified: false,
+        })
+    }
+
+    /// Whether blocks arrive compressed, as announced by `FlashHeader`.
+    pub fn set_compressed(&mut self, compressed: bool) {
+        self.decoder = compressed.then(Decoder::new);
+        self.current = None;
+        self.buffer.clear();
+    }
+
+    fn block_count(&self) -> u16 {
+        self.size.div_ceil(BLOCK_SIZE as u32) as u16
+    }
+
+    fn block_len(&self, block: u16) -> usize {
+        (self.size - block as u32 * BLOCK_SIZE as u32).min(BLOCK_SIZE as u32) as usize
+    }
+
+    fn is_present(&self, block: u16) -> bool {
+        self.present[block as usize / 32] & (1 << (block % 32)) != 0
+    }
+
+    /// Same image as announced by a new `FlashStart`, keep the blocks.
+    pub fn is_resumable(&self, size: u32, crc: u32) -> bool {
+        self.size == size && self.crc == crc && !self.verified
+    }
+
+    /// `FlashBlock`: the following chunks belong to `block`.
+    pub fn seek(&mut self, block: u16) {
+        self.buffer.clear();
+        if let Some(decoder) = &mut self.decoder {
+            *decoder = Decoder::new();
+        }
+        self.next_index = 0;
+        self.current = (block < self.block_count() && !self.is_present(block)).then_some(block);
+    }
+
+    pub fn accept(&mut self, chunk: &Chunk) -> Accept {
+        let Some(block) = self.current else {
+            return Accept::Ignored;
+        };
+        if chunk.block as u32 != block as u32 & BLOCK_TAG_MASK || chunk.index < self.next_index {
+            return Accept::Ignored;
+        }
+        let block_len = self.block_len(block);
+        let collected = match chunk.index > self.next_index {
+            true => None,
+            false => collect(&mut self.buffer, self.decoder.as_mut(), chunk, block_len),
+        };
+        match collected {
+            // stays missing until the host repairs it
+            None => {
+                self.current = None;
+                Accept::Gap
+            }
+            Some(true) => {
+                self.next_index += 1;
+                Accept::Block
+            }
+            Some(false) => {
+                self.next_index += 1;
+                Accept::Buffered
+            }
+        }
+    }
+
+    /// Offset in the slot and data of the collected block.
+    pub fn block(&self) -> (u32, &[u8]) {
+        let block = self.current.unwrap_or_default();
+        (block as u32 * BLOCK_SIZE as u32, &self.buffer)
+    }
+
+    /// Mark the collected block as written to flash.
+    pub fn commit(&mut self) {
+        if let Some(block) = self.current.take() {
+            self.present[block as usize / 32] |= 1 << (block % 32);
+        }
+        self.buffer.clear();
+    }
+
+    pub fn is_complete(&self) -> bool {
+        (0..self.block_count()).all(|block| self.is_present(block))
+    }
+
+    /// Answer to `FlashMissing`: one bit per block starting at `from`, set
+    /// for blocks still missing.
+    pub fn missing(&self, from: u16) -> CanMessage {
+        let missing = (0..32)
+            .filter(|i| {
+                let block = from as u32 + i;
+                block < self.block_count() as u32 && !self.is_present(block as u16)
+            })
+            .fold(0, |bits, i| bits | 1 << i);
+        CanMessage::FlashMissing {
+            block: from,
+            missing,
+        }
+    }
+
+    /// Read the complete image back from flash and check its CRC. The
+    /// returned `Verifier` has seen all of it, check signature and header
+    /// with it, then `set_verified`.
+    pub fn read_back<E>(
+        &self,
+        mut read: impl FnMut(u32, &mut [u8]) -> Result<(), E>,
+    ) -> Result<Verifier, UpdateErrorCode> {
+        let mut verifier = Verifier::new(self.size);
+        let mut crc = 0;
+        let mut buf = [0u8; 256];
+        let mut offset = 0;
+        while offset < self.size {
+            let len = ((self.size - offset) as usize).min(buf.len());
+            read(offset, &mut buf[..len]).map_err(|_| UpdateErrorCode::VerifyFailed)?;
+            crc = crc32_update(crc, &buf[..len]);
+            verifier.update(&buf[..len]);
+            offset += len as u32;
+        }
+        if crc != self.crc {
+            return Err(UpdateErrorCode::VerifyFailed);
+        }
+        Ok(verifier)
+    }
+
+    /// The image may be activated with `FlashComplete`.
+    pub fn set_verified(&mut self) {
+        self.verified = true;
+    }
+
+    pub fn is_verified(&self) -> bool {
+        self.verified
+    }
+
+    /// Answer to a `FlashProgress` request. `received` only reaches `size`
+    /// once the image is verified.
+    pub fn progress(&self) -> CanMessage {
+        let blocks = (0..self.block_count())
+            .filter(|&block| self.is_present(block))
+            .count() as u32;
+        let received = match self.verified {
+            true => self.size,
+            false => (blocks * BLOCK_SIZE as u32).min(self.size - 1),
+        };
+        CanMessage::FlashProgress {
+            received,
+            size: self.size,
+        }
+    }
+}
+
+/// CRC-32 (ISO-HDLC, as used by zlib and `esp_hal_ota`) of `data`.
+pub fn crc32(data: &[u8]) -> u32 {
+    crc32_update(0, data)
+}
+
+/// Continue a CRC-32 over another chunk. `crc32_update(crc32(a), b)` equals
+/// the CRC of `a` followed by `b`.
+pub fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32_update(crc32(b"1234"), b"56789"), crc32(b"123456789"));
+        assert_eq!(crc32(&[]), 0);
+    }
+
+    fn feed(session: &mut Session, image: &[u8], block: u32, range: core::ops::Range<usize>) {
+        for index in range {
+            session.accept(&Chunk::of(image, block, index));
+        }
+    }
+
+    #[test]
+    fn test_chunk() {
+        let image = [0xAA; BLOCK_SIZE + 10];
+        let chunk = Chunk::of(&image, 1, 1);
+        assert_eq!((chunk.block, chunk.index, chunk.data.len()), (1, 1, 4));
+        assert_eq!(Chunk::parse(&chunk.to_bytes()), Ok(chunk));
+        let last = Chunk::of(&image, 0, CHUNKS_PER_BLOCK - 1);
+        assert_eq!(last.data.len(), BLOCK_SIZE % CHUNK_SIZE);
+        assert_eq!(
+            Chunk::parse(&[0x04, 0x02, 0xAA]).map(|c| (c.block, c.index)),
+            Ok((1, 2))
+        );
+        assert!(Chunk::parse(&[0, 0]).is_err());
+        assert!(Chunk::parse(&[0; 9]).is_err());
+    }
+
+    #[test]
+    fn test_session() {
+        let image: [u8; BLOCK_SIZE + 10] = core::array::from_fn(|i| i as u8);
+        let mut session = Session::new(image.len() as u32, crc32(&image));
+
+        // lost chunk: nack once, ignore the rest of the block
+        feed(&mut session, &image, 0, 0..3);
+        assert_eq!(session.accept(&Chunk::of(&image, 0, 4)), Accept::Gap);
+        assert_eq!(session.accept(&Chunk::of(&image, 0, 5)), Accept::Ignored);
+        assert_eq!(
+            session.progress(),
+            CanMessage::FlashProgress {
+                received: 0,
+                size: image.len() as u32
+            }
+        );
+
+        // resend with a duplicate in between
+        feed(&mut session, &image, 0, 0..2);
+        assert_eq!(session.accept(&Chunk::of(&image, 0, 1)), Accept::Ignored);
+        feed(&mut session, &image, 0, 2..CHUNKS_PER_BLOCK - 1);
+        let last = Chunk::of(&image, 0, CHUNKS_PER_BLOCK - 1);
+        assert_eq!(session.accept(&last), Accept::Block);
+        assert_eq!(session.block(), &image[..BLOCK_SIZE]);
+        session.commit();
+        // stale chunk of the previous block after the ack got lost
+        assert_eq!(session.accept(&last), Accept::Ignored);
+
+        // gateway restarts and resumes after the first block
+        feed(&mut session, &image, 1, 0..1);
+        assert!(session.is_resumable(image.len() as u32, crc32(&image)));
+        assert!(!session.is_resumable(image.len() as u32, 0));
+        session.discard_block();
+        assert_eq!(
+            session.progress(),
+            CanMessage::FlashProgress {
+                received: BLOCK_SIZE as u32,
+                size: image.len() as u32
+            }
+        );
+
+        feed(&mut session, &image, 1, 0..1);
+        assert_eq!(session.accept(&Chunk::of(&image, 1, 1)), Accept::Block);
+        session.commit();
+        assert!(session.is_complete());
+        assert_eq!(
+            session.verify(),
+            CanMessage::FlashVerify {
+                written: image.len() as u32,
+                crc: session.crc
+            }
+        );
+        let key = crate::image::SigningKey::from_bytes(&[7; 32]).verifying_key();
+        assert_eq!(session.verify_signature(&key), Err(ImageError::Unsigned));
+    }
+
+    #[test]
+    fn test_compressed() {
+        let image: [u8; BLOCK_SIZE + 100] = core::array::from_fn(|i| (i / 64) as u8);
+        let mut session = Session::new(image.len() as u32, crc32(&image));
+        session.set_compressed(true);
+        let compressed = |block: usize| {
+            let end = ((block + 1) * BLOCK_SIZE).min(image.len());
+            let mut out: Vec<u8, { crate::compress::MAX_COMPRESSED_BLOCK }> = Vec::new();
+            crate::compress::compress(&image[block * BLOCK_SIZE..end], |b| out.push(b).unwrap());
+            out
+        };
+        let block = compressed(0);
+        let chunks = block.len().div_ceil(CHUNK_SIZE);
+        assert!(chunks < CHUNKS_PER_BLOCK / 4);
+
+        // a lost chunk restarts the decoder with the block
+        for index in 0..2 {
+            session.accept(&Chunk::of_block(&block, 0, index));
+        }
+        assert_eq!(session.accept(&Chunk::of_block(&block, 0, 3)), Accept::Gap);
+        for index in 0..chunks - 1 {
+            let chunk = Chunk::of_block(&block, 0, index);
+            assert_eq!(session.accept(&chunk), Accept::Buffered);
+        }
+        let last = Chunk::of_block(&block, 0, chunks - 1);
+        assert_eq!(session.accept(&last), Accept::Block);
+        assert_eq!(session.block(), &image[..BLOCK_SIZE]);
+        session.commit();
+
+        // the last chunk of a block may be short, no other
+        let block = compressed(1);
+        let mut short = Chunk::of_block(&block, 1, 0);
+        short.data.pop();
+        assert_eq!(session.accept(&short), Accept::Gap);
+        for index in 0..block.len().div_ceil(CHUNK_SIZE) {
+            session.accept(&Chunk::of_block(&block, 1, index));
+        }
+        assert_eq!(session.block(), &image[BLOCK_SIZE..]);
+        session.commit();
+        assert!(session.is_complete());
+        assert_eq!(
+            session.verify(),
+            CanMessage::FlashVerify {
+                written: image.len() as u32,
+                crc: crc32(&image),
+            }
+        );
+
+        let mut multicast = Multicast::new(image.len() as u32, crc32(&image)).unwrap();
+        multicast.set_compressed(true);
+        multicast.seek(1);
+        for index in 0..block.len().div_ceil(CHUNK_SIZE) {
+            multicast.accept(&Chunk::of_block(&block, 1, index));
+        }
+        assert_eq!(multicast.block(), (BLOCK_SIZE as u32, &image[BLOCK_SIZE..]));
+    }
+
+    #[test]
+    fn test_multicast() {
+        let image: [u8; BLOCK_SIZE * 2 + 10] = core::array::from_fn(|i| (i * 7) as u8);
+        let mut multicast = Multicast::new(image.len() as u32, crc32(&image)).unwrap();
+        assert!(Multicast::new(SLOT_SIZE + 1, 0).is_none());
+
+        // block 0 loses a chunk, block 2 arrives, block 1 is never announced
+        multicast.seek(0);
+        feed_multicast(&mut multicast, &image, 0, 0..10);
+        assert_eq!(multicast.accept(&Chunk::of(&image, 0, 11)), Accept::Gap);
+        assert_eq!(multicast.accept(&Chunk::of(&image, 0, 12)), Accept::Ignored);
+        multicast.seek(2);
+        assert_eq!(multicast.accept(&Chunk::of(&image, 2, 0)), Accept::Buffered);
+        assert_eq!(multicast.accept(&Chunk::of(&image, 2, 1)), Accept::Block);
+        assert_eq!(
+            multicast.block(),
+            (2 * BLOCK_SIZE as u32, &image[BLOCK_SIZE * 2..])
+        );
+        multicast.commit();
+        assert_eq!(
+            multicast.missing(0),
+            CanMessage::FlashMissing {
+                block: 0,
+                missing: 0b011
+            }
+        );
+
+        // repair
+        let mut flash = [0xFF; BLOCK_SIZE * 2 + 10];
+        flash[BLOCK_SIZE * 2..].copy_from_slice(&image[BLOCK_SIZE * 2..]);
+        for block in [1, 0] {
+            multicast.seek(block);
+            feed_multicast(
+                &mut multicast,
+                &image,
+                block as u32,
+                0..CHUNKS_PER_BLOCK - 1,
+            );
+            let last = Chunk::of(&image, block as u32, CHUNKS_PER_BLOCK - 1);
+            assert_eq!(multicast.accept(&last), Accept::Block);
+            let (offset, data) = multicast.block();
+            flash[offset as usize..offset as usize + data.len()].copy_from_slice(data);
+            multicast.commit();
+        }
+        // already there
+        multicast.seek(1);
+        assert_eq!(multicast.accept(&Chunk::of(&image, 1, 0)), Accept::Ignored);
+        assert!(multicast.is_complete());
+        assert_eq!(
+            multicast.missing(0),
+            CanMessage::FlashMissing {
+                block: 0,
+                missing: 0
+            }
+        );
+        assert_eq!(
+            multicast.progress(),
+            CanMessage::FlashProgress {
+                received: image.len() as u32 - 1,
+                size: image.len() as u32
+            }
+        );
+
+        let read = |flash: &[u8], offset: u32, buf: &mut [u8]| -> Result<(), ()> {
+            buf.copy_from_slice(&flash[offset as usize..offset as usize + buf.len()]);
+            Ok(())
+        };
+        assert!(multicast.read_back(|o, b| read(&flash, o, b)).is_ok());
+        multicast.set_verified();
+        assert!(!multicast.is_resumable(image.len() as u32, crc32(&image)));
+        assert_eq!(
+            multicast.progress(),
+            CanMessage::FlashProgress {
+                received: image.len() as u32,
+                size: image.len() as u32
+            }
+        );
+        flash[5] ^= 1;
+        assert_eq!(
+            multicast.read_back(|o, b| read(&flash, o, b)).err(),
+            Some(UpdateErrorCode::VerifyFailed)
+        );
+    }
+
+    fn feed_multicast(
+        multicast: &mut Multicast,
+        image: &[u8],
+        block: u32,
+        range: core::ops::Range<usize>,
+    ) {
+        for index in range {
+            multicast.accept(&Chunk::of(image, block, index));
+        }
+    }
+
+    #[test]
+    fn test_read_range() {
+        let range = ReadRange {
+            offset: 0x1000,
+            len: 64,
+        };
+        assert_eq!(ReadRange::parse(&range.to_bytes()), Ok(range));
+        assert!(ReadRange::parse(&[0; 4]).is_err());
+        let clamped = ReadRange {
+            offset: 0xF_FFF0,
+            len: 0x10_0000,
+        }
+        .clamp(0x10_0000);
+        assert_eq!(clamped.len, 0x10);
+        assert_eq!(ReadRange { offset: 5, len: 1 }.clamp(2).len, 0);
+    }
+}
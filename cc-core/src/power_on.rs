@@ -0,0 +1,137 @@
+//! What relay channels do after a reset, and the channel states a node
+//! keeps in flash to restore them.
+
+use crate::decode_error::{expect_len, DecodeError};
+use crate::relais_message::RelaisState;
+
+/// Channels with a policy.
+pub const POWER_ON_CHANNELS: usize = 16;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(u8)]
+pub enum PowerOn {
+    #[default]
+    Off = 0,
+    On = 1,
+    /// last state the node stored
+    Restore = 2,
+    /// output as the expander still drives it after a warm reset, off
+    /// after a power loss
+    Keep = 3,
+}
+
+impl PowerOn {
+    fn from_bits(bits: u32) -> Self {
+        match bits & 0x3 {
+            0 => PowerOn::Off,
+            1 => PowerOn::On,
+            2 => PowerOn::Restore,
+            _ => PowerOn::Keep,
+        }
+    }
+}
+
+/// One `PowerOn` per channel, two bits each.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PowerOnPolicy(pub [PowerOn; POWER_ON_CHANNELS]);
+
+impl PowerOnPolicy {
+    pub fn from_u32(bits: u32) -> Self {
+        Self(core::array::from_fn(|num| {
+            PowerOn::from_bits(bits >> (num * 2))
+        }))
+    }
+
+    pub fn to_u32(&self) -> u32 {
+        self.0.iter().enumerate().fold(0, |bits, (num, policy)| {
+            bits | (*policy as u32) << (num * 2)
+        })
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self, DecodeError> {
+        expect_len(data, 4)?;
+        Ok(Self::from_u32(u32::from_le_bytes([
+            data[0], data[1], data[2], data[3],
+        ])))
+    }
+
+    pub fn to_bytes(&self) -> [u8; 4] {
+        self.to_u32().to_le_bytes()
+    }
+
+    /// State of channel `num` after a reset. `stored` is the last state in
+    /// flash, `kept` the output of the expander if it kept running.
+    pub fn initial(
+        &self,
+        num: usize,
+        stored: &RelaisState,
+        kept: Option<RelaisState>,
+    ) -> RelaisState {
+        match self.0.get(num).copied().unwrap_or_default() {
+            PowerOn::Off => RelaisState::Off,
+            PowerOn::On => RelaisState::On,
+            PowerOn::Restore => stored.clone(),
+            PowerOn::Keep => kept.unwrap_or(RelaisState::Off),
+        }
+    }
+}
+
+/// Channel states in two bits each, `Unknown` as `Off`.
+pub fn pack_states(states: &[RelaisState]) -> u32 {
+    states
+        .iter()
+        .take(POWER_ON_CHANNELS)
+        .enumerate()
+        .fold(0, |bits, (num, state)| {
+            let state = match state {
+                RelaisState::Unknown => RelaisState::Off,
+                state => state.clone(),
+            };
+            bits | (state as u32 & 0x3) << (num * 2)
+        })
+}
+
+pub fn unpack_states(bits: u32) -> [RelaisState; POWER_ON_CHANNELS] {
+    core::array::from_fn(|num| {
+        RelaisState::try_from((bits >> (num * 2) & 0x3) as u8).unwrap_or(RelaisState::Off)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policy() {
+        let mut policy = PowerOnPolicy::default();
+        policy.0[0] = PowerOn::On;
+        policy.0[3] = PowerOn::Restore;
+        policy.0[15] = PowerOn::Keep;
+        assert_eq!(PowerOnPolicy::parse(&policy.to_bytes()), Ok(policy));
+        assert!(PowerOnPolicy::parse(&[0; 3]).is_err());
+
+        let stored = RelaisState::On;
+        assert_eq!(policy.initial(0, &RelaisState::Off, None), RelaisState::On);
+        assert_eq!(
+            policy.initial(1, &stored, Some(RelaisState::On)),
+            RelaisState::Off
+        );
+        assert_eq!(policy.initial(3, &stored, None), RelaisState::On);
+        assert_eq!(policy.initial(15, &stored, None), RelaisState::Off);
+        assert_eq!(
+            policy.initial(15, &RelaisState::Off, Some(RelaisState::On)),
+            RelaisState::On
+        );
+    }
+
+    #[test]
+    fn test_states() {
+        let mut states: [RelaisState; POWER_ON_CHANNELS] =
+            core::array::from_fn(|_| RelaisState::Off);
+        states[1] = RelaisState::On;
+        states[7] = RelaisState::Down;
+        states[15] = RelaisState::On;
+        assert_eq!(unpack_states(pack_states(&states)), states);
+        assert_eq!(pack_states(&[RelaisState::Unknown]), 0);
+    }
+}
@@ -1,4 +1,15 @@
+use crate::decode_error::DecodeError;
 use embassy_time::Duration;
+use num_enum::{IntoPrimitive, TryFromPrimitive};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, IntoPrimitive, TryFromPrimitive)]
+#[repr(u8)]
+pub enum RelaisMode {
+    Off = 0,
+    Relais = 1,
+    SoftwareRollershutter = 2,
+    HardwareRollershutter = 3,
+}
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 #[repr(u8)]
@@ -11,7 +22,7 @@ pub enum RelaisState {
 }
 
 impl core::convert::TryFrom<u8> for RelaisState {
-    type Error = ();
+    type Error = DecodeError;
 
     fn try_from(value: u8) -> Result<Self, Self::Error> {
         use RelaisState::*;
@@ -20,12 +31,12 @@ impl core::convert::TryFrom<u8> for RelaisState {
             1 => Up,
             2 => Down,
             3 => On,
-            _ => return Err(()),
+            _ => return Err(DecodeError::UnknownState(value)),
         };
         Ok(result)
     }
 }
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct RelaisMessage {
     pub num: usize,
     pub state: RelaisState,
@@ -34,20 +45,33 @@ pub struct RelaisMessage {
 }
 
 impl RelaisMessage {
-    pub async fn from_bytes(data: &[u8]) -> Result<Self, ()> {
-        if data.len() < 2 {
-            return Err(());
-        }
-
-        let num = data[0] as usize;
-        let state: RelaisState = RelaisState::try_from(data[1])?;
-        let duration = {
-            let mut buf = [0u8; 4];
-            buf.copy_from_slice(&data[2..6]);
-            Duration::from_millis(u32::from_le_bytes(buf) as u64)
+    /// Accepts the full 6 byte frame or the short form with only
+    /// `num` and `state` (no duration, bank 0).
+    pub fn from_bytes(data: &[u8]) -> Result<Self, DecodeError> {
+        let (num, state, duration, bank) = match data {
+            [num, state] => (*num, *state, [0u8; 3], 0),
+            [num, state, d0, d1, d2, bank] => (*num, *state, [*d0, *d1, *d2], *bank),
+            [_] | [] => {
+                return Err(DecodeError::TooShort {
+                    expected: 2,
+                    got: data.len(),
+                })
+            }
+            _ if data.len() < 6 => {
+                return Err(DecodeError::TooShort {
+                    expected: 6,
+                    got: data.len(),
+                })
+            }
+            _ => return Err(DecodeError::Trailing),
         };
 
-        let bank = data[5];
+        let num = num as usize;
+        let state: RelaisState = RelaisState::try_from(state)?;
+        let duration =
+            Duration::from_millis(
+                u32::from_le_bytes([duration[0], duration[1], duration[2], 0]) as u64,
+            );
 
         Ok(RelaisMessage {
             num,
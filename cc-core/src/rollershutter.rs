@@ -0,0 +1,439 @@
+//! Position of a rollershutter, estimated from how long its motor ran up or
+//! down. 0 % is open, 100 % closed. Every run that reaches an end stop sets
+//! the position exactly again.
+
+use crate::decode_error::{expect_len, DecodeError};
+use crate::relais_message::{RelaisMessage, RelaisState};
+use embassy_time::{Duration, Instant};
+
+/// Shutters of a relais node, two outputs each.
+pub const MAX_SHUTTERS: usize = 8;
+
+/// State byte of a `Rollershutter` command that goes to a position.
+pub const POSITION: u8 = 4;
+
+/// Position in 1/1000 closed.
+const FULL: u64 = 1000;
+
+/// Runs to an end stop last this much of the travel longer, so the shutter
+/// surely arrives.
+const OVERRUN: u64 = 100;
+
+/// Time for a full run in each direction, zero while not calibrated.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TravelTimes {
+    pub up: Duration,
+    pub down: Duration,
+}
+
+impl TravelTimes {
+    pub fn is_calibrated(&self) -> bool {
+        self.up.as_millis() > 0 && self.down.as_millis() > 0
+    }
+
+    /// Tenths of a second as u16 each, down in the upper half.
+    pub fn from_u32(bits: u32) -> Self {
+        Self {
+            up: Duration::from_millis((bits & 0xFFFF) as u64 * 100),
+            down: Duration::from_millis((bits >> 16) as u64 * 100),
+        }
+    }
+
+    pub fn to_u32(&self) -> u32 {
+        let tenths = |d: Duration| (d.as_millis() / 100).min(u16::MAX as u64) as u32;
+        tenths(self.up) | tenths(self.down) << 16
+    }
+
+    fn full(&self, up: bool) -> Option<Duration> {
+        let full = match up {
+            true => self.up,
+            false => self.down,
+        };
+        (full.as_millis() > 0).then_some(full)
+    }
+}
+
+/// `ShutterTravel` payload: the shutter, then its `TravelTimes`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Calibration {
+    pub num: u8,
+    pub travel: TravelTimes,
+}
+
+impl Calibration {
+    pub fn parse(data: &[u8]) -> Result<Self, DecodeError> {
+        expect_len(data, 5)?;
+        if data[0] as usize >= MAX_SHUTTERS {
+            return Err(DecodeError::InvalidValue(data[0]));
+        }
+        Ok(Self {
+            num: data[0],
+            travel: TravelTimes::from_u32(u32::from_le_bytes([data[1], data[2], data[3], data[4]])),
+        })
+    }
+
+    pub fn to_bytes(&self) -> [u8; 5] {
+        let t = self.travel.to_u32().to_le_bytes();
+        [self.num, t[0], t[1], t[2], t[3]]
+    }
+}
+
+/// `Rollershutter` payload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShutterCommand {
+    /// up, down or stop, as a `RelaisMessage`
+    Move(RelaisMessage),
+    /// the shutter, `POSITION`, then percent closed
+    Position { num: usize, percent: u8 },
+}
+
+impl ShutterCommand {
+    pub fn parse(data: &[u8]) -> Result<Self, DecodeError> {
+        match data {
+            [num, POSITION, percent] if *percent <= 100 => Ok(ShutterCommand::Position {
+                num: *num as usize,
+                percent: *percent,
+            }),
+            [_, POSITION, percent] => Err(DecodeError::InvalidValue(*percent)),
+            _ => Ok(ShutterCommand::Move(RelaisMessage::from_bytes(data)?)),
+        }
+    }
+
+    pub fn num(&self) -> usize {
+        match self {
+            ShutterCommand::Move(msg) => msg.num,
+            ShutterCommand::Position { num, .. } => *num,
+        }
+    }
+}
+
+/// `RollershutterState` payload: the shutter, its motor and the position,
+/// 255 while unknown.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShutterReport {
+    pub num: u8,
+    pub state: RelaisState,
+    pub position: Option<u8>,
+}
+
+impl ShutterReport {
+    pub fn parse(data: &[u8]) -> Result<Self, DecodeError> {
+        expect_len(data, 3)?;
+        let position = match data[2] {
+            0..=100 => Some(data[2]),
+            255 => None,
+            other => return Err(DecodeError::InvalidValue(other)),
+        };
+        Ok(Self {
+            num: data[0],
+            state: RelaisState::try_from(data[1])?,
+            position,
+        })
+    }
+
+    pub fn to_bytes(&self) -> [u8; 3] {
+        [
+            self.num,
+            self.state.clone() as u8,
+            self.position.unwrap_or(255),
+        ]
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Run {
+    up: bool,
+    since: Instant,
+    /// position when the run started
+    from: Option<u64>,
+    stop_at: Option<Instant>,
+    /// percent to go to once the run referenced the shutter
+    then: Option<u8>,
+}
+
+/// Tracks one shutter. The caller switches the motor to whatever `go_to`
+/// and `poll` return and tells `set` about every other switch.
+#[derive(Debug, Clone, Default)]
+pub struct Shutter {
+    travel: TravelTimes,
+    /// `None` until a run reached an end stop
+    position: Option<u64>,
+    run: Option<Run>,
+}
+
+impl Shutter {
+    pub const fn new(travel: TravelTimes) -> Self {
+        Self {
+            travel,
+            position: None,
+            run: None,
+        }
+    }
+
+    pub fn travel(&self) -> TravelTimes {
+        self.travel
+    }
+
+    /// New travel times, the position is kept unless there are none.
+    pub fn calibrate(&mut self, travel: TravelTimes, now: Instant) {
+        let state = self.state();
+        self.set(&RelaisState::Off, now);
+        self.travel = travel;
+        if !travel.is_calibrated() {
+            self.position = None;
+        }
+        self.set(&state, now);
+    }
+
+    /// `Up`, `Down` or `Off`.
+    pub fn state(&self) -> RelaisState {
+        match self.run {
+            Some(Run { up: true, .. }) => RelaisState::Up,
+            Some(Run { up: false, .. }) => RelaisState::Down,
+            None => RelaisState::Off,
+        }
+    }
+
+    /// Percent closed, estimated while the motor runs.
+    pub fn position(&self, now: Instant) -> Option<u8> {
+        let position = match &self.run {
+            Some(run) => self.estimate(run, now),
+            None => self.position,
+        };
+        position.map(|p| ((p + 5) / 10) as u8)
+    }
+
+    pub fn report(&self, num: u8, now: Instant) -> ShutterReport {
+        ShutterReport {
+            num,
+            state: self.state(),
+            position: self.position(now),
+        }
+    }
+
+    /// When `poll` has something to do.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.run.and_then(|run| run.stop_at)
+    }
+
+    /// The motor was switched to `state` by a command or timer. Runs stop
+    /// by themselves at the end stop.
+    pub fn set(&mut self, state: &RelaisState, now: Instant) {
+        if let Some(run) = self.run.take() {
+            self.position = self.estimate(&run, now);
+        }
+        let up = match state {
+            RelaisState::Up => true,
+            RelaisState::Down => false,
+            _ => return,
+        };
+        let to_end = match (self.position, up) {
+            (None, _) => FULL,
+            (Some(position), true) => position,
+            (Some(position), false) => FULL - position,
+        };
+        self.run = Some(Run {
+            up,
+            since: now,
+            from: self.position,
+            stop_at: self.time_for(up, to_end + OVERRUN).map(|time| now + time),
+            then: None,
+        });
+    }
+
+    /// The motor started only at `now`, later than the run was set, e.g.
+    /// after the dead time of a reversal.
+    pub fn started(&mut self, now: Instant) {
+        if let Some(run) = &mut self.run {
+            let delay = now.saturating_duration_since(run.since);
+            run.since += delay;
+            run.stop_at = run.stop_at.map(|at| at + delay);
+        }
+    }
+
+    /// Motor state that takes the shutter to `percent`, `None` without
+    /// travel times. An unknown position is referenced at the top first.
+    pub fn go_to(&mut self, percent: u8, now: Instant) -> Option<RelaisState> {
+        if !self.travel.is_calibrated() {
+            return None;
+        }
+        let target = percent.min(100) as u64 * 10;
+        self.set(&RelaisState::Off, now);
+        let Some(position) = self.position else {
+            self.set(&RelaisState::Up, now);
+            if let Some(run) = &mut self.run {
+                run.then = Some(percent);
+            }
+            return Some(RelaisState::Up);
+        };
+        let (state, up) = match target.cmp(&position) {
+            core::cmp::Ordering::Equal => return Some(RelaisState::Off),
+            core::cmp::Ordering::Less => (RelaisState::Up, true),
+            core::cmp::Ordering::Greater => (RelaisState::Down, false),
+        };
+        self.set(&state, now);
+        // the end positions run into the stop and reference the shutter
+        if target != 0 && target != FULL {
+            let stop_at = self
+                .time_for(up, position.abs_diff(target))
+                .map(|time| now + time);
+            if let Some(run) = &mut self.run {
+                run.stop_at = stop_at;
+            }
+        }
+        Some(state)
+    }
+
+    /// Motor state due at `now`, if any.
+    pub fn poll(&mut self, now: Instant) -> Option<RelaisState> {
+        let run = self.run?;
+        if run.stop_at? > now {
+            return None;
+        }
+        self.set(&RelaisState::Off, now);
+        match run.then {
+            Some(percent) => self.go_to(percent, now),
+            None => Some(RelaisState::Off),
+        }
+    }
+
+    /// Rounded up, so a run of `time_for(n)` moves at least `n`.
+    fn time_for(&self, up: bool, amount: u64) -> Option<Duration> {
+        let full = self.travel.full(up)?.as_millis();
+        Some(Duration::from_millis((full * amount).div_ceil(FULL)))
+    }
+
+    fn estimate(&self, run: &Run, now: Instant) -> Option<u64> {
+        let full = self.travel.full(run.up)?.as_millis();
+        let moved = now.saturating_duration_since(run.since).as_millis() * FULL / full;
+        let to_end = match (run.from, run.up) {
+            (None, _) => FULL,
+            (Some(from), true) => from,
+            (Some(from), false) => FULL - from,
+        };
+        if moved >= to_end + OVERRUN {
+            return Some(if run.up { 0 } else { FULL });
+        }
+        let from = run.from?;
+        Some(match run.up {
+            true => from.saturating_sub(moved),
+            false => (from + moved).min(FULL),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(ms: u64) -> Instant {
+        Instant::from_millis(ms)
+    }
+
+    fn shutter() -> Shutter {
+        Shutter::new(TravelTimes {
+            up: Duration::from_secs(20),
+            down: Duration::from_secs(10),
+        })
+    }
+
+    #[test]
+    fn test_payloads() {
+        let calibration = Calibration {
+            num: 3,
+            travel: TravelTimes {
+                up: Duration::from_millis(21_300),
+                down: Duration::from_secs(19),
+            },
+        };
+        assert_eq!(Calibration::parse(&calibration.to_bytes()), Ok(calibration));
+        assert!(Calibration::parse(&[8, 0, 0, 0, 0]).is_err());
+
+        assert_eq!(
+            ShutterCommand::parse(&[2, POSITION, 40]),
+            Ok(ShutterCommand::Position {
+                num: 2,
+                percent: 40
+            })
+        );
+        assert_eq!(
+            ShutterCommand::parse(&[2, POSITION, 101]),
+            Err(DecodeError::InvalidValue(101))
+        );
+        assert!(matches!(
+            ShutterCommand::parse(&[2, RelaisState::Down as u8]),
+            Ok(ShutterCommand::Move(RelaisMessage { num: 2, .. }))
+        ));
+
+        let report = ShutterReport {
+            num: 1,
+            state: RelaisState::Up,
+            position: None,
+        };
+        assert_eq!(ShutterReport::parse(&report.to_bytes()), Ok(report));
+        assert!(ShutterReport::parse(&[1, 0, 101]).is_err());
+    }
+
+    #[test]
+    fn test_reference() {
+        let mut shutter = shutter();
+        assert_eq!(shutter.position(at(0)), None);
+
+        // unknown position, goes to the top first
+        assert_eq!(shutter.go_to(40, at(0)), Some(RelaisState::Up));
+        assert_eq!(shutter.deadline(), Some(at(22_000)));
+        assert_eq!(shutter.poll(at(21_999)), None);
+        assert_eq!(shutter.poll(at(22_000)), Some(RelaisState::Down));
+        assert_eq!(shutter.position(at(22_000)), Some(0));
+        assert_eq!(shutter.position(at(24_000)), Some(20));
+        assert_eq!(shutter.deadline(), Some(at(26_000)));
+        assert_eq!(shutter.poll(at(26_000)), Some(RelaisState::Off));
+        assert_eq!(shutter.position(at(30_000)), Some(40));
+
+        // a reversal starts late, the target stays the same
+        assert_eq!(shutter.go_to(60, at(27_000)), Some(RelaisState::Down));
+        assert_eq!(shutter.deadline(), Some(at(29_000)));
+        shutter.started(at(27_500));
+        assert_eq!(shutter.position(at(27_500)), Some(40));
+        assert_eq!(shutter.poll(at(29_500)), Some(RelaisState::Off));
+        assert_eq!(shutter.position(at(30_000)), Some(60));
+        shutter.go_to(40, at(30_000));
+        assert_eq!(shutter.poll(at(34_000)), Some(RelaisState::Off));
+
+        // stopped by hand halfway up
+        shutter.set(&RelaisState::Up, at(34_000));
+        shutter.set(&RelaisState::Off, at(38_000));
+        assert_eq!(shutter.position(at(38_000)), Some(20));
+        assert_eq!(shutter.go_to(20, at(39_000)), Some(RelaisState::Off));
+        assert_eq!(shutter.state(), RelaisState::Off);
+    }
+
+    #[test]
+    fn test_end_stops() {
+        let mut shutter = shutter();
+        // down by hand from an unknown position is referenced at the bottom
+        shutter.set(&RelaisState::Down, at(0));
+        assert_eq!(shutter.position(at(5_000)), None);
+        assert_eq!(shutter.poll(at(11_000)), Some(RelaisState::Off));
+        assert_eq!(shutter.position(at(11_000)), Some(100));
+
+        // the ends overrun the estimate
+        assert_eq!(shutter.go_to(0, at(20_000)), Some(RelaisState::Up));
+        assert_eq!(shutter.deadline(), Some(at(42_000)));
+        assert_eq!(shutter.position(at(40_000)), Some(0));
+        assert_eq!(shutter.poll(at(42_000)), Some(RelaisState::Off));
+        assert_eq!(shutter.position(at(42_000)), Some(0));
+
+        // without travel times nothing is tracked
+        let mut shutter = Shutter::default();
+        assert_eq!(shutter.go_to(50, at(0)), None);
+        shutter.set(&RelaisState::Down, at(0));
+        assert_eq!(shutter.deadline(), None);
+        assert_eq!(shutter.position(at(60_000)), None);
+
+        // calibrating a running shutter keeps it running
+        shutter.calibrate(TravelTimes::from_u32(100 | 100 << 16), at(60_000));
+        assert_eq!(shutter.state(), RelaisState::Down);
+        assert_eq!(shutter.deadline(), Some(at(71_000)));
+    }
+}
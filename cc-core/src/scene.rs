@@ -0,0 +1,97 @@
+//! Scenes: target states for some relay channels, stored on the node and
+//! applied together on `SceneRecall`.
+
+use crate::decode_error::{expect_len, DecodeError};
+use crate::relais_message::RelaisState;
+use embassy_time::Duration;
+
+/// Scenes a node stores, numbered from 0.
+pub const MAX_SCENES: u8 = 16;
+
+/// Channels a scene covers.
+pub const SCENE_CHANNELS: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Scene {
+    pub num: u8,
+    /// applied this long after the recall, whole seconds up to 255
+    pub delay: Duration,
+    /// `None` leaves the channel as it is
+    pub states: [Option<RelaisState>; SCENE_CHANNELS],
+}
+
+impl Scene {
+    pub fn new(num: u8) -> Self {
+        Self {
+            num,
+            delay: Duration::from_secs(0),
+            states: Default::default(),
+        }
+    }
+
+    /// Number, delay in seconds, mask of the channels set, then two bits
+    /// of state per channel.
+    pub fn parse(data: &[u8]) -> Result<Self, DecodeError> {
+        expect_len(data, 8)?;
+        if data[0] >= MAX_SCENES {
+            return Err(DecodeError::InvalidValue(data[0]));
+        }
+        let mask = u16::from_le_bytes([data[2], data[3]]);
+        let bits = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
+        let mut scene = Self::new(data[0]);
+        scene.delay = Duration::from_secs(data[1] as u64);
+        for (i, state) in scene.states.iter_mut().enumerate() {
+            if mask & (1 << i) != 0 {
+                *state = Some(RelaisState::try_from((bits >> (i * 2) & 0x3) as u8)?);
+            }
+        }
+        Ok(scene)
+    }
+
+    pub fn to_bytes(&self) -> [u8; 8] {
+        let mut mask = 0u16;
+        let mut bits = 0u32;
+        for (i, state) in self.states.iter().enumerate() {
+            // Unknown is never a target, leave the channel alone
+            if let Some(state) = state.clone().filter(|s| *s != RelaisState::Unknown) {
+                mask |= 1 << i;
+                bits |= (state as u32 & 0x3) << (i * 2);
+            }
+        }
+        let mut bytes = [0; 8];
+        bytes[0] = self.num;
+        bytes[1] = self.delay.as_secs().min(u8::MAX as u64) as u8;
+        bytes[2..4].copy_from_slice(&mask.to_le_bytes());
+        bytes[4..].copy_from_slice(&bits.to_le_bytes());
+        bytes
+    }
+
+    /// Channels to set, in order.
+    pub fn targets(&self) -> impl Iterator<Item = (usize, &RelaisState)> {
+        self.states
+            .iter()
+            .enumerate()
+            .filter_map(|(num, state)| Some((num, state.as_ref()?)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scene() {
+        let mut scene = Scene::new(3);
+        scene.delay = Duration::from_secs(30);
+        scene.states[0] = Some(RelaisState::On);
+        scene.states[5] = Some(RelaisState::Off);
+        scene.states[15] = Some(RelaisState::Down);
+        assert_eq!(Scene::parse(&scene.to_bytes()), Ok(scene.clone()));
+        assert!(scene.targets().map(|(num, _)| num).eq([0, 5, 15]));
+
+        let mut bytes = scene.to_bytes();
+        bytes[0] = MAX_SCENES;
+        assert!(Scene::parse(&bytes).is_err());
+        assert!(Scene::parse(&bytes[..4]).is_err());
+    }
+}
@@ -0,0 +1,282 @@
+//! Gateway supervision: a node that stops hearing the gateway switches to
+//! autonomous mode instead of resetting, and reports when it is back.
+
+use crate::decode_error::{expect_len, DecodeError};
+use embassy_time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Mode {
+    Online = 0,
+    /// gateway missing, the node follows its `Policy`
+    Autonomous = 1,
+}
+
+impl TryFrom<u8> for Mode {
+    type Error = DecodeError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Mode::Online),
+            1 => Ok(Mode::Autonomous),
+            _ => Err(DecodeError::UnknownState(value)),
+        }
+    }
+}
+
+/// What rollershutters do when the gateway disappears.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ShutterSafeState {
+    #[default]
+    Keep = 0,
+    Stop = 1,
+    Up = 2,
+    Down = 3,
+}
+
+/// Behaviour in autonomous mode, one byte in the config.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Policy {
+    pub shutters: ShutterSafeState,
+    /// switch all relais off, otherwise they hold their state
+    pub relais_off: bool,
+    /// bindings only switch while autonomous, the gateway handles buttons
+    /// otherwise
+    pub bindings_autonomous_only: bool,
+}
+
+impl Policy {
+    pub fn from_byte(byte: u8) -> Self {
+        let shutters = match byte & 0x3 {
+            0 => ShutterSafeState::Keep,
+            1 => ShutterSafeState::Stop,
+            2 => ShutterSafeState::Up,
+            _ => ShutterSafeState::Down,
+        };
+        Self {
+            shutters,
+            relais_off: byte & 0x4 != 0,
+            bindings_autonomous_only: byte & 0x8 != 0,
+        }
+    }
+
+    pub fn to_byte(&self) -> u8 {
+        self.shutters as u8
+            | (self.relais_off as u8) << 2
+            | (self.bindings_autonomous_only as u8) << 3
+    }
+}
+
+/// Mode change, `duration` is how long the node spent in the mode before.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModeReport {
+    pub mode: Mode,
+    /// seconds
+    pub duration: u32,
+}
+
+impl ModeReport {
+    pub fn parse(data: &[u8]) -> Result<Self, DecodeError> {
+        expect_len(data, 5)?;
+        Ok(Self {
+            mode: Mode::try_from(data[0])?,
+            duration: u32::from_le_bytes([data[1], data[2], data[3], data[4]]),
+        })
+    }
+
+    pub fn to_bytes(&self) -> [u8; 5] {
+        let d = self.duration.to_le_bytes();
+        [self.mode as u8, d[0], d[1], d[2], d[3]]
+    }
+}
+
+/// How often the gateway sends `Echo` and how many missing ones a node
+/// tolerates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Supervision {
+    /// whole seconds, at least one
+    pub interval: Duration,
+    /// autonomous after more than this many intervals without `Echo`
+    pub max_misses: u8,
+}
+
+impl Default for Supervision {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(40),
+            max_misses: 3,
+        }
+    }
+}
+
+impl Supervision {
+    /// Interval in seconds as u16, then the misses.
+    pub fn parse(data: &[u8]) -> Result<Self, DecodeError> {
+        expect_len(data, 3)?;
+        let secs = u16::from_le_bytes([data[0], data[1]]);
+        if secs == 0 {
+            return Err(DecodeError::InvalidValue(0));
+        }
+        Ok(Self {
+            interval: Duration::from_secs(secs as u64),
+            max_misses: data[2],
+        })
+    }
+
+    pub fn to_bytes(&self) -> [u8; 3] {
+        let secs = self.interval.as_secs().clamp(1, u16::MAX as u64) as u16;
+        let s = secs.to_le_bytes();
+        [s[0], s[1], self.max_misses]
+    }
+}
+
+/// `PingDisable` payload: suspend supervision for the duration, zero
+/// resumes it.
+pub fn parse_suspend(data: &[u8]) -> Result<Duration, DecodeError> {
+    expect_len(data, 4)?;
+    let secs = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+    Ok(Duration::from_secs(secs as u64))
+}
+
+pub fn suspend_to_bytes(duration: Duration) -> [u8; 4] {
+    (duration.as_secs().min(u32::MAX as u64) as u32).to_le_bytes()
+}
+
+/// Counts missed intervals, the caller runs the timer.
+#[derive(Debug, Clone)]
+pub struct Supervisor {
+    mode: Mode,
+    misses: u8,
+    supervision: Supervision,
+    suspended: bool,
+}
+
+impl Supervisor {
+    pub fn new(supervision: Supervision) -> Self {
+        Self {
+            mode: Mode::Online,
+            misses: 0,
+            supervision,
+            suspended: false,
+        }
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.supervision.interval
+    }
+
+    /// New settings, counting starts over.
+    pub fn configure(&mut self, supervision: Supervision) {
+        self.supervision = supervision;
+        self.misses = 0;
+    }
+
+    /// Maintenance: missing intervals no longer count, the mode stays.
+    pub fn suspend(&mut self) {
+        self.suspended = true;
+    }
+
+    /// Counting starts over.
+    pub fn resume(&mut self) {
+        self.suspended = false;
+        self.misses = 0;
+    }
+
+    pub fn is_suspended(&self) -> bool {
+        self.suspended
+    }
+
+    /// The gateway was heard. Returns the new mode on a change.
+    pub fn seen(&mut self) -> Option<Mode> {
+        self.misses = 0;
+        self.switch(Mode::Online)
+    }
+
+    /// An interval passed without the gateway.
+    pub fn missed(&mut self) -> Option<Mode> {
+        if self.suspended {
+            return None;
+        }
+        self.misses = self.misses.saturating_add(1);
+        if self.misses > self.supervision.max_misses {
+            return self.switch(Mode::Autonomous);
+        }
+        None
+    }
+
+    fn switch(&mut self, mode: Mode) -> Option<Mode> {
+        (self.mode != mode).then(|| {
+            self.mode = mode;
+            mode
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_supervisor() {
+        let mut supervisor = Supervisor::new(Supervision::default());
+        assert_eq!(supervisor.seen(), None);
+        for _ in 0..3 {
+            assert_eq!(supervisor.missed(), None);
+        }
+        assert_eq!(supervisor.missed(), Some(Mode::Autonomous));
+        assert_eq!(supervisor.missed(), None);
+        assert_eq!(supervisor.mode(), Mode::Autonomous);
+        assert_eq!(supervisor.seen(), Some(Mode::Online));
+        assert_eq!(supervisor.missed(), None);
+    }
+
+    #[test]
+    fn test_suspend() {
+        let mut supervisor = Supervisor::new(Supervision {
+            interval: Duration::from_secs(10),
+            max_misses: 1,
+        });
+        assert_eq!(supervisor.missed(), None);
+        supervisor.suspend();
+        for _ in 0..10 {
+            assert_eq!(supervisor.missed(), None);
+        }
+        supervisor.resume();
+        assert_eq!(supervisor.missed(), None);
+        assert_eq!(supervisor.missed(), Some(Mode::Autonomous));
+
+        supervisor.configure(Supervision {
+            interval: Duration::from_secs(5),
+            max_misses: 0,
+        });
+        assert_eq!(supervisor.interval(), Duration::from_secs(5));
+        assert_eq!(supervisor.seen(), Some(Mode::Online));
+        assert_eq!(supervisor.missed(), Some(Mode::Autonomous));
+    }
+
+    #[test]
+    fn test_policy() {
+        let policy = Policy {
+            shutters: ShutterSafeState::Up,
+            relais_off: false,
+            bindings_autonomous_only: true,
+        };
+        assert_eq!(Policy::from_byte(policy.to_byte()), policy);
+        assert_eq!(Policy::from_byte(0), Policy::default());
+        let report = ModeReport {
+            mode: Mode::Online,
+            duration: 3600,
+        };
+        assert_eq!(ModeReport::parse(&report.to_bytes()), Ok(report));
+        let supervision = Supervision::default();
+        assert_eq!(Supervision::parse(&supervision.to_bytes()), Ok(supervision));
+        assert!(Supervision::parse(&[0, 0, 3]).is_err());
+        let suspend = Duration::from_secs(3600);
+        assert_eq!(parse_suspend(&suspend_to_bytes(suspend)), Ok(suspend));
+    }
+}
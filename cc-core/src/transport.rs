@@ -0,0 +1,507 @@
+//! Multi-frame transport in the style of ISO-TP. Payloads of any message
+//! type up to `MAX_LEN` bytes travel as `Segment` frames: a first frame
+//! with the length and the message type, consecutive frames with seven
+//! bytes each, and flow control from the receiver after the first frame
+//! and every block. Nothing here allocates or keeps time, the caller runs
+//! the timers and calls `Receiver::reset` when the peer goes quiet.
+
+use crate::can_message_type::CanMessageType;
+use crate::decode_error::{expect_len, DecodeError};
+use crate::message::Payload;
+use embassy_time::Duration;
+use heapless::Vec;
+
+/// Longest payload, the first frame has 12 bits of length.
+pub const MAX_LEN: usize = 0xFFF;
+
+/// Data bytes in a first frame.
+const FIRST_DATA: usize = 5;
+
+/// Data bytes in a consecutive frame.
+const CONSECUTIVE_DATA: usize = 7;
+
+/// Longest separation a flow control frame asks for.
+pub const MAX_SEPARATION_MS: u8 = 127;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum FlowStatus {
+    Continue = 0,
+    /// receiver busy, another flow control follows
+    Wait = 1,
+    /// payload does not fit, the transfer is over
+    Overflow = 2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlowControl {
+    pub status: FlowStatus,
+    /// consecutive frames until the next flow control, 0 for all
+    pub block_size: u8,
+    /// minimum gap between consecutive frames, whole milliseconds
+    pub separation: Duration,
+}
+
+impl FlowControl {
+    pub fn overflow() -> Self {
+        Self {
+            status: FlowStatus::Overflow,
+            block_size: 0,
+            separation: Duration::from_millis(0),
+        }
+    }
+}
+
+/// Payload of a `Segment` frame, the high nibble of the first byte tells
+/// the kind like the protocol control information of ISO-TP.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Segment {
+    First {
+        len: u16,
+        msg_type: CanMessageType,
+        data: Payload,
+    },
+    Consecutive {
+        /// 1, 2, .. 15, 0, 1, ..
+        seq: u8,
+        data: Payload,
+    },
+    Flow(FlowControl),
+}
+
+impl Segment {
+    pub fn parse(data: &[u8]) -> Result<Self, DecodeError> {
+        let Some(&pci) = data.first() else {
+            return Err(DecodeError::TooShort {
+                expected: 1,
+                got: 0,
+            });
+        };
+        match pci >> 4 {
+            1 => {
+                if data.len() < 3 {
+                    return Err(DecodeError::TooShort {
+                        expected: 3,
+                        got: data.len(),
+                    });
+                }
+                let len = u16::from_be_bytes([pci & 0xF, data[1]]);
+                let msg_type = CanMessageType::from(data[2]);
+                if matches!(
+                    msg_type,
+                    CanMessageType::InvalidMessage | CanMessageType::Segment
+                ) {
+                    return Err(DecodeError::InvalidValue(data[2]));
+                }
+                expect_len(data, 3 + (len as usize).min(FIRST_DATA))?;
+                Ok(Segment::First {
+                    len,
+                    msg_type,
+                    data: Payload::from_slice(&data[3..]).unwrap(),
+                })
+            }
+            2 => {
+                if data.len() < 2 {
+                    return Err(DecodeError::TooShort {
+                        expected: 2,
+                        got: data.len(),
+                    });
+                }
+                Ok(Segment::Consecutive {
+                    seq: pci & 0xF,
+                    data: Payload::from_slice(&data[1..]).map_err(|_| DecodeError::Trailing)?,
+                })
+            }
+            3 => {
+                expect_len(data, 3)?;
+                let status = match pci & 0xF {
+                    0 => FlowStatus::Continue,
+                    1 => FlowStatus::Wait,
+                    2 => FlowStatus::Overflow,
+                    _ => return Err(DecodeError::UnknownState(pci)),
+                };
+                if data[2] > MAX_SEPARATION_MS {
+                    return Err(DecodeError::InvalidValue(data[2]));
+                }
+                Ok(Segment::Flow(FlowControl {
+                    status,
+                    block_size: data[1],
+                    separation: Duration::from_millis(data[2] as u64),
+                }))
+            }
+            _ => Err(DecodeError::UnknownState(pci)),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Payload {
+        let mut out = Payload::new();
+        match self {
+            Segment::First {
+                len,
+                msg_type,
+                data,
+            } => {
+                let len = len.to_be_bytes();
+                out.extend_from_slice(&[0x10 | (len[0] & 0xF), len[1], *msg_type as u8])
+                    .unwrap();
+                out.extend_from_slice(&data[..data.len().min(FIRST_DATA)])
+                    .unwrap();
+            }
+            Segment::Consecutive { seq, data } => {
+                out.push(0x20 | (seq & 0xF)).unwrap();
+                out.extend_from_slice(&data[..data.len().min(CONSECUTIVE_DATA)])
+                    .unwrap();
+            }
+            Segment::Flow(flow) => {
+                let separation = flow.separation.as_millis().min(MAX_SEPARATION_MS as u64);
+                out.extend_from_slice(&[
+                    0x30 | flow.status as u8,
+                    flow.block_size,
+                    separation as u8,
+                ])
+                .unwrap();
+            }
+        }
+        out
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransportError {
+    /// payload longer than `MAX_LEN`
+    TooLong(usize),
+    /// the receiver has no room for the payload
+    Overflow,
+    /// a consecutive frame got lost or repeated
+    Sequence { expected: u8, got: u8 },
+    /// segment that does not fit the state of the transfer
+    Unexpected,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SendState {
+    First,
+    AwaitFlow,
+    Sending,
+    Done,
+}
+
+/// Sending side of one transfer.
+#[derive(Debug, Clone)]
+pub struct Sender<'a> {
+    msg_type: CanMessageType,
+    data: &'a [u8],
+    sent: usize,
+    seq: u8,
+    /// frames left in the current block, `None` without a limit
+    budget: Option<u8>,
+    separation: Duration,
+    state: SendState,
+}
+
+impl<'a> Sender<'a> {
+    pub fn new(msg_type: CanMessageType, data: &'a [u8]) -> Result<Self, TransportError> {
+        if data.len() > MAX_LEN {
+            return Err(TransportError::TooLong(data.len()));
+        }
+        Ok(Self {
+            msg_type,
+            data,
+            sent: 0,
+            seq: 0,
+            budget: None,
+            separation: Duration::from_millis(0),
+            state: SendState::First,
+        })
+    }
+
+    /// Next frame to send, `None` while waiting for flow control and once
+    /// everything is sent.
+    pub fn next_segment(&mut self) -> Option<Segment> {
+        match self.state {
+            SendState::First => {
+                self.sent = self.data.len().min(FIRST_DATA);
+                self.state = match self.sent == self.data.len() {
+                    true => SendState::Done,
+                    false => SendState::AwaitFlow,
+                };
+                Some(Segment::First {
+                    len: self.data.len() as u16,
+                    msg_type: self.msg_type,
+                    data: Payload::from_slice(&self.data[..self.sent]).unwrap(),
+                })
+            }
+            SendState::Sending => {
+                let end = (self.sent + CONSECUTIVE_DATA).min(self.data.len());
+                let data = Payload::from_slice(&self.data[self.sent..end]).unwrap();
+                self.sent = end;
+                self.seq = (self.seq + 1) & 0xF;
+                if let Some(budget) = &mut self.budget {
+                    *budget -= 1;
+                }
+                self.state = match (self.sent == self.data.len(), self.budget) {
+                    (true, _) => SendState::Done,
+                    (false, Some(0)) => SendState::AwaitFlow,
+                    _ => SendState::Sending,
+                };
+                Some(Segment::Consecutive {
+                    seq: self.seq,
+                    data,
+                })
+            }
+            SendState::AwaitFlow | SendState::Done => None,
+        }
+    }
+
+    /// Flow control of the receiver.
+    pub fn flow(&mut self, flow: &FlowControl) -> Result<(), TransportError> {
+        if self.state != SendState::AwaitFlow {
+            return Err(TransportError::Unexpected);
+        }
+        match flow.status {
+            FlowStatus::Continue => {
+                self.budget = (flow.block_size > 0).then_some(flow.block_size);
+                self.separation = flow.separation;
+                self.state = SendState::Sending;
+                Ok(())
+            }
+            FlowStatus::Wait => Ok(()),
+            FlowStatus::Overflow => {
+                self.state = SendState::Done;
+                Err(TransportError::Overflow)
+            }
+        }
+    }
+
+    /// Gap the receiver asked for between consecutive frames.
+    pub fn separation(&self) -> Duration {
+        self.separation
+    }
+
+    pub fn is_waiting(&self) -> bool {
+        self.state == SendState::AwaitFlow
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.state == SendState::Done
+    }
+}
+
+/// What the receiver wants after a segment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    Pending,
+    /// send this flow control to the sender
+    Flow(FlowControl),
+    /// payload complete, see `Receiver::data`
+    Complete(CanMessageType),
+}
+
+/// Receiving side, reassembles one transfer at a time into `N` bytes.
+#[derive(Debug, Clone)]
+pub struct Receiver<const N: usize> {
+    buf: Vec<u8, N>,
+    len: usize,
+    msg_type: CanMessageType,
+    seq: u8,
+    block_size: u8,
+    left: u8,
+    separation: Duration,
+    active: bool,
+}
+
+impl<const N: usize> Receiver<N> {
+    /// Flow control after every `block_size` consecutive frames, 0 for
+    /// none after the first frame.
+    pub const fn new(block_size: u8, separation: Duration) -> Self {
+        Self {
+            buf: Vec::new(),
+            len: 0,
+            msg_type: CanMessageType::InvalidMessage,
+            seq: 0,
+            block_size,
+            left: 0,
+            separation,
+            active: false,
+        }
+    }
+
+    fn proceed(&self) -> Step {
+        Step::Flow(FlowControl {
+            status: FlowStatus::Continue,
+            block_size: self.block_size,
+            separation: self.separation,
+        })
+    }
+
+    /// A first frame replaces an unfinished transfer.
+    pub fn feed(&mut self, segment: &Segment) -> Result<Step, TransportError> {
+        match segment {
+            Segment::First {
+                len,
+                msg_type,
+                data,
+            } => {
+                self.reset();
+                let len = *len as usize;
+                if len > N {
+                    return Ok(Step::Flow(FlowControl::overflow()));
+                }
+                self.len = len;
+                self.msg_type = *msg_type;
+                self.buf.extend_from_slice(data).unwrap();
+                if self.buf.len() == len {
+                    return Ok(Step::Complete(self.msg_type));
+                }
+                self.active = true;
+                self.left = self.block_size;
+                Ok(self.proceed())
+            }
+            Segment::Consecutive { seq, data } => {
+                if !self.active {
+                    return Err(TransportError::Unexpected);
+                }
+                let expected = (self.seq + 1) & 0xF;
+                if *seq != expected {
+                    self.reset();
+                    return Err(TransportError::Sequence {
+                        expected,
+                        got: *seq,
+                    });
+                }
+                self.seq = expected;
+                // the last frame may carry padding
+                let take = data.len().min(self.len - self.buf.len());
+                self.buf.extend_from_slice(&data[..take]).unwrap();
+                if self.buf.len() == self.len {
+                    self.active = false;
+                    return Ok(Step::Complete(self.msg_type));
+                }
+                if self.block_size > 0 {
+                    self.left -= 1;
+                    if self.left == 0 {
+                        self.left = self.block_size;
+                        return Ok(self.proceed());
+                    }
+                }
+                Ok(Step::Pending)
+            }
+            Segment::Flow(_) => Err(TransportError::Unexpected),
+        }
+    }
+
+    /// Payload of the last complete transfer.
+    pub fn data(&self) -> &[u8] {
+        match self.active {
+            true => &[],
+            false => &self.buf,
+        }
+    }
+
+    /// Whether a transfer is under way.
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Drop an unfinished transfer, e.g. after a timeout.
+    pub fn reset(&mut self) {
+        self.buf.clear();
+        self.len = 0;
+        self.seq = 0;
+        self.active = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run a transfer through `receiver`, parsing every frame on the way.
+    fn transfer<const N: usize>(
+        data: &[u8],
+        receiver: &mut Receiver<N>,
+    ) -> Result<Step, TransportError> {
+        let mut sender = Sender::new(CanMessageType::CustomString, data)?;
+        while let Some(segment) = sender.next_segment() {
+            let segment = Segment::parse(&segment.to_bytes()).unwrap();
+            match receiver.feed(&segment)? {
+                Step::Flow(flow) => {
+                    let flow = Segment::parse(&Segment::Flow(flow).to_bytes()).unwrap();
+                    let Segment::Flow(flow) = flow else {
+                        unreachable!()
+                    };
+                    sender.flow(&flow)?;
+                }
+                Step::Complete(msg_type) => {
+                    assert!(sender.is_done());
+                    return Ok(Step::Complete(msg_type));
+                }
+                Step::Pending => {}
+            }
+        }
+        Ok(Step::Pending)
+    }
+
+    #[test]
+    fn test_transfer() {
+        let data: [u8; 300] = core::array::from_fn(|i| i as u8);
+        let mut receiver: Receiver<512> = Receiver::new(4, Duration::from_millis(2));
+        assert_eq!(
+            transfer(&data, &mut receiver),
+            Ok(Step::Complete(CanMessageType::CustomString))
+        );
+        assert_eq!(receiver.data(), &data[..]);
+
+        // fits into the first frame, no flow control
+        assert_eq!(
+            transfer(b"abc", &mut receiver),
+            Ok(Step::Complete(CanMessageType::CustomString))
+        );
+        assert_eq!(receiver.data(), b"abc");
+
+        let mut small: Receiver<64> = Receiver::new(0, Duration::from_millis(0));
+        assert_eq!(transfer(&data, &mut small), Err(TransportError::Overflow));
+        assert!(Sender::new(CanMessageType::CustomString, &[0; MAX_LEN + 1]).is_err());
+    }
+
+    #[test]
+    fn test_sequence() {
+        let data = [7u8; 40];
+        let mut receiver: Receiver<64> = Receiver::new(0, Duration::from_millis(0));
+        let mut sender = Sender::new(CanMessageType::LogDownload, &data).unwrap();
+        let first = sender.next_segment().unwrap();
+        let Ok(Step::Flow(flow)) = receiver.feed(&first) else {
+            panic!("no flow control");
+        };
+        sender.flow(&flow).unwrap();
+        assert_eq!(sender.flow(&flow), Err(TransportError::Unexpected));
+        // two consecutive frames lost
+        sender.next_segment().unwrap();
+        sender.next_segment().unwrap();
+        let third = sender.next_segment().unwrap();
+        assert_eq!(
+            receiver.feed(&third),
+            Err(TransportError::Sequence {
+                expected: 1,
+                got: 3
+            })
+        );
+        assert!(!receiver.is_active());
+        assert_eq!(receiver.feed(&third), Err(TransportError::Unexpected));
+    }
+
+    #[test]
+    fn test_segment() {
+        assert!(Segment::parse(&[0x10, 10, 0xFF, 1, 2, 3, 4, 5]).is_err());
+        assert!(Segment::parse(&[0x10, 10, 10, 1, 2]).is_err());
+        assert!(Segment::parse(&[0x31, 0, 200]).is_err());
+        assert!(Segment::parse(&[0x40]).is_err());
+        assert!(Segment::parse(&[]).is_err());
+        let first = Segment::First {
+            len: 0xABC,
+            msg_type: CanMessageType::LogDownload,
+            data: Payload::from_slice(&[1, 2, 3, 4, 5]).unwrap(),
+        };
+        assert_eq!(Segment::parse(&first.to_bytes()), Ok(first));
+    }
+}
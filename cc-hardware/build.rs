@@ -1,3 +1,4 @@
+use cancomponents_core::image::{ImageHeader, Version, PROTOCOL_VERSION};
 use std::process::Command;
 
 fn main() {
@@ -7,20 +8,104 @@ fn main() {
     println!("cargo:rustc-link-arg=-Tlinkall.x");
     let profile = std::env::var("PROFILE").unwrap();
     println!("cargo:rerun-if-changed=target/xtensa-esp32-none-elf/{profile}/cancomponents");
-    create_esp32_image();
+    update_key();
+    let header = image_header();
+    create_esp32_image(&header);
 }
 
-fn create_esp32_image() {
+/// What this build runs on. CANCOMPONENTS_DEVICE_TYPE restricts it to one
+/// `DeviceType` (default: any), CANCOMPONENTS_HWREV to a range of hardware
+/// revisions like `1-2` (default: any).
+fn image_header() -> ImageHeader {
+    println!("cargo:rerun-if-env-changed=CANCOMPONENTS_DEVICE_TYPE");
+    println!("cargo:rerun-if-env-changed=CANCOMPONENTS_HWREV");
+    let device_type = std::env::var("CANCOMPONENTS_DEVICE_TYPE")
+        .map(|v| v.parse().expect("CANCOMPONENTS_DEVICE_TYPE: not a number"))
+        .unwrap_or(0);
+    let (hwrev_min, hwrev_max) = match std::env::var("CANCOMPONENTS_HWREV") {
+        Ok(range) => {
+            let (min, max) = range.split_once('-').unwrap_or((&range, &range));
+            let parse = |v: &str| v.parse().expect("CANCOMPONENTS_HWREV: expected e.g. 1-2");
+            (parse(min), parse(max))
+        }
+        Err(_) => (0, u8::MAX),
+    };
+    let version = |key| std::env::var(key).unwrap().parse().unwrap_or(u8::MAX);
+    let header = ImageHeader {
+        protocol: PROTOCOL_VERSION,
+        device_type,
+        hwrev_min,
+        hwrev_max,
+        version: Version {
+            major: version("CARGO_PKG_VERSION_MAJOR"),
+            minor: version("CARGO_PKG_VERSION_MINOR"),
+            patch: version("CARGO_PKG_VERSION_PATCH"),
+        },
+    };
+    let out = std::path::PathBuf::from(std::env::var("OUT_DIR").unwrap()).join("image_header.bin");
+    std::fs::write(out, header.stamp()).unwrap();
+    header
+}
+
+/// Compile in the public key images have to be signed with (see `ccsign keygen`):
+/// CANCOMPONENTS_UPDATE_KEY, else keys/update.pub. Debug builds may opt in
+/// to keys/dev.pub with CANCOMPONENTS_DEV_KEY=1, see keys/README.md.
+fn update_key() {
+    println!("cargo:rerun-if-env-changed=CANCOMPONENTS_UPDATE_KEY");
+    println!("cargo:rerun-if-env-changed=CANCOMPONENTS_DEV_KEY");
+    println!("cargo:rerun-if-changed=keys/update.pub");
+    let release = std::env::var("PROFILE").unwrap() == "release";
+    let dev_key = std::env::var("CANCOMPONENTS_DEV_KEY").is_ok_and(|v| v == "1");
+    let path = match std::env::var("CANCOMPONENTS_UPDATE_KEY") {
+        Ok(path) => path,
+        Err(_) if std::path::Path::new("keys/update.pub").exists() => "keys/update.pub".to_string(),
+        Err(_) if dev_key && !release => {
+            println!(
+                "cargo:warning=Using the development update key keys/dev.pub, \
+                 this build accepts no update over CAN"
+            );
+            "keys/dev.pub".to_string()
+        }
+        Err(_) => panic!(
+            "no update key. Create one with `ccsign keygen keys/update` (keep update.key \
+             safe, it signs every image) or point CANCOMPONENTS_UPDATE_KEY to the public \
+             key. Debug builds can use the development key with CANCOMPONENTS_DEV_KEY=1"
+        ),
+    };
+    println!("cargo:rerun-if-changed={path}");
+    let key = std::fs::read(&path).unwrap_or_else(|e| {
+        panic!(
+            "update key {path}: {e}. Create one with `ccsign keygen keys/update` \
+             or point CANCOMPONENTS_UPDATE_KEY to the public key"
+        )
+    });
+    if key.len() != 32 {
+        panic!("update key {path}: expected 32 bytes, got {}", key.len());
+    }
+    let out = std::path::PathBuf::from(std::env::var("OUT_DIR").unwrap()).join("update.pub");
+    std::fs::write(out, key).unwrap();
+}
+
+fn create_esp32_image(header: &ImageHeader) {
     let profile = std::env::var("PROFILE").unwrap();
     let target_binary = format!("target/xtensa-esp32-none-elf/{profile}/cancomponents");
     let ota_binary = format!("target/xtensa-esp32-none-elf/{profile}/cancomponents.bin");
 
-    if let Err(e) = Command::new("espflash")
+    match Command::new("espflash")
         .args(["save-image", "--chip", "esp32", &target_binary, &ota_binary])
         .status()
     {
-        println!("cargo:warning=Failed to create ESP32 image: {e}");
-        // Kein panic! - Build soll auch ohne Image weiterlaufen
+        Ok(status) if status.success() => {
+            // the bootloader ignores anything after the image
+            let mut image = std::fs::read(&ota_binary).unwrap();
+            image.extend_from_slice(&header.stamp());
+            std::fs::write(&ota_binary, image).unwrap();
+        }
+        Ok(status) => println!("cargo:warning=Failed to create ESP32 image: {status}"),
+        Err(e) => {
+            println!("cargo:warning=Failed to create ESP32 image: {e}");
+            // Kein panic! - Build soll auch ohne Image weiterlaufen
+        }
     }
 }
 
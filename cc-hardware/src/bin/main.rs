@@ -1,6 +1,8 @@
 #![no_std]
 #![no_main]
 
+use cancomponents::binding;
+use cancomponents::boot_guard;
 use cancomponents::button::Button;
 use cancomponents::can;
 use cancomponents::config;
@@ -9,7 +11,9 @@ use cancomponents::device;
 use cancomponents::echo_guard;
 use cancomponents::gpio_interrupt;
 use cancomponents::relais::Relais;
+use cancomponents::transport;
 use cancomponents::update;
+use cancomponents_core::can_id::CanId;
 use cancomponents_core::can_message_type::CanMessageType;
 use cancomponents_core::device_type::DeviceType;
 use embassy_executor::Spawner;
@@ -35,7 +39,9 @@ async fn main(spawner: Spawner) -> ! {
     esp_hal_embassy::init(timg0.timer0);
 
     config::init().await;
+    boot_guard::init(&spawner).await;
     device::init().await;
+    binding::init().await;
     update::init(&spawner).await;
     gpio_interrupt::init(peripherals.IO_MUX);
     can::init(
@@ -45,6 +51,8 @@ async fn main(spawner: Spawner) -> ! {
         &spawner,
     )
     .await;
+    transport::init(&spawner).await;
+    device::claim(&spawner).await;
 
     let device_type = config()
         .await
@@ -87,11 +95,10 @@ async fn main(spawner: Spawner) -> ! {
         (_, _) => {}
     };
 
-    let data = [1];
     Timer::after(Duration::from_millis(5_000)).await;
-    can::send_can_message(CanMessageType::Available, &data, false).await;
+    announce().await;
     Timer::after(Duration::from_millis(1_000)).await;
-    can::send_can_message(CanMessageType::Available, &data, false).await;
+    announce().await;
 
     echo_guard::init(&spawner).await;
     /*
@@ -119,3 +126,8 @@ async fn main(spawner: Spawner) -> ! {
         Timer::after(Duration::from_millis(3_000)).await;
     }
 }
+
+async fn announce() {
+    let id = CanId::new(0, 0, CanMessageType::Available);
+    device::device().await.announce(id, &[], true).await;
+}
@@ -0,0 +1,73 @@
+use crate::can::{send_message, GROUPS};
+use crate::config::config;
+use crate::echo_guard;
+use crate::relais;
+use cancomponents_core::binding::{source_filter_mask, Binding, MAX_BINDINGS};
+use cancomponents_core::button_message::ButtonMessage;
+use cancomponents_core::can_id::CanId;
+use cancomponents_core::message::CanMessage;
+use cancomponents_core::supervisor::Mode;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::mutex::Mutex;
+
+static BINDINGS: Mutex<CriticalSectionRawMutex, [Option<Binding>; MAX_BINDINGS as usize]> =
+    Mutex::new([None; MAX_BINDINGS as usize]);
+
+/// Load the table, before `can::init` computes the filter from it.
+pub async fn init() {
+    let mut bindings = BINDINGS.lock().await;
+    let mut config = config().await;
+    for slot in 0..MAX_BINDINGS {
+        bindings[slot as usize] = config.get_binding(slot).await;
+    }
+}
+
+/// Acceptance filter bits of the device id that still let every source
+/// through.
+pub async fn filter_mask() -> u8 {
+    source_filter_mask(BINDINGS.lock().await.iter().flatten())
+}
+
+/// `ButtonEvent` of any node, before the address check. Switches the
+/// relais of every matching binding, unless the policy leaves buttons to
+/// the gateway while it is there.
+pub async fn button_event(id: CanId, msg: &ButtonMessage) {
+    if echo_guard::mode().await == Mode::Online
+        && echo_guard::policy().await.bindings_autonomous_only
+    {
+        return;
+    }
+    let groups = *GROUPS.lock().await;
+    let bindings = *BINDINGS.lock().await;
+    for binding in bindings.iter().flatten() {
+        let ours = binding.group == 0 || groups.contains(binding.group);
+        if ours && binding.matches(id.device_type, id.device_id, msg) {
+            relais::bound(binding.relay as usize, binding.action);
+        }
+    }
+}
+
+/// Data: replace or clear a binding. Sources outside of the acceptance
+/// filter are only heard after a restart. Remote request: send the table.
+pub async fn handler(msg: CanMessage) {
+    let (slot, binding) = match msg {
+        CanMessage::Request(_) => {
+            let bindings = *BINDINGS.lock().await;
+            for binding in bindings.iter().flatten() {
+                send_message(&CanMessage::BindingDefine(*binding)).await;
+            }
+            return;
+        }
+        CanMessage::BindingDefine(binding) => (binding.slot, Some(binding)),
+        CanMessage::BindingClear(slot) => (slot, None),
+        _ => return,
+    };
+    if config()
+        .await
+        .set_binding(slot, binding.as_ref())
+        .await
+        .is_ok()
+    {
+        BINDINGS.lock().await[slot as usize] = binding;
+    }
+}
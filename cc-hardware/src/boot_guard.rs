@@ -0,0 +1,93 @@
+use crate::config::{self, config};
+use crate::error::{self, Component, ErrorCode, Severity};
+use cancomponents_core::ota::UpdateErrorCode;
+use embassy_executor::Spawner;
+use embassy_futures::select::{select, Either};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_time::{Duration, Timer};
+use esp_hal_ota::{Ota, OtaImgState};
+use esp_println::println;
+use esp_storage::FlashStorage;
+
+/// Seconds a new image has to hear from the gateway, unless configured.
+pub const DEFAULT_CONFIRM_WINDOW: u32 = 300;
+/// Unconfirmed boots before rolling back, catches crash loops.
+const MAX_BOOT_ATTEMPTS: u8 = 3;
+
+static CONFIRM_CHANNEL: Channel<CriticalSectionRawMutex, (), 1> = Channel::new();
+
+/// A frame to our own address was handled, so the gateway reaches us and
+/// CAN works in both directions.
+pub fn confirm() {
+    let _ = CONFIRM_CHANNEL.try_send(());
+}
+
+/// Call early after `config::init`. A freshly flashed image starts
+/// unconfirmed: it is only marked valid once the gateway talked to it
+/// within the confirm window, otherwise the previous slot is booted again.
+/// The bootloader does the same if the image crashes before getting here.
+pub async fn init(spawner: &Spawner) {
+    let Ok(mut ota) = Ota::new(FlashStorage::new()) else {
+        return;
+    };
+    match ota.get_ota_image_state() {
+        Ok(OtaImgState::EspOtaImgNew) | Ok(OtaImgState::EspOtaImgPendingVerify) => {}
+        // factory image or already confirmed
+        _ => return,
+    }
+
+    let mut config = config().await;
+    let attempts = config
+        .get_u8(config::Key::BootAttempts)
+        .await
+        .unwrap_or(0)
+        .saturating_add(1);
+    let _ = config.set_u8(config::Key::BootAttempts, attempts).await;
+    if attempts > MAX_BOOT_ATTEMPTS {
+        println!("image not confirmed after {attempts} boots, rolling back");
+        let _ = config.set_u8(config::Key::BootAttempts, 0).await;
+        rollback(ota);
+    }
+    let window = config
+        .get_u32(config::Key::ConfirmWindow)
+        .await
+        .unwrap_or(DEFAULT_CONFIRM_WINDOW);
+    drop(config);
+
+    println!("image pending verify, attempt {attempts}, window {window}s");
+    spawner.spawn(boot_guard_task(ota, window)).unwrap();
+}
+
+#[embassy_executor::task]
+async fn boot_guard_task(mut ota: Ota<FlashStorage>, window: u32) {
+    let timeout = Timer::after(Duration::from_secs(window as u64));
+    match select(CONFIRM_CHANNEL.receive(), timeout).await {
+        Either::First(_) => {
+            if ota.ota_mark_app_valid().is_ok() {
+                println!("image confirmed");
+                let _ = config().await.set_u8(config::Key::BootAttempts, 0).await;
+            } else {
+                error::report(
+                    Component::Ota,
+                    ErrorCode::Unknown,
+                    Severity::Error,
+                    UpdateErrorCode::Confirm as u8,
+                    &[0u8, 0u8, 0u8],
+                )
+                .await;
+            }
+        }
+        Either::Second(_) => {
+            println!("no gateway within {window}s, rolling back");
+            let _ = config().await.set_u8(config::Key::BootAttempts, 0).await;
+            rollback(ota);
+        }
+    }
+}
+
+fn rollback(mut ota: Ota<FlashStorage>) -> ! {
+    // marks the image invalid and restarts into the previous slot
+    let _ = ota.ota_mark_app_invalid_rollback();
+    esp_hal::system::software_reset();
+}
@@ -13,6 +13,9 @@ use esp_hal::gpio::InputConfig;
 use esp_hal::gpio::Pull;
 use esp_println::println;
 
+/// Inputs of a button node.
+pub const BUTTON_COUNT: usize = 4;
+
 const DEBOUNCE_TIME: Duration = Duration::from_millis(10); // Entprellzeit
 const MULTI_CLICK_MAX: Duration = Duration::from_millis(200); // Zeitfenster für Double/Triple/Quad
 const HOLD_THRESHOLD: Duration = Duration::from_millis(800); // Ab wann "Hold"
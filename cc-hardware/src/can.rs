@@ -1,9 +1,21 @@
+use crate::binding;
+use crate::boot_guard;
 use crate::config;
 use crate::device::device;
-use crate::relais::relais_handler;
+use crate::echo_guard;
+use crate::error::{self, Component, ErrorCode, Severity};
+use crate::metadata;
+use crate::relais::{
+    dead_time_handler, power_on_handler, relais_handler, scene_handler, travel_handler,
+};
+use crate::transport;
 use crate::update::update;
 use cancomponents_core::can_id::CanId;
 use cancomponents_core::can_message_type::CanMessageType;
+use cancomponents_core::decode_error::DecodeError;
+use cancomponents_core::device_message::is_address_conflict;
+use cancomponents_core::group::Groups;
+use cancomponents_core::message::CanMessage;
 use embassy_executor::Spawner;
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_sync::channel::Channel;
@@ -18,8 +30,14 @@ use esp_println::println;
 pub static CAN_CHANNEL: Channel<CriticalSectionRawMutex, EspTwaiFrame, 32> = Channel::new();
 pub static DEVICE_ID: Mutex<CriticalSectionRawMutex, u8> = Mutex::new(255);
 pub static DEVICE_TYPE: Mutex<CriticalSectionRawMutex, u8> = Mutex::new(255);
+pub static GROUPS: Mutex<CriticalSectionRawMutex, Groups> = Mutex::new(Groups(0));
 
-pub fn make_filter(device_type: u8, device_id: u8) -> DualExtendedFilter {
+pub fn make_filter(
+    device_type: u8,
+    device_id: u8,
+    groups: Groups,
+    source_mask: u8,
+) -> DualExtendedFilter {
     let is_ng = true;
 
     let full_id = ((is_ng as u32) << 28)
@@ -31,11 +49,17 @@ pub fn make_filter(device_type: u8, device_id: u8) -> DualExtendedFilter {
     let code1 = ((full_id >> 13) & 0xFFFF) as u16;
     let mask1 = ((full_mask >> 13) & 0xFFFF) as u16;
 
+    // id 0 of any type: broadcast (0/0), multicast to a type and our
+    // groups, type and group are checked in dispatch. widened to the
+    // sources of our bindings
     let full_id2 = (is_ng as u32) << 28;
+    let full_mask2 =
+        0x10000000 | ((groups.filter_mask() as u32) << 22) | ((source_mask as u32) << 8);
 
     let code2 = ((full_id2 >> 13) & 0xFFFF) as u16;
-    println!("{code1:#x} {code2:#x} {mask1:#x} ");
-    DualExtendedFilter::new_from_code_mask([code1, code2], [mask1, mask1])
+    let mask2 = ((full_mask2 >> 13) & 0xFFFF) as u16;
+    println!("{code1:#x} {code2:#x} {mask1:#x} {mask2:#x}");
+    DualExtendedFilter::new_from_code_mask([code1, code2], [mask1, mask2])
 }
 
 pub async fn init(
@@ -56,10 +80,12 @@ pub async fn init(
 
     let device_type = *DEVICE_TYPE.lock().await;
     let device_id = *DEVICE_ID.lock().await;
+    let groups = *GROUPS.lock().await;
+    let source_mask = binding::filter_mask().await;
 
     let mut twai_config =
         twai::TwaiConfiguration::new(twai, rx, tx, TWAI_BAUDRATE, TwaiMode::Normal);
-    let filter = make_filter(device_type, device_id);
+    let filter = make_filter(device_type, device_id, groups, source_mask);
     twai_config.set_filter(filter);
     let twai = twai_config.into_async().start();
     while twai.is_bus_off() {
@@ -80,18 +106,71 @@ pub async fn dispatch(frame: &EspTwaiFrame) {
             return;
         }
     };
-    // type can be filtered, id is incomplete. also allow broadcast (== 0)
-    if id.device_id != *DEVICE_ID.lock().await && id.device_id != 0 {
+    // the filter lets through more than our address, broadcast (0/0),
+    // multicast to our type (TYPE/0) and our groups
+    // button events of other nodes, for the bindings
+    let device_type = *DEVICE_TYPE.lock().await;
+    let device_id = *DEVICE_ID.lock().await;
+    let ours = id.is_for(device_type, device_id, *GROUPS.lock().await);
+    if !ours && id.msg_type != CanMessageType::ButtonEvent {
         return;
     }
 
+    // reject malformed payloads before any handler touches them
+    let msg = match CanMessage::decode(id, frame.data(), frame.is_remote_frame()) {
+        Ok(msg) => msg,
+        Err(DecodeError::UnknownMessageType) => return unknown_handler(frame).await,
+        // for the node that sent it to report
+        Err(_) if !ours => return,
+        Err(e) => {
+            println!("WARN: {} {e}", id);
+            error::report(
+                Component::Can,
+                ErrorCode::InvalidData,
+                Severity::Warning,
+                e.code(),
+                &[id.msg_type as u8, frame.data().len() as u8, e.detail()],
+            )
+            .await;
+            return;
+        }
+    };
+
+    if id.msg_type == CanMessageType::ButtonEvent {
+        if let CanMessage::ButtonEvent(event) = msg {
+            binding::button_event(id, &event).await;
+        }
+        return;
+    }
+
+    if is_address_conflict(
+        device_type,
+        device_id,
+        id,
+        frame.is_remote_frame(),
+        frame.data(),
+    ) {
+        return device().await.address_conflict(id).await;
+    }
+
+    // the gateway reaches us on our own address, not only by broadcast
+    if id.device_type == device_type & 0x3F && id.device_id == device_id {
+        boot_guard::confirm();
+    }
+
     // this adds quite a bit of delay. careful with that...
     //println!("recv: {frame:?}");
     match id.msg_type {
-        CanMessageType::Relais => relais_handler(id, frame.data(), frame.is_remote_frame()).await,
-        CanMessageType::Rollershutter => {
-            relais_handler(id, frame.data(), frame.is_remote_frame()).await
+        CanMessageType::Relais
+        | CanMessageType::Rollershutter
+        | CanMessageType::RollershutterState => relais_handler(msg).await,
+        CanMessageType::BindingDefine | CanMessageType::BindingClear => binding::handler(msg).await,
+        CanMessageType::SceneDefine | CanMessageType::SceneRecall | CanMessageType::SceneStore => {
+            scene_handler(msg).await
         }
+        CanMessageType::DeadTime => dead_time_handler(msg).await,
+        CanMessageType::ShutterTravel => travel_handler(msg).await,
+        CanMessageType::PowerOn => power_on_handler(msg).await,
         CanMessageType::RelaisMode => {
             let _ = device()
                 .await
@@ -114,6 +193,15 @@ pub async fn dispatch(frame: &EspTwaiFrame) {
                 )
                 .await;
         }
+        CanMessageType::AutonomousPolicy => echo_guard::policy_handler(msg).await,
+        CanMessageType::Supervision => echo_guard::supervision_handler(msg).await,
+        CanMessageType::Segment => transport::handler(msg).await,
+        CanMessageType::Metadata => {
+            metadata::handler(id, frame.data(), frame.is_remote_frame()).await
+        }
+        CanMessageType::NodeMode => {
+            echo_guard::mode_handler(id, frame.data(), frame.is_remote_frame()).await
+        }
         CanMessageType::Uptime => {
             device()
                 .await
@@ -144,6 +232,12 @@ pub async fn dispatch(frame: &EspTwaiFrame) {
                 .custom_string(id, frame.data(), frame.is_remote_frame())
                 .await;
         }
+        CanMessageType::DeviceGroup => {
+            device()
+                .await
+                .groups(id, frame.data(), frame.is_remote_frame())
+                .await
+        }
         CanMessageType::DeviceIdType => {
             let _ = device()
                 .await
@@ -172,18 +266,19 @@ pub async fn dispatch(frame: &EspTwaiFrame) {
                 )
                 .await;
         }
-        CanMessageType::Restart => {
+        CanMessageType::ApplicationVersionString => {
             device()
                 .await
-                .restart(id, frame.data(), frame.is_remote_frame())
+                .application_version(id, frame.data(), frame.is_remote_frame())
                 .await
         }
-        CanMessageType::FlashStart => {
-            update()
+        CanMessageType::Restart => {
+            device()
                 .await
-                .start(id, frame.data(), frame.is_remote_frame())
+                .restart(id, frame.data(), frame.is_remote_frame())
                 .await
         }
+        CanMessageType::FlashStart => update().await.start(id, msg).await,
         CanMessageType::FlashProgress => {
             update()
                 .await
@@ -205,13 +300,7 @@ pub async fn dispatch(frame: &EspTwaiFrame) {
         CanMessageType::FlashWrite => {
             update()
                 .await
-                .write(id, frame.data(), frame.is_remote_frame(), false)
-                .await
-        }
-        CanMessageType::FlashComplete => {
-            update()
-                .await
-                .write(id, frame.data(), frame.is_remote_frame(), true)
+                .write(id, frame.data(), frame.is_remote_frame())
                 .await
         }
         CanMessageType::FlashVerify => {
@@ -226,9 +315,38 @@ pub async fn dispatch(frame: &EspTwaiFrame) {
                 .erase(id, frame.data(), frame.is_remote_frame())
                 .await
         }
+        CanMessageType::FlashBlock => {
+            update()
+                .await
+                .block(id, frame.data(), frame.is_remote_frame())
+                .await
+        }
+        CanMessageType::FlashMissing => update().await.missing(msg).await,
+        CanMessageType::FlashComplete => {
+            update()
+                .await
+                .complete(id, frame.data(), frame.is_remote_frame())
+                .await
+        }
+        CanMessageType::FlashHeader => update().await.header(msg).await,
+        CanMessageType::UpdateConfirmWindow => {
+            update()
+                .await
+                .confirm_window(id, frame.data(), frame.is_remote_frame())
+                .await
+        }
         CanMessageType::UpdateSilence => silence(frame).await,
         CanMessageType::Ping => ping(id).await,
-        CanMessageType::Available => ping(id).await,
+        CanMessageType::PingDisable => echo_guard::suspend_handler(msg).await,
+        CanMessageType::Echo => echo_guard::echo(),
+        // the gateway asking whether we are there
+        CanMessageType::Available if !frame.is_remote_frame() => ping(id).await,
+        CanMessageType::Available => {
+            device()
+                .await
+                .announce(id, frame.data(), frame.is_remote_frame())
+                .await
+        }
         _ => unknown_handler(frame).await,
     }
 }
@@ -264,6 +382,10 @@ pub async fn send_can_message(msg_id: CanMessageType, data: &[u8], rtr: bool) {
     CAN_CHANNEL.send(frame).await
 }
 
+pub async fn send_message(msg: &CanMessage) {
+    send_can_message(msg.msg_type(), &msg.encode(), msg.is_request()).await
+}
+
 #[embassy_executor::task]
 pub async fn can_recieve_task(mut rx: twai::TwaiRx<'static, Async>) {
     println!("can_recieve_task started");
@@ -1,3 +1,6 @@
+use cancomponents_core::binding::Binding;
+use cancomponents_core::rollershutter::TravelTimes;
+use cancomponents_core::scene::Scene;
 use core::ops::Range;
 use core::result::Result;
 use embassy_embedded_hal::adapter::BlockingAsync;
@@ -11,6 +14,16 @@ use sequential_storage::map::{fetch_item, store_item};
 
 pub const CONFIG_PARTITION: Range<u32> = 0x9000..0xFC000;
 
+/// Travel times of the rollershutters are stored under this key plus
+/// their number.
+const TRAVEL_KEY: u8 = 48;
+
+/// Scenes are stored under this key plus their number, past `Key`.
+const SCENE_KEY: u8 = 64;
+
+/// Bindings are stored under this key plus their slot.
+const BINDING_KEY: u8 = 96;
+
 pub static CONFIG: Mutex<CriticalSectionRawMutex, Option<Config>> = Mutex::new(None);
 
 #[derive(Copy, Clone, IntoPrimitive, TryFromPrimitive)]
@@ -23,6 +36,19 @@ pub enum Key {
     CustomString = 5,
     Baudrate = 6,
     HardwareRevision = 7,
+    BootAttempts = 8,
+    ConfirmWindow = 9,
+    Groups = 10,
+    AutonomousPolicy = 11,
+    EchoInterval = 12,
+    EchoMisses = 13,
+    MetadataName = 14,
+    MetadataRoom = 15,
+    MetadataFloor = 16,
+    MetadataNotes = 17,
+    RelaisStates = 18,
+    PowerOn = 19,
+    DeadTime = 20,
 }
 
 pub async fn init() {
@@ -90,6 +116,95 @@ impl Config {
         .map_err(|_| ())
     }
 
+    pub async fn get_scene(&mut self, num: u8) -> Option<Scene> {
+        let raw = fetch_item::<u8, &[u8], _>(
+            &mut self.flash,
+            CONFIG_PARTITION.clone(),
+            &mut self.cache,
+            &mut self.buffer,
+            &(SCENE_KEY + num),
+        )
+        .await
+        .ok()
+        .flatten()?;
+        Scene::parse(raw).ok()
+    }
+
+    pub async fn set_scene(&mut self, scene: &Scene) -> Result<(), ()> {
+        store_item(
+            &mut self.flash,
+            CONFIG_PARTITION.clone(),
+            &mut self.cache,
+            &mut self.buffer,
+            &(SCENE_KEY + scene.num),
+            &scene.to_bytes().as_slice(),
+        )
+        .await
+        .map_err(|_| ())
+    }
+
+    /// Not calibrated unless stored.
+    pub async fn get_travel(&mut self, num: u8) -> TravelTimes {
+        fetch_item::<u8, u32, _>(
+            &mut self.flash,
+            CONFIG_PARTITION.clone(),
+            &mut self.cache,
+            &mut self.buffer,
+            &(TRAVEL_KEY + num),
+        )
+        .await
+        .ok()
+        .flatten()
+        .map(TravelTimes::from_u32)
+        .unwrap_or_default()
+    }
+
+    pub async fn set_travel(&mut self, num: u8, travel: TravelTimes) -> Result<(), ()> {
+        store_item(
+            &mut self.flash,
+            CONFIG_PARTITION.clone(),
+            &mut self.cache,
+            &mut self.buffer,
+            &(TRAVEL_KEY + num),
+            &travel.to_u32(),
+        )
+        .await
+        .map_err(|_| ())
+    }
+
+    pub async fn get_binding(&mut self, slot: u8) -> Option<Binding> {
+        let raw = fetch_item::<u8, &[u8], _>(
+            &mut self.flash,
+            CONFIG_PARTITION.clone(),
+            &mut self.cache,
+            &mut self.buffer,
+            &(BINDING_KEY + slot),
+        )
+        .await
+        .ok()
+        .flatten()?;
+        Binding::parse(raw).ok()
+    }
+
+    /// `None` clears the slot.
+    pub async fn set_binding(&mut self, slot: u8, binding: Option<&Binding>) -> Result<(), ()> {
+        let bytes = binding.map(Binding::to_bytes);
+        let value: &[u8] = match &bytes {
+            Some(bytes) => bytes,
+            None => &[],
+        };
+        store_item(
+            &mut self.flash,
+            CONFIG_PARTITION.clone(),
+            &mut self.cache,
+            &mut self.buffer,
+            &(BINDING_KEY + slot),
+            &value,
+        )
+        .await
+        .map_err(|_| ())
+    }
+
     /// Hole z.B. eine u32 (z. B. Counter etc.)
     pub async fn get_u32(&mut self, key: Key) -> Option<u32> {
         fetch_item::<u8, u32, _>(
@@ -117,6 +232,32 @@ impl Config {
         .map_err(|_| ())
     }
 
+    pub async fn get_u64(&mut self, key: Key) -> Option<u64> {
+        fetch_item::<u8, u64, _>(
+            &mut self.flash,
+            CONFIG_PARTITION.clone(),
+            &mut self.cache,
+            &mut self.buffer,
+            &(key as u8),
+        )
+        .await
+        .ok()
+        .flatten()
+    }
+
+    pub async fn set_u64(&mut self, key: Key, value: u64) -> Result<(), ()> {
+        store_item(
+            &mut self.flash,
+            CONFIG_PARTITION.clone(),
+            &mut self.cache,
+            &mut self.buffer,
+            &(key as u8),
+            &value,
+        )
+        .await
+        .map_err(|_| ())
+    }
+
     pub async fn get_u8(&mut self, key: Key) -> Option<u8> {
         fetch_item::<u8, u8, _>(
             &mut self.flash,
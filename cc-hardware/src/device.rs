@@ -1,19 +1,36 @@
 use esp_println::println;
 
-use crate::can::{send_can_message, DEVICE_ID, DEVICE_TYPE};
+use crate::button::BUTTON_COUNT;
+use crate::can::{send_can_message, send_message, DEVICE_ID, DEVICE_TYPE, GROUPS};
 use crate::config::{self, config};
-use crate::error::{Component, ErrorCode, ErrorReport, Severity};
+use crate::error::{self, Component, ErrorCode, Severity};
+use crate::metadata;
+use crate::relais::MAX_RELAIS;
+use crate::update::running_header;
+use cancomponents_core::announcement::{Announcement, Capabilities, Channels};
 use cancomponents_core::can_id::CanId;
 use cancomponents_core::can_message_type::CanMessageType;
-use cancomponents_core::device_message::IdTypeMsg;
+use cancomponents_core::device_message::{IdTypeMsg, UNASSIGNED_ID};
+use cancomponents_core::device_type::DeviceType;
+use cancomponents_core::extension::Extension;
+use cancomponents_core::group::Groups;
+use cancomponents_core::image::PROTOCOL_VERSION;
+use cancomponents_core::message::CanMessage;
+use cancomponents_core::relais_message::RelaisMode;
+use embassy_executor::Spawner;
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_sync::mutex::Mutex;
-use embassy_time::Instant;
+use embassy_time::{Duration, Instant, Timer};
 use esp_hal::efuse::Efuse;
 use heapless::String;
 
 static DEVICE: Mutex<CriticalSectionRawMutex, Option<Device>> = Mutex::new(None);
 
+/// `AddressClaim` is repeated every interval plus up to the jitter, so
+/// nodes powered up together spread their claims.
+const CLAIM_INTERVAL: Duration = Duration::from_secs(5);
+const CLAIM_JITTER_MS: u64 = 2_000;
+
 pub async fn init() {
     let mut device_guard = DEVICE.lock().await;
 
@@ -32,9 +49,11 @@ pub async fn init() {
             uid1: 0,
             mac,
             boot_time: Instant::now(),
+            conflict_reported: false,
         };
         *DEVICE_ID.lock().await = device.id;
         *DEVICE_TYPE.lock().await = device.dtype;
+        *GROUPS.lock().await = Groups(config.get_u64(config::Key::Groups).await.unwrap_or(0));
         *device_guard = Some(device);
     }
 }
@@ -44,6 +63,34 @@ pub async fn device(
     let guard = DEVICE.lock().await;
     embassy_sync::mutex::MutexGuard::map(guard, |opt| opt.as_mut().expect("Device not initialized"))
 }
+
+/// Without an address, announce our MAC until the gateway assigns one.
+pub async fn claim(spawner: &Spawner) {
+    let device = device().await;
+    if device.id == UNASSIGNED_ID {
+        spawner.spawn(claim_task(device.mac)).unwrap();
+    }
+}
+
+#[embassy_executor::task]
+async fn claim_task(mac: u64) {
+    println!("no address, claiming");
+    let mut seed = mac ^ Instant::now().as_ticks() | 1;
+    let mut delay = Duration::from_millis(0);
+    loop {
+        // xorshift, the MAC makes the sequence differ between nodes
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        Timer::after(delay + Duration::from_millis(seed % CLAIM_JITTER_MS)).await;
+        if *DEVICE_ID.lock().await != UNASSIGNED_ID {
+            return;
+        }
+        send_message(&CanMessage::AddressClaim(mac)).await;
+        delay = CLAIM_INTERVAL;
+    }
+}
+
 pub struct Device {
     custom_string: String<8>,
     id: u8,
@@ -52,6 +99,8 @@ pub struct Device {
     uid1: u64,
     mac: u64,
     boot_time: Instant,
+    /// reported once, the other node answers our report with its own
+    conflict_reported: bool,
 }
 
 impl Device {
@@ -72,6 +121,7 @@ impl Device {
         self.uid0(id, data, true).await;
         self.uid1(id, data, true).await;
         self.custom_string(id, data, true).await;
+        metadata::send_all().await;
         let mut return_id = id;
         return_id.msg_type = CanMessageType::HwRev;
         self.u8_val(return_id, data, true, config::Key::HardwareRevision)
@@ -84,11 +134,21 @@ impl Device {
             .await;
         self.application_version(id, data, true).await;
     }
+    /// Take a new id and type if the message carries our UID, or carries
+    /// none and the gateway selected us with `DeviceUid0` and `DeviceUid1`
+    /// before. A node that claimed an address restarts to listen on it,
+    /// otherwise the filter follows after the next restart.
     pub async fn id_type(&mut self, _id: CanId, data: &[u8], _remote_request: bool) -> Option<()> {
-        //temporary disabled because gateway issues
-        //if self.uid0 == self.mac && self.uid1 == self.mac {
-        println!("set id and type");
-        let (id, dtype) = IdTypeMsg::parse(data)?;
+        let (id, dtype, uid) = IdTypeMsg::parse(data).ok()?;
+        let selected = match uid {
+            Some(uid) => uid == self.mac,
+            None => self.uid0 == self.mac && self.uid1 == self.mac,
+        };
+        if !selected {
+            return None;
+        }
+        println!("set id {id} and type {dtype}");
+        let claimed = self.id == UNASSIGNED_ID;
         self.id = id;
         self.dtype = dtype;
 
@@ -97,17 +157,52 @@ impl Device {
         config.set_u8(config::Key::DeviceType, dtype).await.ok()?;
         *DEVICE_ID.lock().await = id;
         *DEVICE_TYPE.lock().await = dtype;
+        if claimed {
+            Timer::after(Duration::from_millis(100)).await;
+            esp_hal::system::software_reset();
+        }
         Some(())
-        //}
+    }
+
+    /// Another node sent from our address. Report it once, with the lower
+    /// bytes of our MAC so the gateway can tell the nodes apart.
+    pub async fn address_conflict(&mut self, id: CanId) {
+        println!("address conflict: {id}");
+        if self.conflict_reported {
+            return;
+        }
+        self.conflict_reported = true;
+        let mac = self.mac.to_be_bytes();
+        error::report(
+            Component::Device,
+            ErrorCode::AddressConflict,
+            Severity::RepeatingError,
+            id.msg_type as u8,
+            &[mac[5], mac[6], mac[7]],
+        )
+        .await;
+    }
+
+    /// Remote request: the configured groups. Data: replace them, the
+    /// acceptance filter picks them up after a restart.
+    pub async fn groups(&mut self, _id: CanId, data: &[u8], remote_request: bool) {
+        let mut config = config().await;
+        if remote_request {
+            let groups = Groups(config.get_u64(config::Key::Groups).await.unwrap_or(0));
+            drop(config);
+            send_message(&CanMessage::DeviceGroup(groups)).await;
+        } else if let Ok(bytes) = data.try_into() {
+            let _ = config
+                .set_u64(config::Key::Groups, u64::from_le_bytes(bytes))
+                .await;
+        }
     }
 
     pub async fn uid0(&mut self, _id: CanId, data: &[u8], remote_request: bool) {
         if remote_request {
             let txdata = self.mac.to_le_bytes();
             send_can_message(CanMessageType::DeviceUid0, &txdata, false).await;
-        } else {
-            let mut buf = [0u8; 8];
-            buf.copy_from_slice(data);
+        } else if let Ok(buf) = <[u8; 8]>::try_from(data) {
             self.uid0 = u64::from_le_bytes(buf);
             println!("set uid0 to {}", self.uid0);
         }
@@ -117,9 +212,7 @@ impl Device {
         if remote_request {
             let txdata = self.mac.to_le_bytes();
             send_can_message(CanMessageType::DeviceUid1, &txdata, false).await;
-        } else {
-            let mut buf = [0u8; 8];
-            buf.copy_from_slice(data);
+        } else if let Ok(buf) = <[u8; 8]>::try_from(data) {
             self.uid1 = u64::from_le_bytes(buf);
             println!("set uid1 to {}", self.uid1);
         }
@@ -141,7 +234,7 @@ impl Device {
             let mut config = config().await;
             config.set_u8(key, data[0]).await.ok()?;
         } else {
-            ErrorReport::send(
+            error::report(
                 Component::Device,
                 ErrorCode::InvalidData,
                 Severity::Warning,
@@ -181,7 +274,10 @@ impl Device {
         Some(())
     }
 
-    pub async fn application_version(&mut self, _id: CanId, _data: &[u8], _remote_request: bool) {
+    pub async fn application_version(&mut self, _id: CanId, _data: &[u8], remote_request: bool) {
+        if !remote_request {
+            return;
+        }
         let version = env!("VERGEN_GIT_DESCRIBE");
         let version_bytes = version.as_bytes();
 
@@ -191,6 +287,63 @@ impl Device {
 
         send_can_message(CanMessageType::ApplicationVersionString, &buf, false).await;
     }
+    /// Remote request or boot: who we are, followed by our channels.
+    pub async fn announce(&mut self, _id: CanId, _data: &[u8], remote_request: bool) {
+        if !remote_request {
+            return;
+        }
+        let mut config = config().await;
+        let hwrev = config
+            .get_u8(config::Key::HardwareRevision)
+            .await
+            .unwrap_or(0);
+        let extension = config
+            .get_u8(config::Key::ExtensionMode)
+            .await
+            .map(Extension::from)
+            .unwrap_or(Extension::Off);
+        let relais_mode = config
+            .get_u8(config::Key::RelaisMode)
+            .await
+            .and_then(|v| RelaisMode::try_from(v).ok())
+            .unwrap_or(RelaisMode::Relais);
+        drop(config);
+
+        let mut capabilities = Capabilities::SIGNED_UPDATE
+            | Capabilities::COMPRESSED_UPDATE
+            | Capabilities::MULTICAST_UPDATE
+            | Capabilities::ADDRESS_CLAIM;
+        let mut channels = Channels::default();
+        match DeviceType::from(self.dtype) {
+            DeviceType::Relais => match relais_mode {
+                RelaisMode::Relais => {
+                    capabilities = capabilities | Capabilities::RELAIS;
+                    channels.relais = MAX_RELAIS as u8;
+                }
+                _ => {
+                    capabilities = capabilities | Capabilities::ROLLERSHUTTER;
+                    channels.rollershutters = (MAX_RELAIS / 2) as u8;
+                }
+            },
+            DeviceType::Button => {
+                capabilities = capabilities | Capabilities::BUTTONS;
+                channels.buttons = BUTTON_COUNT as u8;
+            }
+            _ => {}
+        }
+
+        let announcement = Announcement {
+            protocol: PROTOCOL_VERSION,
+            device_type: self.dtype,
+            hwrev,
+            version: running_header().version,
+            extension,
+            capabilities,
+        };
+        send_message(&CanMessage::Available(Some(announcement))).await;
+        send_message(&CanMessage::Channels(channels)).await;
+    }
+
     pub async fn restart(&mut self, _id: CanId, data: &[u8], remote_request: bool) {
         if !remote_request && data.len() == 1 && data[0] == 1 {
             esp_hal::system::software_reset();
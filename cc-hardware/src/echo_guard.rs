@@ -1,36 +1,171 @@
+use crate::can::send_message;
+use crate::config::{self, config};
+use crate::relais;
+use cancomponents_core::can_id::CanId;
+use cancomponents_core::message::CanMessage;
+use cancomponents_core::supervisor::{Mode, ModeReport, Policy, Supervision, Supervisor};
 use embassy_executor::Spawner;
 use embassy_futures::select::{select, Either};
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_sync::channel::Channel;
-use embassy_time::{Duration, Timer};
+use embassy_sync::mutex::Mutex;
+use embassy_time::{Duration, Instant, Timer};
 use esp_println::println;
 
-pub static ECHO_CHANNEL: Channel<CriticalSectionRawMutex, bool, 2> = Channel::new();
+/// Input of the guard task.
+pub enum Liveness {
+    Echo,
+    /// `PingDisable`, zero resumes
+    Suspend(Duration),
+    Configure(Supervision),
+}
+
+pub static ECHO_CHANNEL: Channel<CriticalSectionRawMutex, Liveness, 2> = Channel::new();
+
+/// Current mode and since when.
+static MODE: Mutex<CriticalSectionRawMutex, (Mode, Instant)> =
+    Mutex::new((Mode::Online, Instant::from_ticks(0)));
 
 pub async fn init(spawner: &Spawner) {
     spawner.spawn(echo_guard_task()).unwrap();
 }
 
+pub async fn mode() -> Mode {
+    MODE.lock().await.0
+}
+
+pub async fn policy() -> Policy {
+    let byte = config().await.get_u8(config::Key::AutonomousPolicy).await;
+    Policy::from_byte(byte.unwrap_or(0))
+}
+
+async fn supervision() -> Supervision {
+    let mut config = config().await;
+    let default = Supervision::default();
+    let interval = config.get_u32(config::Key::EchoInterval).await;
+    Supervision {
+        interval: interval
+            .filter(|secs| *secs > 0)
+            .map_or(default.interval, |secs| Duration::from_secs(secs as u64)),
+        max_misses: config
+            .get_u8(config::Key::EchoMisses)
+            .await
+            .unwrap_or(default.max_misses),
+    }
+}
+
+/// `Echo` of the gateway, dropped when the guard has some queued already.
+pub fn echo() {
+    let _ = ECHO_CHANNEL.try_send(Liveness::Echo);
+}
+
+pub async fn suspend_handler(msg: CanMessage) {
+    if let CanMessage::PingDisable(duration) = msg {
+        ECHO_CHANNEL.send(Liveness::Suspend(duration)).await;
+    }
+}
+
+/// Data: store the policy. Remote request: send it, the default if none
+/// is stored.
+pub async fn policy_handler(msg: CanMessage) {
+    match msg {
+        CanMessage::AutonomousPolicy(policy) => {
+            let _ = config()
+                .await
+                .set_u8(config::Key::AutonomousPolicy, policy.to_byte())
+                .await;
+        }
+        CanMessage::Request(_) => send_message(&CanMessage::AutonomousPolicy(policy().await)).await,
+        _ => {}
+    }
+}
+
+/// Data: store interval and misses, used right away. Remote request: send
+/// them.
+pub async fn supervision_handler(msg: CanMessage) {
+    match msg {
+        CanMessage::Supervision(new) => {
+            let mut config = config().await;
+            let secs = new.interval.as_secs() as u32;
+            if config
+                .set_u32(config::Key::EchoInterval, secs)
+                .await
+                .is_ok()
+                && config
+                    .set_u8(config::Key::EchoMisses, new.max_misses)
+                    .await
+                    .is_ok()
+            {
+                ECHO_CHANNEL.send(Liveness::Configure(new)).await;
+            }
+        }
+        CanMessage::Request(_) => send_message(&CanMessage::Supervision(supervision().await)).await,
+        _ => {}
+    }
+}
+
+/// Remote request: the current mode and how long the node is in it.
+pub async fn mode_handler(_id: CanId, _data: &[u8], remote_request: bool) {
+    if remote_request {
+        let (mode, since) = *MODE.lock().await;
+        send_report(mode, since).await;
+    }
+}
+
+async fn send_report(mode: Mode, since: Instant) {
+    let duration = since.elapsed().as_secs().min(u32::MAX as u64) as u32;
+    send_message(&CanMessage::NodeMode(ModeReport { mode, duration })).await;
+}
+
+/// Without the gateway the node keeps running on its own, see `Policy`.
+/// A reset would switch every relais off.
 #[embassy_executor::task]
 pub async fn echo_guard_task() {
     println!("echo_guard_task started");
-    let mut miss_count = 0;
+    let mut supervisor = Supervisor::new(supervision().await);
+    // end of a maintenance window
+    let mut resume_at: Option<Instant> = None;
     loop {
-        let delay = Timer::after(Duration::from_secs(40));
+        let delay = match resume_at {
+            Some(at) => Timer::at(at),
+            None => Timer::after(supervisor.interval()),
+        };
         let echo_recv = ECHO_CHANNEL.receive();
-        match select(echo_recv, delay).await {
-            Either::First(_) => {
-                miss_count = 0;
-                Timer::after(Duration::from_secs(40)).await;
-            }
-            Either::Second(_) => {
-                miss_count += 1;
-                if miss_count > 3 {
-                    println!("Echos missed, restarting");
-                    Timer::after(Duration::from_secs(2)).await;
-                    esp_hal::system::software_reset();
+        let change = match select(echo_recv, delay).await {
+            Either::First(Liveness::Echo) => supervisor.seen(),
+            Either::First(Liveness::Suspend(duration)) => {
+                if duration.as_ticks() == 0 {
+                    resume_at = None;
+                    supervisor.resume();
+                } else {
+                    println!("supervision suspended for {} s", duration.as_secs());
+                    resume_at = Some(Instant::now() + duration);
+                    supervisor.suspend();
                 }
+                None
+            }
+            Either::First(Liveness::Configure(new)) => {
+                supervisor.configure(new);
+                None
+            }
+            Either::Second(_) if resume_at.take().is_some() => {
+                supervisor.resume();
+                None
+            }
+            Either::Second(_) => supervisor.missed(),
+        };
+        let Some(mode) = change else {
+            continue;
+        };
+        let since = core::mem::replace(&mut *MODE.lock().await, (mode, Instant::now())).1;
+        match mode {
+            Mode::Autonomous => {
+                println!("gateway missing, autonomous");
+                relais::autonomous(policy().await);
             }
+            Mode::Online => println!("gateway back"),
         }
+        // reaches the gateway once it is back, with the time without it
+        send_report(mode, since).await;
     }
 }
@@ -1,4 +1,6 @@
 #![no_std]
+pub mod binding;
+pub mod boot_guard;
 pub mod button;
 pub mod can;
 pub mod config;
@@ -6,6 +8,8 @@ pub mod device;
 pub mod echo_guard;
 pub mod error;
 pub mod gpio_interrupt;
+pub mod metadata;
 pub mod relais;
 pub mod relais_manager;
+pub mod transport;
 pub mod update;
@@ -0,0 +1,58 @@
+use crate::config::{self, config};
+use crate::error::{self, Component, ErrorCode, Severity};
+use crate::transport;
+use cancomponents_core::can_id::CanId;
+use cancomponents_core::can_message_type::CanMessageType;
+use cancomponents_core::metadata::{Entry, Field, MAX_VALUE_LEN};
+
+fn key(field: Field) -> config::Key {
+    match field {
+        Field::Name => config::Key::MetadataName,
+        Field::Room => config::Key::MetadataRoom,
+        Field::Floor => config::Key::MetadataFloor,
+        Field::Notes => config::Key::MetadataNotes,
+    }
+}
+
+/// Data: one field that fits a frame. Remote request: send all fields.
+pub async fn handler(_id: CanId, data: &[u8], remote_request: bool) {
+    match remote_request {
+        true => send_all().await,
+        false => set(data).await,
+    }
+}
+
+/// Every field, empty ones too, each as its own transfer.
+pub async fn send_all() {
+    for field in Field::ALL {
+        let value = config()
+            .await
+            .get_str::<MAX_VALUE_LEN>(key(field))
+            .await
+            .unwrap_or_default();
+        let entry = Entry { field, value };
+        transport::send(CanMessageType::Metadata, &entry.to_bytes()).await;
+    }
+}
+
+/// Store a field, values that are too long or no UTF-8 are reported.
+pub async fn set(data: &[u8]) {
+    let entry = match Entry::parse(data) {
+        Ok(entry) => entry,
+        Err(e) => {
+            return error::report(
+                Component::Device,
+                ErrorCode::InvalidData,
+                Severity::Warning,
+                e.code(),
+                &[
+                    CanMessageType::Metadata as u8,
+                    data.first().copied().unwrap_or(0),
+                    e.detail(),
+                ],
+            )
+            .await;
+        }
+    };
+    let _ = config().await.set_str(key(entry.field), &entry.value).await;
+}
@@ -1,44 +1,168 @@
-use crate::can::send_can_message;
+use crate::can::{send_can_message, send_message};
 use crate::config::{self, config};
+use crate::error::{self, Component, ErrorCode, Severity};
 use crate::relais_manager::RelayManager;
-use cancomponents_core::can_id::CanId;
+use cancomponents_core::binding::Action;
 use cancomponents_core::can_message_type::CanMessageType;
-use cancomponents_core::relais_message::{RelaisMessage, RelaisState};
+use cancomponents_core::interlock::{self, Interlock, DEFAULT_DEAD_TIME};
+use cancomponents_core::message::CanMessage;
+use cancomponents_core::power_on::{pack_states, unpack_states, PowerOnPolicy};
+use cancomponents_core::relais_message::{RelaisMessage, RelaisMode, RelaisState};
+use cancomponents_core::rollershutter::{Calibration, Shutter, ShutterCommand, MAX_SHUTTERS};
+use cancomponents_core::scene::Scene;
+use cancomponents_core::supervisor::{Policy, ShutterSafeState};
 use embassy_executor::Spawner;
 use embassy_futures::select::{select, Either};
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_sync::channel::Channel;
-use embassy_time::{Instant, Timer};
+use embassy_time::{Duration, Instant, Timer};
 use esp_hal::gpio::interconnect::PeripheralOutput;
 use esp_hal::i2c::master::{Config, I2c};
 use esp_hal::Async;
-use num_enum::{IntoPrimitive, TryFromPrimitive};
 
-const MAX_RELAIS: usize = 16;
+/// Outputs of a relais node, two expanders of 8.
+pub const MAX_RELAIS: usize = 16;
 
-#[derive(Copy, Clone, IntoPrimitive, TryFromPrimitive)]
-#[repr(u8)]
-pub enum RelaisMode {
-    Off = 0,
-    Relais = 1,
-    SoftwareRollershutter = 2,
-    HardwareRollershutter = 3,
+/// Changed states are written to flash this long after the first change,
+/// a burst of switching costs one write.
+const PERSIST_DELAY: Duration = Duration::from_secs(5);
+
+static RELAIS_CHANNEL: Channel<CriticalSectionRawMutex, Command, MAX_RELAIS> = Channel::new();
+
+enum Command {
+    Relais(RelaisMessage),
+    Scene(Scene),
+    Store { scene: u8, delay: Duration },
+    Binding { num: usize, action: Action },
+    Autonomous(Policy),
+    Position { num: usize, percent: u8 },
+    Calibrate(Calibration),
+    DeadTime(Duration),
+    // position of every shutter
+    Report,
+}
+
+/// `Relais`, `Rollershutter` and requests for `RollershutterState`.
+pub async fn relais_handler(msg: CanMessage) {
+    let msg_type = msg.msg_type();
+    let command = match msg {
+        CanMessage::Relais(msg) | CanMessage::Rollershutter(ShutterCommand::Move(msg)) => {
+            if msg.num >= MAX_RELAIS {
+                return invalid_channel(msg_type, msg.num).await;
+            }
+            Command::Relais(msg)
+        }
+        CanMessage::Rollershutter(ShutterCommand::Position { num, percent }) => {
+            Command::Position { num, percent }
+        }
+        CanMessage::Request(CanMessageType::RollershutterState) => Command::Report,
+        _ => return,
+    };
+    RELAIS_CHANNEL.send(command).await;
+}
+
+/// Data: store the pause of reversals, used from the next stop. Remote
+/// request: send it.
+pub async fn dead_time_handler(msg: CanMessage) {
+    let mut config = config().await;
+    match msg {
+        CanMessage::DeadTime(dead_time) => {
+            let ms = dead_time.as_millis() as u32;
+            let _ = config.set_u32(config::Key::DeadTime, ms).await;
+            let _ = RELAIS_CHANNEL.try_send(Command::DeadTime(dead_time));
+        }
+        CanMessage::Request(_) => {
+            let dead_time = config.get_u32(config::Key::DeadTime).await;
+            drop(config);
+            let dead_time = dead_time
+                .map(|ms| Duration::from_millis(ms as u64))
+                .unwrap_or(DEFAULT_DEAD_TIME);
+            send_message(&CanMessage::DeadTime(dead_time)).await;
+        }
+        _ => {}
+    }
+}
+
+/// Data: store the travel times of a shutter, used right away. Remote
+/// request: send those of every shutter.
+pub async fn travel_handler(msg: CanMessage) {
+    match msg {
+        CanMessage::ShutterTravel(calibration) => {
+            let _ = config()
+                .await
+                .set_travel(calibration.num, calibration.travel)
+                .await;
+            let _ = RELAIS_CHANNEL.try_send(Command::Calibrate(calibration));
+        }
+        CanMessage::Request(_) => {
+            for num in 0..MAX_SHUTTERS as u8 {
+                let travel = config().await.get_travel(num).await;
+                send_message(&CanMessage::ShutterTravel(Calibration { num, travel })).await;
+            }
+        }
+        _ => {}
+    }
 }
 
-static RELAIS_CHANNEL: Channel<CriticalSectionRawMutex, RelaisMessage, MAX_RELAIS> = Channel::new();
+/// Switch for a binding, dropped on nodes without relais and for relais
+/// the node does not have.
+pub fn bound(num: usize, action: Action) {
+    if num >= MAX_RELAIS {
+        return;
+    }
+    let _ = RELAIS_CHANNEL.try_send(Command::Binding { num, action });
+}
 
-pub async fn relais_handler(_id: CanId, data: &[u8], _remote_request: bool) {
-    if let Ok(msg) = RelaisMessage::from_bytes(data).await {
-        RELAIS_CHANNEL.send(msg).await;
+/// Data: store the power-on policy, used from the next reset. Remote
+/// request: send it.
+pub async fn power_on_handler(msg: CanMessage) {
+    let mut config = config().await;
+    match msg {
+        CanMessage::PowerOn(policy) => {
+            let _ = config.set_u32(config::Key::PowerOn, policy.to_u32()).await;
+        }
+        CanMessage::Request(_) => {
+            let policy = config.get_u32(config::Key::PowerOn).await;
+            drop(config);
+            let policy = policy.map(PowerOnPolicy::from_u32).unwrap_or_default();
+            send_message(&CanMessage::PowerOn(policy)).await;
+        }
+        _ => {}
     }
-    // silent error, already reportet is relais_message
+}
+
+/// Gateway gone, move to the safe state of `policy`.
+pub fn autonomous(policy: Policy) {
+    let _ = RELAIS_CHANNEL.try_send(Command::Autonomous(policy));
+}
+
+/// Scenes are often recalled by broadcast, nodes without relais drop them
+/// instead of blocking dispatch on the full channel.
+pub async fn scene_handler(msg: CanMessage) {
+    let command = match msg {
+        CanMessage::SceneDefine(scene) => {
+            let _ = config().await.set_scene(&scene).await;
+            return;
+        }
+        CanMessage::SceneRecall(num) => match config().await.get_scene(num).await {
+            Some(scene) => Command::Scene(scene),
+            None => return,
+        },
+        CanMessage::SceneStore { scene, delay } => Command::Store { scene, delay },
+        _ => return,
+    };
+    let _ = RELAIS_CHANNEL.try_send(command);
 }
 
 pub struct Relais {
     i2c: I2c<'static, Async>,
     expanders: [u8; 2],
+    /// expanders changed since the last `flush`
+    dirty: [bool; 2],
     bank_addr: [u8; 2],
     relais_mode: RelaisMode,
+    /// between the requested and the driven state of each shutter
+    interlocks: [Interlock; MAX_SHUTTERS],
 }
 
 impl Relais {
@@ -49,12 +173,34 @@ impl Relais {
         bank_addr: [u8; 2],
         spawner: &Spawner,
     ) {
-        let relais_mode = config()
-            .await
+        let mut config = config().await;
+        let relais_mode = config
             .get_u8(config::Key::RelaisMode)
             .await
             .and_then(|v| RelaisMode::try_from(v).ok())
             .unwrap_or(RelaisMode::Relais);
+        let policy = config
+            .get_u32(config::Key::PowerOn)
+            .await
+            .map(PowerOnPolicy::from_u32)
+            .unwrap_or_default();
+        let stored = unpack_states(config.get_u32(config::Key::RelaisStates).await.unwrap_or(0));
+        let mut shutters = heapless::Vec::new();
+        if matches!(
+            relais_mode,
+            RelaisMode::SoftwareRollershutter | RelaisMode::HardwareRollershutter
+        ) {
+            for num in 0..MAX_SHUTTERS {
+                let travel = config.get_travel(num as u8).await;
+                let _ = shutters.push(Shutter::new(travel));
+            }
+        }
+        let dead_time = config
+            .get_u32(config::Key::DeadTime)
+            .await
+            .map(|ms| Duration::from_millis(ms as u64))
+            .unwrap_or(DEFAULT_DEAD_TIME);
+        drop(config);
 
         let mut i2c = I2c::new(i2c0, Config::default())
             .unwrap()
@@ -62,19 +208,51 @@ impl Relais {
             .with_scl(scl)
             .into_async();
 
-        i2c.write(bank_addr[0], &[0x3, 0x0]).ok();
-        i2c.write(bank_addr[1], &[0x3, 0x0]).ok();
-        i2c.write(bank_addr[0], &[0x1, 0x0]).ok();
-        i2c.write(bank_addr[1], &[0x1, 0x0]).ok();
+        // after a warm reset the expanders are still configured as outputs
+        // and drive what we set before, after power-up they are inputs
+        let mut kept = [None; 2];
+        for (expander, addr) in bank_addr.iter().enumerate() {
+            let (mut direction, mut output) = ([0xFF], [0]);
+            if i2c.write_read(*addr, &[0x3], &mut direction).is_ok()
+                && direction[0] == 0
+                && i2c.write_read(*addr, &[0x1], &mut output).is_ok()
+            {
+                kept[expander] = Some(output[0]);
+            }
+        }
 
-        let relais = Relais {
+        let mut relais = Relais {
             i2c,
             expanders: [0, 0],
+            dirty: [true, true],
             bank_addr,
             relais_mode,
+            interlocks: core::array::from_fn(|_| Interlock::new(dead_time)),
         };
 
-        spawner.spawn(relais_task(relais)).unwrap();
+        // shutters always start stopped
+        let mut initial: [RelaisState; MAX_RELAIS] = core::array::from_fn(|_| RelaisState::Off);
+        if relais_mode == RelaisMode::Relais {
+            for (num, state) in initial.iter_mut().enumerate() {
+                let (expander, bit) = Self::MAPPING[num];
+                let kept = kept[expander].map(|bits| match bits & (1 << bit) {
+                    0 => RelaisState::Off,
+                    _ => RelaisState::On,
+                });
+                *state = policy.initial(num, &stored[num], kept);
+                relais.set(num, state);
+            }
+        }
+
+        // outputs first, the expanders drive them once configured
+        relais.flush();
+        for addr in bank_addr {
+            relais.i2c.write(addr, &[0x3, 0x0]).ok();
+        }
+
+        spawner
+            .spawn(relais_task(relais, initial, shutters))
+            .unwrap();
     }
     /// Each entry: (expander index, bit position)
     const MAPPING: [(usize, u8); MAX_RELAIS] = [
@@ -96,41 +274,69 @@ impl Relais {
         (0, 0),
     ];
 
-    pub fn set(&mut self, num: usize, state: &RelaisState) {
+    /// Channels usable in the configured mode, two relais per shutter.
+    pub fn channels(&self) -> usize {
+        match self.relais_mode {
+            RelaisMode::Relais => MAX_RELAIS,
+            _ => MAX_RELAIS / 2,
+        }
+    }
+
+    /// Stage a state, `flush` writes it. Returns what the outputs drive,
+    /// `Off` for a shutter waiting out the dead time of a reversal.
+    pub fn set(&mut self, num: usize, state: &RelaisState) -> RelaisState {
         match self.relais_mode {
             RelaisMode::Relais => {
                 self.sethw(num, state);
+                state.clone()
             }
-            RelaisMode::SoftwareRollershutter => match state {
-                RelaisState::Up => {
-                    self.sethw(num * 2, &RelaisState::On);
-                    self.sethw(num * 2 + 1, &RelaisState::Off);
-                }
-                RelaisState::Down => {
-                    self.sethw(num * 2, &RelaisState::On);
-                    self.sethw(num * 2 + 1, &RelaisState::Off);
-                }
-                _ => {
-                    self.sethw(num * 2, &RelaisState::Off);
-                    self.sethw(num * 2 + 1, &RelaisState::Off);
-                }
-            },
-            RelaisMode::HardwareRollershutter => match state {
-                RelaisState::Up => {
-                    self.sethw(num * 2, &RelaisState::On);
-                    self.sethw(num * 2 + 1, &RelaisState::Off);
-                }
-                RelaisState::Down => {
-                    self.sethw(num * 2, &RelaisState::On);
-                    self.sethw(num * 2 + 1, &RelaisState::On);
-                }
-                _ => {
-                    self.sethw(num * 2, &RelaisState::Off);
-                    self.sethw(num * 2 + 1, &RelaisState::Off);
-                }
-            },
-            _ => {}
+            // reversals pause in between
+            RelaisMode::SoftwareRollershutter | RelaisMode::HardwareRollershutter => {
+                let Some(interlock) = self.interlocks.get_mut(num) else {
+                    return RelaisState::Off;
+                };
+                let output = interlock.request(state, Instant::now());
+                self.drive(num, &output);
+                output
+            }
+            _ => RelaisState::Off,
+        }
+    }
+
+    /// Shutters that started at `now` after the dead time, staged.
+    pub fn poll(&mut self, now: Instant) -> heapless::Vec<(usize, RelaisState), MAX_SHUTTERS> {
+        let started: heapless::Vec<(usize, RelaisState), MAX_SHUTTERS> = self
+            .interlocks
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(num, interlock)| interlock.poll(now).map(|output| (num, output)))
+            .collect();
+        for (num, output) in &started {
+            self.drive(*num, output);
         }
+        started
+    }
+
+    /// When `poll` has a direction to start.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.interlocks.iter().filter_map(Interlock::deadline).min()
+    }
+
+    pub fn set_dead_time(&mut self, dead_time: Duration) {
+        for interlock in self.interlocks.iter_mut() {
+            interlock.set_dead_time(dead_time);
+        }
+    }
+
+    /// Both relais of a shutter, never both directions at once.
+    fn drive(&mut self, num: usize, output: &RelaisState) {
+        let [first, second] = interlock::outputs(self.relais_mode, output);
+        let switch = |on| match on {
+            true => RelaisState::On,
+            false => RelaisState::Off,
+        };
+        self.sethw(num * 2, &switch(first));
+        self.sethw(num * 2 + 1, &switch(second));
     }
 
     fn sethw(&mut self, num: usize, state: &RelaisState) {
@@ -141,42 +347,274 @@ impl Relais {
             } else {
                 self.expanders[expander] &= !mask;
             }
-            self.i2c
-                .write(self.bank_addr[expander], &[0x1, self.expanders[expander]])
-                .ok();
+            self.dirty[expander] = true;
+        }
+    }
+
+    /// One write per expander, so everything staged switches together.
+    pub fn flush(&mut self) {
+        for expander in 0..self.expanders.len() {
+            if core::mem::take(&mut self.dirty[expander]) {
+                self.i2c
+                    .write(self.bank_addr[expander], &[0x1, self.expanders[expander]])
+                    .ok();
+            }
         }
     }
 }
 
 #[embassy_executor::task]
-async fn relais_task(mut relais: Relais) {
+async fn relais_task(
+    mut relais: Relais,
+    initial: [RelaisState; MAX_RELAIS],
+    // empty unless the node drives rollershutters
+    mut shutters: heapless::Vec<Shutter, MAX_SHUTTERS>,
+) {
     let mut manager: RelayManager<MAX_RELAIS> = RelayManager::new();
+    let now = Instant::now();
+    for (num, state) in initial.iter().enumerate() {
+        manager.apply_command(num, state, Duration::from_millis(0), now);
+    }
+    // recalled scene waiting for its delay
+    let mut pending: Option<(Instant, Scene)> = None;
+    let mut persisted = pack_states(&initial);
+    let mut persist_at: Option<Instant> = None;
 
     loop {
         let now = Instant::now();
 
+        // timed relays are stored as the state they fall back to
+        let steady: [RelaisState; MAX_RELAIS] = core::array::from_fn(|num| manager.steady(num));
+        let states = pack_states(&steady);
+        if states == persisted {
+            persist_at = None;
+        } else if *persist_at.get_or_insert(now + PERSIST_DELAY) <= now {
+            if config()
+                .await
+                .set_u32(config::Key::RelaisStates, states)
+                .await
+                .is_ok()
+            {
+                persisted = states;
+            }
+            persist_at = None;
+        }
+
         // 1. Abgelaufene Zeitsteuerungen
         for (num, state) in manager.poll_expired(now).into_iter() {
-            relais.set(num, &state);
-            let data: &[u8; 1] = &[state as u8];
+            let output = relais.set(num, &state);
+            relais.flush();
+            let data: &[u8; 1] = &[output as u8];
             send_can_message(CanMessageType::RelaisState, data, false).await;
+            track(&mut shutters, num, &state, now).await;
+        }
+        // directions that waited for the dead time of a reversal
+        let started = relais.poll(now);
+        if !started.is_empty() {
+            relais.flush();
+        }
+        for (num, output) in started {
+            send_can_message(CanMessageType::RelaisState, &[output as u8], false).await;
+            if let Some(shutter) = shutters.get_mut(num) {
+                shutter.started(now);
+            }
+        }
+        // shutters that reached their position or end stop
+        let due: heapless::Vec<(usize, RelaisState), MAX_SHUTTERS> = shutters
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(num, shutter)| shutter.poll(now).map(|state| (num, state)))
+            .collect();
+        for (num, state) in due {
+            drive(&mut relais, &mut manager, &shutters[num], num, state, now).await;
+        }
+        if let Some((_, scene)) = pending.take_if(|(at, _)| *at <= now) {
+            apply_scene(&mut relais, &mut manager, &mut shutters, &scene, now).await;
         }
 
         // 2. Warte auf nächsten Befehl oder nächstes Timeout
         let recv = RELAIS_CHANNEL.receive();
-        let delay = Timer::after(manager.next_timeout(now));
+        let mut timeout = manager.next_timeout(now);
+        if let Some((at, _)) = &pending {
+            timeout = timeout.min(at.saturating_duration_since(now));
+        }
+        if let Some(at) = persist_at {
+            timeout = timeout.min(at.saturating_duration_since(now));
+        }
+        if let Some(at) = relais.deadline() {
+            timeout = timeout.min(at.saturating_duration_since(now));
+        }
+        for at in shutters.iter().filter_map(Shutter::deadline) {
+            timeout = timeout.min(at.saturating_duration_since(now));
+        }
+        let delay = Timer::after(timeout);
+
+        let command = match select(recv, delay).await {
+            // toggles depend on the state when the event arrives
+            Either::First(Command::Binding { num, action }) if num < relais.channels() => {
+                let (state, duration) = action.resolve(&manager.current(num));
+                Either::First(Command::Relais(RelaisMessage {
+                    num,
+                    state,
+                    duration,
+                    bank: 0,
+                }))
+            }
+            other => other,
+        };
 
-        match select(recv, delay).await {
-            Either::First(msg) => {
+        match command {
+            // beyond the channels of the mode, e.g. the second relais of a shutter
+            Either::First(Command::Relais(msg)) if msg.num >= relais.channels() => {
+                invalid_channel(CanMessageType::Relais, msg.num).await
+            }
+            Either::First(Command::Relais(msg)) => {
                 let changed =
                     manager.apply_command(msg.num, &msg.state, msg.duration, Instant::now());
                 if changed {
-                    relais.set(msg.num, &msg.state);
-                    let data: &[u8; 1] = &[msg.state as u8];
+                    let output = relais.set(msg.num, &msg.state);
+                    relais.flush();
+                    let data: &[u8; 1] = &[output as u8];
                     send_can_message(CanMessageType::RelaisState, data, false).await;
+                    track(&mut shutters, msg.num, &msg.state, Instant::now()).await;
+                }
+            }
+            // a later recall replaces one still waiting
+            Either::First(Command::Scene(scene)) => {
+                pending = Some((Instant::now() + scene.delay, scene));
+            }
+            Either::First(Command::Store { scene, delay }) => {
+                let mut stored = Scene::new(scene);
+                stored.delay = delay;
+                for num in 0..relais.channels() {
+                    stored.states[num] = Some(manager.current(num));
+                }
+                if config().await.set_scene(&stored).await.is_ok() {
+                    send_message(&CanMessage::SceneDefine(stored)).await;
+                }
+            }
+            Either::First(Command::Autonomous(policy)) => {
+                let mut safe = Scene::new(0);
+                let target = match relais.relais_mode {
+                    RelaisMode::Relais if policy.relais_off => Some(RelaisState::Off),
+                    RelaisMode::Relais => None,
+                    _ => match policy.shutters {
+                        ShutterSafeState::Keep => None,
+                        ShutterSafeState::Stop => Some(RelaisState::Off),
+                        ShutterSafeState::Up => Some(RelaisState::Up),
+                        ShutterSafeState::Down => Some(RelaisState::Down),
+                    },
+                };
+                safe.states = core::array::from_fn(|_| target.clone());
+                apply_scene(
+                    &mut relais,
+                    &mut manager,
+                    &mut shutters,
+                    &safe,
+                    Instant::now(),
+                )
+                .await;
+            }
+            Either::First(Command::Position { num, percent }) => {
+                let now = Instant::now();
+                match shutters.get_mut(num).and_then(|s| s.go_to(percent, now)) {
+                    Some(state) => {
+                        drive(&mut relais, &mut manager, &shutters[num], num, state, now).await
+                    }
+                    // no shutter or not calibrated
+                    None => {
+                        error::report(
+                            Component::Relais,
+                            ErrorCode::InvalidData,
+                            Severity::Warning,
+                            0,
+                            &[CanMessageType::Rollershutter as u8, num as u8, percent],
+                        )
+                        .await
+                    }
+                }
+            }
+            Either::First(Command::Calibrate(calibration)) => {
+                let num = calibration.num as usize;
+                if let Some(shutter) = shutters.get_mut(num) {
+                    shutter.calibrate(calibration.travel, Instant::now());
                 }
             }
-            Either::Second(_) => {}
+            Either::First(Command::DeadTime(dead_time)) => relais.set_dead_time(dead_time),
+            Either::First(Command::Report) => {
+                let now = Instant::now();
+                for (num, shutter) in shutters.iter().enumerate() {
+                    report(shutter, num, now).await;
+                }
+            }
+            Either::First(Command::Binding { .. }) | Either::Second(_) => {}
         }
     }
 }
+
+async fn invalid_channel(msg_type: CanMessageType, num: usize) {
+    error::report(
+        Component::Relais,
+        ErrorCode::InvalidData,
+        Severity::Warning,
+        0,
+        &[msg_type as u8, num as u8],
+    )
+    .await
+}
+
+async fn apply_scene(
+    relais: &mut Relais,
+    manager: &mut RelayManager<MAX_RELAIS>,
+    shutters: &mut [Shutter],
+    scene: &Scene,
+    now: Instant,
+) {
+    let channels = relais.channels();
+    let mut changed: heapless::Vec<(usize, RelaisState, RelaisState), MAX_RELAIS> =
+        heapless::Vec::new();
+    for (num, state) in scene.targets().filter(|(num, _)| *num < channels) {
+        if manager.apply_command(num, state, Duration::from_millis(0), now) {
+            let output = relais.set(num, state);
+            let _ = changed.push((num, state.clone(), output));
+        }
+    }
+    relais.flush();
+    for (num, state, output) in changed {
+        send_can_message(CanMessageType::RelaisState, &[output as u8], false).await;
+        track(shutters, num, &state, now).await;
+    }
+}
+
+/// Switch a shutter to what its tracker decided.
+async fn drive(
+    relais: &mut Relais,
+    manager: &mut RelayManager<MAX_RELAIS>,
+    shutter: &Shutter,
+    num: usize,
+    state: RelaisState,
+    now: Instant,
+) {
+    if manager.apply_command(num, &state, Duration::from_millis(0), now) {
+        let output = relais.set(num, &state);
+        relais.flush();
+        send_can_message(CanMessageType::RelaisState, &[output as u8], false).await;
+    }
+    report(shutter, num, now).await;
+}
+
+/// Keep the position of shutter `num` in line with a switch its tracker
+/// did not decide.
+async fn track(shutters: &mut [Shutter], num: usize, state: &RelaisState, now: Instant) {
+    if let Some(shutter) = shutters.get_mut(num) {
+        shutter.set(state, now);
+        report(shutter, num, now).await;
+    }
+}
+
+async fn report(shutter: &Shutter, num: usize, now: Instant) {
+    send_message(&CanMessage::RollershutterState(
+        shutter.report(num as u8, now),
+    ))
+    .await;
+}
@@ -86,7 +86,10 @@ impl<const N: usize> RelayManager<N> {
                     scheduled: None,
                 };
                 relay.update(now, state.clone(), duration);
-                entry.insert(relay).unwrap();
+                // full, more relays than the map holds
+                if entry.insert(relay).is_err() {
+                    return false;
+                }
                 changed = true;
             }
         }
@@ -94,6 +97,25 @@ impl<const N: usize> RelayManager<N> {
         changed
     }
 
+    /// Last state set, `Off` for relays never switched.
+    pub fn current(&self, num: usize) -> RelaisState {
+        self.relays
+            .get(&num)
+            .map(|relay| relay.current.clone())
+            .unwrap_or(RelaisState::Off)
+    }
+
+    /// State the relay settles in, `Off` for one switched on for a time.
+    pub fn steady(&self, num: usize) -> RelaisState {
+        match self.relays.get(&num) {
+            Some(ActiveRelais {
+                scheduled: Some((_, state)),
+                ..
+            }) => state.clone(),
+            _ => self.current(num),
+        }
+    }
+
     pub fn poll_expired(&mut self, now: Instant) -> heapless::Vec<(usize, RelaisState), N> {
         let mut result = heapless::Vec::new();
         for (&num, relay) in self.relays.iter_mut() {
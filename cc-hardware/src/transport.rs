@@ -0,0 +1,117 @@
+use crate::can::{send_can_message, send_message};
+use crate::error::{self, Component, ErrorCode, Severity};
+use crate::metadata;
+use cancomponents_core::can_message_type::CanMessageType;
+use cancomponents_core::message::CanMessage;
+use cancomponents_core::transport::{FlowControl, Receiver, Segment, Sender, Step};
+use embassy_executor::Spawner;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_sync::mutex::Mutex;
+use embassy_time::{with_timeout, Duration, Timer};
+use esp_println::println;
+use heapless::Vec;
+
+/// Longest payload sent or received in one transfer.
+pub const MAX_TRANSFER: usize = 256;
+
+/// Wait this long for flow control of the gateway.
+const FLOW_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Queued transfers, enough for all metadata fields at once.
+static OUTGOING: Channel<CriticalSectionRawMutex, (CanMessageType, Vec<u8, MAX_TRANSFER>), 4> =
+    Channel::new();
+
+static FLOW: Channel<CriticalSectionRawMutex, FlowControl, 2> = Channel::new();
+
+/// Flow control after every 8 frames, the gateway sends as fast as it can.
+static RECEIVER: Mutex<CriticalSectionRawMutex, Receiver<MAX_TRANSFER>> =
+    Mutex::new(Receiver::new(8, Duration::from_millis(0)));
+
+pub async fn init(spawner: &Spawner) {
+    spawner.spawn(sender_task()).unwrap();
+}
+
+/// One frame if `data` fits, otherwise queued for the sender task. Never
+/// waits for the gateway, dispatch has to keep passing flow control on.
+pub async fn send(msg_type: CanMessageType, data: &[u8]) {
+    if data.len() <= 8 {
+        return send_can_message(msg_type, data, false).await;
+    }
+    let queued = Vec::from_slice(data)
+        .ok()
+        .and_then(|data| OUTGOING.try_send((msg_type, data)).ok());
+    if queued.is_none() {
+        report(msg_type, data.len()).await;
+    }
+}
+
+async fn report(msg_type: CanMessageType, len: usize) {
+    error::report(
+        Component::Can,
+        ErrorCode::InvalidData,
+        Severity::Warning,
+        0,
+        &[msg_type as u8, (len >> 8) as u8, len as u8],
+    )
+    .await;
+}
+
+/// `Segment` frames of the gateway.
+pub async fn handler(msg: CanMessage) {
+    let CanMessage::Segment(segment) = msg else {
+        return;
+    };
+    if let Segment::Flow(flow) = segment {
+        let _ = FLOW.try_send(flow);
+        return;
+    }
+    let mut receiver = RECEIVER.lock().await;
+    match receiver.feed(&segment) {
+        Ok(Step::Pending) => {}
+        Ok(Step::Flow(flow)) => send_message(&CanMessage::Segment(Segment::Flow(flow))).await,
+        Ok(Step::Complete(msg_type)) => {
+            let data: Vec<u8, MAX_TRANSFER> = Vec::from_slice(receiver.data()).unwrap();
+            drop(receiver);
+            complete(msg_type, &data).await;
+        }
+        Err(e) => {
+            println!("WARN: transfer {e:?}");
+            report(CanMessageType::Segment, 0).await;
+        }
+    }
+}
+
+async fn complete(msg_type: CanMessageType, data: &[u8]) {
+    match msg_type {
+        CanMessageType::Metadata => metadata::set(data).await,
+        _ => report(msg_type, data.len()).await,
+    }
+}
+
+#[embassy_executor::task]
+async fn sender_task() {
+    loop {
+        let (msg_type, data) = OUTGOING.receive().await;
+        let mut sender = Sender::new(msg_type, &data).unwrap();
+        // flow control left over from an aborted transfer
+        FLOW.clear();
+        loop {
+            while let Some(segment) = sender.next_segment() {
+                send_message(&CanMessage::Segment(segment)).await;
+                Timer::after(sender.separation()).await;
+            }
+            if sender.is_done() {
+                break;
+            }
+            let aborted = match with_timeout(FLOW_TIMEOUT, FLOW.receive()).await {
+                Ok(flow) => sender.flow(&flow).is_err(),
+                Err(_) => true,
+            };
+            if aborted {
+                report(msg_type, data.len()).await;
+                break;
+            }
+        }
+    }
+}
@@ -1,39 +1,48 @@
-use crate::error::{Component, ErrorCode, ErrorReport, Severity};
+use crate::boot_guard::DEFAULT_CONFIRM_WINDOW;
+use crate::can::send_message;
+use crate::config::{self, config};
+use crate::error::{self, Component, ErrorCode, Severity};
 use cancomponents_core::can_id::CanId;
+use cancomponents_core::image::{
+    ImageError, ImageHeader, VerifyingKey, HEADER_LEN, PUBLIC_KEY_LENGTH,
+};
+use cancomponents_core::message::{CanMessage, Payload};
+use cancomponents_core::ota::{
+    Accept, Chunk, Multicast, ReadRange, Session, UpdateErrorCode, BLOCK_SIZE, SLOT_COUNT,
+};
+use core::ops::Range;
 use embassy_executor::Spawner;
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_sync::mutex::Mutex;
-use esp_hal_ota::Ota;
+use embassy_time::Timer;
+use embedded_storage::nor_flash::NorFlash;
+use embedded_storage::ReadStorage;
+use esp_hal_ota::{Ota, OtaImgState};
 use esp_println::println;
 use esp_storage::FlashStorage;
-use heapless::Vec;
-use num_enum::FromPrimitive;
 
-// Buffer-Größe als Konstanto
-const OTA_BUFFER_SIZE: usize = 4096;
-static CURRENT_BUFFER: Mutex<CriticalSectionRawMutex, Vec<u8, OTA_BUFFER_SIZE>> =
-    Mutex::new(Vec::new());
+/// Images have to be signed with the matching private key.
+const UPDATE_KEY: &[u8; PUBLIC_KEY_LENGTH] =
+    include_bytes!(concat!(env!("OUT_DIR"), "/update.pub"));
+/// Header build.rs stamps into this image.
+const RUNNING_HEADER: &[u8; HEADER_LEN] =
+    include_bytes!(concat!(env!("OUT_DIR"), "/image_header.bin"));
+/// ota_0 and ota_1 from partitions.csv
+const OTA_SLOTS: [Range<u32>; SLOT_COUNT as usize] = [0x200000..0x300000, 0x300000..0x400000];
 static UPDATE: Mutex<CriticalSectionRawMutex, Option<Update>> = Mutex::new(None);
 static OTA: Mutex<CriticalSectionRawMutex, Option<Ota<FlashStorage>>> = Mutex::new(None);
 
-#[repr(u8)]
-#[derive(Debug, Copy, Clone, PartialEq, Eq, FromPrimitive)]
-pub enum UpdateErrorCode {
-    #[num_enum(default)]
-    Unknown = 0,
-    InvalidData = 1,
-    Begin = 2,
-    Init = 3,
-    Write = 4,
-    NotStarted = 5,
-    VerifyFailed = 6,
-}
-
 pub async fn init(_spawner: &Spawner) {
     let mut update_guard = UPDATE.lock().await;
 
     if update_guard.is_none() {
-        let update = Update {};
+        let update = Update {
+            session: None,
+            header: None,
+            compressed: false,
+            multicast: None,
+            verified: None,
+        };
         *update_guard = Some(update);
     }
 }
@@ -44,25 +53,70 @@ pub async fn update(
     embassy_sync::mutex::MutexGuard::map(guard, |opt| opt.as_mut().expect("Update not initialized"))
 }
 
-pub struct Update {}
+pub struct Update {
+    session: Option<Session>,
+    /// announced with `FlashHeader`, with the allow downgrade flag
+    header: Option<(ImageHeader, bool)>,
+    /// blocks of the announced image arrive compressed, `Session`
+    /// decompresses them into the block buffer
+    compressed: bool,
+    /// update sent to all nodes of our type
+    multicast: Option<Multicast>,
+    /// slot holding a multicast image whose signature was checked
+    verified: Option<usize>,
+}
+
+pub fn running_header() -> ImageHeader {
+    ImageHeader::parse_stamp(RUNNING_HEADER).expect("build.rs stamps a valid header")
+}
 
 impl Update {
-    pub async fn start(&mut self, id: CanId, data: &[u8], _remote_request: bool) {
-        if data.len() < 8 {
-            ErrorReport::send(
+    /// Remote request: answer CRC and size of the running update, zero
+    /// without one. Data: begin an update, or resume the running one at the
+    /// last written block if CRC and size are the same.
+    pub async fn start(&mut self, id: CanId, msg: CanMessage) {
+        let (crc, size) = match msg {
+            CanMessage::FlashStart { crc, size } => (crc, size),
+            CanMessage::Request(_) => {
+                let (crc, size) = match (&self.session, &self.multicast) {
+                    (Some(session), _) => (session.crc, session.size),
+                    (None, Some(multicast)) => (multicast.crc, multicast.size),
+                    (None, None) => (0, 0),
+                };
+                send_message(&CanMessage::FlashStart { crc, size }).await;
+                return;
+            }
+            _ => return,
+        };
+        println!("start update: crc {crc} size {size}");
+
+        if let Err(code) = self.check_header().await {
+            println!("image rejected: {code:?}");
+            error::report(
                 Component::Update,
                 ErrorCode::InvalidData,
                 Severity::Warning,
-                UpdateErrorCode::InvalidData as u8,
-                &[id.msg_type as u8, data.len() as u8, 0u8],
+                code as u8,
+                &[0u8, 0u8, 0u8],
             )
             .await;
             return;
         }
+        // the pending slot is about to be overwritten
+        self.verified = None;
+        if id.device_id == 0 {
+            return self.start_multicast(size, crc).await;
+        }
+        self.multicast = None;
 
-        let size = u32::from_be_bytes(data[4..8].try_into().unwrap());
-        let crc = u32::from_be_bytes(data[0..4].try_into().unwrap());
-        println!("start update: crc {crc} size {size}");
+        if let Some(session) = self.session.as_mut() {
+            if session.is_resumable(size, crc) && OTA.lock().await.is_some() {
+                session.set_compressed(self.compressed);
+                println!("resume update: {:?}", session.progress());
+                send_message(&session.progress()).await;
+                return;
+            }
+        }
 
         match Ota::new(FlashStorage::new()) {
             Ok(mut ota) => {
@@ -70,9 +124,14 @@ impl Update {
                     let next_ota = ota.get_next_ota_partition();
                     println!("next ota part: {next_ota:?}");
                     *OTA.lock().await = Some(ota);
+                    let mut session = Session::new(size, crc);
+                    session.set_compressed(self.compressed);
+                    // ready for the first block
+                    send_message(&session.progress()).await;
+                    self.session = Some(session);
                 } else {
                     // ota_begin fehlgeschlagen
-                    ErrorReport::send(
+                    error::report(
                         Component::Ota,
                         ErrorCode::Unknown,
                         Severity::RecoverableError,
@@ -84,7 +143,7 @@ impl Update {
                 }
             }
             Err(_) => {
-                ErrorReport::send(
+                error::report(
                     Component::Ota,
                     ErrorCode::Unknown,
                     Severity::RecoverableError,
@@ -97,72 +156,512 @@ impl Update {
             }
         }
     }
-    pub async fn write(
-        &mut self,
-        _id: CanId,
-        data: &[u8],
-        _remote_request: bool,
-        force_flush: bool,
-    ) {
-        let mut buffer = CURRENT_BUFFER.lock().await;
-        let should_flush = {
-            if buffer.extend_from_slice(data).is_err() {
-                true // Buffer voll -> sofort flushen
-            } else {
-                force_flush || buffer.len() == OTA_BUFFER_SIZE
+    /// Remote request: answer the header of the running image. Data:
+    /// announce the header of the next image, checked by `start`.
+    pub async fn header(&mut self, msg: CanMessage) {
+        if msg.is_request() {
+            send_message(&CanMessage::FlashHeader {
+                header: running_header(),
+                allow_downgrade: false,
+                compressed: false,
+            })
+            .await;
+            return;
+        }
+        if let CanMessage::FlashHeader {
+            header,
+            allow_downgrade,
+            compressed,
+        } = msg
+        {
+            self.header = Some((header, allow_downgrade));
+            self.compressed = compressed;
+        }
+    }
+
+    async fn check_header(&self) -> Result<(), UpdateErrorCode> {
+        let (header, allow_downgrade) = self.header.ok_or(UpdateErrorCode::NoHeader)?;
+        let mut config = config().await;
+        let device_type = config.get_u8(config::Key::DeviceType).await.unwrap_or(0);
+        let hwrev = config
+            .get_u8(config::Key::HardwareRevision)
+            .await
+            .unwrap_or(0);
+        header.check(
+            device_type,
+            hwrev,
+            running_header().version,
+            allow_downgrade,
+        )
+    }
+
+    /// Collect a `FlashWrite` chunk. Every full block is written to flash
+    /// and acknowledged with `FlashProgress`; a missing chunk is answered
+    /// the same way right away so the host resends from there.
+    pub async fn write(&mut self, id: CanId, data: &[u8], _remote_request: bool) {
+        if self.multicast.is_some() {
+            return self.write_multicast(id, data).await;
+        }
+        let Some(session) = self.session.as_mut() else {
+            return;
+        };
+        let chunk = match Chunk::parse(data) {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                error::report(
+                    Component::Update,
+                    ErrorCode::InvalidData,
+                    Severity::Warning,
+                    UpdateErrorCode::InvalidData as u8,
+                    &[id.msg_type as u8, data.len() as u8, e.detail()],
+                )
+                .await;
+                return;
             }
         };
+        match session.accept(&chunk) {
+            Accept::Ignored | Accept::Buffered => return,
+            Accept::Gap => {
+                println!("chunk {} missing, resend", chunk.index);
+                send_message(&session.progress()).await;
+                return;
+            }
+            Accept::Block => {}
+        }
 
-        if should_flush {
-            println!("write chunk");
-            let mut ota_guard = OTA.lock().await;
-            if let Some(ref mut ota) = *ota_guard {
-                match ota.ota_write_chunk(&buffer) {
-                    Ok(true) => {
-                        println!("last chunk");
-                        if ota
-                            .ota_flush(true, true)
-                            .inspect_err(|e| {
-                                println!("{e:?}");
-                            })
-                            .is_err()
-                        {
-                            ErrorReport::send(
-                                Component::Update,
-                                ErrorCode::InvalidData,
-                                Severity::Warning,
-                                UpdateErrorCode::VerifyFailed as u8,
-                                &[0u8, 0u8, 0u8],
-                            )
-                            .await;
-                        } else {
-                            esp_hal::system::software_reset();
-                        }
-                    }
-                    Ok(false) => {
-                        // continue writing
-                    }
-                    Err(e) => {
-                        println!("Write failed: {:?}", e);
-                        ErrorReport::send(
-                            Component::Ota,
-                            ErrorCode::Unknown,
-                            Severity::RecoverableError,
-                            UpdateErrorCode::Write as u8,
-                            &[0u8, 0u8, 0u8],
-                        )
-                        .await;
-                    }
+        println!("write chunk");
+        let mut ota_guard = OTA.lock().await;
+        let Some(ref mut ota) = *ota_guard else {
+            return;
+        };
+        let written = ota.ota_write_chunk(session.block());
+        if written.is_ok() {
+            session.commit();
+        }
+        // acknowledge, on error this makes the host resend the block
+        send_message(&session.progress()).await;
+        match written {
+            Ok(true) => {
+                println!("last chunk");
+                // let the acknowledgement go out before flash is busy
+                Timer::after_millis(100).await;
+                let verified = VerifyingKey::from_bytes(UPDATE_KEY)
+                    .map_err(|_| ImageError::BadSignature)
+                    .and_then(|key| session.verify_signature(&key));
+                let announced = self.header.map(|(header, _)| header);
+                if session.header().ok() != announced {
+                    // the data does not match what start checked
+                    println!("image header differs from the announced one");
+                    *ota_guard = None;
+                    self.session = None;
+                    wipe_pending_slot();
+                    error::report(
+                        Component::Update,
+                        ErrorCode::InvalidData,
+                        Severity::Warning,
+                        UpdateErrorCode::HeaderMismatch as u8,
+                        &[0u8, 0u8, 0u8],
+                    )
+                    .await;
+                } else if let Err(e) = verified {
+                    // never activate the slot, a new upload starts over
+                    println!("rejecting image: {e}");
+                    *ota_guard = None;
+                    self.session = None;
+                    wipe_pending_slot();
+                    error::report(
+                        Component::Update,
+                        ErrorCode::InvalidData,
+                        Severity::Warning,
+                        UpdateErrorCode::Signature as u8,
+                        &[e.code(), 0u8, 0u8],
+                    )
+                    .await;
+                } else if ota
+                    .ota_flush(true, true)
+                    .inspect_err(|e| {
+                        println!("{e:?}");
+                    })
+                    .is_err()
+                {
+                    error::report(
+                        Component::Update,
+                        ErrorCode::InvalidData,
+                        Severity::Warning,
+                        UpdateErrorCode::VerifyFailed as u8,
+                        &[0u8, 0u8, 0u8],
+                    )
+                    .await;
+                } else {
+                    esp_hal::system::software_reset();
                 }
             }
+            Ok(false) => {
+                // continue writing
+            }
+            Err(e) => {
+                println!("Write failed: {:?}", e);
+                error::report(
+                    Component::Ota,
+                    ErrorCode::Unknown,
+                    Severity::RecoverableError,
+                    UpdateErrorCode::Write as u8,
+                    &[0u8, 0u8, 0u8],
+                )
+                .await;
+            }
+        }
+    }
 
-            buffer.clear();
+    /// `FlashStart` sent to all nodes of our type. Acknowledged once like a
+    /// normal update, blocks are not.
+    async fn start_multicast(&mut self, size: u32, crc: u32) {
+        *OTA.lock().await = None;
+        self.session = None;
+        if let Some(multicast) = self.multicast.as_mut() {
+            if multicast.is_resumable(size, crc) {
+                multicast.set_compressed(self.compressed);
+                send_message(&multicast.progress()).await;
+                return;
+            }
+        }
+        match Multicast::new(size, crc) {
+            Some(mut multicast) => {
+                multicast.set_compressed(self.compressed);
+                println!("start multicast update: crc {crc} size {size}");
+                send_message(&multicast.progress()).await;
+                self.multicast = Some(multicast);
+            }
+            None => {
+                self.multicast = None;
+                error::report(
+                    Component::Update,
+                    ErrorCode::InvalidData,
+                    Severity::Warning,
+                    UpdateErrorCode::Begin as u8,
+                    &[0u8, 0u8, 0u8],
+                )
+                .await;
+            }
         }
     }
 
-    pub async fn progress(&mut self, _id: CanId, _data: &[u8], _remote_request: bool) {}
-    pub async fn select(&mut self, _id: CanId, _data: &[u8], _remote_request: bool) {}
-    pub async fn erase(&mut self, _id: CanId, _data: &[u8], _remote_request: bool) {}
-    pub async fn read(&mut self, _id: CanId, _data: &[u8], _remote_request: bool) {}
-    pub async fn verify(&mut self, _id: CanId, _data: &[u8], _remote_request: bool) {}
+    /// The next `FlashWrite` chunks of a multicast update belong to this
+    /// block.
+    pub async fn block(&mut self, _id: CanId, data: &[u8], remote_request: bool) {
+        if let (Some(multicast), Ok(block), false) = (
+            self.multicast.as_mut(),
+            <[u8; 2]>::try_from(data),
+            remote_request,
+        ) {
+            multicast.seek(u16::from_be_bytes(block));
+        }
+    }
+
+    /// Blocks of a multicast update still missing, 32 from the asked one on.
+    pub async fn missing(&mut self, msg: CanMessage) {
+        let Some(multicast) = self.multicast.as_ref() else {
+            return;
+        };
+        if let CanMessage::FlashMissing { block, .. } = msg {
+            send_message(&multicast.missing(block)).await;
+        }
+    }
+
+    /// Write whole blocks straight into the pending slot, in any order. The
+    /// image is checked once the last one is there.
+    async fn write_multicast(&mut self, id: CanId, data: &[u8]) {
+        let Some(multicast) = self.multicast.as_mut() else {
+            return;
+        };
+        let Ok(chunk) = Chunk::parse(data) else {
+            return;
+        };
+        if multicast.accept(&chunk) != Accept::Block {
+            return;
+        }
+        let Some(slot) = pending_slot() else {
+            return;
+        };
+        let (offset, block) = multicast.block();
+        let address = OTA_SLOTS[slot].start + offset;
+        let mut flash = FlashStorage::new();
+        let written = NorFlash::erase(&mut flash, address, address + BLOCK_SIZE as u32)
+            .and_then(|_| NorFlash::write(&mut flash, address, block));
+        if written.is_err() {
+            // stays missing, the host sends it again
+            println!("write block at {offset:#x} failed");
+            multicast.seek(u16::MAX);
+            return;
+        }
+        multicast.commit();
+        if !multicast.is_complete() {
+            return;
+        }
+
+        let start = OTA_SLOTS[slot].start;
+        let checked = multicast
+            .read_back(|offset, buf| flash.read(start + offset, buf))
+            .and_then(|verifier| {
+                let key =
+                    VerifyingKey::from_bytes(UPDATE_KEY).map_err(|_| UpdateErrorCode::Signature)?;
+                verifier
+                    .verify(&key)
+                    .map_err(|_| UpdateErrorCode::Signature)?;
+                let announced = self.header.map(|(header, _)| header);
+                match verifier.header() {
+                    Ok(header) if Some(header) == announced => Ok(()),
+                    _ => Err(UpdateErrorCode::HeaderMismatch),
+                }
+            });
+        match checked {
+            Ok(()) => {
+                println!("multicast update complete");
+                multicast.set_verified();
+                self.verified = Some(slot);
+            }
+            Err(code) => {
+                println!("multicast image rejected: {code:?}");
+                self.multicast = None;
+                wipe_pending_slot();
+                error::report(
+                    Component::Update,
+                    ErrorCode::InvalidData,
+                    Severity::Warning,
+                    code as u8,
+                    &[id.msg_type as u8, 0u8, 0u8],
+                )
+                .await;
+            }
+        }
+    }
+
+    /// Activate a verified multicast update and restart. Sent to all nodes
+    /// of a type at once, nodes without a verified image ignore it.
+    pub async fn complete(&mut self, _id: CanId, _data: &[u8], remote_request: bool) {
+        if remote_request || !self.multicast.as_ref().is_some_and(Multicast::is_verified) {
+            return;
+        }
+        let (Some(slot), Ok(mut ota)) = (pending_slot(), Ota::new(FlashStorage::new())) else {
+            return;
+        };
+        println!("activate ota_{slot}");
+        // new, so boot_guard rolls back if the gateway does not confirm it
+        ota.set_target_ota_boot_partition(slot, OtaImgState::EspOtaImgNew);
+        esp_hal::system::software_reset();
+    }
+
+    /// Bytes received so far and the size announced by `FlashStart`, both
+    /// zero without a running update.
+    pub async fn progress(&mut self, _id: CanId, _data: &[u8], remote_request: bool) {
+        if !remote_request {
+            return;
+        }
+        let msg = match (&self.session, &self.multicast) {
+            (Some(session), _) => session.progress(),
+            (None, Some(multicast)) => multicast.progress(),
+            (None, None) => CanMessage::FlashProgress {
+                received: 0,
+                size: 0,
+            },
+        };
+        send_message(&msg).await;
+    }
+
+    /// Remote request: answer the booted slot (0xFF for factory).
+    /// Data: boot from the given slot after the next restart, the booted
+    /// one or one holding a multicast image verified since boot. That one
+    /// boots unconfirmed, like any update.
+    pub async fn select(&mut self, id: CanId, data: &[u8], remote_request: bool) {
+        let mut ota = match Ota::new(FlashStorage::new()) {
+            Ok(ota) => ota,
+            Err(_) => {
+                error::report(
+                    Component::Ota,
+                    ErrorCode::Unknown,
+                    Severity::RecoverableError,
+                    UpdateErrorCode::Init as u8,
+                    &[0u8, 0u8, 0u8],
+                )
+                .await;
+                return;
+            }
+        };
+        if remote_request {
+            let booted = ota
+                .get_currently_booted_partition()
+                .map(|slot| slot as u8)
+                .unwrap_or(0xFF);
+            send_message(&CanMessage::FlashSelect(booted)).await;
+            return;
+        }
+        let booted = ota.get_currently_booted_partition();
+        match data {
+            // stays confirmed or not
+            [slot] if booted == Some(*slot as usize) => {
+                println!("keep booting ota_{slot}");
+                let state = ota
+                    .get_ota_image_state()
+                    .unwrap_or(OtaImgState::EspOtaImgNew);
+                ota.set_target_ota_boot_partition(*slot as usize, state);
+            }
+            [slot] if *slot < SLOT_COUNT && self.verified == Some(*slot as usize) => {
+                println!("boot from ota_{slot} after restart");
+                ota.set_target_ota_boot_partition(*slot as usize, OtaImgState::EspOtaImgNew);
+            }
+            _ => {
+                error::report(
+                    Component::Update,
+                    ErrorCode::InvalidData,
+                    Severity::Warning,
+                    UpdateErrorCode::InvalidData as u8,
+                    &[
+                        id.msg_type as u8,
+                        data.len() as u8,
+                        data.first().copied().unwrap_or(0),
+                    ],
+                )
+                .await;
+            }
+        }
+    }
+
+    /// Abort a running update and wipe the pending slot. Erasing takes a
+    /// few seconds, the node answers with `FlashErase` when done.
+    pub async fn erase(&mut self, _id: CanId, _data: &[u8], remote_request: bool) {
+        if remote_request {
+            return;
+        }
+        *OTA.lock().await = None;
+        self.session = None;
+        self.multicast = None;
+        self.verified = None;
+
+        let Some(slot) = pending_slot() else {
+            return;
+        };
+        println!("erase ota_{slot}");
+        let range = &OTA_SLOTS[slot];
+        if NorFlash::erase(&mut FlashStorage::new(), range.start, range.end).is_err() {
+            error::report(
+                Component::Ota,
+                ErrorCode::Unknown,
+                Severity::RecoverableError,
+                UpdateErrorCode::Write as u8,
+                &[slot as u8, 0u8, 0u8],
+            )
+            .await;
+            return;
+        }
+        send_message(&CanMessage::FlashErase).await;
+    }
+
+    /// Stream back a `ReadRange` of the pending slot, 8 bytes per frame.
+    pub async fn read(&mut self, id: CanId, data: &[u8], remote_request: bool) {
+        if remote_request {
+            return;
+        }
+        let range = match ReadRange::parse(data) {
+            Ok(range) => range,
+            Err(e) => {
+                error::report(
+                    Component::Update,
+                    ErrorCode::InvalidData,
+                    Severity::Warning,
+                    UpdateErrorCode::InvalidData as u8,
+                    &[id.msg_type as u8, data.len() as u8, e.detail()],
+                )
+                .await;
+                return;
+            }
+        };
+        let Some(slot) = pending_slot() else {
+            return;
+        };
+        let slot = &OTA_SLOTS[slot];
+        let range = range.clamp(slot.end - slot.start);
+
+        let mut flash = FlashStorage::new();
+        let mut offset = slot.start + range.offset;
+        let end = offset + range.len;
+        while offset < end {
+            let mut buf = [0u8; 8];
+            let len = ((end - offset) as usize).min(buf.len());
+            if flash.read(offset, &mut buf[..len]).is_err() {
+                error::report(
+                    Component::Ota,
+                    ErrorCode::Unknown,
+                    Severity::RecoverableError,
+                    UpdateErrorCode::Unknown as u8,
+                    &offset.to_be_bytes(),
+                )
+                .await;
+                return;
+            }
+            send_message(&CanMessage::FlashRead(
+                Payload::from_slice(&buf[..len]).unwrap(),
+            ))
+            .await;
+            offset += len as u32;
+        }
+    }
+
+    /// CRC over everything written to flash so far, to compare with the
+    /// image on the host.
+    pub async fn verify(&mut self, id: CanId, _data: &[u8], remote_request: bool) {
+        if !remote_request {
+            return;
+        }
+        match &self.session {
+            Some(session) => send_message(&session.verify()).await,
+            None => {
+                error::report(
+                    Component::Update,
+                    ErrorCode::InvalidData,
+                    Severity::Warning,
+                    UpdateErrorCode::NotStarted as u8,
+                    &[id.msg_type as u8, 0u8, 0u8],
+                )
+                .await
+            }
+        }
+    }
+
+    /// Seconds a new image waits for the gateway before rolling back.
+    pub async fn confirm_window(&mut self, _id: CanId, data: &[u8], remote_request: bool) {
+        let mut config = config().await;
+        if remote_request {
+            let window = config
+                .get_u32(config::Key::ConfirmWindow)
+                .await
+                .unwrap_or(DEFAULT_CONFIRM_WINDOW);
+            drop(config);
+            send_message(&CanMessage::UpdateConfirmWindow(window)).await;
+        } else if let Ok(buf) = <[u8; 4]>::try_from(data) {
+            // silent error, like the other config values
+            let _ = config
+                .set_u32(config::Key::ConfirmWindow, u32::from_le_bytes(buf))
+                .await;
+        }
+    }
+}
+
+/// Slot an update goes to, the one that is not booted.
+fn pending_slot() -> Option<usize> {
+    let mut ota = Ota::new(FlashStorage::new()).ok()?;
+    ota.get_next_ota_partition()
+        .filter(|slot| *slot < OTA_SLOTS.len())
+}
+
+/// Erase the start of the pending slot after rejecting the image in it, so
+/// nothing can boot the rest.
+fn wipe_pending_slot() {
+    let Some(slot) = pending_slot() else {
+        return;
+    };
+    let start = OTA_SLOTS[slot].start;
+    let wiped = NorFlash::erase(&mut FlashStorage::new(), start, start + BLOCK_SIZE as u32);
+    if wiped.is_err() {
+        println!("erase ota_{slot} failed");
+    }
 }
@@ -0,0 +1,1207 @@
+use cancomponents_core::binding::{Action, Binding};
+use cancomponents_core::button_message::ButtonState;
+use cancomponents_core::metadata::Field;
+use cancomponents_core::power_on::{PowerOn, POWER_ON_CHANNELS};
+use cancomponents_core::relais_message::{RelaisMessage, RelaisState};
+use cancomponents_core::rollershutter::{
+    Calibration, ShutterCommand, ShutterReport, TravelTimes, MAX_SHUTTERS,
+};
+use cancomponents_core::scene::{Scene, SCENE_CHANNELS};
+use cancomponents_core::supervisor::{Policy, ShutterSafeState, Supervision};
+use cancomponents_host::client::Client;
+use cancomponents_host::commands::{self, RESTART_TIMEOUT};
+use cancomponents_host::error::Result;
+use cancomponents_host::frame::NodeAddr;
+use cancomponents_host::multicast;
+use cancomponents_host::ota::{self, Pacing, UploadOptions};
+use cancomponents_host::sign;
+use cancomponents_host::sim::SimNode;
+use cancomponents_host::socketcan::SocketCan;
+use clap::{Parser, Subcommand, ValueEnum};
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
+
+/// Commission and inspect cancomponents nodes. Nodes are addressed as
+/// TYPE/ID, e.g. 5/12 for relais node 12.
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    /// SocketCAN interface
+    #[arg(short, long, default_value = "can0")]
+    interface: String,
+    /// how long to wait for answers, e.g. 500ms or 2s
+    #[arg(short, long, value_parser = humantime::parse_duration, default_value = "500ms")]
+    timeout: Duration,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// list all nodes answering a broadcast ping
+    Scan,
+    /// show parameters of a node
+    Info { node: NodeAddr },
+    /// assign a new TYPE/ID, active after the next restart
+    SetId { node: NodeAddr, new: NodeAddr },
+    /// give nodes without address one, bound to their MAC
+    Claim {
+        /// device type to assign, default: the one the node has configured
+        #[arg(long = "type")]
+        device_type: Option<u8>,
+        /// how long to listen for claims, nodes repeat them every few seconds
+        #[arg(long = "for", value_parser = humantime::parse_duration, default_value = "15s")]
+        duration: Duration,
+    },
+    /// set the custom string (max. 8 bytes)
+    SetName { node: NodeAddr, name: String },
+    /// switch a relay, optionally only for some time
+    Relay {
+        node: NodeAddr,
+        num: usize,
+        state: Switch,
+        /// switch off again after this time, e.g. 30s or 5min
+        #[arg(long = "for", value_parser = humantime::parse_duration)]
+        duration: Option<Duration>,
+    },
+    /// show the rollershutters of a node, move one or send it to a position
+    Shutter {
+        node: NodeAddr,
+        #[arg(value_parser = clap::value_parser!(u8).range(0..MAX_SHUTTERS as i64))]
+        num: Option<u8>,
+        #[arg(requires = "num")]
+        motion: Option<Motion>,
+        /// percent closed, 0 is open
+        #[arg(
+            long,
+            requires = "num",
+            conflicts_with = "motion",
+            value_parser = clap::value_parser!(u8).range(0..=100)
+        )]
+        to: Option<u8>,
+        /// stop again after this time, e.g. 5s
+        #[arg(long = "for", requires = "motion", value_parser = humantime::parse_duration)]
+        duration: Option<Duration>,
+    },
+    /// show the travel times of the rollershutters of a node or set those
+    /// of one
+    Travel {
+        node: NodeAddr,
+        #[arg(value_parser = clap::value_parser!(u8).range(0..MAX_SHUTTERS as i64), requires = "up")]
+        num: Option<u8>,
+        /// full run from closed to open, e.g. 21s or 21s500ms
+        #[arg(long, value_parser = humantime::parse_duration, requires_all = ["num", "down"])]
+        up: Option<Duration>,
+        /// full run from open to closed
+        #[arg(long, value_parser = humantime::parse_duration, requires = "up")]
+        down: Option<Duration>,
+    },
+    /// show the pause of the shutter motors between the directions or
+    /// change it
+    DeadTime {
+        node: NodeAddr,
+        /// e.g. 500ms, at most 65s
+        #[arg(value_parser = humantime::parse_duration)]
+        pause: Option<Duration>,
+    },
+    /// show the groups of a node or replace them, active after a restart
+    Groups {
+        node: NodeAddr,
+        /// new groups, `--clear` to leave all
+        #[arg(value_parser = clap::value_parser!(u8).range(1..=63))]
+        groups: Vec<u8>,
+        #[arg(long, conflicts_with = "groups")]
+        clear: bool,
+    },
+    /// switch a relay on every member of a group
+    GroupRelay {
+        #[arg(value_parser = clap::value_parser!(u8).range(1..=63))]
+        group: u8,
+        num: usize,
+        state: Switch,
+        /// only nodes of this device type
+        #[arg(long = "type", default_value_t = 0)]
+        device_type: u8,
+        /// switch off again after this time, e.g. 30s or 5min
+        #[arg(long = "for", value_parser = humantime::parse_duration)]
+        duration: Option<Duration>,
+    },
+    /// show name, room, floor and notes of a node or set some of them
+    Meta {
+        node: NodeAddr,
+        #[arg(long)]
+        name: Option<String>,
+        #[arg(long)]
+        room: Option<String>,
+        #[arg(long)]
+        floor: Option<String>,
+        #[arg(long)]
+        notes: Option<String>,
+    },
+    /// show what a node does without the gateway or replace it
+    Policy {
+        node: NodeAddr,
+        /// rollershutters when the gateway goes missing
+        #[arg(long)]
+        shutters: Option<SafeState>,
+        /// switch all relais off, otherwise they hold
+        #[arg(long, requires = "shutters")]
+        relais_off: bool,
+        /// buttons only switch through bindings without the gateway
+        #[arg(long, requires = "shutters")]
+        bindings_autonomous_only: bool,
+    },
+    /// show what the relays of a node do after a reset or change it, e.g.
+    /// `0=restore 3=keep`
+    PowerOn {
+        node: NodeAddr,
+        #[arg(value_parser = parse_power_on)]
+        changes: Vec<(usize, Behaviour)>,
+    },
+    /// show how often a node expects an echo or change it
+    Supervision {
+        node: NodeAddr,
+        /// time between echoes of the gateway, e.g. 40s
+        #[arg(long, value_parser = humantime::parse_duration, requires = "misses")]
+        interval: Option<Duration>,
+        /// missing echoes before the node runs on its own
+        #[arg(long, requires = "interval")]
+        misses: Option<u8>,
+    },
+    /// send echoes as the gateway does, once or every `--every`
+    Echo {
+        #[arg(long, value_parser = humantime::parse_duration)]
+        every: Option<Duration>,
+    },
+    /// stop nodes from counting missing echoes, 0s resumes; 0/0 for all
+    Suspend {
+        node: NodeAddr,
+        #[arg(value_parser = humantime::parse_duration)]
+        duration: Duration,
+    },
+    /// restart a node and wait until it is back
+    Restart {
+        node: NodeAddr,
+        /// do not wait for the node to announce itself
+        #[arg(long)]
+        no_wait: bool,
+    },
+    /// upload a signed firmware image (see ccsign) and wait for the reboot
+    Flash {
+        node: NodeAddr,
+        image: PathBuf,
+        /// version the node has to report afterwards, default: any other
+        #[arg(long)]
+        expect_version: Option<String>,
+        /// pause after every frame
+        #[arg(long, value_parser = humantime::parse_duration, default_value = "0ms")]
+        frame_gap: Duration,
+        /// wipe an interrupted upload instead of continuing it
+        #[arg(long)]
+        no_resume: bool,
+        /// wait this long for a block acknowledgement before asking for it
+        #[arg(long, value_parser = humantime::parse_duration, default_value = "1s")]
+        ack_timeout: Duration,
+        /// flash an image older than the running one
+        #[arg(long)]
+        allow_downgrade: bool,
+        /// send the image compressed, needs nodes that support it
+        #[arg(long)]
+        compress: bool,
+    },
+    /// upload a signed firmware image to all nodes of a device type at once
+    FlashAll {
+        device_type: u8,
+        image: PathBuf,
+        /// pause after every block so slow nodes keep up
+        #[arg(long, value_parser = humantime::parse_duration, default_value = "100ms")]
+        block_gap: Duration,
+        /// flash an image older than the running one
+        #[arg(long)]
+        allow_downgrade: bool,
+        /// send the image compressed, needs nodes that support it
+        #[arg(long)]
+        compress: bool,
+    },
+    /// define, store or recall scenes (0-15)
+    Scene {
+        #[command(subcommand)]
+        action: SceneAction,
+    },
+    /// manage the button bindings a relay node switches on by itself
+    Binding {
+        #[command(subcommand)]
+        action: BindingAction,
+    },
+    /// inspect or control the OTA slots of a node
+    Ota {
+        node: NodeAddr,
+        #[command(subcommand)]
+        action: OtaAction,
+    },
+    /// run a simulated node on the interface, e.g. on vcan0
+    Simulate {
+        #[arg(default_value = "5/12")]
+        node: NodeAddr,
+        #[arg(long, default_value_t = 0x0000_1234_5678_9abc)]
+        uid: u64,
+        /// only accept images signed for this public key
+        #[arg(long)]
+        update_key: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum OtaAction {
+    /// show update progress, CRC of the written data and the booted slot
+    Status,
+    /// read back part of the pending slot into a file
+    Read {
+        #[arg(value_parser = parse_u32)]
+        offset: u32,
+        #[arg(value_parser = parse_u32)]
+        len: u32,
+        output: PathBuf,
+    },
+    /// abort a running update and wipe the pending slot
+    Erase,
+    /// boot from slot 0 or 1 after the next restart
+    Select { slot: u8 },
+    /// show or set how long a new image waits for the gateway before it
+    /// rolls back, e.g. 5min
+    ConfirmWindow {
+        #[arg(value_parser = humantime::parse_duration)]
+        window: Option<Duration>,
+    },
+}
+
+#[derive(Subcommand)]
+enum SceneAction {
+    /// set relays of a scene, e.g. 0=on 3=off, the others stay as they are
+    Define {
+        node: NodeAddr,
+        #[arg(value_parser = clap::value_parser!(u8).range(0..16))]
+        scene: u8,
+        #[arg(value_parser = parse_scene_target)]
+        targets: Vec<(usize, Switch)>,
+        /// apply this long after the recall, whole seconds
+        #[arg(long, value_parser = humantime::parse_duration)]
+        delay: Option<Duration>,
+    },
+    /// store the current relay states as a scene
+    Store {
+        node: NodeAddr,
+        #[arg(value_parser = clap::value_parser!(u8).range(0..16))]
+        scene: u8,
+        /// apply this long after the recall, whole seconds
+        #[arg(long, value_parser = humantime::parse_duration)]
+        delay: Option<Duration>,
+    },
+    /// apply a scene on a node, on TYPE/0 or on everyone with 0/0
+    Recall {
+        node: NodeAddr,
+        #[arg(value_parser = clap::value_parser!(u8).range(0..16))]
+        scene: u8,
+    },
+    /// apply a scene on every member of a group
+    GroupRecall {
+        #[arg(value_parser = clap::value_parser!(u8).range(1..=63))]
+        group: u8,
+        #[arg(value_parser = clap::value_parser!(u8).range(0..16))]
+        scene: u8,
+        /// only nodes of this device type
+        #[arg(long = "type", default_value_t = 0)]
+        device_type: u8,
+    },
+}
+
+#[derive(Subcommand)]
+enum BindingAction {
+    /// show the bindings of a node
+    List { node: NodeAddr },
+    /// bind a button event of SOURCE to a relay of NODE
+    Set {
+        node: NodeAddr,
+        #[arg(value_parser = clap::value_parser!(u8).range(0..32))]
+        slot: u8,
+        source: NodeAddr,
+        button: u8,
+        event: Event,
+        relay: u8,
+        action: BindAction,
+        /// how long `timed` keeps the relay on, whole seconds
+        #[arg(long = "for", value_parser = humantime::parse_duration, required_if_eq("action", "timed"))]
+        duration: Option<Duration>,
+        /// only applied while NODE is a member of this group
+        #[arg(long, value_parser = clap::value_parser!(u8).range(1..=63))]
+        group: Option<u8>,
+    },
+    /// remove the binding in a slot
+    Clear {
+        node: NodeAddr,
+        #[arg(value_parser = clap::value_parser!(u8).range(0..32))]
+        slot: u8,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Event {
+    Pressed,
+    Released,
+    Hold,
+    Single,
+    Double,
+    Triple,
+    Quadruple,
+}
+
+impl From<Event> for ButtonState {
+    fn from(event: Event) -> Self {
+        match event {
+            Event::Pressed => ButtonState::Pressed,
+            Event::Released => ButtonState::Released,
+            Event::Hold => ButtonState::Hold,
+            Event::Single => ButtonState::Single,
+            Event::Double => ButtonState::Double,
+            Event::Triple => ButtonState::Tripple,
+            Event::Quadruple => ButtonState::Quadruple,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum SafeState {
+    Keep,
+    Stop,
+    Up,
+    Down,
+}
+
+impl From<SafeState> for ShutterSafeState {
+    fn from(state: SafeState) -> Self {
+        match state {
+            SafeState::Keep => ShutterSafeState::Keep,
+            SafeState::Stop => ShutterSafeState::Stop,
+            SafeState::Up => ShutterSafeState::Up,
+            SafeState::Down => ShutterSafeState::Down,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum BindAction {
+    Toggle,
+    On,
+    Off,
+    Timed,
+    Up,
+    Down,
+}
+
+/// `NUM=STATE`, e.g. `3=on`.
+fn parse_scene_target(s: &str) -> std::result::Result<(usize, Switch), String> {
+    let (num, state) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected NUM=STATE, got {s:?}"))?;
+    let num: usize = num.parse().map_err(|_| format!("invalid relay {num:?}"))?;
+    if num >= SCENE_CHANNELS {
+        return Err(format!("relay {num} is not below {SCENE_CHANNELS}"));
+    }
+    Ok((num, Switch::from_str(state, true)?))
+}
+
+/// `NUM=BEHAVIOUR`, e.g. `3=restore`.
+fn parse_power_on(s: &str) -> std::result::Result<(usize, Behaviour), String> {
+    let (num, behaviour) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected NUM=BEHAVIOUR, got {s:?}"))?;
+    let num: usize = num.parse().map_err(|_| format!("invalid relay {num:?}"))?;
+    if num >= POWER_ON_CHANNELS {
+        return Err(format!("relay {num} is not below {POWER_ON_CHANNELS}"));
+    }
+    Ok((num, Behaviour::from_str(behaviour, true)?))
+}
+
+/// Decimal or 0x prefixed hex.
+fn parse_u32(s: &str) -> std::result::Result<u32, std::num::ParseIntError> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Switch {
+    On,
+    Off,
+    Up,
+    Down,
+}
+
+impl From<Switch> for RelaisState {
+    fn from(switch: Switch) -> Self {
+        match switch {
+            Switch::On => RelaisState::On,
+            Switch::Off => RelaisState::Off,
+            Switch::Up => RelaisState::Up,
+            Switch::Down => RelaisState::Down,
+        }
+    }
+}
+
+/// What a relay does after a reset.
+#[derive(Clone, Copy, ValueEnum)]
+enum Behaviour {
+    Off,
+    On,
+    /// the last state the node stored
+    Restore,
+    /// unchanged after a warm reset, off after a power loss
+    Keep,
+}
+
+impl From<Behaviour> for PowerOn {
+    fn from(behaviour: Behaviour) -> Self {
+        match behaviour {
+            Behaviour::Off => PowerOn::Off,
+            Behaviour::On => PowerOn::On,
+            Behaviour::Restore => PowerOn::Restore,
+            Behaviour::Keep => PowerOn::Keep,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Motion {
+    Up,
+    Down,
+    Stop,
+}
+
+impl From<Motion> for RelaisState {
+    fn from(motion: Motion) -> Self {
+        match motion {
+            Motion::Up => RelaisState::Up,
+            Motion::Down => RelaisState::Down,
+            Motion::Stop => RelaisState::Off,
+        }
+    }
+}
+
+fn print_shutter(report: &ShutterReport) {
+    match report.position {
+        Some(position) => println!("shutter {}: {:?} at {position} %", report.num, report.state),
+        None => println!(
+            "shutter {}: {:?}, position unknown",
+            report.num, report.state
+        ),
+    }
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(cli).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(cli: Cli) -> Result<()> {
+    let bus = SocketCan::open(&cli.interface)?;
+    if let Command::Simulate {
+        node,
+        uid,
+        update_key,
+    } = cli.command
+    {
+        println!("simulating node {node} on {}", cli.interface);
+        let mut sim = SimNode::new(node, uid);
+        sim.update_key = update_key
+            .map(|path| sign::read_verifying_key(&path))
+            .transpose()?;
+        return sim.run(bus).await;
+    }
+
+    let client = Client::new(bus);
+    match cli.command {
+        Command::Scan => {
+            for node in commands::scan(&client, cli.timeout).await? {
+                println!("{node}");
+            }
+        }
+        Command::Info { node } => {
+            let info = commands::info(&client, node, cli.timeout).await?;
+            println!("node:      {node}");
+            print_field("uid", info.uid.map(|uid| format!("{uid:012x}")));
+            print_field("name", info.name);
+            print_field("version", info.version);
+            print_field("hwrev", info.hwrev);
+            print_field("uptime", info.uptime_minutes.map(|m| format!("{m} min")));
+            print_field("relais", info.relais_mode.map(|m| format!("{m:?}")));
+            print_field("extension", info.extension.map(|e| format!("{e:?}")));
+            if let Some(metadata) = &info.metadata {
+                print_metadata(metadata);
+            }
+            // older firmware has no announcement
+            let announcement = commands::announcement(&client, node, cli.timeout)
+                .await
+                .ok()
+                .flatten();
+            print_field("firmware", announcement.map(|(a, _)| a.version));
+            print_field("features", announcement.map(|(a, _)| a.capabilities));
+            print_field(
+                "channels",
+                announcement.map(|(_, c)| {
+                    format!(
+                        "{} relais, {} rollershutters, {} buttons",
+                        c.relais, c.rollershutters, c.buttons
+                    )
+                }),
+            );
+        }
+        Command::SetId { node, new } => {
+            commands::set_id(&client, node, new, cli.timeout).await?;
+            println!("{node} will use {new} after the next restart");
+        }
+        Command::Claim {
+            device_type,
+            duration,
+        } => {
+            let claims = commands::claim(&client, device_type, duration, cli.timeout).await?;
+            for claim in &claims {
+                match claim.addr {
+                    Some(addr) => println!("{:012x}: {addr}", claim.uid),
+                    None => println!("{:012x}: no device type or no free id", claim.uid),
+                }
+            }
+            if claims.is_empty() {
+                println!("no claims");
+            }
+        }
+        Command::SetName { node, name } => {
+            commands::set_name(&client, node, &name, cli.timeout).await?;
+        }
+        Command::Relay {
+            node,
+            num,
+            state,
+            duration,
+        } => {
+            let duration = duration.unwrap_or_default();
+            let reported =
+                commands::relay(&client, node, num, state.into(), duration, cli.timeout).await?;
+            match reported {
+                Some(state) => println!("relay {num}: {state:?}"),
+                None => println!("relay {num}: no change reported"),
+            }
+        }
+        Command::Shutter {
+            node,
+            num,
+            motion,
+            to,
+            duration,
+        } => {
+            let command = match (num, motion, to) {
+                (Some(num), Some(motion), _) => Some(ShutterCommand::Move(RelaisMessage {
+                    num: num as usize,
+                    state: motion.into(),
+                    duration: embassy_time::Duration::from_millis(
+                        duration.unwrap_or_default().as_millis() as u64,
+                    ),
+                    bank: 0,
+                })),
+                (Some(num), None, Some(percent)) => Some(ShutterCommand::Position {
+                    num: num as usize,
+                    percent,
+                }),
+                _ => None,
+            };
+            let reports = match command {
+                Some(command) => {
+                    vec![commands::shutter(&client, node, command, cli.timeout).await?]
+                }
+                None => commands::shutters(&client, node, cli.timeout)
+                    .await?
+                    .into_iter()
+                    .filter(|report| num.is_none_or(|num| num == report.num))
+                    .collect(),
+            };
+            reports.iter().for_each(print_shutter);
+        }
+        Command::Travel {
+            node,
+            num,
+            up,
+            down,
+        } => {
+            if let (Some(num), Some(up), Some(down)) = (num, up, down) {
+                let travel = TravelTimes {
+                    up: embassy_time::Duration::from_millis(up.as_millis() as u64),
+                    down: embassy_time::Duration::from_millis(down.as_millis() as u64),
+                };
+                commands::calibrate(&client, node, Calibration { num, travel }, cli.timeout)
+                    .await?;
+            }
+            for Calibration { num, travel } in
+                commands::travel_times(&client, node, cli.timeout).await?
+            {
+                match travel.is_calibrated() {
+                    true => println!(
+                        "shutter {num}: up {:.1} s, down {:.1} s",
+                        travel.up.as_millis() as f64 / 1000.0,
+                        travel.down.as_millis() as f64 / 1000.0
+                    ),
+                    false => println!("shutter {num}: not calibrated"),
+                }
+            }
+        }
+        Command::DeadTime { node, pause } => {
+            if let Some(pause) = pause {
+                commands::set_dead_time(&client, node, pause, cli.timeout).await?;
+            }
+            let dead_time = commands::dead_time(&client, node, cli.timeout).await?;
+            println!("dead time: {} ms", dead_time.as_millis());
+        }
+        Command::Groups {
+            node,
+            groups,
+            clear,
+        } => {
+            if clear || !groups.is_empty() {
+                let new = groups.into_iter().collect();
+                commands::set_groups(&client, node, new, cli.timeout).await?;
+                println!("{node} uses the new groups after the next restart");
+            } else {
+                let groups = commands::groups(&client, node, cli.timeout).await?;
+                let groups: Vec<String> = groups.iter().map(|g| g.to_string()).collect();
+                match groups.is_empty() {
+                    true => println!("no groups"),
+                    false => println!("{}", groups.join(" ")),
+                }
+            }
+        }
+        Command::Meta {
+            node,
+            name,
+            room,
+            floor,
+            notes,
+        } => {
+            let fields = [
+                (Field::Name, name),
+                (Field::Room, room),
+                (Field::Floor, floor),
+                (Field::Notes, notes),
+            ];
+            for (field, value) in fields {
+                if let Some(value) = value {
+                    commands::set_metadata(&client, node, field, &value, cli.timeout).await?;
+                }
+            }
+            print_metadata(&commands::metadata(&client, node, cli.timeout).await?);
+        }
+        Command::Policy {
+            node,
+            shutters,
+            relais_off,
+            bindings_autonomous_only,
+        } => {
+            if let Some(shutters) = shutters {
+                let new = Policy {
+                    shutters: shutters.into(),
+                    relais_off,
+                    bindings_autonomous_only,
+                };
+                commands::set_policy(&client, node, new, cli.timeout).await?;
+            }
+            let policy = commands::policy(&client, node, cli.timeout).await?;
+            let report = commands::node_mode(&client, node, cli.timeout).await?;
+            println!("mode:      {:?} for {} s", report.mode, report.duration);
+            println!("shutters:  {:?}", policy.shutters);
+            match policy.relais_off {
+                true => println!("relais:    off"),
+                false => println!("relais:    hold"),
+            }
+            match policy.bindings_autonomous_only {
+                true => println!("bindings:  without the gateway only"),
+                false => println!("bindings:  always"),
+            }
+        }
+        Command::PowerOn { node, changes } => {
+            let policy = match changes.is_empty() {
+                true => commands::power_on(&client, node, cli.timeout).await?,
+                false => {
+                    let changes: Vec<_> = changes
+                        .into_iter()
+                        .map(|(num, behaviour)| (num, behaviour.into()))
+                        .collect();
+                    commands::update_power_on(&client, node, &changes, cli.timeout).await?
+                }
+            };
+            for (num, behaviour) in policy.0.iter().enumerate() {
+                println!("relay {num:<2}  {behaviour:?}");
+            }
+        }
+        Command::Supervision {
+            node,
+            interval,
+            misses,
+        } => {
+            if let (Some(interval), Some(max_misses)) = (interval, misses) {
+                let new = Supervision {
+                    interval: embassy_time::Duration::from_secs(interval.as_secs()),
+                    max_misses,
+                };
+                commands::set_supervision(&client, node, new, cli.timeout).await?;
+            }
+            let supervision = commands::supervision(&client, node, cli.timeout).await?;
+            println!("interval:  {} s", supervision.interval.as_secs());
+            println!("misses:    {}", supervision.max_misses);
+        }
+        Command::Echo { every } => loop {
+            commands::echo(&client).await?;
+            match every {
+                Some(every) => tokio::time::sleep(every).await,
+                None => break,
+            }
+        },
+        Command::Suspend { node, duration } => {
+            commands::suspend_supervision(&client, node, duration).await?;
+        }
+        Command::GroupRelay {
+            group,
+            num,
+            state,
+            device_type,
+            duration,
+        } => {
+            let duration = duration.unwrap_or_default();
+            commands::group_relay(&client, group, device_type, num, state.into(), duration).await?;
+        }
+        Command::Restart { node, no_wait } => {
+            let timeout = if no_wait {
+                Duration::ZERO
+            } else {
+                RESTART_TIMEOUT.max(cli.timeout)
+            };
+            commands::restart(&client, node, timeout).await?;
+        }
+        Command::Flash {
+            node,
+            image,
+            expect_version,
+            frame_gap,
+            ack_timeout,
+            no_resume,
+            allow_downgrade,
+            compress,
+        } => {
+            let image = std::fs::read(&image)?;
+            if no_resume {
+                ota::erase(&client, node).await?;
+            }
+            let options = UploadOptions {
+                expect_version: expect_version.as_deref(),
+                pacing: Pacing {
+                    frame_gap,
+                    ack_timeout,
+                    ..Default::default()
+                },
+                allow_downgrade,
+                compress,
+            };
+            let report = ota::upload(
+                &client,
+                node,
+                &image,
+                &options,
+                cli.timeout,
+                |sent, total| {
+                    eprint!("\r{sent}/{total} bytes ({}%)", sent * 100 / total);
+                    let _ = std::io::stderr().flush();
+                },
+            )
+            .await;
+            eprintln!();
+            let report = report?;
+            if report.resumed_from > 0 {
+                println!("resumed at {} bytes", report.resumed_from);
+            }
+            println!(
+                "{node}: {} -> {} ({}, {} bytes, {} sent, crc {:08x}, {} blocks resent)",
+                report.old_version.as_deref().unwrap_or("?"),
+                report.new_version,
+                report.header.version,
+                report.size,
+                report.transfer_size,
+                report.crc,
+                report.retransmits
+            );
+        }
+        Command::FlashAll {
+            device_type,
+            image,
+            block_gap,
+            allow_downgrade,
+            compress,
+        } => {
+            let image = std::fs::read(&image)?;
+            let nodes: Vec<_> = commands::scan(&client, cli.timeout)
+                .await?
+                .into_iter()
+                .filter(|node| node.device_type == device_type)
+                .collect();
+            let options = UploadOptions {
+                pacing: Pacing {
+                    block_gap,
+                    ..Default::default()
+                },
+                allow_downgrade,
+                compress,
+                ..Default::default()
+            };
+            let report = multicast::upload(
+                &client,
+                &nodes,
+                &image,
+                &options,
+                cli.timeout,
+                |sent, total| {
+                    eprint!("\r{sent}/{total} bytes ({}%)", sent * 100 / total);
+                    let _ = std::io::stderr().flush();
+                },
+            )
+            .await;
+            eprintln!();
+            let report = report?;
+            println!(
+                "{} ({} bytes, {} sent, crc {:08x}, {} blocks resent)",
+                report.header.version,
+                report.size,
+                report.transfer_size,
+                report.crc,
+                report.repaired
+            );
+            for (node, result) in &report.nodes {
+                match result {
+                    Ok(version) => println!("{node}: {version}"),
+                    Err(e) => println!("{node}: {e}"),
+                }
+            }
+        }
+        Command::Scene { action } => match action {
+            SceneAction::Define {
+                node,
+                scene,
+                targets,
+                delay,
+            } => {
+                let mut definition = Scene::new(scene);
+                definition.delay =
+                    embassy_time::Duration::from_secs(delay.unwrap_or_default().as_secs());
+                for (num, state) in targets {
+                    definition.states[num] = Some(state.into());
+                }
+                commands::define_scene(&client, node, definition).await?;
+            }
+            SceneAction::Store { node, scene, delay } => {
+                let delay = delay.unwrap_or_default();
+                let stored =
+                    commands::store_scene(&client, node, scene, delay, cli.timeout).await?;
+                for (num, state) in stored.targets() {
+                    println!("relay {num}: {state:?}");
+                }
+            }
+            SceneAction::Recall { node, scene } => {
+                commands::recall_scene(&client, node, scene).await?;
+            }
+            SceneAction::GroupRecall {
+                group,
+                scene,
+                device_type,
+            } => {
+                commands::group_recall_scene(&client, group, device_type, scene).await?;
+            }
+        },
+        Command::Binding { action } => match action {
+            BindingAction::List { node } => {
+                let bindings = commands::bindings(&client, node, cli.timeout).await?;
+                for b in &bindings {
+                    let group = match b.group {
+                        0 => String::new(),
+                        group => format!(" in group {group}"),
+                    };
+                    println!(
+                        "{:>2}: {}/{} button {} {:?} -> relay {} {:?}{group}",
+                        b.slot, b.source_type, b.source_id, b.button, b.event, b.relay, b.action
+                    );
+                }
+                if bindings.is_empty() {
+                    println!("no bindings");
+                }
+            }
+            BindingAction::Set {
+                node,
+                slot,
+                source,
+                button,
+                event,
+                relay,
+                action,
+                duration,
+                group,
+            } => {
+                let action = match action {
+                    BindAction::Toggle => Action::Toggle,
+                    BindAction::On => Action::On,
+                    BindAction::Off => Action::Off,
+                    BindAction::Timed => Action::Timed(embassy_time::Duration::from_secs(
+                        duration.unwrap_or_default().as_secs(),
+                    )),
+                    BindAction::Up => Action::Up,
+                    BindAction::Down => Action::Down,
+                };
+                let binding = Binding {
+                    slot,
+                    source_type: source.device_type,
+                    source_id: source.device_id,
+                    button,
+                    event: event.into(),
+                    group: group.unwrap_or(0),
+                    relay,
+                    action,
+                };
+                commands::set_binding(&client, node, binding, cli.timeout).await?;
+            }
+            BindingAction::Clear { node, slot } => {
+                commands::clear_binding(&client, node, slot).await?;
+            }
+        },
+        Command::Ota { node, action } => match action {
+            OtaAction::Status => {
+                let (received, size) = ota::progress(&client, node, cli.timeout).await?;
+                let (written, crc) = ota::verify(&client, node, cli.timeout)
+                    .await
+                    .map(|(written, crc)| (written.to_string(), format!("{crc:08x}")))
+                    .unwrap_or_else(|_| ("-".into(), "-".into()));
+                let booted = ota::booted_slot(&client, node, cli.timeout).await?;
+                let running = ota::running_header(&client, node, cli.timeout).await?;
+                println!(
+                    "running:  {} for type {} hwrev {}-{}, protocol {}",
+                    running.version,
+                    running.device_type,
+                    running.hwrev_min,
+                    running.hwrev_max,
+                    running.protocol
+                );
+                match ota::pending(&client, node, cli.timeout).await? {
+                    Some((crc, size)) => println!("update:   {size} bytes, crc {crc:08x}"),
+                    None => println!("update:   -"),
+                }
+                println!("received: {received}/{size} bytes");
+                println!("written:  {written} bytes, crc {crc}");
+                match booted {
+                    Some(slot) => println!("booted:   ota_{slot}"),
+                    None => println!("booted:   factory"),
+                }
+            }
+            OtaAction::Read {
+                offset,
+                len,
+                output,
+            } => {
+                let data = ota::read(&client, node, offset, len, cli.timeout).await?;
+                std::fs::write(&output, &data)?;
+                println!("{} bytes written to {}", data.len(), output.display());
+            }
+            OtaAction::Erase => ota::erase(&client, node).await?,
+            OtaAction::Select { slot } => {
+                ota::select(&client, node, slot).await?;
+                println!("{node} boots from ota_{slot} after the next restart");
+            }
+            OtaAction::ConfirmWindow {
+                window: Some(window),
+            } => {
+                ota::set_confirm_window(&client, node, window).await?;
+            }
+            OtaAction::ConfirmWindow { window: None } => {
+                let window = ota::confirm_window(&client, node, cli.timeout).await?;
+                println!("{}", humantime::format_duration(window));
+            }
+        },
+        Command::Simulate { .. } => unreachable!(),
+    }
+    Ok(())
+}
+
+fn print_metadata(metadata: &commands::Metadata) {
+    for (name, field) in [
+        ("label", Field::Name),
+        ("room", Field::Room),
+        ("floor", Field::Floor),
+        ("notes", Field::Notes),
+    ] {
+        let value = metadata.get(field);
+        print_field(name, (!value.is_empty()).then_some(value));
+    }
+}
+
+fn print_field(name: &str, value: Option<impl std::fmt::Display>) {
+    match value {
+        Some(value) => println!("{:<10} {value}", format!("{name}:")),
+        None => println!("{:<10} -", format!("{name}:")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_cli() {
+        Cli::command().debug_assert();
+        let cli =
+            Cli::try_parse_from(["ccctl", "relay", "5/12", "3", "on", "--for", "30s"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Relay {
+                num: 3,
+                duration: Some(d),
+                ..
+            } if d == Duration::from_secs(30)
+        ));
+        let cli = Cli::try_parse_from(["ccctl", "ota", "5/12", "read", "0x100", "64", "out.bin"])
+            .unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Ota {
+                action: OtaAction::Read {
+                    offset: 0x100,
+                    len: 64,
+                    ..
+                },
+                ..
+            }
+        ));
+        let cli = Cli::try_parse_from(["ccctl", "groups", "5/12", "1", "3"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Groups { groups, clear: false, .. } if groups == [1, 3]
+        ));
+        assert!(Cli::try_parse_from(["ccctl", "groups", "5/12", "1", "--clear"]).is_err());
+        assert!(Cli::try_parse_from(["ccctl", "groups", "5/12", "64"]).is_err());
+        let cli = Cli::try_parse_from(["ccctl", "scene", "define", "5/12", "3", "0=on", "4=down"])
+            .unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Scene {
+                action: SceneAction::Define { scene: 3, targets, .. }
+            } if targets.len() == 2 && targets[1].0 == 4
+        ));
+        assert!(Cli::try_parse_from(["ccctl", "scene", "define", "5/12", "3", "16=on"]).is_err());
+        assert!(Cli::try_parse_from(["ccctl", "scene", "recall", "5/12", "16"]).is_err());
+        let cli = Cli::try_parse_from([
+            "ccctl", "binding", "set", "5/12", "0", "4/3", "1", "single", "2", "timed", "--for",
+            "2min",
+        ])
+        .unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Binding {
+                action: BindingAction::Set { duration: Some(d), .. }
+            } if d == Duration::from_secs(120)
+        ));
+        assert!(Cli::try_parse_from([
+            "ccctl", "binding", "set", "5/12", "0", "4/3", "1", "single", "2", "timed"
+        ])
+        .is_err());
+        let cli = Cli::try_parse_from(["ccctl", "policy", "5/12", "--shutters", "up"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Policy {
+                shutters: Some(SafeState::Up),
+                relais_off: false,
+                ..
+            }
+        ));
+        assert!(Cli::try_parse_from(["ccctl", "policy", "5/12", "--relais-off"]).is_err());
+        let cli = Cli::try_parse_from([
+            "ccctl",
+            "supervision",
+            "5/12",
+            "--interval",
+            "20s",
+            "--misses",
+            "2",
+        ])
+        .unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Supervision {
+                interval: Some(i),
+                misses: Some(2),
+                ..
+            } if i == Duration::from_secs(20)
+        ));
+        assert!(Cli::try_parse_from(["ccctl", "supervision", "5/12", "--misses", "2"]).is_err());
+        let cli =
+            Cli::try_parse_from(["ccctl", "power-on", "5/12", "0=restore", "15=keep"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::PowerOn { changes, .. }
+                if changes.len() == 2 && matches!(changes[1], (15, Behaviour::Keep))
+        ));
+        assert!(Cli::try_parse_from(["ccctl", "power-on", "5/12", "16=on"]).is_err());
+        assert!(Cli::try_parse_from(["ccctl", "power-on", "5/12", "3=last"]).is_err());
+        let cli = Cli::try_parse_from(["ccctl", "shutter", "5/12", "2", "--to", "40"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Shutter {
+                num: Some(2),
+                motion: None,
+                to: Some(40),
+                ..
+            }
+        ));
+        assert!(
+            Cli::try_parse_from(["ccctl", "shutter", "5/12", "2", "up", "--to", "40"]).is_err()
+        );
+        assert!(Cli::try_parse_from(["ccctl", "shutter", "5/12", "8", "up"]).is_err());
+        assert!(Cli::try_parse_from(["ccctl", "shutter", "5/12", "1", "--to", "101"]).is_err());
+        let cli = Cli::try_parse_from([
+            "ccctl", "travel", "5/12", "1", "--up", "21s500ms", "--down", "19s",
+        ])
+        .unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Travel {
+                num: Some(1),
+                up: Some(up),
+                ..
+            } if up == Duration::from_millis(21_500)
+        ));
+        assert!(Cli::try_parse_from(["ccctl", "travel", "5/12", "1", "--up", "20s"]).is_err());
+        let cli = Cli::try_parse_from(["ccctl", "dead-time", "5/12", "800ms"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::DeadTime { pause: Some(p), .. } if p == Duration::from_millis(800)
+        ));
+        let cli = Cli::try_parse_from(["ccctl", "flash-all", "5", "fw.bin"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::FlashAll {
+                device_type: 5,
+                block_gap,
+                ..
+            } if block_gap == Duration::from_millis(100)
+        ));
+    }
+}
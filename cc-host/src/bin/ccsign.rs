@@ -0,0 +1,88 @@
+use cancomponents_host::error::Result;
+use cancomponents_host::sign;
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+/// Sign firmware images for cancomponents nodes. Nodes only activate images
+/// signed with the private key matching the public key built into them.
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// create PREFIX.key (keep it secret) and PREFIX.pub (for the firmware build)
+    Keygen { prefix: PathBuf },
+    /// append a signature to an image stamped by the firmware build
+    Sign {
+        #[arg(short, long)]
+        key: PathBuf,
+        image: PathBuf,
+        /// default: IMAGE with .signed.bin
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// check the signature of a signed image and show its header
+    Verify {
+        #[arg(short, long)]
+        key: PathBuf,
+        image: PathBuf,
+    },
+}
+
+fn main() -> ExitCode {
+    match run(Cli::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(cli: Cli) -> Result<()> {
+    match cli.command {
+        Command::Keygen { prefix } => {
+            sign::keygen(&prefix)?;
+            println!("created {0}.key and {0}.pub", prefix.display());
+        }
+        Command::Sign { key, image, output } => {
+            let key = sign::read_signing_key(&key)?;
+            let signed = sign::sign(&std::fs::read(&image)?, &key)?;
+            let output = output.unwrap_or_else(|| image.with_extension("signed.bin"));
+            std::fs::write(&output, signed)?;
+            println!("{}", output.display());
+        }
+        Command::Verify { key, image } => {
+            let key = sign::read_verifying_key(&key)?;
+            let header = sign::verify(&std::fs::read(&image)?, &key)?;
+            println!("{}: signature ok", image.display());
+            println!(
+                "version {} for type {} hwrev {}-{}, protocol {}",
+                header.version,
+                header.device_type,
+                header.hwrev_min,
+                header.hwrev_max,
+                header.protocol
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_cli() {
+        Cli::command().debug_assert();
+        let cli = Cli::try_parse_from(["ccsign", "sign", "-k", "a.key", "fw.bin"]).unwrap();
+        assert!(matches!(cli.command, Command::Sign { output: None, .. }));
+    }
+}
@@ -0,0 +1,16 @@
+use crate::error::Result;
+use crate::frame::Frame;
+use async_trait::async_trait;
+
+/// A CAN interface the host can talk through. Implemented for SocketCAN and
+/// for the in-process `MemoryBus` used in tests and simulations.
+///
+/// Both methods take `&self` so one interface can be read by a background
+/// task while other tasks send.
+#[async_trait]
+pub trait CanBus: Send + Sync {
+    async fn send(&self, frame: &Frame) -> Result<()>;
+    /// Next frame from another participant. Frames sent through this very
+    /// interface are not returned.
+    async fn recv(&self) -> Result<Frame>;
+}
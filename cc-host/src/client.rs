@@ -0,0 +1,283 @@
+use crate::bus::CanBus;
+use crate::error::{Error, Result};
+use crate::frame::{Frame, NodeAddr};
+use cancomponents_core::can_id::CanId;
+use cancomponents_core::can_message_type::CanMessageType;
+use cancomponents_core::message::{CanMessage, Payload};
+use cancomponents_core::transport::{Receiver, Segment, Sender, Step, MAX_LEN};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_millis(500);
+
+const FRAME_BUFFER: usize = 1024;
+
+/// Talks to nodes through a `CanBus`. A background task reads the bus and
+/// fans incoming frames out to every `Subscription`, so replies can be
+/// awaited while other traffic keeps flowing.
+pub struct Client<B> {
+    bus: Arc<B>,
+    frames: broadcast::Sender<Frame>,
+    reader: JoinHandle<()>,
+}
+
+impl<B: CanBus + 'static> Client<B> {
+    /// Must be called from within a tokio runtime.
+    pub fn new(bus: B) -> Self {
+        let bus = Arc::new(bus);
+        let (frames, _) = broadcast::channel(FRAME_BUFFER);
+        let reader = tokio::spawn(read_task(bus.clone(), frames.clone()));
+        Self {
+            bus,
+            frames,
+            reader,
+        }
+    }
+
+    pub fn bus(&self) -> &B {
+        &self.bus
+    }
+
+    /// Frames received from now on.
+    pub fn subscribe(&self) -> Subscription {
+        Subscription {
+            rx: self.frames.subscribe(),
+        }
+    }
+
+    pub async fn send_frame(&self, frame: &Frame) -> Result<()> {
+        self.bus.send(frame).await
+    }
+
+    pub async fn send(&self, addr: NodeAddr, msg: &CanMessage) -> Result<()> {
+        self.send_frame(&Frame::from_message(addr, msg)).await
+    }
+
+    pub async fn send_group(&self, group: u8, device_type: u8, msg: &CanMessage) -> Result<()> {
+        self.send_frame(&Frame::to_group(group, device_type, msg))
+            .await
+    }
+
+    /// Send a remote request for `msg_type` and wait for the node's answer.
+    pub async fn request(
+        &self,
+        addr: NodeAddr,
+        msg_type: CanMessageType,
+        timeout: Duration,
+    ) -> Result<CanMessage> {
+        self.transact(addr, &CanMessage::Request(msg_type), msg_type, timeout)
+            .await
+    }
+
+    /// Send `msg` and wait for a data frame of type `reply` from the same node.
+    pub async fn transact(
+        &self,
+        addr: NodeAddr,
+        msg: &CanMessage,
+        reply: CanMessageType,
+        timeout: Duration,
+    ) -> Result<CanMessage> {
+        // subscribe first, a fast node may answer before send() returns
+        let mut sub = self.subscribe();
+        self.send(addr, msg).await?;
+        sub.wait_for(reply, timeout, |frame| {
+            (!frame.rtr && frame.addr() == addr && frame.id.msg_type == reply)
+                .then(|| frame.message())
+        })
+        .await?
+        .map_err(Error::from)
+    }
+
+    /// Send `data` as one `msg_type` frame if it fits, otherwise segmented
+    /// at the pace the node asks for. `timeout` applies to each flow
+    /// control.
+    pub async fn send_long(
+        &self,
+        addr: NodeAddr,
+        msg_type: CanMessageType,
+        data: &[u8],
+        timeout: Duration,
+    ) -> Result<()> {
+        if let Ok(data) = Payload::from_slice(data) {
+            let id = CanId::new(addr.device_type, addr.device_id, msg_type);
+            let frame = Frame {
+                id,
+                data,
+                rtr: false,
+            };
+            return self.send_frame(&frame).await;
+        }
+        let mut sender = Sender::new(msg_type, data).map_err(Error::Transport)?;
+        let mut sub = self.subscribe();
+        loop {
+            while let Some(segment) = sender.next_segment() {
+                self.send(addr, &CanMessage::Segment(segment)).await?;
+                let separation = Duration::from_millis(sender.separation().as_millis());
+                if !separation.is_zero() {
+                    tokio::time::sleep(separation).await;
+                }
+            }
+            if sender.is_done() {
+                return Ok(());
+            }
+            let flow = sub
+                .wait_for(CanMessageType::Segment, timeout, |frame| {
+                    if frame.rtr || frame.addr() != addr {
+                        return None;
+                    }
+                    match frame.message() {
+                        Ok(CanMessage::Segment(Segment::Flow(flow))) => Some(flow),
+                        _ => None,
+                    }
+                })
+                .await?;
+            sender.flow(&flow).map_err(Error::Transport)?;
+        }
+    }
+
+    /// Next `msg_type` payload of `addr` on `sub`, a single frame or a
+    /// segmented one. `timeout` applies to each frame.
+    pub async fn receive_long(
+        &self,
+        sub: &mut Subscription,
+        addr: NodeAddr,
+        msg_type: CanMessageType,
+        timeout: Duration,
+    ) -> Result<Vec<u8>> {
+        // one flow control after the first frame, then the rest at once
+        let mut receiver: Receiver<MAX_LEN> =
+            Receiver::new(0, embassy_time::Duration::from_ticks(0));
+        loop {
+            let frame = sub
+                .wait_for(msg_type, timeout, |frame| {
+                    let ours = !frame.rtr && frame.addr() == addr;
+                    let kind = frame.id.msg_type;
+                    (ours && (kind == msg_type || kind == CanMessageType::Segment))
+                        .then(|| frame.clone())
+                })
+                .await?;
+            if frame.id.msg_type == msg_type {
+                return Ok(frame.data.to_vec());
+            }
+            let segment = match frame.message()? {
+                // flow control for a transfer of ours
+                CanMessage::Segment(Segment::Flow(_)) => continue,
+                CanMessage::Segment(segment) => segment,
+                _ => continue,
+            };
+            match receiver.feed(&segment).map_err(Error::Transport)? {
+                Step::Pending => {}
+                Step::Flow(flow) => {
+                    self.send(addr, &CanMessage::Segment(Segment::Flow(flow)))
+                        .await?
+                }
+                Step::Complete(kind) if kind == msg_type => return Ok(receiver.data().to_vec()),
+                Step::Complete(_) => {}
+            }
+        }
+    }
+}
+
+impl<B> Drop for Client<B> {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+async fn read_task<B: CanBus>(bus: Arc<B>, frames: broadcast::Sender<Frame>) {
+    while let Ok(frame) = bus.recv().await {
+        let _ = frames.send(frame);
+    }
+}
+
+pub struct Subscription {
+    rx: broadcast::Receiver<Frame>,
+}
+
+impl Subscription {
+    pub async fn next(&mut self) -> Result<Frame> {
+        loop {
+            match self.rx.recv().await {
+                Ok(frame) => return Ok(frame),
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return Err(Error::Closed),
+            }
+        }
+    }
+
+    /// Next frame if one is already buffered.
+    pub fn try_next(&mut self) -> Option<Frame> {
+        loop {
+            match self.rx.try_recv() {
+                Ok(frame) => return Some(frame),
+                Err(TryRecvError::Lagged(_)) => continue,
+                Err(_) => return None,
+            }
+        }
+    }
+
+    /// Wait until `matcher` returns `Some` for a received frame. `waiting_for`
+    /// is only used for the timeout error.
+    pub async fn wait_for<T>(
+        &mut self,
+        waiting_for: CanMessageType,
+        timeout: Duration,
+        mut matcher: impl FnMut(&Frame) -> Option<T>,
+    ) -> Result<T> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let frame = tokio::time::timeout_at(deadline, self.next())
+                .await
+                .map_err(|_| Error::Timeout(waiting_for))??;
+            if let Some(result) = matcher(&frame) {
+                return Ok(result);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::MemoryBus;
+
+    #[tokio::test]
+    async fn test_request_uptime() {
+        let bus = MemoryBus::new();
+        let node = bus.endpoint();
+        let addr = NodeAddr::new(5, 12);
+
+        tokio::spawn(async move {
+            loop {
+                let frame = node.recv().await.unwrap();
+                if frame.rtr && frame.addr() == addr {
+                    let reply = Frame::from_message(addr, &CanMessage::Uptime(42));
+                    node.send(&reply).await.unwrap();
+                }
+            }
+        });
+
+        let client = Client::new(bus);
+        let reply = client
+            .request(addr, CanMessageType::Uptime, DEFAULT_TIMEOUT)
+            .await
+            .unwrap();
+        assert_eq!(reply, CanMessage::Uptime(42));
+
+        let timeout = client
+            .request(
+                NodeAddr::new(5, 13),
+                CanMessageType::Uptime,
+                DEFAULT_TIMEOUT,
+            )
+            .await;
+        assert!(matches!(
+            timeout,
+            Err(Error::Timeout(CanMessageType::Uptime))
+        ));
+    }
+}
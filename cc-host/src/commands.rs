@@ -0,0 +1,1536 @@
+use crate::bus::CanBus;
+use crate::client::{Client, Subscription};
+use crate::error::{Error, Result};
+use crate::frame::NodeAddr;
+use cancomponents_core::announcement::{Announcement, Channels};
+use cancomponents_core::binding::Binding;
+use cancomponents_core::can_message_type::CanMessageType;
+use cancomponents_core::device_message::UNASSIGNED_ID;
+use cancomponents_core::extension::Extension;
+use cancomponents_core::group::{Groups, MAX_GROUP};
+use cancomponents_core::message::{CanMessage, Payload};
+use cancomponents_core::metadata::{Entry, Field, MAX_VALUE_LEN};
+use cancomponents_core::power_on::{PowerOn, PowerOnPolicy};
+use cancomponents_core::relais_message::{RelaisMessage, RelaisMode, RelaisState};
+use cancomponents_core::rollershutter::{Calibration, ShutterCommand, ShutterReport, MAX_SHUTTERS};
+use cancomponents_core::scene::Scene;
+use cancomponents_core::supervisor::{ModeReport, Policy, Supervision};
+use heapless::String;
+use std::time::Duration;
+
+/// Address every node listens to in addition to its own.
+pub const BROADCAST: NodeAddr = NodeAddr {
+    device_type: 0,
+    device_id: 0,
+};
+
+/// Type of a node that has none configured, as it appears on the bus.
+pub const UNASSIGNED_TYPE: u8 = 0x3F;
+
+/// Nodes send `Available` only a few seconds after reset.
+pub const RESTART_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NodeInfo {
+    pub uptime_minutes: Option<u32>,
+    pub uid: Option<u64>,
+    pub name: Option<String<8>>,
+    pub hwrev: Option<u8>,
+    pub relais_mode: Option<RelaisMode>,
+    pub extension: Option<Extension>,
+    pub version: Option<String<8>>,
+    /// `None` for firmware without metadata
+    pub metadata: Option<Metadata>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Metadata {
+    pub name: String<MAX_VALUE_LEN>,
+    pub room: String<MAX_VALUE_LEN>,
+    pub floor: String<MAX_VALUE_LEN>,
+    pub notes: String<MAX_VALUE_LEN>,
+}
+
+impl Metadata {
+    pub fn get(&self, field: Field) -> &str {
+        match field {
+            Field::Name => &self.name,
+            Field::Room => &self.room,
+            Field::Floor => &self.floor,
+            Field::Notes => &self.notes,
+        }
+    }
+
+    fn set(&mut self, entry: Entry) {
+        let value = match entry.field {
+            Field::Name => &mut self.name,
+            Field::Room => &mut self.room,
+            Field::Floor => &mut self.floor,
+            Field::Notes => &mut self.notes,
+        };
+        *value = entry.value;
+    }
+}
+
+impl NodeInfo {
+    fn is_complete(&self) -> bool {
+        self.uptime_minutes.is_some()
+            && self.uid.is_some()
+            && self.name.is_some()
+            && self.hwrev.is_some()
+            && self.relais_mode.is_some()
+            && self.extension.is_some()
+            && self.version.is_some()
+    }
+}
+
+/// Ping everyone and collect who answers within `timeout`.
+pub async fn scan<B: CanBus + 'static>(
+    client: &Client<B>,
+    timeout: Duration,
+) -> Result<Vec<NodeAddr>> {
+    let mut sub = client.subscribe();
+    client.send(BROADCAST, &CanMessage::Ping).await?;
+
+    let mut nodes = Vec::new();
+    let collect = sub.wait_for(CanMessageType::Ping, timeout, |frame| {
+        if !frame.rtr && frame.id.msg_type == CanMessageType::Ping && frame.addr() != BROADCAST {
+            let addr = frame.addr();
+            if !nodes.contains(&addr) {
+                nodes.push(addr);
+            }
+        }
+        None::<()>
+    });
+    match collect.await {
+        Ok(()) | Err(Error::Timeout(_)) => {}
+        Err(e) => return Err(e),
+    }
+    nodes.sort_by_key(|addr| (addr.device_type, addr.device_id));
+    Ok(nodes)
+}
+
+/// Ask a node for all of its parameters via `RequestParameter`.
+pub async fn info<B: CanBus + 'static>(
+    client: &Client<B>,
+    addr: NodeAddr,
+    timeout: Duration,
+) -> Result<NodeInfo> {
+    let mut sub = client.subscribe();
+    let mut metadata_sub = client.subscribe();
+    client.send(addr, &CanMessage::RequestParameter).await?;
+    // long fields wait for our flow control, collect them alongside
+    let metadata = receive_metadata(client, &mut metadata_sub, addr, timeout);
+
+    let mut info = NodeInfo::default();
+    let collect = sub.wait_for(CanMessageType::RequestParameter, timeout, |frame| {
+        if frame.rtr || frame.addr() != addr {
+            return None;
+        }
+        match frame.message() {
+            Ok(CanMessage::Uptime(minutes)) => info.uptime_minutes = Some(minutes),
+            Ok(CanMessage::DeviceUid0(uid)) => info.uid = Some(uid),
+            Ok(CanMessage::CustomString(name)) => info.name = Some(name),
+            Ok(CanMessage::HwRev(hwrev)) => info.hwrev = Some(hwrev),
+            Ok(CanMessage::RelaisMode(mode)) => info.relais_mode = Some(mode),
+            Ok(CanMessage::ExtensionMode(extension)) => info.extension = Some(extension),
+            Ok(CanMessage::ApplicationVersionString(version)) => info.version = Some(version),
+            _ => {}
+        }
+        info.is_complete().then_some(())
+    });
+    let (collected, metadata) = tokio::join!(collect, metadata);
+    info.metadata = metadata.ok();
+    match collected {
+        Ok(()) => {}
+        // older firmware does not answer everything, keep what we got
+        Err(Error::Timeout(_)) if info != NodeInfo::default() => {}
+        Err(e) => return Err(e),
+    }
+    Ok(info)
+}
+
+/// The fields a node sends after a request, each in its own transfer.
+async fn receive_metadata<B: CanBus + 'static>(
+    client: &Client<B>,
+    sub: &mut Subscription,
+    addr: NodeAddr,
+    timeout: Duration,
+) -> Result<Metadata> {
+    let mut metadata = Metadata::default();
+    let mut missing = Field::ALL.to_vec();
+    while !missing.is_empty() {
+        let data = client
+            .receive_long(sub, addr, CanMessageType::Metadata, timeout)
+            .await?;
+        let entry = Entry::parse(&data)?;
+        missing.retain(|field| *field != entry.field);
+        metadata.set(entry);
+    }
+    Ok(metadata)
+}
+
+/// Name, room, floor and notes of a node.
+pub async fn metadata<B: CanBus + 'static>(
+    client: &Client<B>,
+    addr: NodeAddr,
+    timeout: Duration,
+) -> Result<Metadata> {
+    let mut sub = client.subscribe();
+    client
+        .send(addr, &CanMessage::Request(CanMessageType::Metadata))
+        .await?;
+    receive_metadata(client, &mut sub, addr, timeout).await
+}
+
+/// Replace one field and read the fields back, an empty value clears it.
+pub async fn set_metadata<B: CanBus + 'static>(
+    client: &Client<B>,
+    addr: NodeAddr,
+    field: Field,
+    value: &str,
+    timeout: Duration,
+) -> Result<()> {
+    let entry = Entry::new(field, value)?;
+    client
+        .send_long(addr, CanMessageType::Metadata, &entry.to_bytes(), timeout)
+        .await?;
+    let readback = metadata(client, addr, timeout).await?;
+    if readback.get(field) != value {
+        return Err(Error::InvalidArgument(format!(
+            "node reports {:?} for {field:?} after setting it",
+            readback.get(field)
+        )));
+    }
+    Ok(())
+}
+
+/// Ask a node who it is. `None` for firmware that answers `Available`
+/// without an announcement.
+pub async fn announcement<B: CanBus + 'static>(
+    client: &Client<B>,
+    addr: NodeAddr,
+    timeout: Duration,
+) -> Result<Option<(Announcement, Channels)>> {
+    let mut sub = client.subscribe();
+    client
+        .send(addr, &CanMessage::Request(CanMessageType::Available))
+        .await?;
+
+    let mut announcement = None;
+    sub.wait_for(CanMessageType::Available, timeout, |frame| {
+        if frame.rtr || frame.addr() != addr {
+            return None;
+        }
+        match (frame.message(), announcement) {
+            (Ok(CanMessage::Available(None)), _) => Some(None),
+            (Ok(CanMessage::Available(Some(a))), _) => {
+                announcement = Some(a);
+                None
+            }
+            (Ok(CanMessage::Channels(channels)), Some(a)) => Some(Some((a, channels))),
+            _ => None,
+        }
+    })
+    .await
+}
+
+/// Assign a new type and id, bound to the UID the node reports so no other
+/// node takes it. The node's acceptance filter only picks up the new address
+/// after a restart.
+pub async fn set_id<B: CanBus + 'static>(
+    client: &Client<B>,
+    addr: NodeAddr,
+    new: NodeAddr,
+    timeout: Duration,
+) -> Result<()> {
+    let uid = match client
+        .request(addr, CanMessageType::DeviceUid0, timeout)
+        .await?
+    {
+        CanMessage::DeviceUid0(uid) => uid,
+        _ => unreachable!("request only returns the requested type"),
+    };
+    let msg = CanMessage::DeviceIdType {
+        id: new.device_id,
+        device_type: new.device_type,
+        uid: Some(uid),
+    };
+    client.send(addr, &msg).await
+}
+
+/// A node that asked for an address with `AddressClaim`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Claim {
+    pub uid: u64,
+    /// assigned address, `None` if the node has no device type and none
+    /// was given, or its type has no free id left
+    pub addr: Option<NodeAddr>,
+}
+
+/// Answer the `AddressClaim`s of unaddressed nodes for `duration`. Every
+/// node gets the lowest id not used by the nodes answering a `scan`, with
+/// `device_type` or else the type it has configured. The assignment is
+/// bound to the node's UID; the node restarts and comes up on its address.
+pub async fn claim<B: CanBus + 'static>(
+    client: &Client<B>,
+    device_type: Option<u8>,
+    duration: Duration,
+    timeout: Duration,
+) -> Result<Vec<Claim>> {
+    let mut used = scan(client, timeout).await?;
+    let mut claims: Vec<Claim> = Vec::new();
+    let mut sub = client.subscribe();
+    let deadline = tokio::time::Instant::now() + duration;
+    loop {
+        let frame = match tokio::time::timeout_at(deadline, sub.next()).await {
+            Ok(frame) => frame?,
+            Err(_) => return Ok(claims),
+        };
+        let Ok(CanMessage::AddressClaim(uid)) = frame.message() else {
+            continue;
+        };
+        // a node that missed its assignment claims again
+        let claim = match claims.iter().find(|claim| claim.uid == uid) {
+            Some(claim) => *claim,
+            None => {
+                let device_type = device_type
+                    .or(Some(frame.addr().device_type))
+                    .filter(|&t| t != 0 && t != UNASSIGNED_TYPE);
+                let addr = device_type.and_then(|device_type| {
+                    (1..UNASSIGNED_ID)
+                        .map(|id| NodeAddr::new(device_type, id))
+                        .find(|addr| !used.contains(addr))
+                });
+                used.extend(addr);
+                claims.push(Claim { uid, addr });
+                *claims.last().unwrap()
+            }
+        };
+        if let Some(addr) = claim.addr {
+            let msg = CanMessage::DeviceIdType {
+                id: addr.device_id,
+                device_type: addr.device_type,
+                uid: Some(uid),
+            };
+            client.send(BROADCAST, &msg).await?;
+        }
+    }
+}
+
+/// Set the custom string and read it back.
+pub async fn set_name<B: CanBus + 'static>(
+    client: &Client<B>,
+    addr: NodeAddr,
+    name: &str,
+    timeout: Duration,
+) -> Result<()> {
+    let name: String<8> = String::try_from(name)
+        .map_err(|_| Error::InvalidArgument(format!("name {name:?} is longer than 8 bytes")))?;
+    client
+        .send(addr, &CanMessage::CustomString(name.clone()))
+        .await?;
+    match client
+        .request(addr, CanMessageType::CustomString, timeout)
+        .await?
+    {
+        CanMessage::CustomString(readback) if readback == name => Ok(()),
+        other => Err(Error::InvalidArgument(format!(
+            "node reports {other:?} after setting name"
+        ))),
+    }
+}
+
+/// Switch a relay. Returns the state the node reports, or `None` if it did
+/// not report one (the node stays silent when nothing changed).
+pub async fn relay<B: CanBus + 'static>(
+    client: &Client<B>,
+    addr: NodeAddr,
+    num: usize,
+    state: RelaisState,
+    duration: Duration,
+    timeout: Duration,
+) -> Result<Option<RelaisState>> {
+    let msg = CanMessage::Relais(RelaisMessage {
+        num,
+        state,
+        duration: embassy_time::Duration::from_millis(duration.as_millis() as u64),
+        bank: 0,
+    });
+    match client
+        .transact(addr, &msg, CanMessageType::RelaisState, timeout)
+        .await
+    {
+        Ok(CanMessage::RelaisState(state)) => Ok(Some(state)),
+        Ok(_) | Err(Error::Timeout(_)) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Move a rollershutter or send it to a position. Returns the report of
+/// the node as the motor starts or stops.
+pub async fn shutter<B: CanBus + 'static>(
+    client: &Client<B>,
+    addr: NodeAddr,
+    command: ShutterCommand,
+    timeout: Duration,
+) -> Result<ShutterReport> {
+    let num = command.num() as u8;
+    let mut sub = client.subscribe();
+    client
+        .send(addr, &CanMessage::Rollershutter(command))
+        .await?;
+    sub.wait_for(CanMessageType::RollershutterState, timeout, |frame| {
+        if frame.rtr || frame.addr() != addr {
+            return None;
+        }
+        match frame.message() {
+            Ok(CanMessage::RollershutterState(report)) if report.num == num => Some(Ok(report)),
+            // no shutter there or no travel times
+            Ok(CanMessage::DeviceError(report)) => Some(Err(Error::Node(report))),
+            _ => None,
+        }
+    })
+    .await?
+}
+
+/// Motor and position of every shutter, empty for nodes in `Relais` mode.
+pub async fn shutters<B: CanBus + 'static>(
+    client: &Client<B>,
+    addr: NodeAddr,
+    timeout: Duration,
+) -> Result<Vec<ShutterReport>> {
+    let mut reports = Vec::new();
+    collect(
+        client,
+        addr,
+        CanMessageType::RollershutterState,
+        timeout,
+        |msg| {
+            if let CanMessage::RollershutterState(report) = msg {
+                reports.push(report);
+            }
+            reports.len() == MAX_SHUTTERS
+        },
+    )
+    .await?;
+    reports.sort_by_key(|report| report.num);
+    Ok(reports)
+}
+
+/// Pause of the shutter motors between the directions.
+pub async fn dead_time<B: CanBus + 'static>(
+    client: &Client<B>,
+    addr: NodeAddr,
+    timeout: Duration,
+) -> Result<Duration> {
+    match client
+        .request(addr, CanMessageType::DeadTime, timeout)
+        .await?
+    {
+        CanMessage::DeadTime(dead_time) => Ok(dead_time.into()),
+        other => Err(Error::InvalidArgument(format!(
+            "node reports {other:?} for its dead time"
+        ))),
+    }
+}
+
+/// Replace the pause between the directions and read it back, nodes use
+/// it from the next stop.
+pub async fn set_dead_time<B: CanBus + 'static>(
+    client: &Client<B>,
+    addr: NodeAddr,
+    new: Duration,
+    timeout: Duration,
+) -> Result<()> {
+    if new.as_millis() > u16::MAX as u128 {
+        return Err(Error::InvalidArgument(format!(
+            "dead time of {} ms is above {} ms",
+            new.as_millis(),
+            u16::MAX
+        )));
+    }
+    let dead_time = embassy_time::Duration::from_millis(new.as_millis() as u64);
+    client.send(addr, &CanMessage::DeadTime(dead_time)).await?;
+    let readback = self::dead_time(client, addr, timeout).await?;
+    if readback != new {
+        return Err(Error::InvalidArgument(format!(
+            "node reports {readback:?} after setting {new:?}"
+        )));
+    }
+    Ok(())
+}
+
+/// Travel times of every shutter, zero where not calibrated.
+pub async fn travel_times<B: CanBus + 'static>(
+    client: &Client<B>,
+    addr: NodeAddr,
+    timeout: Duration,
+) -> Result<Vec<Calibration>> {
+    let mut calibrations = Vec::new();
+    collect(
+        client,
+        addr,
+        CanMessageType::ShutterTravel,
+        timeout,
+        |msg| {
+            if let CanMessage::ShutterTravel(calibration) = msg {
+                calibrations.push(calibration);
+            }
+            calibrations.len() == MAX_SHUTTERS
+        },
+    )
+    .await?;
+    calibrations.sort_by_key(|calibration| calibration.num);
+    Ok(calibrations)
+}
+
+/// Store the travel times of a shutter and read them back, the node uses
+/// them right away.
+pub async fn calibrate<B: CanBus + 'static>(
+    client: &Client<B>,
+    addr: NodeAddr,
+    calibration: Calibration,
+    timeout: Duration,
+) -> Result<()> {
+    client
+        .send(addr, &CanMessage::ShutterTravel(calibration))
+        .await?;
+    if !travel_times(client, addr, timeout)
+        .await?
+        .contains(&calibration)
+    {
+        return Err(Error::InvalidArgument(format!(
+            "node did not store the travel times of shutter {}",
+            calibration.num
+        )));
+    }
+    Ok(())
+}
+
+/// Request `msg_type` and pass every answer to `done` until it returns
+/// true or `timeout` passes.
+async fn collect<B: CanBus + 'static>(
+    client: &Client<B>,
+    addr: NodeAddr,
+    msg_type: CanMessageType,
+    timeout: Duration,
+    mut done: impl FnMut(CanMessage) -> bool,
+) -> Result<()> {
+    let mut sub = client.subscribe();
+    client.send(addr, &CanMessage::Request(msg_type)).await?;
+    let collect = sub.wait_for(msg_type, timeout, |frame| {
+        if frame.rtr || frame.addr() != addr || frame.id.msg_type != msg_type {
+            return None;
+        }
+        frame.message().ok().map(&mut done).filter(|done| *done)
+    });
+    match collect.await {
+        Ok(_) | Err(Error::Timeout(_)) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Groups the node is configured for, active after its last restart.
+pub async fn groups<B: CanBus + 'static>(
+    client: &Client<B>,
+    addr: NodeAddr,
+    timeout: Duration,
+) -> Result<Groups> {
+    match client
+        .request(addr, CanMessageType::DeviceGroup, timeout)
+        .await?
+    {
+        CanMessage::DeviceGroup(groups) => Ok(groups),
+        other => Err(Error::InvalidArgument(format!(
+            "node reports {other:?} for its groups"
+        ))),
+    }
+}
+
+/// Replace the groups of a node and read them back. The node's acceptance
+/// filter only picks them up after a restart.
+pub async fn set_groups<B: CanBus + 'static>(
+    client: &Client<B>,
+    addr: NodeAddr,
+    new: Groups,
+    timeout: Duration,
+) -> Result<()> {
+    client.send(addr, &CanMessage::DeviceGroup(new)).await?;
+    let readback = groups(client, addr, timeout).await?;
+    if readback != new {
+        return Err(Error::InvalidArgument(format!(
+            "node reports groups {readback:?} after setting {new:?}"
+        )));
+    }
+    Ok(())
+}
+
+/// Switch a relay on every member of `group`, only on nodes of
+/// `device_type` unless it is 0. Members do not acknowledge.
+pub async fn group_relay<B: CanBus + 'static>(
+    client: &Client<B>,
+    group: u8,
+    device_type: u8,
+    num: usize,
+    state: RelaisState,
+    duration: Duration,
+) -> Result<()> {
+    if !(1..=MAX_GROUP).contains(&group) {
+        return Err(Error::InvalidArgument(format!(
+            "group {group} is not in 1..={MAX_GROUP}"
+        )));
+    }
+    let msg = CanMessage::Relais(RelaisMessage {
+        num,
+        state,
+        duration: embassy_time::Duration::from_millis(duration.as_millis() as u64),
+        bank: 0,
+    });
+    client.send_group(group, device_type, &msg).await
+}
+
+/// Replace a stored scene. Nodes do not acknowledge, recall it to check.
+pub async fn define_scene<B: CanBus + 'static>(
+    client: &Client<B>,
+    addr: NodeAddr,
+    scene: Scene,
+) -> Result<()> {
+    client.send(addr, &CanMessage::SceneDefine(scene)).await
+}
+
+/// Store the current relay states of a node as a scene. Returns what the
+/// node stored.
+pub async fn store_scene<B: CanBus + 'static>(
+    client: &Client<B>,
+    addr: NodeAddr,
+    scene: u8,
+    delay: Duration,
+    timeout: Duration,
+) -> Result<Scene> {
+    let msg = CanMessage::SceneStore {
+        scene,
+        delay: embassy_time::Duration::from_secs(delay.as_secs()),
+    };
+    match client
+        .transact(addr, &msg, CanMessageType::SceneDefine, timeout)
+        .await?
+    {
+        CanMessage::SceneDefine(stored) if stored.num == scene => Ok(stored),
+        other => Err(Error::InvalidArgument(format!(
+            "node reports {other:?} after storing scene {scene}"
+        ))),
+    }
+}
+
+/// Recall a scene on a node, its type (TYPE/0) or everyone (0/0).
+pub async fn recall_scene<B: CanBus + 'static>(
+    client: &Client<B>,
+    addr: NodeAddr,
+    scene: u8,
+) -> Result<()> {
+    client.send(addr, &CanMessage::SceneRecall(scene)).await
+}
+
+/// Recall a scene on every member of `group`, only on nodes of
+/// `device_type` unless it is 0.
+pub async fn group_recall_scene<B: CanBus + 'static>(
+    client: &Client<B>,
+    group: u8,
+    device_type: u8,
+    scene: u8,
+) -> Result<()> {
+    client
+        .send_group(group, device_type, &CanMessage::SceneRecall(scene))
+        .await
+}
+
+/// Bindings a node stores, in slot order.
+pub async fn bindings<B: CanBus + 'static>(
+    client: &Client<B>,
+    addr: NodeAddr,
+    timeout: Duration,
+) -> Result<Vec<Binding>> {
+    let mut sub = client.subscribe();
+    client
+        .send(addr, &CanMessage::Request(CanMessageType::BindingDefine))
+        .await?;
+
+    // the node sends one frame per binding and nothing without any
+    let mut bindings = Vec::new();
+    let collect = sub.wait_for(CanMessageType::BindingDefine, timeout, |frame| {
+        if frame.rtr || frame.addr() != addr {
+            return None;
+        }
+        if let Ok(CanMessage::BindingDefine(binding)) = frame.message() {
+            bindings.push(binding);
+        }
+        None::<()>
+    });
+    match collect.await {
+        Ok(()) | Err(Error::Timeout(_)) => {}
+        Err(e) => return Err(e),
+    }
+    bindings.sort_by_key(|binding| binding.slot);
+    Ok(bindings)
+}
+
+/// Store a binding in its slot and read the table back.
+pub async fn set_binding<B: CanBus + 'static>(
+    client: &Client<B>,
+    addr: NodeAddr,
+    binding: Binding,
+    timeout: Duration,
+) -> Result<()> {
+    client
+        .send(addr, &CanMessage::BindingDefine(binding))
+        .await?;
+    if !bindings(client, addr, timeout).await?.contains(&binding) {
+        return Err(Error::InvalidArgument(format!(
+            "node did not store binding {}",
+            binding.slot
+        )));
+    }
+    Ok(())
+}
+
+pub async fn clear_binding<B: CanBus + 'static>(
+    client: &Client<B>,
+    addr: NodeAddr,
+    slot: u8,
+) -> Result<()> {
+    client.send(addr, &CanMessage::BindingClear(slot)).await
+}
+
+/// What the node does once it stops hearing the gateway.
+pub async fn policy<B: CanBus + 'static>(
+    client: &Client<B>,
+    addr: NodeAddr,
+    timeout: Duration,
+) -> Result<Policy> {
+    match client
+        .request(addr, CanMessageType::AutonomousPolicy, timeout)
+        .await?
+    {
+        CanMessage::AutonomousPolicy(policy) => Ok(policy),
+        other => Err(Error::InvalidArgument(format!(
+            "node reports {other:?} for its policy"
+        ))),
+    }
+}
+
+/// Replace the policy and read it back, used from the next time the
+/// gateway goes missing.
+pub async fn set_policy<B: CanBus + 'static>(
+    client: &Client<B>,
+    addr: NodeAddr,
+    new: Policy,
+    timeout: Duration,
+) -> Result<()> {
+    client
+        .send(addr, &CanMessage::AutonomousPolicy(new))
+        .await?;
+    let readback = policy(client, addr, timeout).await?;
+    if readback != new {
+        return Err(Error::InvalidArgument(format!(
+            "node reports policy {readback:?} after setting {new:?}"
+        )));
+    }
+    Ok(())
+}
+
+/// Whether the node runs autonomously and for how long.
+pub async fn node_mode<B: CanBus + 'static>(
+    client: &Client<B>,
+    addr: NodeAddr,
+    timeout: Duration,
+) -> Result<ModeReport> {
+    match client
+        .request(addr, CanMessageType::NodeMode, timeout)
+        .await?
+    {
+        CanMessage::NodeMode(report) => Ok(report),
+        other => Err(Error::InvalidArgument(format!(
+            "node reports {other:?} for its mode"
+        ))),
+    }
+}
+
+/// Echo interval and tolerated misses of a node.
+pub async fn supervision<B: CanBus + 'static>(
+    client: &Client<B>,
+    addr: NodeAddr,
+    timeout: Duration,
+) -> Result<Supervision> {
+    match client
+        .request(addr, CanMessageType::Supervision, timeout)
+        .await?
+    {
+        CanMessage::Supervision(supervision) => Ok(supervision),
+        other => Err(Error::InvalidArgument(format!(
+            "node reports {other:?} for its supervision"
+        ))),
+    }
+}
+
+/// Replace interval and misses and read them back, nodes use them right
+/// away.
+pub async fn set_supervision<B: CanBus + 'static>(
+    client: &Client<B>,
+    addr: NodeAddr,
+    new: Supervision,
+    timeout: Duration,
+) -> Result<()> {
+    if new.interval.as_secs() == 0 || new.interval.as_secs() > u16::MAX as u64 {
+        return Err(Error::InvalidArgument(format!(
+            "interval of {} s is not in 1..={}",
+            new.interval.as_secs(),
+            u16::MAX
+        )));
+    }
+    client.send(addr, &CanMessage::Supervision(new)).await?;
+    let readback = supervision(client, addr, timeout).await?;
+    if readback != new {
+        return Err(Error::InvalidArgument(format!(
+            "node reports {readback:?} after setting {new:?}"
+        )));
+    }
+    Ok(())
+}
+
+/// What each relay of a node does after a reset.
+pub async fn power_on<B: CanBus + 'static>(
+    client: &Client<B>,
+    addr: NodeAddr,
+    timeout: Duration,
+) -> Result<PowerOnPolicy> {
+    match client
+        .request(addr, CanMessageType::PowerOn, timeout)
+        .await?
+    {
+        CanMessage::PowerOn(policy) => Ok(policy),
+        other => Err(Error::InvalidArgument(format!(
+            "node reports {other:?} for its power-on policy"
+        ))),
+    }
+}
+
+/// Replace the power-on policy and read it back, nodes use it from their
+/// next reset.
+pub async fn set_power_on<B: CanBus + 'static>(
+    client: &Client<B>,
+    addr: NodeAddr,
+    new: PowerOnPolicy,
+    timeout: Duration,
+) -> Result<()> {
+    client.send(addr, &CanMessage::PowerOn(new)).await?;
+    let readback = power_on(client, addr, timeout).await?;
+    if readback != new {
+        return Err(Error::InvalidArgument(format!(
+            "node reports {readback:?} after setting {new:?}"
+        )));
+    }
+    Ok(())
+}
+
+/// Change the power-on behaviour of single relays, the others keep theirs.
+pub async fn update_power_on<B: CanBus + 'static>(
+    client: &Client<B>,
+    addr: NodeAddr,
+    changes: &[(usize, PowerOn)],
+    timeout: Duration,
+) -> Result<PowerOnPolicy> {
+    let mut policy = power_on(client, addr, timeout).await?;
+    for &(num, behaviour) in changes {
+        let Some(slot) = policy.0.get_mut(num) else {
+            return Err(Error::InvalidArgument(format!(
+                "relay {num} is not below {}",
+                policy.0.len()
+            )));
+        };
+        *slot = behaviour;
+    }
+    set_power_on(client, addr, policy, timeout).await?;
+    Ok(policy)
+}
+
+/// Tell every node the gateway is alive, sent once per interval.
+pub async fn echo<B: CanBus + 'static>(client: &Client<B>) -> Result<()> {
+    client
+        .send(BROADCAST, &CanMessage::Echo(Payload::new()))
+        .await
+}
+
+/// Stop nodes from counting missing echoes for `duration`, e.g. while the
+/// gateway is down for maintenance. Zero resumes. Nodes do not
+/// acknowledge.
+pub async fn suspend_supervision<B: CanBus + 'static>(
+    client: &Client<B>,
+    addr: NodeAddr,
+    duration: Duration,
+) -> Result<()> {
+    let duration = embassy_time::Duration::from_secs(duration.as_secs());
+    client.send(addr, &CanMessage::PingDisable(duration)).await
+}
+
+/// Restart a node and, unless `timeout` is zero, wait until it announces
+/// itself again.
+pub async fn restart<B: CanBus + 'static>(
+    client: &Client<B>,
+    addr: NodeAddr,
+    timeout: Duration,
+) -> Result<()> {
+    if timeout.is_zero() {
+        return client.send(addr, &CanMessage::Restart).await;
+    }
+    client
+        .transact(
+            addr,
+            &CanMessage::Restart,
+            CanMessageType::Available,
+            timeout,
+        )
+        .await
+        .map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{Subscription, DEFAULT_TIMEOUT};
+    use crate::frame::Frame;
+    use crate::memory::MemoryBus;
+    use crate::sim::SimNode;
+    use cancomponents_core::announcement::Capabilities;
+    use cancomponents_core::binding::Action;
+    use cancomponents_core::button_message::{ButtonMessage, ButtonState};
+    use cancomponents_core::decode_error::DecodeError;
+    use cancomponents_core::error_report::ErrorCode;
+    use cancomponents_core::rollershutter::TravelTimes;
+    use cancomponents_core::supervisor::{Mode, ShutterSafeState};
+
+    fn setup(nodes: &[NodeAddr]) -> Client<MemoryBus> {
+        let bus = MemoryBus::new();
+        for (uid, addr) in nodes.iter().enumerate() {
+            tokio::spawn(SimNode::new(*addr, uid as u64).run(bus.endpoint()));
+        }
+        Client::new(bus)
+    }
+
+    #[tokio::test]
+    async fn test_scan_and_info() {
+        let a = NodeAddr::new(5, 12);
+        let b = NodeAddr::new(4, 3);
+        let client = setup(&[a, b]);
+
+        let nodes = scan(&client, Duration::from_millis(100)).await.unwrap();
+        assert_eq!(nodes, vec![b, a]);
+
+        let info = info(&client, a, DEFAULT_TIMEOUT).await.unwrap();
+        assert!(info.is_complete());
+        assert_eq!(info.uid, Some(0));
+        assert_eq!(info.metadata, Some(Metadata::default()));
+
+        let (announcement, channels) = announcement(&client, b, DEFAULT_TIMEOUT)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(announcement.device_type, 4);
+        assert!(announcement
+            .capabilities
+            .contains(Capabilities::RELAIS | Capabilities::ADDRESS_CLAIM));
+        assert_eq!(channels.relais, 16);
+    }
+
+    #[tokio::test]
+    async fn test_set_id_name_relay_restart() {
+        let a = NodeAddr::new(5, 12);
+        let client = setup(&[a]);
+
+        set_name(&client, a, "kitchen", DEFAULT_TIMEOUT)
+            .await
+            .unwrap();
+        assert!(set_name(&client, a, "way too long", DEFAULT_TIMEOUT)
+            .await
+            .is_err());
+
+        let state = relay(
+            &client,
+            a,
+            3,
+            RelaisState::On,
+            Duration::from_secs(30),
+            DEFAULT_TIMEOUT,
+        )
+        .await
+        .unwrap();
+        assert_eq!(state, Some(RelaisState::On));
+
+        let moved = NodeAddr::new(5, 20);
+        set_id(&client, a, moved, DEFAULT_TIMEOUT).await.unwrap();
+        restart(&client, moved, DEFAULT_TIMEOUT).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_groups() {
+        let floor = NodeAddr::new(5, 12);
+        let other = NodeAddr::new(5, 13);
+        let lamps = NodeAddr::new(7, 1);
+        let client = setup(&[floor, other, lamps]);
+
+        let first_floor: Groups = [3].into_iter().collect();
+        set_groups(&client, floor, first_floor, DEFAULT_TIMEOUT)
+            .await
+            .unwrap();
+        set_groups(&client, lamps, first_floor, DEFAULT_TIMEOUT)
+            .await
+            .unwrap();
+        assert_eq!(
+            groups(&client, other, DEFAULT_TIMEOUT).await.unwrap(),
+            Groups::default()
+        );
+        // active after the restart only
+        let switch = |device_type| {
+            let client = &client;
+            async move {
+                let mut sub = client.subscribe();
+                group_relay(client, 3, device_type, 1, RelaisState::On, Duration::ZERO)
+                    .await
+                    .unwrap();
+                let mut switched = Vec::new();
+                let _ = sub
+                    .wait_for(
+                        CanMessageType::RelaisState,
+                        Duration::from_millis(100),
+                        |frame| {
+                            if frame.id.msg_type == CanMessageType::RelaisState {
+                                switched.push(frame.addr());
+                            }
+                            None::<()>
+                        },
+                    )
+                    .await;
+                switched.sort();
+                switched
+            }
+        };
+        assert_eq!(switch(0).await, vec![]);
+        restart(&client, floor, DEFAULT_TIMEOUT).await.unwrap();
+        restart(&client, lamps, DEFAULT_TIMEOUT).await.unwrap();
+        assert_eq!(switch(0).await, vec![floor, lamps]);
+        assert_eq!(switch(5).await, vec![floor]);
+        assert!(
+            group_relay(&client, 0, 0, 1, RelaisState::On, Duration::ZERO)
+                .await
+                .is_err()
+        );
+    }
+
+    /// Relay states the nodes report within a short time.
+    async fn reported(sub: &mut Subscription) -> Vec<(NodeAddr, RelaisState)> {
+        let mut reported = Vec::new();
+        let _ = sub
+            .wait_for(
+                CanMessageType::RelaisState,
+                Duration::from_millis(100),
+                |frame| {
+                    if let Ok(CanMessage::RelaisState(state)) = frame.message() {
+                        reported.push((frame.addr(), state));
+                    }
+                    None::<()>
+                },
+            )
+            .await;
+        reported
+    }
+
+    #[tokio::test]
+    async fn test_scenes() {
+        let a = NodeAddr::new(5, 12);
+        let b = NodeAddr::new(5, 13);
+        let client = setup(&[a, b]);
+
+        let mut scene = Scene::new(1);
+        scene.states[0] = Some(RelaisState::On);
+        scene.states[2] = Some(RelaisState::On);
+        define_scene(&client, a, scene.clone()).await.unwrap();
+        scene.states[2] = None;
+        define_scene(&client, b, scene).await.unwrap();
+
+        let mut sub = client.subscribe();
+        recall_scene(&client, NodeAddr::new(5, 0), 1).await.unwrap();
+        let mut states = reported(&mut sub).await;
+        states.sort_by_key(|(addr, _)| *addr);
+        let on = RelaisState::On;
+        assert_eq!(
+            states,
+            vec![(a, on.clone()), (a, on.clone()), (b, on.clone())]
+        );
+
+        // the current states become scene 2, recalled after a delay
+        let stored = store_scene(&client, a, 2, Duration::from_secs(1), DEFAULT_TIMEOUT)
+            .await
+            .unwrap();
+        assert_eq!(stored.states[2], Some(RelaisState::On));
+        assert_eq!(stored.states[3], Some(RelaisState::Off));
+        relay(
+            &client,
+            a,
+            2,
+            RelaisState::Off,
+            Duration::ZERO,
+            DEFAULT_TIMEOUT,
+        )
+        .await
+        .unwrap();
+        let mut sub = client.subscribe();
+        recall_scene(&client, a, 2).await.unwrap();
+        assert_eq!(reported(&mut sub).await, vec![]);
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert_eq!(reported(&mut sub).await, vec![(a, on)]);
+
+        // unknown scenes are ignored
+        recall_scene(&client, a, 7).await.unwrap();
+        assert_eq!(reported(&mut sub).await, vec![]);
+    }
+
+    #[tokio::test]
+    async fn test_bindings() {
+        let relays = NodeAddr::new(5, 12);
+        let other = NodeAddr::new(5, 13);
+        let client = setup(&[relays, other]);
+        let button = NodeAddr::new(4, 3);
+
+        let toggle = Binding {
+            slot: 0,
+            source_type: button.device_type,
+            source_id: button.device_id,
+            button: 1,
+            event: ButtonState::Single,
+            group: 0,
+            relay: 2,
+            action: Action::Toggle,
+        };
+        // only for members of group 9
+        let grouped = Binding {
+            slot: 1,
+            relay: 3,
+            group: 9,
+            ..toggle
+        };
+        set_binding(&client, relays, toggle, DEFAULT_TIMEOUT)
+            .await
+            .unwrap();
+        set_binding(&client, relays, grouped, DEFAULT_TIMEOUT)
+            .await
+            .unwrap();
+        assert_eq!(
+            bindings(&client, relays, DEFAULT_TIMEOUT).await.unwrap(),
+            vec![toggle, grouped]
+        );
+        assert_eq!(
+            bindings(&client, other, DEFAULT_TIMEOUT).await.unwrap(),
+            vec![]
+        );
+
+        // the button node sends its event, the gateway is not involved
+        let press = |state| {
+            let event = CanMessage::ButtonEvent(ButtonMessage::new(1, state, 0));
+            let frame = Frame::from_message(button, &event);
+            let client = &client;
+            async move { client.send_frame(&frame).await }
+        };
+        let mut sub = client.subscribe();
+        press(ButtonState::Single).await.unwrap();
+        assert_eq!(reported(&mut sub).await, vec![(relays, RelaisState::On)]);
+        press(ButtonState::Single).await.unwrap();
+        assert_eq!(reported(&mut sub).await, vec![(relays, RelaisState::Off)]);
+        press(ButtonState::Double).await.unwrap();
+        assert_eq!(reported(&mut sub).await, vec![]);
+
+        clear_binding(&client, relays, 0).await.unwrap();
+        assert_eq!(
+            bindings(&client, relays, DEFAULT_TIMEOUT).await.unwrap(),
+            vec![grouped]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_policy() {
+        let relays = NodeAddr::new(5, 12);
+        let client = setup(&[relays]);
+        let button = NodeAddr::new(4, 3);
+
+        assert_eq!(
+            policy(&client, relays, DEFAULT_TIMEOUT).await.unwrap(),
+            Policy::default()
+        );
+        let report = node_mode(&client, relays, DEFAULT_TIMEOUT).await.unwrap();
+        assert_eq!(report.mode, Mode::Online);
+
+        let new = Policy {
+            shutters: ShutterSafeState::Down,
+            relais_off: true,
+            bindings_autonomous_only: true,
+        };
+        set_policy(&client, relays, new, DEFAULT_TIMEOUT)
+            .await
+            .unwrap();
+        assert_eq!(policy(&client, relays, DEFAULT_TIMEOUT).await.unwrap(), new);
+
+        // the gateway is there, so it handles the button itself
+        let binding = Binding {
+            slot: 0,
+            source_type: button.device_type,
+            source_id: button.device_id,
+            button: 1,
+            event: ButtonState::Single,
+            group: 0,
+            relay: 2,
+            action: Action::On,
+        };
+        set_binding(&client, relays, binding, DEFAULT_TIMEOUT)
+            .await
+            .unwrap();
+        let mut sub = client.subscribe();
+        let event = CanMessage::ButtonEvent(ButtonMessage::new(1, ButtonState::Single, 0));
+        client
+            .send_frame(&Frame::from_message(button, &event))
+            .await
+            .unwrap();
+        assert_eq!(reported(&mut sub).await, vec![]);
+    }
+
+    #[tokio::test]
+    async fn test_shutter() {
+        let bus = MemoryBus::new();
+        let node = NodeAddr::new(5, 12);
+        let mut sim = SimNode::new(node, 0);
+        sim.relais_mode = RelaisMode::SoftwareRollershutter;
+        tokio::spawn(sim.run(bus.endpoint()));
+        let client = Client::new(bus);
+        let to = |percent| ShutterCommand::Position { num: 2, percent };
+
+        // no travel times yet
+        assert!(matches!(
+            shutter(&client, node, to(50), DEFAULT_TIMEOUT).await,
+            Err(Error::Node(_))
+        ));
+        let calibration = Calibration {
+            num: 2,
+            travel: TravelTimes {
+                up: embassy_time::Duration::from_millis(400),
+                down: embassy_time::Duration::from_millis(300),
+            },
+        };
+        calibrate(&client, node, calibration, DEFAULT_TIMEOUT)
+            .await
+            .unwrap();
+        let calibrations = travel_times(&client, node, DEFAULT_TIMEOUT).await.unwrap();
+        assert_eq!(calibrations.len(), MAX_SHUTTERS);
+        assert_eq!(calibrations[2], calibration);
+        assert_eq!(
+            dead_time(&client, node, DEFAULT_TIMEOUT).await.unwrap(),
+            Duration::from_millis(500)
+        );
+        set_dead_time(&client, node, Duration::from_millis(200), DEFAULT_TIMEOUT)
+            .await
+            .unwrap();
+        assert!(
+            set_dead_time(&client, node, Duration::from_secs(70), DEFAULT_TIMEOUT)
+                .await
+                .is_err()
+        );
+
+        // referenced at the top first, then down after the dead time
+        let mut sub = client.subscribe();
+        let report = shutter(&client, node, to(50), DEFAULT_TIMEOUT)
+            .await
+            .unwrap();
+        assert_eq!(report.state, RelaisState::Up);
+        assert_eq!(report.position, None);
+        let travel = Duration::from_secs(2);
+        let stopped = sub.wait_for(
+            CanMessageType::RollershutterState,
+            travel,
+            |frame| match frame.message() {
+                Ok(CanMessage::RollershutterState(report)) if report.state == RelaisState::Off => {
+                    Some(report)
+                }
+                _ => None,
+            },
+        );
+        let position = stopped.await.unwrap().position.unwrap();
+        assert!((49..=55).contains(&position), "stopped at {position}");
+
+        let reports = shutters(&client, node, DEFAULT_TIMEOUT).await.unwrap();
+        assert_eq!(reports.len(), MAX_SHUTTERS);
+        assert_eq!(reports[2].position, Some(position));
+        assert_eq!(reports[0].position, None);
+
+        // down by hand runs into the end stop
+        let down = ShutterCommand::Move(RelaisMessage {
+            num: 2,
+            state: RelaisState::Down,
+            duration: embassy_time::Duration::from_millis(0),
+            bank: 0,
+        });
+        shutter(&client, node, down, DEFAULT_TIMEOUT).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let reports = shutters(&client, node, DEFAULT_TIMEOUT).await.unwrap();
+        assert_eq!(reports[2].state, RelaisState::Off);
+        assert_eq!(reports[2].position, Some(100));
+    }
+
+    #[tokio::test]
+    async fn test_power_on() {
+        let node = NodeAddr::new(5, 12);
+        let client = setup(&[node]);
+
+        assert_eq!(
+            power_on(&client, node, DEFAULT_TIMEOUT).await.unwrap(),
+            PowerOnPolicy::default()
+        );
+        let policy = update_power_on(
+            &client,
+            node,
+            &[(0, PowerOn::Restore), (15, PowerOn::Keep)],
+            DEFAULT_TIMEOUT,
+        )
+        .await
+        .unwrap();
+        assert_eq!(policy.0[0], PowerOn::Restore);
+        let policy = update_power_on(&client, node, &[(1, PowerOn::On)], DEFAULT_TIMEOUT)
+            .await
+            .unwrap();
+        assert_eq!(
+            power_on(&client, node, DEFAULT_TIMEOUT).await.unwrap(),
+            policy
+        );
+        assert_eq!(policy.0[..2], [PowerOn::Restore, PowerOn::On]);
+        assert_eq!(policy.0[15], PowerOn::Keep);
+        assert!(
+            update_power_on(&client, node, &[(16, PowerOn::On)], DEFAULT_TIMEOUT)
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_supervision() {
+        let node = NodeAddr::new(5, 12);
+        let client = setup(&[node]);
+
+        assert_eq!(
+            supervision(&client, node, DEFAULT_TIMEOUT).await.unwrap(),
+            Supervision::default()
+        );
+        let new = Supervision {
+            interval: embassy_time::Duration::from_secs(10),
+            max_misses: 5,
+        };
+        set_supervision(&client, node, new, DEFAULT_TIMEOUT)
+            .await
+            .unwrap();
+        assert_eq!(
+            supervision(&client, node, DEFAULT_TIMEOUT).await.unwrap(),
+            new
+        );
+        let zero = Supervision {
+            interval: embassy_time::Duration::from_secs(0),
+            ..new
+        };
+        assert!(set_supervision(&client, node, zero, DEFAULT_TIMEOUT)
+            .await
+            .is_err());
+
+        // liveness frames are not answered
+        let mut sub = client.subscribe();
+        echo(&client).await.unwrap();
+        suspend_supervision(&client, BROADCAST, Duration::from_secs(600))
+            .await
+            .unwrap();
+        let answer = sub.wait_for(CanMessageType::Echo, Duration::from_millis(200), |frame| {
+            (frame.addr() == node).then_some(())
+        });
+        assert!(matches!(answer.await, Err(Error::Timeout(_))));
+    }
+
+    #[tokio::test]
+    async fn test_metadata() {
+        let node = NodeAddr::new(5, 12);
+        let client = setup(&[node]);
+
+        // segmented, then a single frame
+        set_metadata(
+            &client,
+            node,
+            Field::Name,
+            "Kitchen north wall",
+            DEFAULT_TIMEOUT,
+        )
+        .await
+        .unwrap();
+        set_metadata(&client, node, Field::Room, "Attic", DEFAULT_TIMEOUT)
+            .await
+            .unwrap();
+        let notes = "ü".repeat(MAX_VALUE_LEN / 2);
+        set_metadata(&client, node, Field::Notes, &notes, DEFAULT_TIMEOUT)
+            .await
+            .unwrap();
+        let metadata = metadata(&client, node, DEFAULT_TIMEOUT).await.unwrap();
+        assert_eq!(metadata.name, "Kitchen north wall");
+        assert_eq!(metadata.room, "Attic");
+        assert_eq!(metadata.floor, "");
+        assert_eq!(metadata.notes, notes.as_str());
+        let info = info(&client, node, DEFAULT_TIMEOUT).await.unwrap();
+        assert_eq!(info.metadata, Some(metadata));
+
+        assert!(matches!(
+            set_metadata(
+                &client,
+                node,
+                Field::Floor,
+                &"x".repeat(17),
+                DEFAULT_TIMEOUT
+            )
+            .await,
+            Err(Error::Decode(DecodeError::TooLong { max: 16, .. }))
+        ));
+        // the node checks as well
+        let mut sub = client.subscribe();
+        let mut too_long = vec![Field::Floor as u8];
+        too_long.extend_from_slice(&[b'x'; 17]);
+        client
+            .send_long(node, CanMessageType::Metadata, &too_long, DEFAULT_TIMEOUT)
+            .await
+            .unwrap();
+        let report = sub.wait_for(
+            CanMessageType::DeviceError,
+            DEFAULT_TIMEOUT,
+            |frame| match frame.message() {
+                Ok(CanMessage::DeviceError(report)) => Some(report),
+                _ => None,
+            },
+        );
+        let report = report.await.unwrap();
+        assert_eq!(
+            report.local_code,
+            DecodeError::TooLong { max: 0, got: 0 }.code()
+        );
+        assert_eq!(report.details[2], 16);
+    }
+
+    #[tokio::test]
+    async fn test_claim() {
+        let bus = MemoryBus::new();
+        let existing = NodeAddr::new(5, 1);
+        tokio::spawn(SimNode::new(existing, 1).run(bus.endpoint()));
+        let typed = NodeAddr::new(5, UNASSIGNED_ID);
+        tokio::spawn(SimNode::new(typed, 0xa1b2).run(bus.endpoint()));
+        let untyped = NodeAddr::new(UNASSIGNED_TYPE, UNASSIGNED_ID);
+        tokio::spawn(SimNode::new(untyped, 0xc3d4).run(bus.endpoint()));
+        let client = Client::new(bus);
+
+        let wait = Duration::from_millis(700);
+        let mut claims = claim(&client, None, wait, DEFAULT_TIMEOUT).await.unwrap();
+        claims.sort_by_key(|claim| claim.uid);
+        let assigned = NodeAddr::new(5, 2);
+        assert_eq!(
+            claims,
+            vec![
+                Claim {
+                    uid: 0xa1b2,
+                    addr: Some(assigned)
+                },
+                Claim {
+                    uid: 0xc3d4,
+                    addr: None
+                },
+            ]
+        );
+        let claims = claim(&client, Some(7), wait, DEFAULT_TIMEOUT)
+            .await
+            .unwrap();
+        let lamp = NodeAddr::new(7, 1);
+        assert_eq!(
+            claims,
+            vec![Claim {
+                uid: 0xc3d4,
+                addr: Some(lamp)
+            }]
+        );
+        let nodes = scan(&client, Duration::from_millis(100)).await.unwrap();
+        assert_eq!(nodes, vec![existing, assigned, lamp]);
+
+        // an assignment for another UID is ignored
+        let msg = CanMessage::DeviceIdType {
+            id: 9,
+            device_type: 5,
+            uid: Some(0xffff),
+        };
+        client.send(BROADCAST, &msg).await.unwrap();
+        let nodes = scan(&client, Duration::from_millis(100)).await.unwrap();
+        assert_eq!(nodes, vec![existing, assigned, lamp]);
+    }
+
+    #[tokio::test]
+    async fn test_address_conflict() {
+        let bus = MemoryBus::new();
+        let addr = NodeAddr::new(5, 12);
+        let client = Client::new(bus.endpoint());
+        let mut sub = client.subscribe();
+        tokio::spawn(SimNode::new(addr, 1).run(bus.endpoint()));
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        // the second node announces itself on the same address
+        tokio::spawn(SimNode::new(addr, 2).run(bus.endpoint()));
+
+        let mut reports = Vec::new();
+        let _ = sub
+            .wait_for(
+                CanMessageType::DeviceError,
+                Duration::from_millis(200),
+                |frame| {
+                    if let Ok(CanMessage::DeviceError(report)) = frame.message() {
+                        reports.push((frame.addr(), report.code, report.details[2]));
+                    }
+                    None::<()>
+                },
+            )
+            .await;
+        reports.sort_by_key(|report| report.2);
+        assert_eq!(
+            reports,
+            vec![
+                (addr, ErrorCode::AddressConflict, 1),
+                (addr, ErrorCode::AddressConflict, 2)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_available_ping() {
+        let bus = MemoryBus::new();
+        let addr = NodeAddr::new(5, 12);
+        let client = Client::new(bus.endpoint());
+        let mut sub = client.subscribe();
+        tokio::spawn(SimNode::new(addr, 1).run(bus.endpoint()));
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        // the gateway pings the node on its own address
+        let mut ping = Frame::from_message(addr, &CanMessage::Available(None));
+        ping.data.clear();
+        client.send_frame(&ping).await.unwrap();
+        let conflict = sub
+            .wait_for(
+                CanMessageType::DeviceError,
+                Duration::from_millis(100),
+                |frame| match frame.message() {
+                    Ok(CanMessage::DeviceError(report)) => Some(report),
+                    _ => None,
+                },
+            )
+            .await;
+        assert!(conflict.is_err(), "{conflict:?}");
+    }
+}
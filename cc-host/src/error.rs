@@ -0,0 +1,34 @@
+use cancomponents_core::can_message_type::CanMessageType;
+use cancomponents_core::decode_error::DecodeError;
+use cancomponents_core::error_report::ErrorReport;
+use cancomponents_core::image::ImageError;
+use cancomponents_core::transport::TransportError;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid payload: {0}")]
+    Decode(#[from] DecodeError),
+    #[error("no such can interface: {0}")]
+    NoSuchInterface(String),
+    #[error("timeout waiting for {0:?}")]
+    Timeout(CanMessageType),
+    #[error("bus closed")]
+    Closed,
+    #[error("invalid argument: {0}")]
+    InvalidArgument(String),
+    #[error("node reported {0:?}")]
+    Node(ErrorReport),
+    #[error("update not applied: {0}")]
+    NotApplied(String),
+    #[error("block {0} not accepted after repeated retransmits")]
+    BlockRejected(u32),
+    #[error("{0}")]
+    Image(#[from] ImageError),
+    #[error("transfer failed: {0:?}")]
+    Transport(TransportError),
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
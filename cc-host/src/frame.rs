@@ -0,0 +1,94 @@
+use crate::error::Error;
+use cancomponents_core::can_id::CanId;
+use cancomponents_core::decode_error::DecodeError;
+use cancomponents_core::message::{CanMessage, Payload};
+
+/// Device type and id of a node on the bus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NodeAddr {
+    pub device_type: u8,
+    pub device_id: u8,
+}
+
+impl NodeAddr {
+    pub fn new(device_type: u8, device_id: u8) -> Self {
+        Self {
+            device_type,
+            device_id,
+        }
+    }
+}
+
+impl From<CanId> for NodeAddr {
+    fn from(id: CanId) -> Self {
+        Self::new(id.device_type, id.device_id)
+    }
+}
+
+/// Parses the `TYPE/ID` notation used by `Display`.
+impl core::str::FromStr for NodeAddr {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidArgument(format!("expected TYPE/ID, got {s:?}"));
+        let (device_type, device_id) = s.split_once('/').ok_or_else(invalid)?;
+        let device_type: u8 = device_type.trim().parse().map_err(|_| invalid())?;
+        let device_id: u8 = device_id.trim().parse().map_err(|_| invalid())?;
+        if device_type > 0x3F {
+            return Err(invalid());
+        }
+        Ok(Self::new(device_type, device_id))
+    }
+}
+
+impl core::fmt::Display for NodeAddr {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}/{}", self.device_type, self.device_id)
+    }
+}
+
+/// An extended-id CAN frame as seen on the bus.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub id: CanId,
+    pub data: Payload,
+    pub rtr: bool,
+}
+
+impl Frame {
+    pub fn from_message(addr: NodeAddr, msg: &CanMessage) -> Self {
+        Self {
+            id: CanId::new(addr.device_type, addr.device_id, msg.msg_type()),
+            data: msg.encode(),
+            rtr: msg.is_request(),
+        }
+    }
+
+    /// To the members of `group`, of every type with `device_type` 0.
+    pub fn to_group(group: u8, device_type: u8, msg: &CanMessage) -> Self {
+        Self {
+            id: CanId::group(group, device_type, msg.msg_type()),
+            data: msg.encode(),
+            rtr: msg.is_request(),
+        }
+    }
+
+    pub fn addr(&self) -> NodeAddr {
+        NodeAddr::from(self.id)
+    }
+
+    pub fn message(&self) -> Result<CanMessage, DecodeError> {
+        CanMessage::decode(self.id, &self.data, self.rtr)
+    }
+}
+
+impl core::fmt::Display for Frame {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}", self.id)?;
+        if self.rtr {
+            write!(f, " RTR")
+        } else {
+            write!(f, " {:02x?}", self.data.as_slice())
+        }
+    }
+}
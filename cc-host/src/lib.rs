@@ -0,0 +1,11 @@
+pub mod bus;
+pub mod client;
+pub mod commands;
+pub mod error;
+pub mod frame;
+pub mod memory;
+pub mod multicast;
+pub mod ota;
+pub mod sign;
+pub mod sim;
+pub mod socketcan;
@@ -0,0 +1,451 @@
+use crate::bus::CanBus;
+use crate::client::{Client, Subscription};
+use crate::error::{Error, Result};
+use crate::frame::{Frame, NodeAddr};
+use crate::ota::{encode, update_error, UploadOptions, ERASE_TIMEOUT, MAX_RETRIES, REBOOT_TIMEOUT};
+use cancomponents_core::can_message_type::CanMessageType;
+use cancomponents_core::image::{self, ImageHeader};
+use cancomponents_core::message::CanMessage;
+use cancomponents_core::ota::{crc32, Chunk, BLOCK_SIZE, CHUNK_SIZE};
+use heapless::String;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Outcome of a multicast update, per node.
+#[derive(Debug)]
+pub struct MulticastReport {
+    pub size: usize,
+    /// bytes of block data sent to the group, less than `size` if compressed
+    pub transfer_size: usize,
+    pub crc: u32,
+    pub header: ImageHeader,
+    /// blocks sent again to single nodes
+    pub repaired: u32,
+    /// new version, or why the node was not updated
+    pub nodes: BTreeMap<NodeAddr, Result<String<8>>>,
+}
+
+/// Update all `nodes` at once. They have to share one device type: the
+/// image goes to TYPE/0 a single time, then every node is asked for the
+/// blocks it missed and gets them sent individually. Once all nodes hold a
+/// verified image a `FlashComplete` to TYPE/0 restarts them together.
+/// Nodes that refuse or fail the update are left out and reported, the
+/// others carry on. `progress` is called with block data sent and its total
+/// size.
+pub async fn upload<B: CanBus + 'static>(
+    client: &Client<B>,
+    nodes: &[NodeAddr],
+    image: &[u8],
+    options: &UploadOptions<'_>,
+    timeout: Duration,
+    mut progress: impl FnMut(usize, usize),
+) -> Result<MulticastReport> {
+    let group = match nodes {
+        [first, ..] if nodes.iter().all(|n| n.device_type == first.device_type) => {
+            NodeAddr::new(first.device_type, 0)
+        }
+        _ => {
+            return Err(Error::InvalidArgument(
+                "multicast needs nodes of one device type".into(),
+            ))
+        }
+    };
+    image::split(image)?;
+    let header = ImageHeader::find(image)?;
+    let size = image.len() as u32;
+    let crc = crc32(image);
+    let blocks = encode(image, options.compress);
+    let transfer_size = blocks.iter().map(Vec::len).sum();
+    let pacing = options.pacing;
+
+    let mut results = BTreeMap::new();
+    let mut old_versions = BTreeMap::new();
+    for &node in nodes {
+        match client
+            .request(node, CanMessageType::ApplicationVersionString, timeout)
+            .await
+        {
+            Ok(CanMessage::ApplicationVersionString(version)) => {
+                old_versions.insert(node, version);
+            }
+            Ok(_) => unreachable!("request only returns the requested type"),
+            Err(e) => {
+                results.insert(node, Err(e));
+            }
+        }
+    }
+
+    // every node acknowledges the start or says why it refuses the image
+    let mut sub = client.subscribe();
+    let announce = CanMessage::FlashHeader {
+        header,
+        allow_downgrade: options.allow_downgrade,
+        compressed: options.compress,
+    };
+    client.send(group, &announce).await?;
+    client
+        .send(group, &CanMessage::FlashStart { crc, size })
+        .await?;
+    let mut waiting: Vec<NodeAddr> = old_versions.keys().copied().collect();
+    let _ = sub
+        .wait_for(CanMessageType::FlashProgress, pacing.ack_timeout, |frame| {
+            let node = waiting.iter().position(|&n| n == frame.addr())?;
+            let answer = match frame.message() {
+                Ok(CanMessage::FlashProgress { .. }) => Ok(()),
+                _ => Err(Error::Node(update_error(frame, frame.addr())?)),
+            };
+            if let Err(e) = answer {
+                results.insert(frame.addr(), Err(e));
+            }
+            waiting.swap_remove(node);
+            waiting.is_empty().then_some(())
+        })
+        .await;
+    for node in waiting {
+        results.insert(node, Err(Error::Timeout(CanMessageType::FlashProgress)));
+    }
+    let mut active: Vec<NodeAddr> = old_versions
+        .keys()
+        .filter(|node| !results.contains_key(node))
+        .copied()
+        .collect();
+
+    let mut sent = 0;
+    for (block, data) in blocks.iter().enumerate() {
+        send_block(client, group, data, block as u32, options).await?;
+        sent += data.len();
+        progress(sent, transfer_size);
+        drop_failed(&mut sub, &mut active, &mut results);
+    }
+
+    let mut repaired = 0;
+    for node in active.clone() {
+        match repair(client, &mut sub, node, &blocks, size, options, timeout).await {
+            Ok(resent) => repaired += resent,
+            Err(e) => {
+                active.retain(|&n| n != node);
+                results.insert(node, Err(e));
+            }
+        }
+    }
+
+    if !active.is_empty() {
+        client.send(group, &CanMessage::FlashComplete).await?;
+        let mut booting = active.clone();
+        let _ = sub
+            .wait_for(CanMessageType::Available, REBOOT_TIMEOUT, |frame| {
+                let node = booting.iter().position(|&n| n == frame.addr())?;
+                if matches!(frame.message(), Ok(CanMessage::Available(_))) {
+                    booting.swap_remove(node);
+                }
+                booting.is_empty().then_some(())
+            })
+            .await;
+        for node in active {
+            let applied = |version: &String<8>| match options.expect_version {
+                Some(expected) => version.as_str() == expected,
+                None => old_versions.get(&node) != Some(version),
+            };
+            let new_version = match client
+                .request(node, CanMessageType::ApplicationVersionString, timeout)
+                .await
+            {
+                Ok(CanMessage::ApplicationVersionString(version)) if applied(&version) => {
+                    Ok(version)
+                }
+                Ok(CanMessage::ApplicationVersionString(version)) => Err(Error::NotApplied(
+                    format!("node still reports version {version}"),
+                )),
+                Ok(_) => unreachable!("request only returns the requested type"),
+                Err(e) => Err(e),
+            };
+            results.insert(node, new_version);
+        }
+    }
+
+    Ok(MulticastReport {
+        size: image.len(),
+        transfer_size,
+        crc,
+        header,
+        repaired,
+        nodes: results,
+    })
+}
+
+/// `FlashBlock` and the chunks of `block` as sent in `data`, then give the
+/// nodes time to write it.
+async fn send_block<B: CanBus + 'static>(
+    client: &Client<B>,
+    addr: NodeAddr,
+    data: &[u8],
+    block: u32,
+    options: &UploadOptions<'_>,
+) -> Result<()> {
+    client
+        .send(addr, &CanMessage::FlashBlock(block as u16))
+        .await?;
+    for index in 0..data.len().div_ceil(CHUNK_SIZE) {
+        let chunk = Chunk::of_block(data, block, index);
+        client.send(addr, &CanMessage::FlashWrite(chunk)).await?;
+        if !options.pacing.frame_gap.is_zero() {
+            tokio::time::sleep(options.pacing.frame_gap).await;
+        }
+    }
+    if !options.pacing.block_gap.is_zero() {
+        tokio::time::sleep(options.pacing.block_gap).await;
+    }
+    Ok(())
+}
+
+/// Remove nodes that reported an update error in the meantime.
+fn drop_failed(
+    sub: &mut Subscription,
+    active: &mut Vec<NodeAddr>,
+    results: &mut BTreeMap<NodeAddr, Result<String<8>>>,
+) {
+    while let Some(frame) = sub.try_next() {
+        let failed = |frame: &Frame| {
+            let node = active.iter().position(|&n| n == frame.addr())?;
+            update_error(frame, frame.addr()).map(|report| (node, report))
+        };
+        if let Some((node, report)) = failed(&frame) {
+            results.insert(active.swap_remove(node), Err(Error::Node(report)));
+        }
+    }
+}
+
+/// Send `node` the blocks it missed until it has all of them, then wait
+/// for it to verify the image. Returns the number of blocks sent again.
+async fn repair<B: CanBus + 'static>(
+    client: &Client<B>,
+    sub: &mut Subscription,
+    node: NodeAddr,
+    blocks: &[Vec<u8>],
+    size: u32,
+    options: &UploadOptions<'_>,
+    timeout: Duration,
+) -> Result<u32> {
+    let mut resent = 0;
+    for _ in 0..=MAX_RETRIES {
+        let missed = missing(client, node, size, timeout).await?;
+        if missed.is_empty() {
+            wait_verified(client, sub, node, size, options).await?;
+            return Ok(resent);
+        }
+        for block in missed {
+            let Some(data) = blocks.get(block as usize) else {
+                continue;
+            };
+            send_block(client, node, data, block as u32, options).await?;
+            resent += 1;
+        }
+    }
+    let missed = missing(client, node, size, timeout).await?;
+    Err(Error::BlockRejected(
+        missed.first().copied().unwrap_or(0) as u32
+    ))
+}
+
+/// Blocks of a multicast update `node` does not have yet.
+pub async fn missing<B: CanBus + 'static>(
+    client: &Client<B>,
+    node: NodeAddr,
+    size: u32,
+    timeout: Duration,
+) -> Result<Vec<u16>> {
+    let blocks = size.div_ceil(BLOCK_SIZE as u32) as u16;
+    let mut missing = Vec::new();
+    for from in (0..blocks).step_by(32) {
+        let request = CanMessage::FlashMissing {
+            block: from,
+            missing: 0,
+        };
+        let reply = client
+            .transact(node, &request, CanMessageType::FlashMissing, timeout)
+            .await?;
+        if let CanMessage::FlashMissing {
+            block,
+            missing: bits,
+        } = reply
+        {
+            missing.extend((0..32).filter(|i| bits & (1 << i) != 0).map(|i| block + i));
+        }
+    }
+    Ok(missing)
+}
+
+/// The node reads the image back once it has all blocks; `FlashProgress`
+/// reaches the full size when that passed.
+async fn wait_verified<B: CanBus + 'static>(
+    client: &Client<B>,
+    sub: &mut Subscription,
+    node: NodeAddr,
+    size: u32,
+    options: &UploadOptions<'_>,
+) -> Result<()> {
+    let deadline = tokio::time::Instant::now() + ERASE_TIMEOUT;
+    loop {
+        client
+            .send(node, &CanMessage::Request(CanMessageType::FlashProgress))
+            .await?;
+        let answer = sub
+            .wait_for(
+                CanMessageType::FlashProgress,
+                options.pacing.ack_timeout,
+                |frame| {
+                    if let Some(report) = update_error(frame, node) {
+                        return Some(Err(Error::Node(report)));
+                    }
+                    match frame.message() {
+                        Ok(CanMessage::FlashProgress { received, .. })
+                            if frame.addr() == node && !frame.rtr =>
+                        {
+                            Some(Ok(received))
+                        }
+                        _ => None,
+                    }
+                },
+            )
+            .await;
+        match answer {
+            Ok(Ok(received)) if received >= size => return Ok(()),
+            Ok(Err(e)) => return Err(e),
+            Ok(Ok(_)) | Err(Error::Timeout(_)) if tokio::time::Instant::now() < deadline => {
+                tokio::time::sleep(options.pacing.ack_timeout).await
+            }
+            Ok(Ok(_)) => return Err(Error::NotApplied("image not verified".into())),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::DEFAULT_TIMEOUT;
+    use crate::memory::MemoryBus;
+    use crate::ota::Pacing;
+    use crate::sign;
+    use crate::sim::SimNode;
+    use async_trait::async_trait;
+    use cancomponents_core::image::{SigningKey, Version, PROTOCOL_VERSION};
+    use cancomponents_core::ota::UpdateErrorCode;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// A node that misses some of the frames on the bus.
+    struct Deaf {
+        inner: MemoryBus,
+        received: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl CanBus for Deaf {
+        async fn send(&self, frame: &Frame) -> Result<()> {
+            self.inner.send(frame).await
+        }
+
+        async fn recv(&self) -> Result<Frame> {
+            loop {
+                let frame = self.inner.recv().await?;
+                let n = self.received.fetch_add(1, Ordering::Relaxed);
+                if ![100, 101, 1000].contains(&n) {
+                    return Ok(frame);
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_multicast() {
+        let bus = MemoryBus::new();
+        let nodes = [
+            NodeAddr::new(5, 1),
+            NodeAddr::new(5, 2),
+            NodeAddr::new(5, 3),
+        ];
+        tokio::spawn(SimNode::new(nodes[0], 1).run(bus.endpoint()));
+        let deaf = Deaf {
+            inner: bus.endpoint(),
+            received: AtomicUsize::new(0),
+        };
+        tokio::spawn(SimNode::new(nodes[1], 2).run(deaf));
+        let mut old = SimNode::new(nodes[2], 3);
+        old.hwrev = 3;
+        tokio::spawn(old.run(bus.endpoint()));
+        // another type listens to the same bus and stays out of it
+        let relay = NodeAddr::new(7, 1);
+        tokio::spawn(SimNode::new(relay, 4).run(bus.endpoint()));
+
+        let header = ImageHeader {
+            protocol: PROTOCOL_VERSION,
+            device_type: 5,
+            hwrev_min: 1,
+            hwrev_max: 2,
+            version: Version {
+                major: 1,
+                minor: 0,
+                patch: 0,
+            },
+        };
+        let mut image: Vec<u8> = (0..BLOCK_SIZE * 3 + 7).map(|i| (i * 5) as u8).collect();
+        image.extend_from_slice(&header.stamp());
+        let image = sign::sign(&image, &SigningKey::from_bytes(&[7; 32])).unwrap();
+
+        let client = Client::new(bus);
+        let options = UploadOptions {
+            pacing: Pacing {
+                frame_gap: Duration::ZERO,
+                ack_timeout: Duration::from_millis(100),
+                block_gap: Duration::ZERO,
+            },
+            ..Default::default()
+        };
+        let report = upload(
+            &client,
+            &nodes,
+            &image,
+            &options,
+            DEFAULT_TIMEOUT,
+            |_, _| {},
+        )
+        .await
+        .unwrap();
+        assert_eq!(report.repaired, 2);
+        let version = format!("{:08x}", crc32(&image));
+        for node in &nodes[..2] {
+            assert_eq!(report.nodes[node].as_ref().unwrap().as_str(), version);
+        }
+        match &report.nodes[&nodes[2]] {
+            Err(Error::Node(report)) => {
+                assert_eq!(report.local_code, UpdateErrorCode::WrongHardware as u8)
+            }
+            other => panic!("unexpected {other:?}"),
+        }
+        let relay_version = client
+            .request(
+                relay,
+                CanMessageType::ApplicationVersionString,
+                DEFAULT_TIMEOUT,
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            relay_version,
+            CanMessage::ApplicationVersionString(String::try_from("sim").unwrap())
+        );
+
+        let mixed = [nodes[0], relay];
+        let err = upload(
+            &client,
+            &mixed,
+            &image,
+            &options,
+            DEFAULT_TIMEOUT,
+            |_, _| {},
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, Error::InvalidArgument(_)));
+    }
+}
@@ -0,0 +1,983 @@
+use crate::bus::CanBus;
+use crate::client::{Client, Subscription};
+use crate::error::{Error, Result};
+use crate::frame::{Frame, NodeAddr};
+use cancomponents_core::can_message_type::CanMessageType;
+use cancomponents_core::compress::{self, MAX_COMPRESSED_BLOCK};
+use cancomponents_core::error_report::{Component, ErrorReport};
+use cancomponents_core::image::{self, ImageHeader};
+use cancomponents_core::message::{CanMessage, Payload};
+use cancomponents_core::ota::{
+    crc32, Chunk, ReadRange, BLOCK_SIZE, CHUNK_SIZE, MAX_READ_LEN, SLOT_COUNT,
+};
+use heapless::String;
+use std::time::Duration;
+
+/// Flashing, verifying and booting the new image takes a while.
+pub const REBOOT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Wiping a whole slot takes a few seconds.
+pub const ERASE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Attempts per block before the upload is given up.
+pub const MAX_RETRIES: u32 = 5;
+
+/// How fast the image is pushed. The node acknowledges every block once it
+/// is in flash; writing stalls its receive task, so that can take a while.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pacing {
+    /// pause after every `FlashWrite` frame
+    pub frame_gap: Duration,
+    /// how long to wait for a block acknowledgement before asking for it
+    pub ack_timeout: Duration,
+    /// pause after every block of a multicast update, nodes do not
+    /// acknowledge them but need the time to write
+    pub block_gap: Duration,
+}
+
+impl Default for Pacing {
+    fn default() -> Self {
+        Self {
+            frame_gap: Duration::ZERO,
+            ack_timeout: Duration::from_secs(1),
+            block_gap: Duration::from_millis(100),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UploadOptions<'a> {
+    /// version the node has to report afterwards, default: any other
+    pub expect_version: Option<&'a str>,
+    pub pacing: Pacing,
+    /// let the node take an image older than the running one
+    pub allow_downgrade: bool,
+    /// send every block compressed, the node decompresses it
+    pub compress: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UploadReport {
+    pub size: usize,
+    /// bytes of block data to send, less than `size` if compressed
+    pub transfer_size: usize,
+    pub crc: u32,
+    /// offset a previous, interrupted upload of the same image had reached
+    pub resumed_from: u32,
+    /// blocks that had to be sent again
+    pub retransmits: u32,
+    pub old_version: Option<String<8>>,
+    pub new_version: String<8>,
+    pub header: ImageHeader,
+}
+
+/// What the node said after a block.
+enum Ack {
+    Received(u32),
+    Rebooted,
+}
+
+/// Push a stamped and signed `image` to a node and wait until it comes back
+/// with a new `ApplicationVersionString`. The node checks the image header
+/// before it takes any data. Blocks the node does not acknowledge are sent
+/// again, an interrupted upload of the same image continues where it
+/// stopped (call `erase` first to start from scratch). If `expect_version`
+/// is given the node has to report exactly that, otherwise any version
+/// different from the one before the update counts as success. `progress`
+/// is called with block data sent and its total size, which differs from
+/// the image size with compression.
+pub async fn upload<B: CanBus + 'static>(
+    client: &Client<B>,
+    addr: NodeAddr,
+    image: &[u8],
+    options: &UploadOptions<'_>,
+    timeout: Duration,
+    mut progress: impl FnMut(usize, usize),
+) -> Result<UploadReport> {
+    if image.is_empty() || image.len() > u32::MAX as usize {
+        return Err(Error::InvalidArgument(format!(
+            "image size {} is not flashable",
+            image.len()
+        )));
+    }
+    // nodes reject unsigned images only after the whole upload
+    image::split(image)?;
+    let header = ImageHeader::find(image)?;
+    let pacing = options.pacing;
+    let old_version = match client
+        .request(addr, CanMessageType::ApplicationVersionString, timeout)
+        .await?
+    {
+        CanMessage::ApplicationVersionString(version) => Some(version),
+        _ => None,
+    };
+
+    let size = image.len() as u32;
+    let crc = crc32(image);
+    let blocks = encode(image, options.compress);
+    let mut sub = client.subscribe();
+    let announce = CanMessage::FlashHeader {
+        header,
+        allow_downgrade: options.allow_downgrade,
+        compressed: options.compress,
+    };
+    let resumed_from = begin(client, &mut sub, addr, image, &announce, pacing, timeout).await?;
+    let mut rebooted = false;
+
+    let mut offset = resumed_from;
+    let mut retries = 0;
+    let mut retransmits = 0;
+    while offset < size && !rebooted {
+        let block = offset / BLOCK_SIZE as u32;
+        let end = (offset + BLOCK_SIZE as u32).min(size);
+        let ack = match send_block(
+            client,
+            &mut sub,
+            addr,
+            &blocks,
+            block,
+            pacing,
+            &mut progress,
+        )
+        .await?
+        {
+            Some(received) => Ack::Received(received),
+            None => wait_ack(client, &mut sub, addr, pacing).await?,
+        };
+        match ack {
+            Ack::Received(received) if received >= end => {
+                offset = end;
+                retries = 0;
+            }
+            Ack::Received(received) => {
+                retries += 1;
+                retransmits += 1;
+                if retries > MAX_RETRIES {
+                    return Err(Error::BlockRejected(block));
+                }
+                // the node keeps whole blocks only
+                offset = received.min(offset) / BLOCK_SIZE as u32 * BLOCK_SIZE as u32;
+            }
+            Ack::Rebooted => rebooted = true,
+        }
+    }
+
+    if !rebooted {
+        sub.wait_for(CanMessageType::Available, REBOOT_TIMEOUT, |frame| {
+            if frame.addr() != addr || frame.rtr {
+                return None;
+            }
+            match frame.message() {
+                Ok(CanMessage::Available(_)) => Some(Ok(())),
+                Ok(CanMessage::DeviceError(report)) if is_update_error(&report) => {
+                    Some(Err(Error::Node(report)))
+                }
+                _ => None,
+            }
+        })
+        .await??;
+    }
+
+    let new_version = match client
+        .request(addr, CanMessageType::ApplicationVersionString, timeout)
+        .await?
+    {
+        CanMessage::ApplicationVersionString(version) => version,
+        other => return Err(Error::NotApplied(format!("unexpected answer {other:?}"))),
+    };
+    let applied = match options.expect_version {
+        Some(expected) => new_version.as_str() == expected,
+        None => old_version.as_ref() != Some(&new_version),
+    };
+    if !applied {
+        return Err(Error::NotApplied(format!(
+            "node still reports version {new_version}"
+        )));
+    }
+    Ok(UploadReport {
+        size: image.len(),
+        transfer_size: blocks.iter().map(Vec::len).sum(),
+        crc,
+        resumed_from,
+        retransmits,
+        old_version,
+        new_version,
+        header,
+    })
+}
+
+/// Announce the image, header first. The node acknowledges with what it
+/// already has of it, nothing for a new update. Returns the offset to
+/// continue at.
+async fn begin<B: CanBus + 'static>(
+    client: &Client<B>,
+    sub: &mut Subscription,
+    addr: NodeAddr,
+    image: &[u8],
+    announce: &CanMessage,
+    pacing: Pacing,
+    timeout: Duration,
+) -> Result<u32> {
+    let start = CanMessage::FlashStart {
+        crc: crc32(image),
+        size: image.len() as u32,
+    };
+    client.send(addr, announce).await?;
+    client.send(addr, &start).await?;
+    let received = match wait_ack(client, sub, addr, pacing).await? {
+        Ack::Received(received) => received,
+        Ack::Rebooted => return Err(Error::NotApplied("node restarted".into())),
+    };
+    let offset = received / BLOCK_SIZE as u32 * BLOCK_SIZE as u32;
+    if offset == 0 {
+        return Ok(0);
+    }
+    let (written, crc) = verify(client, addr, timeout).await?;
+    if written == offset && crc == crc32(&image[..offset as usize]) {
+        return Ok(offset);
+    }
+    // the node holds something else, start over
+    erase(client, addr).await?;
+    client.send(addr, announce).await?;
+    client.send(addr, &start).await?;
+    match wait_ack(client, sub, addr, pacing).await? {
+        Ack::Received(_) => Ok(0),
+        Ack::Rebooted => Err(Error::NotApplied("node restarted".into())),
+    }
+}
+
+/// Header of the image the node runs.
+pub async fn running_header<B: CanBus + 'static>(
+    client: &Client<B>,
+    addr: NodeAddr,
+    timeout: Duration,
+) -> Result<ImageHeader> {
+    match client
+        .request(addr, CanMessageType::FlashHeader, timeout)
+        .await?
+    {
+        CanMessage::FlashHeader { header, .. } => Ok(header),
+        _ => unreachable!("request only returns the requested type"),
+    }
+}
+
+/// Running update as announced by `FlashStart`: CRC and size, `None` if
+/// there is none.
+pub async fn pending<B: CanBus + 'static>(
+    client: &Client<B>,
+    addr: NodeAddr,
+    timeout: Duration,
+) -> Result<Option<(u32, u32)>> {
+    match client
+        .request(addr, CanMessageType::FlashStart, timeout)
+        .await?
+    {
+        CanMessage::FlashStart { size: 0, .. } => Ok(None),
+        CanMessage::FlashStart { crc, size } => Ok(Some((crc, size))),
+        _ => unreachable!("request only returns the requested type"),
+    }
+}
+
+/// The blocks of `image` as sent, each compressed on its own if `compress`.
+pub(crate) fn encode(image: &[u8], compress: bool) -> Vec<Vec<u8>> {
+    image
+        .chunks(BLOCK_SIZE)
+        .map(|block| match compress {
+            true => {
+                let mut out = Vec::with_capacity(MAX_COMPRESSED_BLOCK);
+                compress::compress(block, |byte| out.push(byte));
+                out
+            }
+            false => block.to_vec(),
+        })
+        .collect()
+}
+
+/// Send all chunks of `block`. Stops early and returns where the node wants
+/// to continue if it reports a missing chunk.
+async fn send_block<B: CanBus + 'static>(
+    client: &Client<B>,
+    sub: &mut Subscription,
+    addr: NodeAddr,
+    blocks: &[Vec<u8>],
+    block: u32,
+    pacing: Pacing,
+    progress: &mut impl FnMut(usize, usize),
+) -> Result<Option<u32>> {
+    let data = &blocks[block as usize];
+    let start: usize = blocks[..block as usize].iter().map(Vec::len).sum();
+    let total = start + blocks[block as usize..].iter().map(Vec::len).sum::<usize>();
+    // answers to an earlier block are stale by now
+    while let Some(frame) = sub.try_next() {
+        if let Some(report) = update_error(&frame, addr) {
+            return Err(Error::Node(report));
+        }
+    }
+    for index in 0..data.len().div_ceil(CHUNK_SIZE) {
+        let chunk = Chunk::of_block(data, block, index);
+        let sent = index * CHUNK_SIZE + chunk.data.len();
+        client.send(addr, &CanMessage::FlashWrite(chunk)).await?;
+        progress(start + sent, total);
+        if !pacing.frame_gap.is_zero() {
+            tokio::time::sleep(pacing.frame_gap).await;
+        }
+        while let Some(frame) = sub.try_next() {
+            if let Some(report) = update_error(&frame, addr) {
+                return Err(Error::Node(report));
+            }
+            if let Some(received) = progress_of(&frame, addr) {
+                return Ok(Some(received));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Wait for the acknowledgement of a block, ask for it once if it does not
+/// show up. The last block may be answered by the reboot instead.
+async fn wait_ack<B: CanBus + 'static>(
+    client: &Client<B>,
+    sub: &mut Subscription,
+    addr: NodeAddr,
+    pacing: Pacing,
+) -> Result<Ack> {
+    let matcher = |frame: &Frame| {
+        if let Some(report) = update_error(frame, addr) {
+            return Some(Err(Error::Node(report)));
+        }
+        if let Some(received) = progress_of(frame, addr) {
+            return Some(Ok(Ack::Received(received)));
+        }
+        let available =
+            frame.addr() == addr && !frame.rtr && frame.id.msg_type == CanMessageType::Available;
+        available.then_some(Ok(Ack::Rebooted))
+    };
+    match sub
+        .wait_for(CanMessageType::FlashProgress, pacing.ack_timeout, matcher)
+        .await
+    {
+        Err(Error::Timeout(_)) => {}
+        other => return other?,
+    }
+    client
+        .send(addr, &CanMessage::Request(CanMessageType::FlashProgress))
+        .await?;
+    sub.wait_for(CanMessageType::FlashProgress, pacing.ack_timeout, matcher)
+        .await?
+}
+
+fn progress_of(frame: &Frame, addr: NodeAddr) -> Option<u32> {
+    if frame.addr() != addr || frame.rtr {
+        return None;
+    }
+    match frame.message() {
+        Ok(CanMessage::FlashProgress { received, .. }) => Some(received),
+        _ => None,
+    }
+}
+
+/// Bytes received and announced image size of the running update.
+pub async fn progress<B: CanBus + 'static>(
+    client: &Client<B>,
+    addr: NodeAddr,
+    timeout: Duration,
+) -> Result<(u32, u32)> {
+    match client
+        .request(addr, CanMessageType::FlashProgress, timeout)
+        .await?
+    {
+        CanMessage::FlashProgress { received, size } => Ok((received, size)),
+        _ => unreachable!("request only returns the requested type"),
+    }
+}
+
+/// Bytes written to flash and their CRC.
+pub async fn verify<B: CanBus + 'static>(
+    client: &Client<B>,
+    addr: NodeAddr,
+    timeout: Duration,
+) -> Result<(u32, u32)> {
+    match client
+        .request(addr, CanMessageType::FlashVerify, timeout)
+        .await?
+    {
+        CanMessage::FlashVerify { written, crc } => Ok((written, crc)),
+        _ => unreachable!("request only returns the requested type"),
+    }
+}
+
+/// Read `len` bytes at `offset` of the pending slot. Stops early at the end
+/// of the slot.
+pub async fn read<B: CanBus + 'static>(
+    client: &Client<B>,
+    addr: NodeAddr,
+    offset: u32,
+    len: u32,
+    timeout: Duration,
+) -> Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(len as usize);
+    while (data.len() as u32) < len {
+        let range = ReadRange {
+            offset: offset + data.len() as u32,
+            len: (len - data.len() as u32).min(MAX_READ_LEN),
+        };
+        let before = data.len();
+        read_range(client, addr, range, timeout, &mut data).await?;
+        if data.len() - before < range.len as usize {
+            break;
+        }
+    }
+    Ok(data)
+}
+
+async fn read_range<B: CanBus + 'static>(
+    client: &Client<B>,
+    addr: NodeAddr,
+    range: ReadRange,
+    timeout: Duration,
+    data: &mut Vec<u8>,
+) -> Result<()> {
+    let mut sub = client.subscribe();
+    let request = Payload::from_slice(&range.to_bytes()).unwrap();
+    client.send(addr, &CanMessage::FlashRead(request)).await?;
+
+    let end = data.len() + range.len as usize;
+    let collect = sub.wait_for(CanMessageType::FlashRead, timeout, |frame| {
+        if frame.rtr || frame.addr() != addr || frame.id.msg_type != CanMessageType::FlashRead {
+            return update_error(frame, addr).map(Err);
+        }
+        data.extend_from_slice(&frame.data);
+        (data.len() >= end).then_some(Ok(()))
+    });
+    match collect.await {
+        Ok(Ok(())) => Ok(()),
+        Ok(Err(report)) => Err(Error::Node(report)),
+        // the node stops at the end of the slot
+        Err(Error::Timeout(_)) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Abort a running update and wipe the pending slot.
+pub async fn erase<B: CanBus + 'static>(client: &Client<B>, addr: NodeAddr) -> Result<()> {
+    client
+        .transact(
+            addr,
+            &CanMessage::FlashErase,
+            CanMessageType::FlashErase,
+            ERASE_TIMEOUT,
+        )
+        .await
+        .map(|_| ())
+}
+
+/// Slot the node is running from, `None` for the factory partition.
+pub async fn booted_slot<B: CanBus + 'static>(
+    client: &Client<B>,
+    addr: NodeAddr,
+    timeout: Duration,
+) -> Result<Option<u8>> {
+    match client
+        .request(addr, CanMessageType::FlashSelect, timeout)
+        .await?
+    {
+        CanMessage::FlashSelect(slot) => Ok((slot < SLOT_COUNT).then_some(slot)),
+        _ => unreachable!("request only returns the requested type"),
+    }
+}
+
+/// Boot from `slot` after the next restart.
+pub async fn select<B: CanBus + 'static>(
+    client: &Client<B>,
+    addr: NodeAddr,
+    slot: u8,
+) -> Result<()> {
+    if slot >= SLOT_COUNT {
+        return Err(Error::InvalidArgument(format!("no slot {slot}")));
+    }
+    client.send(addr, &CanMessage::FlashSelect(slot)).await
+}
+
+/// How long a freshly updated node waits for the gateway before it rolls
+/// back to the previous image.
+pub async fn confirm_window<B: CanBus + 'static>(
+    client: &Client<B>,
+    addr: NodeAddr,
+    timeout: Duration,
+) -> Result<Duration> {
+    match client
+        .request(addr, CanMessageType::UpdateConfirmWindow, timeout)
+        .await?
+    {
+        CanMessage::UpdateConfirmWindow(secs) => Ok(Duration::from_secs(secs as u64)),
+        _ => unreachable!("request only returns the requested type"),
+    }
+}
+
+pub async fn set_confirm_window<B: CanBus + 'static>(
+    client: &Client<B>,
+    addr: NodeAddr,
+    window: Duration,
+) -> Result<()> {
+    let secs = u32::try_from(window.as_secs())
+        .map_err(|_| Error::InvalidArgument(format!("window {window:?} is too long")))?;
+    client
+        .send(addr, &CanMessage::UpdateConfirmWindow(secs))
+        .await
+}
+
+fn is_update_error(report: &ErrorReport) -> bool {
+    matches!(report.component, Component::Update | Component::Ota)
+}
+
+pub(crate) fn update_error(frame: &Frame, addr: NodeAddr) -> Option<ErrorReport> {
+    if frame.addr() != addr || frame.rtr {
+        return None;
+    }
+    match frame.message() {
+        Ok(CanMessage::DeviceError(report)) if is_update_error(&report) => Some(report),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::DEFAULT_TIMEOUT;
+    use crate::memory::MemoryBus;
+    use crate::sign;
+    use crate::sim::SimNode;
+    use async_trait::async_trait;
+    use cancomponents_core::image::{ImageError, SigningKey, Version, PROTOCOL_VERSION};
+    use cancomponents_core::ota::{UpdateErrorCode, CHUNKS_PER_BLOCK};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn key() -> SigningKey {
+        SigningKey::from_bytes(&[7; 32])
+    }
+
+    fn header() -> ImageHeader {
+        ImageHeader {
+            protocol: PROTOCOL_VERSION,
+            device_type: 5,
+            hwrev_min: 1,
+            hwrev_max: 2,
+            version: Version {
+                major: 1,
+                minor: 0,
+                patch: 0,
+            },
+        }
+    }
+
+    /// Stamped with `header()` and signed with `key()`.
+    fn signed(image: Vec<u8>) -> Vec<u8> {
+        stamped(image, header())
+    }
+
+    fn stamped(mut image: Vec<u8>, header: ImageHeader) -> Vec<u8> {
+        image.extend_from_slice(&header.stamp());
+        sign::sign(&image, &key()).unwrap()
+    }
+
+    fn options() -> UploadOptions<'static> {
+        UploadOptions {
+            pacing: Pacing {
+                frame_gap: Duration::ZERO,
+                ack_timeout: Duration::from_millis(100),
+                block_gap: Duration::ZERO,
+            },
+            ..Default::default()
+        }
+    }
+
+    /// Drops some of the frames the host sends and repeats others.
+    struct Lossy {
+        inner: MemoryBus,
+        sent: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl CanBus for Lossy {
+        async fn send(&self, frame: &Frame) -> Result<()> {
+            let n = self.sent.fetch_add(1, Ordering::Relaxed);
+            if [20, 700, 1500, 1501].contains(&n) {
+                return Ok(());
+            }
+            if n % 50 == 7 {
+                self.inner.send(frame).await?;
+            }
+            self.inner.send(frame).await
+        }
+
+        async fn recv(&self) -> Result<Frame> {
+            self.inner.recv().await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_upload() {
+        let addr = NodeAddr::new(5, 12);
+        let bus = MemoryBus::new();
+        tokio::spawn(SimNode::new(addr, 1).run(bus.endpoint()));
+        let client = Client::new(bus);
+
+        // one full block plus a partial one
+        let image = signed((0..BLOCK_SIZE + 1001).map(|i| (i * 7) as u8).collect());
+        let mut last = 0;
+        let report = upload(
+            &client,
+            addr,
+            &image,
+            &options(),
+            DEFAULT_TIMEOUT,
+            |sent, _| last = sent,
+        )
+        .await
+        .unwrap();
+        assert_eq!(last, image.len());
+        assert_eq!(report.crc, crc32(&image));
+        assert_eq!(report.old_version.as_deref(), Some("sim"));
+        assert_eq!(report.new_version.as_str(), format!("{:08x}", report.crc));
+        assert_eq!(report.retransmits, 0);
+    }
+
+    #[tokio::test]
+    async fn test_upload_lossy() {
+        let addr = NodeAddr::new(5, 12);
+        let bus = MemoryBus::new();
+        tokio::spawn(SimNode::new(addr, 1).run(bus.endpoint()));
+        let client = Client::new(Lossy {
+            inner: bus,
+            sent: AtomicUsize::new(0),
+        });
+
+        let image = signed((0..BLOCK_SIZE * 3).map(|i| (i * 13) as u8).collect());
+        let report = upload(
+            &client,
+            addr,
+            &image,
+            &options(),
+            DEFAULT_TIMEOUT,
+            |_, _| {},
+        )
+        .await
+        .unwrap();
+        assert!(report.retransmits > 0);
+        assert_eq!(report.new_version.as_str(), format!("{:08x}", report.crc));
+    }
+
+    #[tokio::test]
+    async fn test_upload_compressed() {
+        let addr = NodeAddr::new(5, 12);
+        let bus = MemoryBus::new();
+        tokio::spawn(SimNode::new(addr, 1).run(bus.endpoint()));
+        let client = Client::new(Lossy {
+            inner: bus,
+            sent: AtomicUsize::new(0),
+        });
+
+        // code followed by erased flash, like a real image
+        let mut image: Vec<u8> = (0..BLOCK_SIZE * 2).map(|i| (i / 3 * 13) as u8).collect();
+        image.resize(BLOCK_SIZE * 5 + 17, 0xFF);
+        let image = signed(image);
+        let options = UploadOptions {
+            compress: true,
+            ..options()
+        };
+        let mut last = (0, 0);
+        let report = upload(
+            &client,
+            addr,
+            &image,
+            &options,
+            DEFAULT_TIMEOUT,
+            |sent, total| last = (sent, total),
+        )
+        .await
+        .unwrap();
+        assert_eq!(last, (report.transfer_size, report.transfer_size));
+        assert!(report.transfer_size < image.len() / 2);
+        assert!(report.retransmits > 0);
+        assert_eq!(report.crc, crc32(&image));
+        assert_eq!(report.new_version.as_str(), format!("{:08x}", report.crc));
+    }
+
+    #[tokio::test]
+    async fn test_status_read_erase_select() {
+        let addr = NodeAddr::new(5, 12);
+        let bus = MemoryBus::new();
+        tokio::spawn(SimNode::new(addr, 1).run(bus.endpoint()));
+        let client = Client::new(bus);
+
+        let image = signed((0..BLOCK_SIZE * 2 + 5).map(|i| i as u8).collect());
+        upload(
+            &client,
+            addr,
+            &image,
+            &options(),
+            DEFAULT_TIMEOUT,
+            |_, _| {},
+        )
+        .await
+        .unwrap();
+        let booted = booted_slot(&client, addr, DEFAULT_TIMEOUT).await.unwrap();
+        assert_eq!(booted, Some(1));
+
+        // interrupted update: one block written, some bytes buffered
+        client
+            .send(
+                addr,
+                &CanMessage::FlashStart {
+                    crc: 0,
+                    size: 10_000,
+                },
+            )
+            .await
+            .unwrap();
+        let chunks = (0..CHUNKS_PER_BLOCK)
+            .map(|index| Chunk::of(&image, 0, index))
+            .chain((0..3).map(|index| Chunk::of(&image, 1, index)));
+        for chunk in chunks {
+            client
+                .send(addr, &CanMessage::FlashWrite(chunk))
+                .await
+                .unwrap();
+        }
+        let status = progress(&client, addr, DEFAULT_TIMEOUT).await.unwrap();
+        assert_eq!(status, (BLOCK_SIZE as u32 + 18, 10_000));
+        let (written, crc) = verify(&client, addr, DEFAULT_TIMEOUT).await.unwrap();
+        assert_eq!(written, BLOCK_SIZE as u32);
+        assert_eq!(crc, crc32(&image[..BLOCK_SIZE]));
+
+        // more than one request, and past what was written
+        let back = read(&client, addr, 100, 5000, DEFAULT_TIMEOUT)
+            .await
+            .unwrap();
+        assert_eq!(back.len(), 5000);
+        assert_eq!(back[..BLOCK_SIZE - 100], image[100..BLOCK_SIZE]);
+        assert!(back[BLOCK_SIZE - 100..].iter().all(|&b| b == 0xFF));
+
+        erase(&client, addr).await.unwrap();
+        let status = progress(&client, addr, DEFAULT_TIMEOUT).await.unwrap();
+        assert_eq!(status, (0, 0));
+        let back = read(&client, addr, 0, 8, DEFAULT_TIMEOUT).await.unwrap();
+        assert_eq!(back, vec![0xFF; 8]);
+
+        set_confirm_window(&client, addr, Duration::from_secs(60))
+            .await
+            .unwrap();
+        let window = confirm_window(&client, addr, DEFAULT_TIMEOUT)
+            .await
+            .unwrap();
+        assert_eq!(window, Duration::from_secs(60));
+
+        // the erased slot holds no verified image
+        select(&client, addr, 0).await.unwrap();
+        crate::commands::restart(&client, addr, DEFAULT_TIMEOUT)
+            .await
+            .unwrap();
+        let booted = booted_slot(&client, addr, DEFAULT_TIMEOUT).await.unwrap();
+        assert_eq!(booted, Some(1));
+    }
+
+    #[tokio::test]
+    async fn test_upload_resume() {
+        let addr = NodeAddr::new(5, 12);
+        let bus = MemoryBus::new();
+        tokio::spawn(SimNode::new(addr, 1).run(bus.endpoint()));
+        let image = signed((0..BLOCK_SIZE * 2 + 100).map(|i| (i * 3) as u8).collect());
+
+        // a gateway that dies after one and a half blocks
+        {
+            let client = Client::new(bus.endpoint());
+            let announce = CanMessage::FlashHeader {
+                header: header(),
+                allow_downgrade: false,
+                compressed: false,
+            };
+            client.send(addr, &announce).await.unwrap();
+            let start = CanMessage::FlashStart {
+                crc: crc32(&image),
+                size: image.len() as u32,
+            };
+            client.send(addr, &start).await.unwrap();
+            for index in 0..CHUNKS_PER_BLOCK + 300 {
+                let chunk = Chunk::of(
+                    &image,
+                    (index / CHUNKS_PER_BLOCK) as u32,
+                    index % CHUNKS_PER_BLOCK,
+                );
+                client
+                    .send(addr, &CanMessage::FlashWrite(chunk))
+                    .await
+                    .unwrap();
+            }
+        }
+
+        let client = Client::new(bus);
+        let state = pending(&client, addr, DEFAULT_TIMEOUT).await.unwrap();
+        assert_eq!(state, Some((crc32(&image), image.len() as u32)));
+        let report = upload(
+            &client,
+            addr,
+            &image,
+            &options(),
+            DEFAULT_TIMEOUT,
+            |_, _| {},
+        )
+        .await
+        .unwrap();
+        assert_eq!(report.resumed_from, BLOCK_SIZE as u32);
+        assert_eq!(report.new_version.as_str(), format!("{:08x}", report.crc));
+        let state = pending(&client, addr, DEFAULT_TIMEOUT).await.unwrap();
+        assert_eq!(state, None);
+    }
+
+    #[tokio::test]
+    async fn test_upload_rejected() {
+        let addr = NodeAddr::new(5, 12);
+        let bus = MemoryBus::new();
+        let mut node = SimNode::new(addr, 1);
+        node.corrupt_flash = true;
+        tokio::spawn(node.run(bus.endpoint()));
+        let client = Client::new(bus);
+
+        let image = signed(vec![0x55; 100]);
+        let err = upload(
+            &client,
+            addr,
+            &image,
+            &options(),
+            DEFAULT_TIMEOUT,
+            |_, _| {},
+        )
+        .await
+        .unwrap_err();
+        match err {
+            Error::Node(report) => {
+                assert_eq!(report.local_code, UpdateErrorCode::VerifyFailed as u8)
+            }
+            other => panic!("unexpected {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_upload_unsigned() {
+        let addr = NodeAddr::new(5, 12);
+        let bus = MemoryBus::new();
+        let mut node = SimNode::new(addr, 1);
+        node.update_key = Some(SigningKey::from_bytes(&[8; 32]).verifying_key());
+        tokio::spawn(node.run(bus.endpoint()));
+        let client = Client::new(bus);
+
+        let image = vec![0x55; 100];
+        let err = upload(
+            &client,
+            addr,
+            &image,
+            &options(),
+            DEFAULT_TIMEOUT,
+            |_, _| {},
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, Error::Image(ImageError::Unsigned)));
+
+        // signed with a key the node does not trust
+        let err = upload(
+            &client,
+            addr,
+            &signed(image),
+            &options(),
+            DEFAULT_TIMEOUT,
+            |_, _| {},
+        )
+        .await
+        .unwrap_err();
+        match err {
+            Error::Node(report) => {
+                assert_eq!(report.local_code, UpdateErrorCode::Signature as u8)
+            }
+            other => panic!("unexpected {other:?}"),
+        }
+        assert_eq!(pending(&client, addr, DEFAULT_TIMEOUT).await.unwrap(), None);
+        // nothing of it is left to boot
+        let back = read(&client, addr, 0, 8, DEFAULT_TIMEOUT).await.unwrap();
+        assert_eq!(back, vec![0xFF; 8]);
+    }
+
+    #[tokio::test]
+    async fn test_upload_incompatible() {
+        let addr = NodeAddr::new(5, 12);
+        let bus = MemoryBus::new();
+        let mut node = SimNode::new(addr, 1);
+        node.image.version = Version {
+            major: 1,
+            minor: 2,
+            patch: 0,
+        };
+        tokio::spawn(node.run(bus.endpoint()));
+        let client = Client::new(bus);
+
+        let rejected = |err| match err {
+            Error::Node(report) => UpdateErrorCode::from(report.local_code),
+            other => panic!("unexpected {other:?}"),
+        };
+        let button = ImageHeader {
+            device_type: 4,
+            ..header()
+        };
+        let hwrev3 = ImageHeader {
+            hwrev_min: 3,
+            hwrev_max: 3,
+            ..header()
+        };
+        for (header, code) in [
+            (button, UpdateErrorCode::WrongDevice),
+            (hwrev3, UpdateErrorCode::WrongHardware),
+            (header(), UpdateErrorCode::Downgrade),
+        ] {
+            let image = stamped(vec![0x55; 100], header);
+            let err = upload(
+                &client,
+                addr,
+                &image,
+                &options(),
+                DEFAULT_TIMEOUT,
+                |_, _| {},
+            )
+            .await
+            .unwrap_err();
+            assert_eq!(rejected(err), code);
+        }
+        // nothing was taken
+        assert_eq!(pending(&client, addr, DEFAULT_TIMEOUT).await.unwrap(), None);
+
+        let options = UploadOptions {
+            allow_downgrade: true,
+            ..options()
+        };
+        let image = signed(vec![0x55; 100]);
+        let report = upload(&client, addr, &image, &options, DEFAULT_TIMEOUT, |_, _| {})
+            .await
+            .unwrap();
+        assert_eq!(report.header, header());
+        let running = running_header(&client, addr, DEFAULT_TIMEOUT)
+            .await
+            .unwrap();
+        assert_eq!(running, header());
+
+        let err = upload(
+            &client,
+            addr,
+            &[0x55; 100],
+            &options,
+            DEFAULT_TIMEOUT,
+            |_, _| {},
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, Error::Image(ImageError::Unsigned)));
+    }
+}
@@ -0,0 +1,124 @@
+use crate::error::{Error, Result};
+use cancomponents_core::image::{
+    self, ImageHeader, SigningKey, VerifyingKey, MAGIC, PUBLIC_KEY_LENGTH,
+};
+use std::io::{Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+/// Create a new key pair as `PREFIX.key` (private, 32 byte seed) and
+/// `PREFIX.pub` (public, 32 bytes). Existing keys are never overwritten.
+pub fn keygen(prefix: &Path) -> Result<VerifyingKey> {
+    let mut seed = [0; 32];
+    std::fs::File::open("/dev/urandom")?.read_exact(&mut seed)?;
+    let key = SigningKey::from_bytes(&seed);
+
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(with_extension(prefix, "key"))?
+        .write_all(&seed)?;
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(with_extension(prefix, "pub"))?
+        .write_all(key.verifying_key().as_bytes())?;
+    Ok(key.verifying_key())
+}
+
+fn with_extension(prefix: &Path, extension: &str) -> PathBuf {
+    let mut path = prefix.as_os_str().to_owned();
+    path.push(".");
+    path.push(extension);
+    path.into()
+}
+
+pub fn read_signing_key(path: &Path) -> Result<SigningKey> {
+    Ok(SigningKey::from_bytes(&read_key(path)?))
+}
+
+pub fn read_verifying_key(path: &Path) -> Result<VerifyingKey> {
+    VerifyingKey::from_bytes(&read_key(path)?)
+        .map_err(|_| Error::InvalidArgument(format!("{}: not a public key", path.display())))
+}
+
+fn read_key(path: &Path) -> Result<[u8; PUBLIC_KEY_LENGTH]> {
+    let bytes = std::fs::read(path)?;
+    bytes.as_slice().try_into().map_err(|_| {
+        Error::InvalidArgument(format!(
+            "{}: expected {PUBLIC_KEY_LENGTH} bytes, got {}",
+            path.display(),
+            bytes.len()
+        ))
+    })
+}
+
+/// Append the signature trailer the firmware checks before activating an
+/// image. The image has to carry the header stamped by build.rs.
+pub fn sign(image: &[u8], key: &SigningKey) -> Result<Vec<u8>> {
+    if image::split(image).is_ok() {
+        return Err(Error::InvalidArgument("image is already signed".into()));
+    }
+    ImageHeader::find(image)?;
+    let mut signed = image.to_vec();
+    signed.extend_from_slice(&MAGIC);
+    signed.extend_from_slice(&image::sign(image, key).to_bytes());
+    Ok(signed)
+}
+
+/// Check a signed image the way the firmware does, returns its header.
+pub fn verify(signed: &[u8], key: &VerifyingKey) -> Result<ImageHeader> {
+    let mut verifier = image::Verifier::new(signed.len() as u32);
+    verifier.update(signed);
+    verifier.verify(key)?;
+    Ok(verifier.header()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cancomponents_core::image::ImageError;
+
+    #[test]
+    fn test_keygen_sign_verify() {
+        let dir = std::env::temp_dir().join(format!("ccsign-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let prefix = dir.join("update");
+        let public = keygen(&prefix).unwrap();
+        assert!(keygen(&prefix).is_err());
+
+        let key = read_signing_key(&prefix.with_extension("key")).unwrap();
+        assert_eq!(key.verifying_key(), public);
+        assert_eq!(
+            read_verifying_key(&prefix.with_extension("pub")).unwrap(),
+            public
+        );
+        let mode = std::fs::metadata(prefix.with_extension("key"))
+            .unwrap()
+            .permissions();
+        assert_eq!(
+            std::os::unix::fs::PermissionsExt::mode(&mode) & 0o777,
+            0o600
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let mut image = vec![0xA5; 1000];
+        assert!(matches!(
+            sign(&image, &key),
+            Err(Error::Image(ImageError::NoHeader))
+        ));
+        let header = ImageHeader::parse(&[1, 0, 0, 255, 0, 1, 0]).unwrap();
+        image.extend_from_slice(&header.stamp());
+        assert!(image::split(&image).is_err());
+        let signed = sign(&image, &key).unwrap();
+        assert!(image::split(&signed).is_ok());
+        assert!(sign(&signed, &key).is_err());
+        assert_eq!(verify(&signed, &public).unwrap(), header);
+        let other = SigningKey::from_bytes(&[1; 32]).verifying_key();
+        assert!(matches!(
+            verify(&signed, &other),
+            Err(Error::Image(ImageError::BadSignature))
+        ));
+    }
+}
@@ -0,0 +1,965 @@
+use crate::bus::CanBus;
+use crate::error::Result;
+use crate::frame::{Frame, NodeAddr};
+use cancomponents_core::announcement::{Announcement, Capabilities, Channels};
+use cancomponents_core::binding::{Binding, MAX_BINDINGS};
+use cancomponents_core::button_message::ButtonMessage;
+use cancomponents_core::can_id::CanId;
+use cancomponents_core::can_message_type::CanMessageType;
+use cancomponents_core::device_message::{is_address_conflict, UNASSIGNED_ID};
+use cancomponents_core::error_report::{Component, ErrorCode, ErrorReport, Severity};
+use cancomponents_core::extension::Extension;
+use cancomponents_core::group::Groups;
+use cancomponents_core::image::{ImageHeader, VerifyingKey, Version, PROTOCOL_VERSION};
+use cancomponents_core::interlock::Interlock;
+use cancomponents_core::message::{CanMessage, Payload};
+use cancomponents_core::metadata::{Entry, Field, MAX_VALUE_LEN};
+use cancomponents_core::ota::{
+    crc32, Accept, Chunk, Multicast, ReadRange, Session, UpdateErrorCode, SLOT_COUNT, SLOT_SIZE,
+};
+use cancomponents_core::power_on::PowerOnPolicy;
+use cancomponents_core::relais_message::{RelaisMode, RelaisState};
+use cancomponents_core::rollershutter::{Calibration, Shutter, ShutterCommand, MAX_SHUTTERS};
+use cancomponents_core::scene::{Scene, MAX_SCENES};
+use cancomponents_core::supervisor::{Mode, ModeReport, Policy, Supervision};
+use cancomponents_core::transport::{Receiver, Segment, Sender, Step};
+use heapless::String;
+use std::time::{Duration, Instant};
+
+const RELAIS_COUNT: usize = 16;
+
+/// `AddressClaim` interval, shorter than on the node to keep tests fast.
+const CLAIM_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Longest transfer the node takes, like `MAX_TRANSFER` on the node.
+const MAX_TRANSFER: usize = 256;
+
+/// Wait this long for flow control of the gateway.
+const FLOW_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// A software node that answers like the firmware does. Used to exercise
+/// host tools without hardware, either in-process on a `MemoryBus` or on
+/// `vcan0` via `ccctl simulate`.
+pub struct SimNode {
+    pub addr: NodeAddr,
+    pub uid: u64,
+    pub name: String<8>,
+    pub version: String<8>,
+    pub hwrev: u8,
+    pub relais_mode: RelaisMode,
+    pub extension: Extension,
+    pub relais: [RelaisState; RELAIS_COUNT],
+    /// used unless `relais_mode` is `Relais`, positions are lost on restart
+    pub shutters: [Shutter; MAX_SHUTTERS],
+    /// reversals of the shutters pause like on the node
+    interlocks: [Interlock; MAX_SHUTTERS],
+    /// configured groups, see `active_groups`
+    pub groups: Groups,
+    /// the sim does not supervise the gateway, tests set the mode
+    pub mode: Mode,
+    pub policy: Policy,
+    pub supervision: Supervision,
+    /// stored only, the sim does not restart into it
+    pub power_on: PowerOnPolicy,
+    /// name, room, floor and notes, indexed by `Field`
+    pub metadata: [String<MAX_VALUE_LEN>; 4],
+    /// flip a bit in every flashed image, the node then rejects it
+    pub corrupt_flash: bool,
+    pub confirm_window: u32,
+    /// key flashed images have to be signed with, `None` accepts any image
+    pub update_key: Option<VerifyingKey>,
+    /// header of the running image
+    pub image: ImageHeader,
+    boot_time: Instant,
+    /// written part of ota_0 and ota_1, the rest reads as erased
+    slots: [Vec<u8>; SLOT_COUNT as usize],
+    booted: u8,
+    boot_target: u8,
+    update: Option<Session>,
+    multicast: Option<Multicast>,
+    announced: Option<(ImageHeader, bool)>,
+    /// blocks of the announced image arrive compressed
+    compressed: bool,
+    /// UIDs the gateway selected for a `DeviceIdType` without UID
+    selected: (u64, u64),
+    conflict_reported: bool,
+    scenes: [Option<Scene>; MAX_SCENES as usize],
+    bindings: [Option<Binding>; MAX_BINDINGS as usize],
+    /// segmented transfer of the gateway
+    receiver: Receiver<MAX_TRANSFER>,
+    /// recalled scene waiting for its delay, dropped on restart
+    pending_scene: Option<(tokio::time::Instant, Scene)>,
+    /// groups the node reacts to, like on the node the configured ones
+    /// as of the last boot
+    active_groups: Groups,
+}
+
+impl SimNode {
+    pub fn new(addr: NodeAddr, uid: u64) -> Self {
+        Self {
+            addr,
+            uid,
+            name: String::new(),
+            version: String::try_from("sim").unwrap(),
+            hwrev: 1,
+            relais_mode: RelaisMode::Relais,
+            extension: Extension::Off,
+            relais: core::array::from_fn(|_| RelaisState::Off),
+            shutters: Default::default(),
+            interlocks: Default::default(),
+            corrupt_flash: false,
+            confirm_window: 300,
+            update_key: None,
+            image: ImageHeader {
+                protocol: PROTOCOL_VERSION,
+                device_type: 0,
+                hwrev_min: 0,
+                hwrev_max: u8::MAX,
+                version: Version {
+                    major: 0,
+                    minor: 1,
+                    patch: 0,
+                },
+            },
+            boot_time: Instant::now(),
+            slots: Default::default(),
+            booted: 0,
+            boot_target: 0,
+            update: None,
+            multicast: None,
+            announced: None,
+            compressed: false,
+            selected: (0, 0),
+            conflict_reported: false,
+            groups: Groups::default(),
+            mode: Mode::Online,
+            policy: Policy::default(),
+            supervision: Supervision::default(),
+            power_on: PowerOnPolicy::default(),
+            metadata: Default::default(),
+            receiver: Receiver::new(8, embassy_time::Duration::from_ticks(0)),
+            active_groups: Groups::default(),
+            scenes: Default::default(),
+            bindings: [None; MAX_BINDINGS as usize],
+            pending_scene: None,
+        }
+    }
+
+    /// Same acceptance rules as the hardware filter plus `dispatch`.
+    fn accepts(&self, frame: &Frame) -> bool {
+        frame.id.is_for(
+            self.addr.device_type,
+            self.addr.device_id,
+            self.active_groups,
+        )
+    }
+
+    async fn send<B: CanBus>(&self, bus: &B, msg: CanMessage) -> Result<()> {
+        bus.send(&Frame::from_message(self.addr, &msg)).await
+    }
+
+    pub async fn run<B: CanBus>(mut self, bus: B) -> Result<()> {
+        self.active_groups = self.groups;
+        self.announce(&bus).await?;
+        // spread claims of nodes started together, like the random backoff
+        let mut next_claim = tokio::time::Instant::now() + Duration::from_millis(self.uid % 50);
+        loop {
+            let claim = (self.addr.device_id == UNASSIGNED_ID).then_some(next_claim);
+            let scene = self.pending_scene.as_ref().map(|(at, _)| *at);
+            let shutter = self.shutter_deadline();
+            let frame = tokio::select! {
+                frame = bus.recv() => frame?,
+                _ = sleep_until(claim) => {
+                    self.send(&bus, CanMessage::AddressClaim(self.uid)).await?;
+                    next_claim = tokio::time::Instant::now() + CLAIM_INTERVAL;
+                    continue;
+                }
+                _ = sleep_until(scene) => {
+                    if let Some((_, scene)) = self.pending_scene.take() {
+                        self.apply_scene(&bus, &scene).await?;
+                    }
+                    continue;
+                }
+                _ = sleep_until(shutter) => {
+                    self.poll_shutters(&bus).await?;
+                    continue;
+                }
+            };
+            // bindings listen to the buttons of every node
+            if frame.id.msg_type == CanMessageType::ButtonEvent && !frame.rtr {
+                if let Ok(CanMessage::ButtonEvent(msg)) = frame.message() {
+                    self.button_event(&bus, frame.addr(), msg).await?;
+                }
+                continue;
+            }
+            if !self.accepts(&frame) {
+                continue;
+            }
+            let (device_type, device_id) = (self.addr.device_type, self.addr.device_id);
+            if is_address_conflict(device_type, device_id, frame.id, frame.rtr, &frame.data) {
+                self.address_conflict(&bus, frame.id.msg_type).await?;
+                continue;
+            }
+            // malformed frames are dropped like on the node
+            if let Ok(msg) = frame.message() {
+                let to_group = frame.addr().device_id == 0;
+                self.handle(&bus, msg, to_group).await?;
+            }
+        }
+    }
+
+    async fn handle<B: CanBus>(&mut self, bus: &B, msg: CanMessage, to_group: bool) -> Result<()> {
+        match msg {
+            CanMessage::Ping => self.send(bus, CanMessage::Ping).await?,
+            CanMessage::Request(CanMessageType::Available) => self.announce(bus).await?,
+            CanMessage::Request(CanMessageType::Metadata) => self.send_metadata(bus).await?,
+            CanMessage::Request(CanMessageType::BindingDefine) => {
+                for binding in self.bindings.into_iter().flatten() {
+                    self.send(bus, CanMessage::BindingDefine(binding)).await?;
+                }
+            }
+            CanMessage::Request(CanMessageType::ShutterTravel) => {
+                for (num, shutter) in self.shutters.iter().enumerate() {
+                    let travel = shutter.travel();
+                    let calibration = Calibration {
+                        num: num as u8,
+                        travel,
+                    };
+                    self.send(bus, CanMessage::ShutterTravel(calibration))
+                        .await?;
+                }
+            }
+            CanMessage::Request(CanMessageType::RollershutterState) if self.drives_shutters() => {
+                let now = self.now();
+                for (num, shutter) in self.shutters.iter().enumerate() {
+                    let report = shutter.report(num as u8, now);
+                    self.send(bus, CanMessage::RollershutterState(report))
+                        .await?;
+                }
+            }
+            CanMessage::Request(msg_type) => {
+                if let Some(reply) = self.parameter(msg_type) {
+                    self.send(bus, reply).await?;
+                }
+            }
+            CanMessage::RequestParameter => {
+                for msg_type in [
+                    CanMessageType::Uptime,
+                    CanMessageType::DeviceUid0,
+                    CanMessageType::DeviceUid1,
+                    CanMessageType::CustomString,
+                    CanMessageType::HwRev,
+                    CanMessageType::RelaisMode,
+                    CanMessageType::ExtensionMode,
+                    CanMessageType::ApplicationVersionString,
+                ] {
+                    if let Some(reply) = self.parameter(msg_type) {
+                        self.send(bus, reply).await?;
+                    }
+                }
+                self.send_metadata(bus).await?;
+            }
+            CanMessage::Metadata(data) => self.set_metadata(bus, &data).await?,
+            CanMessage::Segment(segment) => self.segment(bus, segment).await?,
+            CanMessage::DeviceUid0(uid) => self.selected.0 = uid,
+            CanMessage::DeviceUid1(uid) => self.selected.1 = uid,
+            CanMessage::DeviceIdType {
+                id,
+                device_type,
+                uid,
+            } => {
+                let selected = match uid {
+                    Some(uid) => uid == self.uid,
+                    None => self.selected == (self.uid, self.uid),
+                };
+                if selected {
+                    let claimed = self.addr.device_id == UNASSIGNED_ID;
+                    self.addr = NodeAddr::new(device_type, id);
+                    if claimed {
+                        self.restart(bus).await?;
+                    }
+                }
+            }
+            CanMessage::DeviceGroup(groups) => self.groups = groups,
+            CanMessage::CustomString(name) => self.name = name,
+            CanMessage::HwRev(hwrev) => self.hwrev = hwrev,
+            CanMessage::RelaisMode(mode) => self.relais_mode = mode,
+            CanMessage::ExtensionMode(extension) => self.extension = extension,
+            CanMessage::AutonomousPolicy(policy) => self.policy = policy,
+            CanMessage::Supervision(supervision) => self.supervision = supervision,
+            CanMessage::PowerOn(policy) => self.power_on = policy,
+            CanMessage::Relais(cmd) | CanMessage::Rollershutter(ShutterCommand::Move(cmd)) => {
+                if let Some(relais) = self.relais.get_mut(cmd.num) {
+                    *relais = cmd.state.clone();
+                    self.switched(bus, cmd.num, &cmd.state).await?;
+                }
+            }
+            CanMessage::Rollershutter(ShutterCommand::Position { num, percent }) => {
+                let now = self.now();
+                let drives = self.drives_shutters();
+                let state = match self.shutters.get_mut(num) {
+                    Some(shutter) if drives => shutter.go_to(percent, now),
+                    _ => None,
+                };
+                match state {
+                    Some(state) => self.drive(bus, num, state).await?,
+                    None => {
+                        let report = ErrorReport::new(
+                            Component::Relais,
+                            ErrorCode::InvalidData,
+                            Severity::Warning,
+                            0,
+                            &[CanMessageType::Rollershutter as u8, num as u8, percent],
+                        );
+                        self.send(bus, CanMessage::DeviceError(report)).await?;
+                    }
+                }
+            }
+            CanMessage::DeadTime(dead_time) => {
+                for interlock in self.interlocks.iter_mut() {
+                    interlock.set_dead_time(dead_time);
+                }
+            }
+            CanMessage::ShutterTravel(calibration) => {
+                let now = self.now();
+                self.shutters[calibration.num as usize].calibrate(calibration.travel, now);
+            }
+            CanMessage::SceneDefine(scene) => {
+                let num = scene.num as usize;
+                self.scenes[num] = Some(scene);
+            }
+            CanMessage::SceneRecall(num) => {
+                if let Some(scene) = self.scenes[num as usize].clone() {
+                    let at = tokio::time::Instant::now() + scene.delay.into();
+                    self.pending_scene = Some((at, scene));
+                }
+            }
+            CanMessage::SceneStore { scene, delay } => {
+                let mut stored = Scene::new(scene);
+                stored.delay = delay;
+                for num in 0..self.channels() {
+                    stored.states[num] = Some(self.relais[num].clone());
+                }
+                self.scenes[scene as usize] = Some(stored.clone());
+                self.send(bus, CanMessage::SceneDefine(stored)).await?;
+            }
+            CanMessage::BindingDefine(binding) => {
+                self.bindings[binding.slot as usize] = Some(binding)
+            }
+            CanMessage::BindingClear(slot) => self.bindings[slot as usize] = None,
+            CanMessage::Restart => self.restart(bus).await?,
+            CanMessage::FlashHeader {
+                header,
+                allow_downgrade,
+                compressed,
+            } => {
+                self.announced = Some((header, allow_downgrade));
+                self.compressed = compressed;
+            }
+            CanMessage::FlashStart { crc, size } => {
+                let checked = self.announced.ok_or(UpdateErrorCode::NoHeader).and_then(
+                    |(header, allow_downgrade)| {
+                        header.check(
+                            self.addr.device_type,
+                            self.hwrev,
+                            self.image.version,
+                            allow_downgrade,
+                        )
+                    },
+                );
+                if let Err(code) = checked {
+                    return self.send(bus, update_error(code, &[])).await;
+                }
+                if to_group {
+                    return self.start_multicast(bus, size, crc).await;
+                }
+                self.multicast = None;
+                if let Some(session) = self.update.as_mut() {
+                    if session.is_resumable(size, crc) {
+                        session.set_compressed(self.compressed);
+                        let ack = session.progress();
+                        return self.send(bus, ack).await;
+                    }
+                }
+                let pending = self.pending_slot();
+                self.slots[pending].clear();
+                let mut session = Session::new(size, crc);
+                session.set_compressed(self.compressed);
+                self.send(bus, session.progress()).await?;
+                self.update = Some(session);
+            }
+            CanMessage::FlashWrite(chunk) if self.multicast.is_some() => {
+                self.multicast_write(bus, &chunk).await?
+            }
+            CanMessage::FlashWrite(chunk) => self.flash_write(bus, &chunk).await?,
+            CanMessage::FlashBlock(block) => {
+                if let Some(multicast) = self.multicast.as_mut() {
+                    multicast.seek(block);
+                }
+            }
+            CanMessage::FlashMissing { block, .. } => {
+                if let Some(multicast) = &self.multicast {
+                    self.send(bus, multicast.missing(block)).await?;
+                }
+            }
+            CanMessage::FlashComplete
+                if self.multicast.as_ref().is_some_and(Multicast::is_verified) =>
+            {
+                let pending = self.pending_slot();
+                self.finish(bus, pending).await?;
+            }
+            CanMessage::FlashErase => {
+                self.update = None;
+                self.multicast = None;
+                let pending = self.pending_slot();
+                self.slots[pending].clear();
+                self.send(bus, CanMessage::FlashErase).await?;
+            }
+            // the booted slot or a verified multicast image
+            CanMessage::FlashSelect(slot) if slot == self.booted => self.boot_target = slot,
+            CanMessage::FlashSelect(slot)
+                if slot as usize == self.pending_slot()
+                    && self.multicast.as_ref().is_some_and(Multicast::is_verified) =>
+            {
+                self.boot_target = slot
+            }
+            CanMessage::FlashSelect(_) => {
+                self.send(bus, update_error(UpdateErrorCode::InvalidData, &[]))
+                    .await?
+            }
+            CanMessage::UpdateConfirmWindow(window) => self.confirm_window = window,
+            CanMessage::FlashRead(data) => {
+                if let Ok(range) = ReadRange::parse(&data) {
+                    self.flash_read(bus, range.clamp(SLOT_SIZE)).await?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    async fn restart<B: CanBus>(&mut self, bus: &B) -> Result<()> {
+        self.boot_time = Instant::now();
+        self.relais = core::array::from_fn(|_| RelaisState::Off);
+        for shutter in self.shutters.iter_mut() {
+            *shutter = Shutter::new(shutter.travel());
+        }
+        for interlock in self.interlocks.iter_mut() {
+            *interlock = Interlock::new(interlock.dead_time());
+        }
+        self.booted = self.boot_target;
+        self.update = None;
+        self.pending_scene = None;
+        self.active_groups = self.groups;
+        self.announce(bus).await
+    }
+
+    async fn button_event<B: CanBus>(
+        &mut self,
+        bus: &B,
+        source: NodeAddr,
+        msg: ButtonMessage,
+    ) -> Result<()> {
+        if self.mode == Mode::Online && self.policy.bindings_autonomous_only {
+            return Ok(());
+        }
+        for binding in self.bindings.into_iter().flatten() {
+            let ours = binding.group == 0 || self.active_groups.contains(binding.group);
+            let num = binding.relay as usize;
+            if !ours || !binding.matches(source.device_type, source.device_id, &msg) {
+                continue;
+            }
+            if let Some(relais) = self.relais.get_mut(num) {
+                let (state, _) = binding.action.resolve(relais);
+                *relais = state.clone();
+                self.switched(bus, num, &state).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Channels in the configured mode, two relais per shutter.
+    fn channels(&self) -> usize {
+        match self.relais_mode {
+            RelaisMode::Relais => RELAIS_COUNT,
+            _ => RELAIS_COUNT / 2,
+        }
+    }
+
+    /// Switch all channels of the scene together, report the changed ones.
+    async fn apply_scene<B: CanBus>(&mut self, bus: &B, scene: &Scene) -> Result<()> {
+        let channels = self.channels();
+        let mut changed = Vec::new();
+        for (num, state) in scene.targets().filter(|(num, _)| *num < channels) {
+            if self.relais[num] != *state {
+                self.relais[num] = state.clone();
+                changed.push((num, state.clone()));
+            }
+        }
+        for (num, state) in changed {
+            self.switched(bus, num, &state).await?;
+        }
+        Ok(())
+    }
+
+    fn drives_shutters(&self) -> bool {
+        matches!(
+            self.relais_mode,
+            RelaisMode::SoftwareRollershutter | RelaisMode::HardwareRollershutter
+        )
+    }
+
+    /// Time since boot on the clock of the shutter trackers.
+    fn now(&self) -> embassy_time::Instant {
+        embassy_time::Instant::from_millis(self.boot_time.elapsed().as_millis() as u64)
+    }
+
+    fn shutter_deadline(&self) -> Option<tokio::time::Instant> {
+        if !self.drives_shutters() {
+            return None;
+        }
+        let shutters = self.shutters.iter().filter_map(Shutter::deadline);
+        let interlocks = self.interlocks.iter().filter_map(Interlock::deadline);
+        let at = shutters.chain(interlocks).min()?;
+        Some((self.boot_time + Duration::from_millis(at.as_millis())).into())
+    }
+
+    /// Start directions after their dead time, stop shutters at their
+    /// position or end stop.
+    async fn poll_shutters<B: CanBus>(&mut self, bus: &B) -> Result<()> {
+        let now = self.now();
+        for num in 0..MAX_SHUTTERS {
+            if let Some(output) = self.interlocks[num].poll(now) {
+                self.shutters[num].started(now);
+                self.send(bus, CanMessage::RelaisState(output)).await?;
+            }
+        }
+        for num in 0..MAX_SHUTTERS {
+            if let Some(state) = self.shutters[num].poll(now) {
+                self.drive(bus, num, state).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Switch a shutter to what its tracker decided.
+    async fn drive<B: CanBus>(&mut self, bus: &B, num: usize, state: RelaisState) -> Result<()> {
+        let output = self.interlocks[num].request(&state, self.now());
+        if self.relais[num] != state {
+            self.relais[num] = state;
+            self.send(bus, CanMessage::RelaisState(output)).await?;
+        }
+        let report = self.shutters[num].report(num as u8, self.now());
+        self.send(bus, CanMessage::RollershutterState(report)).await
+    }
+
+    /// Report a switch the tracker of shutter `num` did not decide, as the
+    /// state the motor runs in, and keep the position in line with it.
+    async fn switched<B: CanBus>(
+        &mut self,
+        bus: &B,
+        num: usize,
+        state: &RelaisState,
+    ) -> Result<()> {
+        let now = self.now();
+        let shutter = match self.drives_shutters() {
+            true => self.shutters.get_mut(num),
+            false => None,
+        };
+        let Some(shutter) = shutter else {
+            return self.send(bus, CanMessage::RelaisState(state.clone())).await;
+        };
+        let output = self.interlocks[num].request(state, now);
+        shutter.set(state, now);
+        let report = shutter.report(num as u8, now);
+        self.send(bus, CanMessage::RelaisState(output)).await?;
+        self.send(bus, CanMessage::RollershutterState(report)).await
+    }
+
+    async fn announce<B: CanBus>(&self, bus: &B) -> Result<()> {
+        let mut capabilities = Capabilities::SIGNED_UPDATE
+            | Capabilities::COMPRESSED_UPDATE
+            | Capabilities::MULTICAST_UPDATE
+            | Capabilities::ADDRESS_CLAIM;
+        let mut channels = Channels::default();
+        match self.relais_mode {
+            RelaisMode::Relais => {
+                capabilities = capabilities | Capabilities::RELAIS;
+                channels.relais = self.channels() as u8;
+            }
+            _ => {
+                capabilities = capabilities | Capabilities::ROLLERSHUTTER;
+                channels.rollershutters = self.channels() as u8;
+            }
+        }
+        let announcement = Announcement {
+            protocol: PROTOCOL_VERSION,
+            device_type: self.addr.device_type,
+            hwrev: self.hwrev,
+            version: self.image.version,
+            extension: self.extension,
+            capabilities,
+        };
+        self.send(bus, CanMessage::Available(Some(announcement)))
+            .await?;
+        self.send(bus, CanMessage::Channels(channels)).await
+    }
+
+    /// Reported once, the other node answers our report with its own.
+    async fn address_conflict<B: CanBus>(
+        &mut self,
+        bus: &B,
+        msg_type: CanMessageType,
+    ) -> Result<()> {
+        if self.conflict_reported {
+            return Ok(());
+        }
+        self.conflict_reported = true;
+        let uid = self.uid.to_be_bytes();
+        let report = ErrorReport::new(
+            Component::Device,
+            ErrorCode::AddressConflict,
+            Severity::RepeatingError,
+            msg_type as u8,
+            &uid[5..],
+        );
+        self.send(bus, CanMessage::DeviceError(report)).await
+    }
+
+    fn pending_slot(&self) -> usize {
+        (1 - self.booted) as usize
+    }
+
+    async fn flash_write<B: CanBus>(&mut self, bus: &B, chunk: &Chunk) -> Result<()> {
+        let pending = self.pending_slot();
+        // like the firmware, writes without FlashStart are dropped
+        let Some(session) = self.update.as_mut() else {
+            return Ok(());
+        };
+        match session.accept(chunk) {
+            Accept::Ignored | Accept::Buffered => return Ok(()),
+            Accept::Gap => {
+                let nack = session.progress();
+                return self.send(bus, nack).await;
+            }
+            Accept::Block => {}
+        }
+        let slot = &mut self.slots[pending];
+        slot.extend_from_slice(session.block());
+        if self.corrupt_flash && slot.len() == session.block().len() {
+            slot[0] ^= 1;
+        }
+        session.commit();
+        let ack = session.progress();
+        let (complete, size, expected) = (session.is_complete(), session.size, session.crc);
+        let header = session.header();
+        let signature = match self.update_key {
+            Some(key) => session.verify_signature(&key),
+            None => Ok(()),
+        };
+        self.send(bus, ack).await?;
+        if !complete {
+            return Ok(());
+        }
+        let announced = self.announced.map(|(header, _)| header);
+        // rejected images are wiped like on the node
+        if header.ok() != announced {
+            self.update = None;
+            self.slots[pending].clear();
+            return self
+                .send(bus, update_error(UpdateErrorCode::HeaderMismatch, &[]))
+                .await;
+        }
+        if let Err(e) = signature {
+            self.update = None;
+            self.slots[pending].clear();
+            return self
+                .send(bus, update_error(UpdateErrorCode::Signature, &[e.code()]))
+                .await;
+        }
+
+        let slot = &self.slots[pending];
+        let crc = crc32(&slot[..(size as usize).min(slot.len())]);
+        if crc != expected {
+            return self
+                .send(bus, update_error(UpdateErrorCode::VerifyFailed, &[]))
+                .await;
+        }
+        self.finish(bus, pending).await
+    }
+
+    /// Boot the new image in `pending`.
+    async fn finish<B: CanBus>(&mut self, bus: &B, pending: usize) -> Result<()> {
+        let crc = match (&self.update, &self.multicast) {
+            (Some(session), _) => session.crc,
+            (None, Some(multicast)) => multicast.crc,
+            (None, None) => return Ok(()),
+        };
+        // the simulated image reports its CRC as version
+        self.version = String::try_from(format!("{crc:08x}").as_str()).unwrap();
+        self.boot_target = pending as u8;
+        if let Some((header, _)) = self.announced {
+            self.image = header;
+        }
+        self.multicast = None;
+        self.restart(bus).await
+    }
+
+    async fn start_multicast<B: CanBus>(&mut self, bus: &B, size: u32, crc: u32) -> Result<()> {
+        self.update = None;
+        if let Some(multicast) = self.multicast.as_mut() {
+            if multicast.is_resumable(size, crc) {
+                multicast.set_compressed(self.compressed);
+                let ack = multicast.progress();
+                return self.send(bus, ack).await;
+            }
+        }
+        match Multicast::new(size, crc) {
+            Some(mut multicast) => {
+                multicast.set_compressed(self.compressed);
+                let ack = multicast.progress();
+                self.multicast = Some(multicast);
+                self.send(bus, ack).await
+            }
+            None => {
+                self.multicast = None;
+                self.send(bus, update_error(UpdateErrorCode::Begin, &[]))
+                    .await
+            }
+        }
+    }
+
+    async fn multicast_write<B: CanBus>(&mut self, bus: &B, chunk: &Chunk) -> Result<()> {
+        let pending = self.pending_slot();
+        let Some(multicast) = self.multicast.as_mut() else {
+            return Ok(());
+        };
+        if multicast.accept(chunk) != Accept::Block {
+            return Ok(());
+        }
+        let (offset, block) = multicast.block();
+        let slot = &mut self.slots[pending];
+        let end = offset as usize + block.len();
+        if slot.len() < end {
+            slot.resize(end, 0xFF);
+        }
+        slot[offset as usize..end].copy_from_slice(block);
+        multicast.commit();
+        if !multicast.is_complete() {
+            return Ok(());
+        }
+
+        let slot = &self.slots[pending];
+        let checked = multicast
+            .read_back(|offset, buf| {
+                let data = slot.get(offset as usize..offset as usize + buf.len());
+                data.map(|data| buf.copy_from_slice(data)).ok_or(())
+            })
+            .and_then(|verifier| {
+                if let Some(key) = &self.update_key {
+                    verifier
+                        .verify(key)
+                        .map_err(|_| UpdateErrorCode::Signature)?;
+                }
+                let announced = self.announced.map(|(header, _)| header);
+                match verifier.header() {
+                    Ok(header) if Some(header) == announced => Ok(()),
+                    _ => Err(UpdateErrorCode::HeaderMismatch),
+                }
+            });
+        match checked {
+            Ok(()) => {
+                multicast.set_verified();
+                Ok(())
+            }
+            Err(code) => {
+                self.multicast = None;
+                self.slots[pending].clear();
+                self.send(bus, update_error(code, &[])).await
+            }
+        }
+    }
+
+    async fn flash_read<B: CanBus>(&self, bus: &B, range: ReadRange) -> Result<()> {
+        let slot = &self.slots[self.pending_slot()];
+        let bytes: Vec<u8> = (range.offset..range.offset + range.len)
+            .map(|i| slot.get(i as usize).copied().unwrap_or(0xFF))
+            .collect();
+        for chunk in bytes.chunks(8) {
+            let payload = Payload::from_slice(chunk).unwrap();
+            self.send(bus, CanMessage::FlashRead(payload)).await?;
+        }
+        Ok(())
+    }
+
+    fn parameter(&self, msg_type: CanMessageType) -> Option<CanMessage> {
+        let msg = match msg_type {
+            CanMessageType::Uptime => {
+                CanMessage::Uptime((self.boot_time.elapsed().as_secs() / 60) as u32)
+            }
+            CanMessageType::DeviceUid0 => CanMessage::DeviceUid0(self.uid),
+            CanMessageType::DeviceUid1 => CanMessage::DeviceUid1(self.uid),
+            CanMessageType::CustomString => CanMessage::CustomString(self.name.clone()),
+            CanMessageType::HwRev => CanMessage::HwRev(self.hwrev),
+            CanMessageType::DeviceGroup => CanMessage::DeviceGroup(self.groups),
+            CanMessageType::RelaisMode => CanMessage::RelaisMode(self.relais_mode),
+            CanMessageType::ExtensionMode => CanMessage::ExtensionMode(self.extension),
+            CanMessageType::AutonomousPolicy => CanMessage::AutonomousPolicy(self.policy),
+            CanMessageType::Supervision => CanMessage::Supervision(self.supervision),
+            CanMessageType::PowerOn => CanMessage::PowerOn(self.power_on),
+            CanMessageType::DeadTime => CanMessage::DeadTime(self.interlocks[0].dead_time()),
+            CanMessageType::NodeMode => CanMessage::NodeMode(ModeReport {
+                mode: self.mode,
+                duration: self.boot_time.elapsed().as_secs() as u32,
+            }),
+            CanMessageType::ApplicationVersionString => {
+                CanMessage::ApplicationVersionString(self.version.clone())
+            }
+            CanMessageType::FlashProgress => match (&self.update, &self.multicast) {
+                (Some(session), _) => session.progress(),
+                (None, Some(multicast)) => multicast.progress(),
+                (None, None) => CanMessage::FlashProgress {
+                    received: 0,
+                    size: 0,
+                },
+            },
+            CanMessageType::FlashVerify => self.update.as_ref()?.verify(),
+            CanMessageType::FlashStart => match (&self.update, &self.multicast) {
+                (Some(session), _) => CanMessage::FlashStart {
+                    crc: session.crc,
+                    size: session.size,
+                },
+                (None, Some(multicast)) => CanMessage::FlashStart {
+                    crc: multicast.crc,
+                    size: multicast.size,
+                },
+                (None, None) => CanMessage::FlashStart { crc: 0, size: 0 },
+            },
+            CanMessageType::FlashSelect => CanMessage::FlashSelect(self.booted),
+            CanMessageType::UpdateConfirmWindow => {
+                CanMessage::UpdateConfirmWindow(self.confirm_window)
+            }
+            CanMessageType::FlashHeader => CanMessage::FlashHeader {
+                header: self.image,
+                allow_downgrade: false,
+                compressed: false,
+            },
+            _ => return None,
+        };
+        Some(msg)
+    }
+
+    async fn send_metadata<B: CanBus>(&self, bus: &B) -> Result<()> {
+        for field in Field::ALL {
+            let entry = Entry {
+                field,
+                value: self.metadata[field as usize].clone(),
+            };
+            self.send_long(bus, CanMessageType::Metadata, &entry.to_bytes())
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn set_metadata<B: CanBus>(&mut self, bus: &B, data: &[u8]) -> Result<()> {
+        match Entry::parse(data) {
+            Ok(entry) => self.metadata[entry.field as usize] = entry.value,
+            Err(e) => {
+                let details = [
+                    CanMessageType::Metadata as u8,
+                    data.first().copied().unwrap_or(0),
+                    e.detail(),
+                ];
+                let report = ErrorReport::new(
+                    Component::Device,
+                    ErrorCode::InvalidData,
+                    Severity::Warning,
+                    e.code(),
+                    &details,
+                );
+                self.send(bus, CanMessage::DeviceError(report)).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn segment<B: CanBus>(&mut self, bus: &B, segment: Segment) -> Result<()> {
+        match self.receiver.feed(&segment) {
+            Ok(Step::Flow(flow)) => {
+                self.send(bus, CanMessage::Segment(Segment::Flow(flow)))
+                    .await
+            }
+            Ok(Step::Complete(CanMessageType::Metadata)) => {
+                let data = self.receiver.data().to_vec();
+                self.set_metadata(bus, &data).await
+            }
+            Ok(_) | Err(_) => Ok(()),
+        }
+    }
+
+    /// One frame if `data` fits, otherwise segments paced by the flow
+    /// control of the gateway. Other frames meanwhile are dropped.
+    async fn send_long<B: CanBus>(
+        &self,
+        bus: &B,
+        msg_type: CanMessageType,
+        data: &[u8],
+    ) -> Result<()> {
+        if let Ok(data) = Payload::from_slice(data) {
+            let id = CanId::new(self.addr.device_type, self.addr.device_id, msg_type);
+            return bus
+                .send(&Frame {
+                    id,
+                    data,
+                    rtr: false,
+                })
+                .await;
+        }
+        let mut sender = Sender::new(msg_type, data).unwrap();
+        loop {
+            while let Some(segment) = sender.next_segment() {
+                self.send(bus, CanMessage::Segment(segment)).await?;
+            }
+            if sender.is_done() {
+                return Ok(());
+            }
+            let flow = tokio::time::timeout(FLOW_TIMEOUT, async {
+                loop {
+                    let frame = bus.recv().await?;
+                    if frame.rtr || frame.addr() != self.addr {
+                        continue;
+                    }
+                    if let Ok(CanMessage::Segment(Segment::Flow(flow))) = frame.message() {
+                        return Ok::<_, crate::error::Error>(flow);
+                    }
+                }
+            })
+            .await;
+            // the gateway gave up or has no room
+            let Ok(flow) = flow else {
+                return Ok(());
+            };
+            if sender.flow(&flow?).is_err() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn update_error(code: UpdateErrorCode, details: &[u8]) -> CanMessage {
+    CanMessage::DeviceError(ErrorReport::new(
+        Component::Update,
+        ErrorCode::InvalidData,
+        Severity::Warning,
+        code.into(),
+        details,
+    ))
+}
+
+/// Sleep until `at`, forever without one.
+async fn sleep_until(at: Option<tokio::time::Instant>) {
+    match at {
+        Some(at) => tokio::time::sleep_until(at).await,
+        None => std::future::pending().await,
+    }
+}
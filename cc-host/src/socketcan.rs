@@ -0,0 +1,167 @@
+use crate::bus::CanBus;
+use crate::error::{Error, Result};
+use crate::frame::Frame;
+use async_trait::async_trait;
+use cancomponents_core::can_id::CanId;
+use cancomponents_core::message::{Payload, MAX_PAYLOAD};
+use std::ffi::CString;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use tokio::io::unix::AsyncFd;
+use tokio::io::Interest;
+
+const CAN_EFF_MASK: u32 = 0x1FFF_FFFF;
+
+/// Raw SocketCAN interface (`can0`, `vcan0`, ...). Only extended frames are
+/// passed on, standard and error frames are dropped.
+///
+/// Must be opened from within a tokio runtime.
+pub struct SocketCan {
+    fd: AsyncFd<OwnedFd>,
+}
+
+impl SocketCan {
+    pub fn open(interface: &str) -> Result<Self> {
+        let name = CString::new(interface).map_err(|_| Error::NoSuchInterface(interface.into()))?;
+        let ifindex = unsafe { libc::if_nametoindex(name.as_ptr()) };
+        if ifindex == 0 {
+            return Err(Error::NoSuchInterface(interface.into()));
+        }
+
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_CAN,
+                libc::SOCK_RAW | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                libc::CAN_RAW,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let mut addr: libc::sockaddr_can = unsafe { std::mem::zeroed() };
+        addr.can_family = libc::AF_CAN as libc::sa_family_t;
+        addr.can_ifindex = ifindex as libc::c_int;
+        let ret = unsafe {
+            libc::bind(
+                fd.as_raw_fd(),
+                &addr as *const libc::sockaddr_can as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_can>() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        Ok(Self {
+            fd: AsyncFd::new(fd)?,
+        })
+    }
+}
+
+fn to_raw(frame: &Frame) -> libc::can_frame {
+    let mut raw: libc::can_frame = unsafe { std::mem::zeroed() };
+    raw.can_id = u32::from(frame.id) | libc::CAN_EFF_FLAG;
+    if frame.rtr {
+        raw.can_id |= libc::CAN_RTR_FLAG;
+    }
+    raw.can_dlc = frame.data.len() as u8;
+    raw.data[..frame.data.len()].copy_from_slice(&frame.data);
+    raw
+}
+
+fn from_raw(raw: &libc::can_frame) -> Option<Frame> {
+    if raw.can_id & libc::CAN_EFF_FLAG == 0 || raw.can_id & libc::CAN_ERR_FLAG != 0 {
+        return None;
+    }
+    let len = (raw.can_dlc as usize).min(MAX_PAYLOAD);
+    Some(Frame {
+        id: CanId::from(raw.can_id & CAN_EFF_MASK),
+        data: Payload::from_slice(&raw.data[..len]).ok()?,
+        rtr: raw.can_id & libc::CAN_RTR_FLAG != 0,
+    })
+}
+
+#[async_trait]
+impl CanBus for SocketCan {
+    async fn send(&self, frame: &Frame) -> Result<()> {
+        let raw = to_raw(frame);
+        self.fd
+            .async_io(Interest::WRITABLE, |fd| {
+                let n = unsafe {
+                    libc::write(
+                        fd.as_raw_fd(),
+                        &raw as *const libc::can_frame as *const libc::c_void,
+                        std::mem::size_of::<libc::can_frame>(),
+                    )
+                };
+                if n < 0 {
+                    let err = io::Error::last_os_error();
+                    // tx queue full, wait for the socket to drain
+                    if err.raw_os_error() == Some(libc::ENOBUFS) {
+                        return Err(io::ErrorKind::WouldBlock.into());
+                    }
+                    return Err(err);
+                }
+                Ok(())
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn recv(&self) -> Result<Frame> {
+        loop {
+            let raw = self
+                .fd
+                .async_io(Interest::READABLE, |fd| {
+                    let mut raw: libc::can_frame = unsafe { std::mem::zeroed() };
+                    let n = unsafe {
+                        libc::read(
+                            fd.as_raw_fd(),
+                            &mut raw as *mut libc::can_frame as *mut libc::c_void,
+                            std::mem::size_of::<libc::can_frame>(),
+                        )
+                    };
+                    if n < 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                    Ok(raw)
+                })
+                .await?;
+            if let Some(frame) = from_raw(&raw) {
+                return Ok(frame);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::NodeAddr;
+    use cancomponents_core::message::CanMessage;
+
+    #[test]
+    fn test_raw_roundtrip() {
+        let frame = Frame::from_message(NodeAddr::new(5, 12), &CanMessage::Uptime(42));
+        assert_eq!(from_raw(&to_raw(&frame)), Some(frame));
+
+        let request = Frame::from_message(
+            NodeAddr::new(5, 12),
+            &CanMessage::Request(cancomponents_core::can_message_type::CanMessageType::Uptime),
+        );
+        assert_eq!(from_raw(&to_raw(&request)), Some(request));
+    }
+
+    /// Needs a virtual bus: `ip link add vcan0 type vcan && ip link set up vcan0`
+    #[tokio::test]
+    #[ignore = "needs vcan0"]
+    async fn test_vcan_loopback() {
+        let a = SocketCan::open("vcan0").unwrap();
+        let b = SocketCan::open("vcan0").unwrap();
+        let frame = Frame::from_message(NodeAddr::new(5, 12), &CanMessage::Uptime(42));
+        a.send(&frame).await.unwrap();
+        assert_eq!(b.recv().await.unwrap(), frame);
+    }
+}